@@ -0,0 +1,111 @@
+use chip_8::{decode, Chip8, Variant};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// instructions run per benchmark iteration
+const CYCLES: u64 = 1000;
+
+// one of every standard instruction
+const DECODE_MIX: [u16; 34] = [
+    0x00E0, 0x00EE, 0x1234, 0x2345, 0x3A12, 0x4B34, 0x5AB0, 0x6C56, 0x7D78, 0x8AB0, 0x8AB1, 0x8AB2,
+    0x8AB3, 0x8AB4, 0x8AB5, 0x8AB6, 0x8AB7, 0x8ABE, 0x9AB0, 0xA123, 0xB123, 0xC1FF, 0xD125, 0xE19E,
+    0xE1A1, 0xF107, 0xF10A, 0xF115, 0xF118, 0xF11E, 0xF129, 0xF133, 0xF155, 0xF165,
+];
+
+// arithmetic, skips and jumps, the bulk of most games
+const ALU_LOOP: [u8; 16] = [
+    0x60, 0x05, // V0 = 5
+    0x71, 0x03, // V1 += 3
+    0x82, 0x14, // V2 += V1
+    0x83, 0x25, // V3 -= V2
+    0x84, 0x36, // V4 = V3 >> 1
+    0x35, 0x07, // skip if V5 == 7
+    0xC6, 0x0F, // V6 = random & 0x0F
+    0x12, 0x02, // jump 0x202
+];
+
+// BCD, register stores and loads through I
+const MEMORY_LOOP: [u8; 12] = [
+    0xA3, 0x00, // I = 0x300
+    0x70, 0x01, // V0 += 1
+    0xF0, 0x33, // BCD of V0
+    0xF3, 0x55, // store V0..V3
+    0xF3, 0x65, // load V0..V3
+    0x12, 0x00, // jump 0x200
+];
+
+// a call and a return every other instruction
+const CALL_LOOP: [u8; 6] = [
+    0x22, 0x04, // call 0x204
+    0x12, 0x00, // jump 0x200
+    0x00, 0xEE, // return
+];
+
+// a 15 row sprite drawn at a moving position, crossing the edges now and then
+const SPRITE_LOOP: [u8; 10] = [
+    0xA2, 0x00, // I = 0x200, the code itself makes a good enough sprite
+    0x70, 0x07, // V0 += 7
+    0x71, 0x03, // V1 += 3
+    0xD0, 0x1F, // draw 15 rows at V0, V1
+    0x12, 0x02, // jump 0x202
+];
+
+fn machine(rom: &[u8]) -> Chip8 {
+    let mut chip8 = Chip8::builder().rng(StdRng::seed_from_u64(0)).build();
+    chip8.load_rom_bytes(rom).unwrap();
+    chip8
+}
+
+fn run(chip8: &mut Chip8) {
+    for _ in 0..CYCLES {
+        chip8.emulate_cycle().unwrap();
+    }
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(DECODE_MIX.len() as u64));
+    group.bench_function("every opcode", |b| {
+        b.iter(|| {
+            for instruction in DECODE_MIX.iter() {
+                black_box(decode(black_box(*instruction), Variant::Chip8));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_execute(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute");
+    group.throughput(Throughput::Elements(CYCLES));
+    let mixes: [(&str, &[u8]); 3] = [
+        ("alu", &ALU_LOOP),
+        ("memory", &MEMORY_LOOP),
+        ("calls", &CALL_LOOP),
+    ];
+    for (name, rom) in mixes.iter() {
+        let mut chip8 = machine(rom);
+        group.bench_function(*name, |b| b.iter(|| run(&mut chip8)));
+        let mut chip8 = machine(rom);
+        chip8.set_predecode(true);
+        group.bench_function(format!("{} predecoded", name), |b| {
+            b.iter(|| run(&mut chip8))
+        });
+    }
+    group.finish();
+}
+
+fn bench_sprites(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sprites");
+    group.throughput(Throughput::Elements(CYCLES));
+    let mut chip8 = machine(&SPRITE_LOOP);
+    group.bench_function("draw", |b| b.iter(|| run(&mut chip8)));
+    let mut chip8 = machine(&SPRITE_LOOP);
+    chip8.quirks.clipping = false;
+    group.bench_function("draw wrapping", |b| b.iter(|| run(&mut chip8)));
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode, bench_execute, bench_sprites);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+#![no_main]
+// random bytes as a ROM, under every variant, quirk setting and policy. errors are fine,
+// panics aren't
+//
+//   cargo install cargo-fuzz
+//   cargo +nightly fuzz run run_rom
+use chip_8::{Chip8, MachineCodePolicy, MemoryPolicy, Quirks, UnknownOpcodePolicy, Variant};
+use libfuzzer_sys::fuzz_target;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+// enough to get through loops and into whatever they jump to, few enough to keep runs fast
+const MAX_CYCLES: usize = 10_000;
+const CYCLES_PER_FRAME: usize = 14;
+
+fuzz_target!(|data: &[u8]| {
+    // the first three bytes pick the machine, the rest is the ROM
+    let (setup, rom) = match data {
+        [variant, quirks, policies, rom @ ..] => ((*variant, *quirks, *policies), rom),
+        _ => return,
+    };
+    let variant = [Variant::Chip8, Variant::Hires, Variant::Chip8x][setup.0 as usize % 3];
+    let bit = |n: u8| setup.1 & 1 << n != 0;
+    let quirks = Quirks {
+        clipping: bit(0),
+        shift_vy: bit(1),
+        increment_i: bit(2),
+        jump_vx: bit(3),
+        vf_reset: bit(4),
+        display_wait: bit(5),
+        memory: if bit(6) {
+            MemoryPolicy::Fault
+        } else {
+            MemoryPolicy::Wrap
+        },
+        // Fault stops at the first word that isn't code, the others run on into what's behind it
+        machine_code: if setup.2 & 1 != 0 {
+            MachineCodePolicy::Fault
+        } else {
+            MachineCodePolicy::Ignore
+        },
+        unknown_opcode: [
+            UnknownOpcodePolicy::Fault,
+            UnknownOpcodePolicy::Skip,
+            UnknownOpcodePolicy::Warn,
+        ][(setup.2 >> 1) as usize % 3],
+    };
+    let mut chip8 = Chip8::builder()
+        .variant(variant)
+        .quirks(quirks)
+        .predecode(bit(7))
+        .rng(StdRng::seed_from_u64(0))
+        .build();
+    if chip8.load_rom_bytes(rom).is_err() {
+        return;
+    }
+    for cycle in 0..MAX_CYCLES {
+        // a frame boundary now and then, with a key going down and up so FX0A finishes
+        if cycle % CYCLES_PER_FRAME == 0 {
+            chip8.tick_timers();
+            let frame = cycle / CYCLES_PER_FRAME;
+            chip8.set_key((frame / 2 % 16) as u8, frame % 2 == 0);
+        }
+        if chip8.emulate_cycle().is_err() || chip8.has_exited() {
+            break;
+        }
+    }
+    // and what a frontend does with the result
+    let _ = chip8.state_hash();
+    let snapshot = chip8.snapshot();
+    chip8.restore(&snapshot).unwrap();
+});
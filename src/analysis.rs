@@ -0,0 +1,312 @@
+use crate::chip8::chip8::{try_decode, Opcode, Variant, MEM_SIZE};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+// instructions that behave differently between interpreters, and the quirk that picks which way
+const QUIRK_NOTES: [(&str, &str); 9] = [
+    (
+        "8XY6",
+        "shift_vy: shifts VY into VX on the COSMAC VIP, VX in place since CHIP-48",
+    ),
+    (
+        "8XYE",
+        "shift_vy: shifts VY into VX on the COSMAC VIP, VX in place since CHIP-48",
+    ),
+    (
+        "FX55",
+        "increment_i: leaves I at I + X + 1 on the COSMAC VIP",
+    ),
+    (
+        "FX65",
+        "increment_i: leaves I at I + X + 1 on the COSMAC VIP",
+    ),
+    (
+        "BMMM",
+        "jump_vx: jumps to XNN + VX on CHIP-48 and SCHIP instead of NNN + V0",
+    ),
+    ("8XY1", "vf_reset: sets VF to 0 on the COSMAC VIP"),
+    ("8XY2", "vf_reset: sets VF to 0 on the COSMAC VIP"),
+    ("8XY3", "vf_reset: sets VF to 0 on the COSMAC VIP"),
+    (
+        "DXYN",
+        "display_wait and clipping: one sprite per frame on the VIP, wrapping at the edges",
+    ),
+];
+
+// instructions that only some interpreters have
+const VARIANT_NOTES: [(&str, &str); 7] = [
+    ("0NNN", "COSMAC VIP 1802 machine code, skipped here"),
+    ("00FD", "SCHIP exit"),
+    ("0230", "the two-page hires interpreter"),
+    ("02A0", "CHIP-8X"),
+    ("BXYN", "CHIP-8X"),
+    ("F002", "XO-CHIP audio"),
+    ("FX75", "SCHIP RPL flags"),
+];
+
+/// what can be told about a ROM without running it. the code is found by following every path
+/// from the program start, taking both ways at skips and coming back after calls. computed
+/// jumps (BNNN) can't be followed, so code only reached through them counts as data
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Analysis {
+    /// where the program starts
+    pub start: usize,
+    pub rom_size: usize,
+    /// addresses of the reachable instructions
+    pub code: BTreeSet<usize>,
+    /// (from, to)
+    pub jumps: BTreeSet<(usize, usize)>,
+    /// 2MMMs, (from, to)
+    pub calls: BTreeSet<(usize, usize)>,
+    /// BNNN, where the target depends on a register
+    pub computed_jumps: BTreeSet<usize>,
+    /// addresses loaded into I (ANNN), usually sprites and other data
+    pub data_references: BTreeSet<usize>,
+    /// reachable words that don't decode for the variant, with the word
+    pub unknown: BTreeMap<usize, u16>,
+    /// paths that run past the end of the ROM
+    pub runs_off_end: BTreeSet<usize>,
+    /// reachable instructions by opcode name ("8XY4"), and the first address of each
+    pub opcodes: BTreeMap<String, (usize, usize)>,
+}
+
+/// `rom` as loaded for `variant`, at the variant's layout
+pub fn analyze(rom: &[u8], variant: Variant) -> Analysis {
+    let layout = variant.layout();
+    let start = layout.load;
+    let end = (start + rom.len()).min(MEM_SIZE);
+    let word = |address: usize| -> Option<u16> {
+        if address < start || address + 1 >= end {
+            return None;
+        }
+        Some((rom[address - start] as u16) << 8 | rom[address + 1 - start] as u16)
+    };
+    let mut analysis = Analysis {
+        start,
+        rom_size: rom.len(),
+        ..Analysis::default()
+    };
+    let mut pending = vec![layout.start];
+    while let Some(address) = pending.pop() {
+        if analysis.code.contains(&address) {
+            continue;
+        }
+        let instruction = match word(address) {
+            Some(instruction) => instruction,
+            None => {
+                analysis.runs_off_end.insert(address);
+                continue;
+            }
+        };
+        let opcode = match try_decode(instruction, variant) {
+            Some(opcode) => opcode,
+            None => {
+                analysis.unknown.insert(address, instruction);
+                continue;
+            }
+        };
+        analysis.code.insert(address);
+        let count = analysis
+            .opcodes
+            .entry(opcode_name(&opcode))
+            .or_insert((0, address));
+        count.0 += 1;
+        count.1 = count.1.min(address);
+
+        let next = address + 2;
+        match opcode {
+            Opcode::OP_1MMM(target) => {
+                analysis.jumps.insert((address, target));
+                pending.push(target);
+            }
+            Opcode::OP_2MMM(target) => {
+                analysis.calls.insert((address, target));
+                pending.push(target);
+                pending.push(next);
+            }
+            Opcode::OP_BMMM(_) => {
+                analysis.computed_jumps.insert(address);
+            }
+            Opcode::OP_00EE | Opcode::OP_00FD => {}
+            Opcode::OP_3XKK(..)
+            | Opcode::OP_4XKK(..)
+            | Opcode::OP_5XY0(..)
+            | Opcode::OP_5XY1(..)
+            | Opcode::OP_9XY0(..)
+            | Opcode::OP_EX9E(_)
+            | Opcode::OP_EXA1(_) => {
+                pending.push(next);
+                pending.push(next + 2);
+            }
+            Opcode::OP_AMMM(target) => {
+                analysis.data_references.insert(target);
+                pending.push(next);
+            }
+            // XO-CHIP's I = NNNN, the address is in the next word
+            Opcode::OP_F000 => pending.push(next + 2),
+            _ => pending.push(next),
+        }
+    }
+    analysis
+}
+
+/// "OP_8XY4(1, 2)" -> "8XY4"
+pub fn opcode_name(opcode: &Opcode) -> String {
+    let debug = format!("{:?}", opcode);
+    let name = debug.split('(').next().unwrap_or(&debug);
+    name.trim_start_matches("OP_").to_string()
+}
+
+impl Analysis {
+    /// ROM bytes that aren't part of a reachable instruction
+    pub fn data_bytes(&self) -> usize {
+        let code_bytes = (self.start..self.start + self.rom_size)
+            .filter(|address| {
+                self.code.contains(address) || (*address > 0 && self.code.contains(&(address - 1)))
+            })
+            .count();
+        self.rom_size - code_bytes
+    }
+
+    /// the quirks the ROM could depend on, as (opcode, note)
+    pub fn quirk_notes(&self) -> Vec<(&'static str, &'static str)> {
+        QUIRK_NOTES
+            .iter()
+            .filter(|(name, _)| self.opcodes.contains_key(*name))
+            .copied()
+            .collect()
+    }
+
+    /// instructions only some interpreters have, as (opcode, interpreter)
+    pub fn variant_notes(&self) -> Vec<(&'static str, &'static str)> {
+        VARIANT_NOTES
+            .iter()
+            .filter(|(name, _)| self.opcodes.contains_key(*name))
+            .copied()
+            .collect()
+    }
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} bytes: {} reachable instructions, {} bytes of data",
+            self.rom_size,
+            self.code.len(),
+            self.data_bytes()
+        )?;
+        let edges = |f: &mut fmt::Formatter, title: &str, edges: &BTreeSet<(usize, usize)>| {
+            if edges.is_empty() {
+                return Ok(());
+            }
+            writeln!(f, "{}:", title)?;
+            edges
+                .iter()
+                .try_for_each(|(from, to)| writeln!(f, "  {:#05X} -> {:#05X}", from, to))
+        };
+        edges(f, "calls", &self.calls)?;
+        edges(f, "jumps", &self.jumps)?;
+        let addresses = |f: &mut fmt::Formatter, title: &str, addresses: &BTreeSet<usize>| {
+            if addresses.is_empty() {
+                return Ok(());
+            }
+            write!(f, "{}:", title)?;
+            addresses
+                .iter()
+                .try_for_each(|address| write!(f, " {:#05X}", address))?;
+            writeln!(f)
+        };
+        addresses(f, "data at", &self.data_references)?;
+        addresses(f, "computed jumps (not followed) at", &self.computed_jumps)?;
+        addresses(f, "runs past the end of the ROM from", &self.runs_off_end)?;
+        if !self.unknown.is_empty() {
+            write!(f, "unknown instructions:")?;
+            for (address, instruction) in &self.unknown {
+                write!(f, " {:04X}@{:#05X}", instruction, address)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "opcodes:")?;
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(b.0)));
+        for (name, (count, _)) in opcodes {
+            writeln!(f, "  {:<4}  {}", name, count)?;
+        }
+        let quirks = self.quirk_notes();
+        if !quirks.is_empty() {
+            writeln!(f, "compatibility:")?;
+        }
+        for (name, note) in quirks {
+            let (_, first) = self.opcodes[name];
+            writeln!(
+                f,
+                "  uses {} (first at {:#05X}), sensitive to {}",
+                name, first, note
+            )?;
+        }
+        for (name, interpreter) in self.variant_notes() {
+            writeln!(f, "  uses {}, only on {}", name, interpreter)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        let rom = [
+            0x22, 0x08, // 0x200 call 0x208
+            0x30, 0x00, // 0x202 skip if V0 == 0
+            0x12, 0x02, // 0x204 jump 0x202
+            0x12, 0x06, // 0x206 loop forever
+            0xA2, 0x10, // 0x208 I = 0x210
+            0xF1, 0x55, // 0x20A store V0-V1
+            0x80, 0x16, // 0x20C V0 >>= 1
+            0x00, 0xEE, // 0x20E return
+            0xFF, 0x00, // 0x210 data
+        ];
+        let analysis = analyze(&rom, Variant::Chip8);
+        assert_eq!(
+            analysis.code.iter().copied().collect::<Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A, 0x20C, 0x20E]
+        );
+        assert_eq!(analysis.data_bytes(), 2);
+        assert!(analysis.calls.contains(&(0x200, 0x208)));
+        assert!(analysis.jumps.contains(&(0x206, 0x206)));
+        assert!(analysis.data_references.contains(&0x210));
+        assert_eq!(analysis.opcodes["1MMM"], (2, 0x204));
+        assert_eq!(
+            analysis
+                .quirk_notes()
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>(),
+            ["8XY6", "FX55"]
+        );
+        let report = analysis.to_string();
+        assert!(report.starts_with("18 bytes: 8 reachable instructions, 2 bytes of data\n"));
+        assert!(report.contains("  uses FX55 (first at 0x20A), sensitive to increment_i"));
+    }
+
+    #[test]
+    fn test_dead_ends() {
+        // skip over an unknown instruction that's still reachable the other way, then a
+        // computed jump, and a path off the end
+        let rom = [0x30, 0x00, 0x51, 0x23, 0xB2, 0x00, 0x60, 0x00];
+        let analysis = analyze(&rom, Variant::Chip8);
+        assert_eq!(analysis.unknown.get(&0x202), Some(&0x5123));
+        assert!(analysis.computed_jumps.contains(&0x204));
+        assert!(analysis.runs_off_end.is_empty());
+
+        let analysis = analyze(&[0x60, 0x00], Variant::Chip8);
+        assert!(analysis.runs_off_end.contains(&0x202));
+        assert!(analysis
+            .to_string()
+            .contains("runs past the end of the ROM from: 0x202\n"));
+    }
+}
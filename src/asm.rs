@@ -0,0 +1,571 @@
+use crate::chip8::chip8::MAX_ROM_SIZE;
+
+use std::collections::HashMap;
+
+/// compiles Octo (<https://github.com/JohnEarnest/Octo>) source to a ROM. the common part of
+/// the language is supported:
+///
+/// ```text
+///   : name            a label, `: main` is where the program starts
+///   :const name 12    a named number
+///   :alias name v3    a named register
+///   name              calls the label
+///   12 0xFF 0b1010    raw bytes, e.g. sprite data
+///   clear return ; exit jump jump0 bcd save load sprite
+///   vx := n|vy|random n|key|delay, vx += n|vy, vx -= n|vy, vx =- vy,
+///   vx |= &= ^= >>= <<= vy, i := n|label|hex vx, i += vx, delay := vx, buzzer := vx
+///   if vx ==|!= n|vy then ..., if vx key|-key then ...
+///   if ... begin ... else ... end, loop ... while ... again
+/// ```
+///
+/// macros, :calc, :org, :next and the SCHIP/XO-CHIP instructions aren't
+pub fn compile(source: &str) -> Result<Vec<u8>, String> {
+    let mut compiler = Compiler::new(source);
+    compiler.program()?;
+    compiler.finish()
+}
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+// an instruction whose address was a label that wasn't defined yet
+struct Fixup {
+    offset: usize,
+    label: String,
+    line: usize,
+}
+
+enum Block {
+    // the start of the loop and the jumps out of it from `while`
+    Loop { start: usize, exits: Vec<usize> },
+    // the jump over the body, filled in by `else` or `end`
+    If { jump: usize },
+    Else { jump: usize },
+}
+
+struct Compiler<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, usize>,
+    constants: HashMap<&'a str, u16>,
+    aliases: HashMap<&'a str, usize>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+// Octo programs always start at 0x200
+const PROGRAM_START_ADDRESS: usize = 0x200;
+
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = text.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else {
+        text.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+impl<'a> Compiler<'a> {
+    fn new(source: &'a str) -> Compiler<'a> {
+        let tokens = source
+            .lines()
+            .enumerate()
+            .flat_map(|(number, line)| {
+                let line = line.split('#').next().unwrap_or("");
+                line.split_whitespace().map(move |text| Token {
+                    text,
+                    line: number + 1,
+                })
+            })
+            .collect();
+        Compiler {
+            tokens,
+            position: 0,
+            rom: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn here(&self) -> usize {
+        PROGRAM_START_ADDRESS + self.rom.len()
+    }
+
+    // the line of the last token read, for errors
+    fn line(&self) -> usize {
+        match self.tokens.get(self.position.saturating_sub(1)) {
+            Some(token) => token.line,
+            None => 0,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line(), message))
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of file".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|token| token.text)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            text if text == expected => Ok(()),
+            text => self.error(format!("expected {:?}, found {:?}", expected, text)),
+        }
+    }
+
+    fn emit(&mut self, instruction: u16) {
+        self.rom.extend_from_slice(&instruction.to_be_bytes());
+    }
+
+    // fills in the low 12 bits of the instruction at `offset`
+    fn patch(&mut self, offset: usize, address: usize) {
+        self.rom[offset] |= (address >> 8) as u8 & 0x0F;
+        self.rom[offset + 1] = address as u8;
+    }
+
+    fn register_name(&self, text: &str) -> Option<usize> {
+        if let Some(register) = self.aliases.get(text) {
+            return Some(*register);
+        }
+        let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+        match digit.len() {
+            1 => usize::from_str_radix(digit, 16).ok(),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<usize, String> {
+        let text = self.next()?;
+        match self.register_name(text) {
+            Some(register) => Ok(register),
+            None => self.error(format!("expected a register, found {:?}", text)),
+        }
+    }
+
+    fn number(&mut self, max: i32) -> Result<u16, String> {
+        let text = self.next()?;
+        let value = match (self.constants.get(text), parse_number(text)) {
+            (Some(value), _) => *value as i32,
+            (None, Some(value)) => value,
+            (None, None) => return self.error(format!("expected a number, found {:?}", text)),
+        };
+        // negative bytes count down from 256
+        match value {
+            value if (0..=max).contains(&value) => Ok(value as u16),
+            value if max == 0xFF && (-128..0).contains(&value) => Ok((value + 256) as u16),
+            _ => self.error(format!(
+                "{} doesn't fit in {} bits",
+                text,
+                32 - max.leading_zeros()
+            )),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u16, String> {
+        self.number(0xFF)
+    }
+
+    // an instruction with a 12 bit address, which can be a label defined further down
+    fn emit_address(&mut self, opcode: u16) -> Result<(), String> {
+        let text = self.next()?;
+        let address = match (self.labels.get(text), self.constants.get(text)) {
+            (Some(address), _) => *address as u16,
+            (None, Some(value)) if *value <= 0xFFF => *value,
+            (None, None) if parse_number(text).is_some() => {
+                self.position -= 1;
+                self.number(0xFFF)?
+            }
+            _ => {
+                self.fixups.push(Fixup {
+                    offset: self.rom.len(),
+                    label: text.to_string(),
+                    line: self.line(),
+                });
+                0
+            }
+        };
+        self.emit(opcode | address);
+        Ok(())
+    }
+
+    fn program(&mut self) -> Result<(), String> {
+        // the program starts at main, jump there unless it's the first code
+        let mut first = 0;
+        while matches!(
+            self.tokens.get(first).map(|token| token.text),
+            Some(":const" | ":alias")
+        ) {
+            first += 3;
+        }
+        let starts_with_main = match self.tokens.get(first..first + 2) {
+            Some([colon, name]) => colon.text == ":" && name.text == "main",
+            _ => false,
+        };
+        if !starts_with_main {
+            self.fixups.push(Fixup {
+                offset: 0,
+                label: "main".to_string(),
+                line: 1,
+            });
+            self.emit(0x1000);
+        }
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let text = self.next()?;
+        match text {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.insert(name, self.here()).is_some() {
+                    return self.error(format!("{} is defined twice", name));
+                }
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.number(0xFFFF)?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xB000)?,
+            "bcd" => self.emit_fx(0x33)?,
+            "save" => self.emit_fx(0x55)?,
+            "load" => self.emit_fx(0x65)?,
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.number(0xF)?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            }
+            "i" => match self.next()? {
+                ":=" if self.peek() == Some("hex") => {
+                    self.next()?;
+                    self.emit_fx(0x29)?;
+                }
+                ":=" => self.emit_address(0xA000)?,
+                "+=" => self.emit_fx(0x1E)?,
+                operator => return self.error(format!("can't do i {}", operator)),
+            },
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                self.emit_fx(if text == "delay" { 0x15 } else { 0x18 })?;
+            }
+            "if" => {
+                // `then` runs the next instruction only when the condition holds, `begin` a block
+                let (skip_unless, skip_if) = self.condition()?;
+                match self.next()? {
+                    "then" => self.emit(skip_unless),
+                    "begin" => {
+                        self.emit(skip_if);
+                        self.blocks.push(Block::If {
+                            jump: self.rom.len(),
+                        });
+                        self.emit(0x1000);
+                    }
+                    other => {
+                        return self.error(format!("expected then or begin, found {:?}", other))
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump }) => {
+                    self.blocks.push(Block::Else {
+                        jump: self.rom.len(),
+                    });
+                    self.emit(0x1000);
+                    self.patch(jump, self.here());
+                }
+                _ => return self.error("else without if ... begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump }) | Some(Block::Else { jump }) => {
+                    self.patch(jump, self.here())
+                }
+                _ => return self.error("end without if ... begin".to_string()),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here(),
+                exits: Vec::new(),
+            }),
+            "while" => {
+                let (_, skip_if) = self.condition()?;
+                self.emit(skip_if);
+                let offset = self.rom.len();
+                self.emit(0x1000);
+                match self.blocks.iter_mut().rev().find_map(|block| match block {
+                    Block::Loop { exits, .. } => Some(exits),
+                    _ => None,
+                }) {
+                    Some(exits) => exits.push(offset),
+                    None => return self.error("while outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(0x1000 | start as u16);
+                    for exit in exits {
+                        self.patch(exit, self.here());
+                    }
+                }
+                _ => return self.error("again without loop".to_string()),
+            },
+            _ if self.register_name(text).is_some() => {
+                self.position -= 1;
+                self.register_statement()?;
+            }
+            _ if parse_number(text).is_some() || self.constants.contains_key(text) => {
+                self.position -= 1;
+                let byte = self.byte()?;
+                self.rom.push(byte as u8);
+            }
+            _ if text.starts_with(':') => return self.error(format!("{} isn't supported", text)),
+            // anything else is a label to call
+            _ => {
+                self.position -= 1;
+                self.emit_address(0x2000)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_fx(&mut self, low: u16) -> Result<(), String> {
+        let x = self.register()? as u16;
+        self.emit(0xF000 | x << 8 | low);
+        Ok(())
+    }
+
+    fn register_statement(&mut self) -> Result<(), String> {
+        let x = self.register()? as u16;
+        let operator = self.next()?;
+        let rhs = self.peek().unwrap_or("");
+        let vy = self.register_name(rhs).map(|y| y as u16);
+        let instruction = match (operator, rhs, vy) {
+            (":=", "random", _) => {
+                self.next()?;
+                0xC000 | x << 8 | self.byte()?
+            }
+            (":=", "key", _) => 0xF00A | x << 8,
+            (":=", "delay", _) => 0xF007 | x << 8,
+            (":=", _, Some(y)) => 0x8000 | x << 8 | y << 4,
+            (":=", _, None) => 0x6000 | x << 8 | self.byte()?,
+            ("+=", _, Some(y)) => 0x8004 | x << 8 | y << 4,
+            ("+=", _, None) => 0x7000 | x << 8 | self.byte()?,
+            ("-=", _, Some(y)) => 0x8005 | x << 8 | y << 4,
+            ("-=", _, None) => 0x7000 | x << 8 | (0x100 - self.byte()?) & 0xFF,
+            ("=-", _, Some(y)) => 0x8007 | x << 8 | y << 4,
+            ("|=", _, Some(y)) => 0x8001 | x << 8 | y << 4,
+            ("&=", _, Some(y)) => 0x8002 | x << 8 | y << 4,
+            ("^=", _, Some(y)) => 0x8003 | x << 8 | y << 4,
+            (">>=", _, Some(y)) => 0x8006 | x << 8 | y << 4,
+            ("<<=", _, Some(y)) => 0x800E | x << 8 | y << 4,
+            _ => return self.error(format!("can't do v{:X} {} {}", x, operator, rhs)),
+        };
+        // the ones that take a register or a word still have it to read
+        if vy.is_some() || matches!(rhs, "key" | "delay") {
+            self.next()?;
+        }
+        self.emit(instruction);
+        Ok(())
+    }
+
+    // a condition as (skip unless it holds, skip if it holds)
+    fn condition(&mut self) -> Result<(u16, u16), String> {
+        let x = self.register()? as u16;
+        let operator = self.next()?;
+        let (equal, not_equal) = match operator {
+            "key" => return Ok((0xE0A1 | x << 8, 0xE09E | x << 8)),
+            "-key" => return Ok((0xE09E | x << 8, 0xE0A1 | x << 8)),
+            "==" | "!=" => match self.peek().and_then(|rhs| self.register_name(rhs)) {
+                Some(y) => {
+                    self.next()?;
+                    let y = (y as u16) << 4;
+                    (0x5000 | x << 8 | y, 0x9000 | x << 8 | y)
+                }
+                None => {
+                    let kk = self.byte()?;
+                    (0x3000 | x << 8 | kk, 0x4000 | x << 8 | kk)
+                }
+            },
+            _ => return self.error(format!("{} isn't supported in conditions", operator)),
+        };
+        // skipping unless vx == n is skipping if vx != n
+        Ok(match operator {
+            "==" => (not_equal, equal),
+            _ => (equal, not_equal),
+        })
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        if !self.blocks.is_empty() {
+            return Err("unexpected end of file, a loop or if isn't closed".to_string());
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(fixup.label.as_str()) {
+                Some(address) => *address,
+                None if fixup.label == "main" => return Err("there's no `: main`".to_string()),
+                None => {
+                    return Err(format!(
+                        "line {}: unknown label {}",
+                        fixup.line, fixup.label
+                    ))
+                }
+            };
+            self.patch(fixup.offset, address);
+        }
+        if self.rom.len() > MAX_ROM_SIZE {
+            return Err(format!(
+                "the program is {} bytes, the maximum is {}",
+                self.rom.len(),
+                MAX_ROM_SIZE
+            ));
+        }
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2)
+            .map(|pair| (pair[0] as u16) << 8 | *pair.get(1).unwrap_or(&0) as u16)
+            .collect()
+    }
+
+    #[test]
+    fn test_instructions() {
+        let rom = compile(
+            "
+            :alias x v3
+            :const SPEED 2
+            : main
+                clear
+                x := 0x10    # a comment
+                x += SPEED
+                v4 := random 0b1111
+                v4 -= 1
+                va := key
+                x ^= va
+                x >>= x
+                i := hex x
+                i := sprite
+                sprite x v4 5
+                bcd x save v2 load v2
+                delay := x
+                x := delay
+                draw
+                jump main
+            : draw
+                ;
+            : sprite
+                0x80 -1
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&rom),
+            [
+                0x00E0, 0x6310, 0x7302, 0xC40F, 0x74FF, 0xFA0A, 0x83A3, 0x8336, 0xF329, 0xA226,
+                0xD345, 0xF333, 0xF255, 0xF265, 0xF315, 0xF307, 0x2224, 0x1200, 0x00EE, 0x80FF
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let rom = compile(
+            "
+            : sub return
+            : main
+                if v0 == 1 then v1 := 2
+                if v0 != v1 then sub
+                if v2 key begin
+                    v3 := 1
+                else
+                    v3 := 2
+                end
+                loop
+                    v4 += 1
+                    while v4 != 10
+                    if v5 -key then v5 := 0
+                again
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            words(&rom),
+            [
+                // jump main, sub
+                0x1204, 0x00EE, //
+                // 0x204: if .. then
+                0x4001, 0x6102, 0x5010, 0x2202, //
+                // 0x20C: if v2 key begin, skip the jump to else when the key is down
+                0xE29E, 0x1214, 0x6301, 0x1216, 0x6302, //
+                // 0x216: loop
+                0x7401, 0x440A, 0x1222, 0xE59E, 0x6500, 0x1216
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            compile(": main\n  v0 := 256"),
+            Err("line 2: 256 doesn't fit in 8 bits".to_string())
+        );
+        assert_eq!(
+            compile(": main\njump nowhere"),
+            Err("line 2: unknown label nowhere".to_string())
+        );
+        assert_eq!(compile("clear"), Err("there's no `: main`".to_string()));
+        assert_eq!(
+            compile(": main\n:macro"),
+            Err("line 2: :macro isn't supported".to_string())
+        );
+        assert_eq!(
+            compile(": main\nloop"),
+            Err("unexpected end of file, a loop or if isn't closed".to_string())
+        );
+        assert!(compile(": main\n: main")
+            .unwrap_err()
+            .contains("defined twice"));
+        assert!(compile(": main\nv0 := ")
+            .unwrap_err()
+            .contains("end of file"));
+    }
+}
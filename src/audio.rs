@@ -1,23 +1,249 @@
+use chip_8::TIMER_FREQ;
+use clap::ValueEnum;
 use sdl2::audio::AudioCallback;
+use serde::Deserialize;
 
-pub struct SquareWave {
+// how long the buzzer takes to fade in and out. starting and stopping the wave mid-cycle pops
+const ENVELOPE_SECONDS: f32 = 0.005;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+pub struct ToneGenerator {
+    pub waveform: Waveform,
     pub phase_inc: f32,
     pub phase: f32,
     pub volume: f32,
+    // xorshift state for the noise waveform, must be non-zero
+    pub noise_state: u32,
+}
+
+impl ToneGenerator {
+    pub fn new(waveform: Waveform, frequency: f32, sample_rate: i32, volume: f32) -> ToneGenerator {
+        ToneGenerator {
+            waveform,
+            phase_inc: frequency / sample_rate as f32,
+            phase: 0.0,
+            volume,
+            noise_state: 0x1234_5678,
+        }
+    }
+
+    // a single sample in the range -volume..=volume
+    fn sample(&mut self) -> f32 {
+        let value = match self.waveform {
+            Waveform::Square => {
+                if self.phase <= 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => {
+                self.noise_state ^= self.noise_state << 13;
+                self.noise_state ^= self.noise_state >> 17;
+                self.noise_state ^= self.noise_state << 5;
+                (self.noise_state as f32 / u32::MAX as f32) * 2.0 - 1.0
+            }
+        };
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+        value * self.volume
+    }
+}
+
+// plays an XO-CHIP 128 bit pattern buffer, most significant bit first, on a loop
+pub struct PatternPlayer {
+    pub pattern: [u8; 16],
+    // bits per output sample
+    pub bit_inc: f32,
+    // position in the pattern, in bits
+    pub position: f32,
+    pub volume: f32,
+}
+
+impl PatternPlayer {
+    pub fn new(pattern: [u8; 16], playback_rate: f32, sample_rate: i32, volume: f32) -> Self {
+        PatternPlayer {
+            pattern,
+            bit_inc: playback_rate / sample_rate as f32,
+            position: 0.0,
+            volume,
+        }
+    }
+
+    fn sample(&mut self) -> f32 {
+        let bit_index = self.position as usize % 128;
+        let bit = self.pattern[bit_index / 8] >> (7 - bit_index % 8) & 1;
+        self.position = (self.position + self.bit_inc) % 128.0;
+        if bit == 1 {
+            self.volume
+        } else {
+            -self.volume
+        }
+    }
+}
+
+// the audio device callback. uses the pattern buffer once a ROM has loaded one,
+// and the configured tone otherwise. the device keeps running, silence is the gate being
+// closed, so the sound can fade in and out instead of clicking
+pub struct Beeper {
+    pub tone: ToneGenerator,
+    pub pattern: Option<PatternPlayer>,
+    sample_rate: i32,
+    // samples left to play
+    gate: usize,
+    // 0 (silent) to 1 (full volume)
+    envelope: f32,
+    envelope_step: f32,
+}
+
+impl Beeper {
+    pub fn new(tone: ToneGenerator, sample_rate: i32) -> Beeper {
+        Beeper {
+            tone,
+            pattern: None,
+            sample_rate,
+            gate: 0,
+            envelope: 0.0,
+            envelope_step: 1.0 / (ENVELOPE_SECONDS * sample_rate as f32).max(1.0),
+        }
+    }
+
+    // sound until close_gate
+    pub fn open_gate(&mut self) {
+        self.gate = usize::MAX;
+    }
+
+    pub fn close_gate(&mut self) {
+        self.gate = 0;
+    }
+
+    // sound for as long as the sound timer has left. it's counted in samples, so the beep
+    // ends on time even when the next update comes late
+    pub fn set_sound_timer(&mut self, ticks: u8) {
+        self.gate = ticks as usize * self.sample_rate as usize / TIMER_FREQ as usize;
+    }
+
+    pub fn set_pattern(&mut self, pattern: [u8; 16], playback_rate: f32) {
+        let volume = self.tone.volume;
+        match &mut self.pattern {
+            // keep the current position so pitch changes don't click
+            Some(player) => {
+                player.pattern = pattern;
+                player.bit_inc = playback_rate / self.sample_rate as f32;
+            }
+            None => {
+                self.pattern = Some(PatternPlayer::new(
+                    pattern,
+                    playback_rate,
+                    self.sample_rate,
+                    volume,
+                ));
+            }
+        }
+    }
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Beeper {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
+            if self.gate > 0 {
+                self.gate -= 1;
+                self.envelope = (self.envelope + self.envelope_step).min(1.0);
             } else {
-                -self.volume
+                self.envelope = (self.envelope - self.envelope_step).max(0.0);
+            }
+            if self.envelope == 0.0 {
+                *x = 0.0;
+                continue;
+            }
+            let sample = match &mut self.pattern {
+                Some(player) => player.sample(),
+                None => self.tone.sample(),
             };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            *x = sample * self.envelope;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waveforms_stay_in_range() {
+        for waveform in [
+            Waveform::Square,
+            Waveform::Sine,
+            Waveform::Triangle,
+            Waveform::Noise,
+        ] {
+            let mut generator = ToneGenerator::new(waveform, 440.0, 44100, 0.5);
+            let out: Vec<f32> = (0..512).map(|_| generator.sample()).collect();
+            assert!(out.iter().all(|x| x.abs() <= 0.5), "{:?}", waveform);
+            assert!(out.iter().any(|x| *x != out[0]), "{:?}", waveform);
         }
     }
+
+    #[test]
+    fn test_square_wave() {
+        // 4 samples per period
+        let mut generator = ToneGenerator::new(Waveform::Square, 1.0, 4, 1.0);
+        let out: Vec<f32> = (0..4).map(|_| generator.sample()).collect();
+        assert_eq!(out, [1.0, 1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_pattern_playback() {
+        let tone = ToneGenerator::new(Waveform::Square, 440.0, 8, 1.0);
+        let mut beeper = Beeper::new(tone, 8);
+        beeper.open_gate();
+        let mut pattern = [0; 16];
+        pattern[0] = 0b1010_0000;
+        // one bit per sample
+        beeper.set_pattern(pattern, 8.0);
+        let mut out = [0.0; 4];
+        beeper.callback(&mut out);
+        assert_eq!(out, [1.0, -1.0, 1.0, -1.0]);
+
+        // half speed, each bit is held for two samples
+        beeper.set_pattern(pattern, 4.0);
+        beeper.pattern.as_mut().unwrap().position = 0.0;
+        beeper.callback(&mut out);
+        assert_eq!(out, [1.0, 1.0, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_envelope() {
+        // a 4 sample fade at 800 Hz, 40 samples for 3 sound timer ticks
+        let tone = ToneGenerator::new(Waveform::Square, 1.0, 800, 1.0);
+        let mut beeper = Beeper::new(tone, 800);
+        let mut out = [0.0; 50];
+        beeper.callback(&mut out);
+        assert!(out.iter().all(|x| *x == 0.0));
+
+        beeper.set_sound_timer(3);
+        beeper.callback(&mut out);
+        assert_eq!(out[..5], [0.25, 0.5, 0.75, 1.0, 1.0]);
+        assert_eq!(out[39], 1.0);
+        // the gate closes after 3 ticks whether or not anyone says so
+        assert_eq!(out[40..44], [0.75, 0.5, 0.25, 0.0]);
+        assert!(out[44..].iter().all(|x| *x == 0.0));
+
+        beeper.open_gate();
+        beeper.callback(&mut out[..10]);
+        beeper.close_gate();
+        beeper.callback(&mut out[..5]);
+        assert_eq!(out[..5], [0.75, 0.5, 0.25, 0.0, 0.0]);
+    }
 }
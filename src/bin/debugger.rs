@@ -0,0 +1,521 @@
+// a debugger with a window of its own: the game, the registers and stack, the code around pc,
+// memory and the breakpoints, with buttons to run and step. it only uses the library, the same
+// Debugger the SDL frontend's debug server drives. build with --features gui-debug
+use chip_8::analysis::opcode_name;
+use chip_8::debugger::Stop;
+use chip_8::expression::Expression;
+use chip_8::{try_decode, Chip8, Debugger, FrameBudget, Quirks, Status, Symbols, Variant};
+use chip_8::{MEM_SIZE, TIMER_FREQ};
+use clap::Parser;
+use eframe::egui::{self, Color32, ColorImage, Key, RichText, TextureHandle, TextureOptions};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// window pixels per chip-8 pixel
+const SCALE: f32 = 6.0;
+// instructions listed before and after pc
+const LISTING_BEFORE: usize = 8;
+const LISTING_AFTER: usize = 24;
+const MEMORY_ROW: usize = 16;
+// frames run at once to catch up after the window was stalled, the rest are dropped
+const MAX_CATCH_UP: u32 = 4;
+// the keypad on 1234 QWER ASDF ZXCV, like the SDL frontend's default keymap
+const KEYPAD: [(Key, u8); 16] = [
+    (Key::Num1, 0x1),
+    (Key::Num2, 0x2),
+    (Key::Num3, 0x3),
+    (Key::Num4, 0xC),
+    (Key::Q, 0x4),
+    (Key::W, 0x5),
+    (Key::E, 0x6),
+    (Key::R, 0xD),
+    (Key::A, 0x7),
+    (Key::S, 0x8),
+    (Key::D, 0x9),
+    (Key::F, 0xE),
+    (Key::Z, 0xA),
+    (Key::X, 0x0),
+    (Key::C, 0xB),
+    (Key::V, 0xF),
+];
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about = "A CHIP-8 debugger with a GUI", long_about = None)]
+struct Cli {
+    // Path to the ROM file, or an Octo source (.8o) to compile
+    #[clap(value_parser)]
+    rom_path: PathBuf,
+    // Names for the ROM's addresses, in the same format as the emulator's --symbols
+    #[clap(long, value_parser)]
+    symbols: Option<PathBuf>,
+    // Instructions per second
+    #[clap(long, value_parser)]
+    speed: Option<u64>,
+    // Interpreter variant: chip8, hires (64x64) or chip8x (color)
+    #[clap(long, value_parser = Variant::from_str)]
+    variant: Option<Variant>,
+    // Interpreter behaviours to copy: cosmac (the original VIP), schip or modern
+    #[clap(long, value_parser = Quirks::preset)]
+    quirks: Option<Quirks>,
+    // Start running instead of stopped at the first instruction
+    #[clap(long, value_parser)]
+    run: bool,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let app = match DebuggerApp::new(&cli) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([1100.0, 800.0]),
+        ..Default::default()
+    };
+    if let Err(e) = eframe::run_native("chip-8 debugger", options, Box::new(|_| Ok(Box::new(app))))
+    {
+        eprintln!("couldn't open the window: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let error = |e: &dyn std::fmt::Display| format!("couldn't load {}: {}", path.display(), e);
+    if path.extension().is_some_and(|extension| extension == "8o") {
+        let source = fs::read_to_string(path).map_err(|e| error(&e))?;
+        chip_8::asm::compile(&source).map_err(|e| error(&e))
+    } else {
+        fs::read(path).map_err(|e| error(&e))
+    }
+}
+
+struct DebuggerApp {
+    chip8: Chip8,
+    debugger: Debugger,
+    rom: Vec<u8>,
+    budget: FrameBudget,
+    // emulated time not run yet, only counts while running
+    clock: Duration,
+    last_update: Instant,
+    // why the machine isn't running, or that it is
+    status: String,
+    // exited or failed, nothing runs until a reset
+    halted: bool,
+    keys: [bool; 16],
+    screen: Option<TextureHandle>,
+    new_breakpoint: String,
+    breakpoint_error: Option<String>,
+}
+
+impl DebuggerApp {
+    fn new(cli: &Cli) -> Result<DebuggerApp, String> {
+        let rom = read_rom(&cli.rom_path)?;
+        let mut builder = Chip8::builder().variant(cli.variant.unwrap_or(Variant::Chip8));
+        if let Some(quirks) = cli.quirks {
+            builder = builder.quirks(quirks);
+        }
+        if let Some(speed) = cli.speed {
+            builder = builder.speed(speed);
+        }
+        let mut chip8 = builder.build();
+        chip8.load_rom_bytes(&rom).map_err(|e| e.to_string())?;
+        let mut debugger = Debugger::new();
+        if let Some(path) = &cli.symbols {
+            let symbols = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| Symbols::parse(&contents))
+                .map_err(|e| format!("couldn't load symbols {}: {}", path.display(), e))?;
+            debugger.set_symbols(symbols);
+        }
+        if !cli.run {
+            debugger.pause(&chip8);
+        }
+        let mut app = DebuggerApp {
+            chip8,
+            debugger,
+            rom,
+            budget: FrameBudget::default(),
+            clock: Duration::ZERO,
+            last_update: Instant::now(),
+            status: String::new(),
+            halted: false,
+            keys: [false; 16],
+            screen: None,
+            new_breakpoint: String::new(),
+            breakpoint_error: None,
+        };
+        app.update_status();
+        Ok(app)
+    }
+
+    fn running(&self) -> bool {
+        !self.halted && !self.debugger.is_paused()
+    }
+
+    // a 60th of a second of instructions, stopping early where the debugger says so
+    fn run_frame(&mut self) {
+        self.budget.start(&self.chip8);
+        while self.budget.has_time() {
+            if !self.debugger.before_cycle(&self.chip8) {
+                break;
+            }
+            let result = self.chip8.emulate_cycle();
+            // a DXYN held back for the next frame didn't run, the rest of the frame would only
+            // be spent waiting
+            if self.chip8.waiting_for_vblank() {
+                break;
+            }
+            self.budget.spend(&self.chip8);
+            match result {
+                Ok(Status::Running) => {}
+                Ok(Status::Exited) => return self.halt("exited".to_string()),
+                Err(e) => return self.halt(format!("stopped: {}", e)),
+            }
+        }
+        // time stands still while the debugger has the machine stopped
+        if !self.debugger.is_paused() {
+            self.chip8.tick_timers();
+        }
+    }
+
+    fn halt(&mut self, status: String) {
+        self.halted = true;
+        self.status = status;
+    }
+
+    fn update_status(&mut self) {
+        if self.halted {
+            return;
+        }
+        if let Some(stop) = self.debugger.take_stop() {
+            self.status = match stop {
+                Stop::Breakpoint(address) => format!("breakpoint at {:#05X}", address),
+                Stop::Condition(address) => format!("condition came true at {:#05X}", address),
+                Stop::Event(address) => format!("event breakpoint at {:#05X}", address),
+                Stop::Step(address) | Stop::Pause(address) => {
+                    format!("paused at {:#05X}", address)
+                }
+            };
+        } else if !self.debugger.is_paused() {
+            self.status = "running".to_string();
+        }
+    }
+
+    // back to the start of the ROM, still paused if it was
+    fn reset(&mut self) {
+        self.chip8.reset();
+        // the ROM loaded fine the first time
+        self.chip8.load_rom_bytes(&self.rom).unwrap();
+        self.budget = FrameBudget::default();
+        self.halted = false;
+        if self.debugger.is_paused() {
+            self.status = format!("reset, paused at {:#05X}", self.chip8.pc());
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        if self.debugger.is_paused() {
+            self.debugger.resume(&self.chip8);
+        } else {
+            self.debugger.pause(&self.chip8);
+        }
+    }
+
+    // ADDR, or ADDR if COND, where both can be anything a condition can be
+    fn add_breakpoint(&mut self) -> Result<(), String> {
+        let text = self.new_breakpoint.trim();
+        let (address, condition) = match text.split_once(" if ") {
+            Some((address, condition)) => (address, Some(condition)),
+            None => (text, None),
+        };
+        let symbols = self.debugger.symbols();
+        let address = Expression::parse(address, symbols)?.evaluate(&self.chip8);
+        if !(0..MEM_SIZE as i64).contains(&address) {
+            return Err(format!("{} isn't an address", address));
+        }
+        match condition {
+            Some(condition) => {
+                let condition = Expression::parse(condition, symbols)?;
+                self.debugger
+                    .add_conditional_breakpoint(address as usize, condition);
+            }
+            None => self.debugger.add_breakpoint(address as usize),
+        }
+        self.new_breakpoint.clear();
+        Ok(())
+    }
+
+    fn toggle_breakpoint(&mut self, address: usize) {
+        if !self.debugger.remove_breakpoint(address) {
+            self.debugger.add_breakpoint(address);
+        }
+    }
+
+    fn handle_keys(&mut self, ctx: &egui::Context) {
+        let typing = ctx.wants_keyboard_input();
+        let (pause, step, keys) = ctx.input(|input| {
+            let keys = KEYPAD.map(|(key, _)| !typing && input.key_down(key));
+            (
+                input.key_pressed(Key::F5),
+                input.key_pressed(Key::F10),
+                keys,
+            )
+        });
+        if pause && !self.halted {
+            self.toggle_pause();
+        }
+        if step && !self.halted {
+            self.debugger.step(&self.chip8, 1);
+        }
+        for ((_, key), down) in KEYPAD.iter().zip(keys) {
+            if self.keys[*key as usize] != down {
+                self.keys[*key as usize] = down;
+                self.chip8.set_key(*key, down);
+            }
+        }
+    }
+
+    // the frames the time since the last update makes up
+    fn catch_up(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.last_update;
+        self.last_update = now;
+        if !self.running() {
+            self.clock = Duration::ZERO;
+            return;
+        }
+        let frame = Duration::from_secs(1) / TIMER_FREQ as u32;
+        self.clock += elapsed;
+        let mut frames = 0;
+        while self.clock >= frame && self.running() {
+            if frames == MAX_CATCH_UP {
+                self.clock = Duration::ZERO;
+                break;
+            }
+            self.clock -= frame;
+            self.run_frame();
+            frames += 1;
+        }
+    }
+
+    fn screen_image(&self) -> ColorImage {
+        let (width, height) = (
+            self.chip8.framebuffer().width(),
+            self.chip8.display_height(),
+        );
+        let pixels = self
+            .chip8
+            .framebuffer()
+            .iter_rows()
+            .take(height)
+            .flat_map(|row| {
+                row.map(|lit| if lit { Color32::WHITE } else { Color32::BLACK })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        ColorImage {
+            size: [width, height],
+            pixels,
+        }
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let paused = self.debugger.is_paused();
+            let run = ui.add_enabled(
+                !self.halted,
+                egui::Button::new(if paused { "Run (F5)" } else { "Pause (F5)" }),
+            );
+            if run.clicked() {
+                self.toggle_pause();
+            }
+            if ui
+                .add_enabled(!self.halted && paused, egui::Button::new("Step (F10)"))
+                .clicked()
+            {
+                self.debugger.step(&self.chip8, 1);
+            }
+            if ui
+                .add_enabled(!self.halted && paused, egui::Button::new("Step 10"))
+                .clicked()
+            {
+                self.debugger.step(&self.chip8, 10);
+            }
+            if ui.button("Reset").clicked() {
+                self.reset();
+            }
+            ui.separator();
+            ui.label(&self.status);
+        });
+    }
+
+    fn registers(&self, ui: &mut egui::Ui) {
+        let chip8 = &self.chip8;
+        egui::Grid::new("registers").striped(true).show(ui, |ui| {
+            let mut row = |name: &str, value: String| {
+                ui.label(name);
+                ui.monospace(value);
+                ui.end_row();
+            };
+            row("PC", format!("{:03X}", chip8.pc()));
+            row("I", format!("{:03X}", chip8.index()));
+            for (x, value) in chip8.registers().iter().enumerate() {
+                row(&format!("V{:X}", x), format!("{:02X}", value));
+            }
+            row("DT", format!("{:02X}", chip8.delay_timer()));
+            row("ST", format!("{:02X}", chip8.sound_timer));
+            row("SP", format!("{}", chip8.stack().len()));
+        });
+        ui.separator();
+        ui.label("stack");
+        // innermost first, like backtrace
+        for address in chip8.stack().iter().rev() {
+            ui.monospace(format!("{:03X} {}", address, self.debugger.label(*address)));
+        }
+        if chip8.key_wait().is_some() {
+            ui.separator();
+            ui.label("waiting for a key");
+        }
+    }
+
+    fn breakpoints(&mut self, ui: &mut egui::Ui) {
+        let mut remove = None;
+        let breakpoints: Vec<usize> = self.debugger.breakpoints().collect();
+        for address in breakpoints {
+            ui.horizontal(|ui| {
+                if ui.small_button("x").clicked() {
+                    remove = Some(address);
+                }
+                let mut text = format!("{:03X} {}", address, self.debugger.label(address));
+                if let Some(condition) = self.debugger.breakpoint_condition(address) {
+                    text += &format!(" if {}", condition);
+                }
+                ui.monospace(text);
+            });
+        }
+        if let Some(address) = remove {
+            self.debugger.remove_breakpoint(address);
+        }
+        ui.separator();
+        let field = ui.add(
+            egui::TextEdit::singleline(&mut self.new_breakpoint).hint_text("0x2A4 if v0 == 3"),
+        );
+        let entered = field.lost_focus() && ui.input(|input| input.key_pressed(Key::Enter));
+        if ui.button("Add breakpoint").clicked() || entered {
+            self.breakpoint_error = self.add_breakpoint().err();
+        }
+        if let Some(e) = &self.breakpoint_error {
+            ui.colored_label(Color32::RED, e);
+        }
+    }
+
+    // the instructions around pc, clicking one sets or removes a breakpoint on it
+    fn listing(&mut self, ui: &mut egui::Ui) {
+        let pc = self.chip8.pc();
+        let memory = self.chip8.memory();
+        let start = pc.saturating_sub(LISTING_BEFORE * 2);
+        let end = (pc + LISTING_AFTER * 2).min(MEM_SIZE - 1);
+        let breakpoints: Vec<usize> = self.debugger.breakpoints().collect();
+        let mut toggle = None;
+        for address in (start..end).step_by(2) {
+            if let Some(name) = self.debugger.symbols().name(address) {
+                ui.monospace(format!("{}:", name));
+            }
+            let word = u16::from_be_bytes([memory[address], memory[address + 1]]);
+            let instruction = match try_decode(word, self.chip8.variant()) {
+                Some(opcode) => opcode_name(&opcode),
+                None => "data".to_string(),
+            };
+            let marker = if breakpoints.contains(&address) {
+                "●"
+            } else {
+                " "
+            };
+            let text = format!("{} {:03X}  {:04X}  {}", marker, address, word, instruction);
+            let text = RichText::new(text).monospace();
+            if ui.selectable_label(address == pc, text).clicked() {
+                toggle = Some(address);
+            }
+        }
+        if let Some(address) = toggle {
+            self.toggle_breakpoint(address);
+        }
+    }
+
+    // all of memory, 16 bytes a row, with pc and I picked out
+    fn memory(&self, ui: &mut egui::Ui) {
+        let memory = self.chip8.memory();
+        let (pc, index) = (self.chip8.pc(), self.chip8.index());
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show_rows(ui, row_height, MEM_SIZE / MEMORY_ROW, |ui, rows| {
+                for row in rows {
+                    let start = row * MEMORY_ROW;
+                    ui.horizontal(|ui| {
+                        ui.spacing_mut().item_spacing.x = 4.0;
+                        ui.monospace(format!("{:03X} ", start));
+                        let bytes = &memory[start..start + MEMORY_ROW];
+                        for (address, byte) in (start..).zip(bytes) {
+                            let mut text = RichText::new(format!("{:02X}", byte)).monospace();
+                            if address == pc || address == pc + 1 {
+                                text = text.color(Color32::YELLOW);
+                            } else if address == index {
+                                text = text.color(Color32::LIGHT_BLUE);
+                            }
+                            ui.label(text);
+                        }
+                    });
+                }
+            });
+    }
+}
+
+impl eframe::App for DebuggerApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_keys(ctx);
+        self.catch_up();
+        self.update_status();
+
+        let image = self.screen_image();
+        match &mut self.screen {
+            Some(screen) => screen.set(image, TextureOptions::NEAREST),
+            None => self.screen = Some(ctx.load_texture("screen", image, TextureOptions::NEAREST)),
+        }
+
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.controls(ui));
+        egui::SidePanel::left("registers")
+            .resizable(false)
+            .show(ctx, |ui| self.registers(ui));
+        egui::SidePanel::right("breakpoints")
+            .default_width(220.0)
+            .show(ctx, |ui| {
+                ui.heading("Breakpoints");
+                self.breakpoints(ui);
+            });
+        egui::TopBottomPanel::bottom("memory")
+            .resizable(true)
+            .default_height(200.0)
+            .show(ctx, |ui| self.memory(ui));
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(screen) = &self.screen {
+                let size = screen.size_vec2() * SCALE;
+                ui.image((screen.id(), size));
+            }
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| self.listing(ui));
+        });
+
+        if self.running() {
+            ctx.request_repaint_after(Duration::from_secs(1) / TIMER_FREQ as u32);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+// ROMs that ship with the emulator (the "bundled-roms" feature), so it can be tried out without
+// hunting for ROM files first. they were written for this project, so there's no copyright
+// to worry about
+#[cfg(feature = "bundled-roms")]
+pub struct BuiltinRom {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub bytes: &'static [u8],
+}
+
+#[cfg(feature = "bundled-roms")]
+pub const BUILTIN_ROMS: [BuiltinRom; 3] = [
+    BuiltinRom {
+        name: "pong",
+        description: "one player pong against the wall, 1 and 4 move the paddle",
+        bytes: &PONG,
+    },
+    BuiltinRom {
+        name: "keypad",
+        description: "shows the last key pressed",
+        bytes: &KEYPAD,
+    },
+    BuiltinRom {
+        name: "beep",
+        description: "beeps for longer the higher the key pressed",
+        bytes: &BEEP,
+    },
+];
+
+#[cfg(feature = "bundled-roms")]
+const PONG: [u8; 142] = [
+    0x00, 0xE0, // clear the screen
+    0xA2, 0x88, // I = paddle
+    0x60, 0x0D, // V0 = paddle y
+    0x6A, 0x02, // VA = paddle x
+    0xDA, 0x06, // draw the paddle
+    0x61, 0x20, // V1 = ball x
+    0xC2, 0x0F, // V2 = ball y, somewhere
+    0x72, 0x08, // in the middle
+    0x63, 0x01, // V3 = ball dx, right
+    0x64, 0x01, // V4 = ball dy, down
+    0xA2, 0x88, // I = ball, the top of the paddle
+    0xD1, 0x21, // draw the ball
+    0x66, 0x02, // V6 = 2
+    0xF6, 0x15, // 30 moves a second
+    0x66, 0x01, // V6 = key 1
+    0xE6, 0xA1, // skip unless it's held
+    0x22, 0x68, // paddle up
+    0x66, 0x04, // V6 = key 4
+    0xE6, 0xA1, // skip unless it's held
+    0x22, 0x78, // paddle down
+    0xA2, 0x88, // I = ball
+    0xD1, 0x21, // erase the ball
+    0x81, 0x34, // V1 += dx
+    0x82, 0x44, // V2 += dy
+    0x32, 0x00, // skip if at the top
+    0x12, 0x36, // otherwise check the bottom
+    0x64, 0x01, // dy = down
+    0x32, 0x1F, // skip if at the bottom
+    0x12, 0x3C, // otherwise check the right wall
+    0x64, 0xFF, // dy = up
+    0x31, 0x3F, // skip if at the right wall
+    0x12, 0x42, // otherwise check for a miss
+    0x63, 0xFF, // dx = left
+    0x31, 0x00, // skip if the ball got past the paddle
+    0x12, 0x48, // otherwise check for a hit
+    0x12, 0x0A, // serve again
+    0x31, 0x03, // skip if the ball is next to the paddle
+    0x12, 0x5E, // otherwise draw it
+    0x86, 0x20, // V6 = ball y
+    0x86, 0x05, // V6 -= paddle y, VF = no borrow
+    0x3F, 0x01, // skip if the ball is below the paddle's top
+    0x12, 0x5E, // otherwise it missed
+    0x67, 0x05, // V7 = 5
+    0x87, 0x65, // V7 -= V6, VF = no borrow
+    0x3F, 0x01, // skip if it's above the paddle's bottom
+    0x12, 0x5E, // otherwise it missed
+    0x63, 0x01, // dx = right
+    0xD1, 0x21, // draw the ball
+    0xF6, 0x07, // V6 = delay timer
+    0x36, 0x00, // skip when it ran out
+    0x12, 0x60, // otherwise keep waiting
+    0x12, 0x18, // next move
+    0x30, 0x00, // skip if the paddle is at the top
+    0x12, 0x6E, // otherwise move it
+    0x00, 0xEE, // return
+    0xA2, 0x88, // I = paddle
+    0xDA, 0x06, // erase it
+    0x70, 0xFF, // V0 -= 1
+    0xDA, 0x06, // draw it
+    0x00, 0xEE, // return
+    0x30, 0x1A, // skip if the paddle is at the bottom
+    0x12, 0x7E, // otherwise move it
+    0x00, 0xEE, // return
+    0xA2, 0x88, // I = paddle
+    0xDA, 0x06, // erase it
+    0x70, 0x01, // V0 += 1
+    0xDA, 0x06, // draw it
+    0x00, 0xEE, // return
+    0x80, 0x80, 0x80, 0x80, 0x80, 0x80, // paddle
+];
+
+#[cfg(feature = "bundled-roms")]
+const KEYPAD: [u8; 16] = [
+    0x00, 0xE0, // clear the screen
+    0xF0, 0x0A, // V0 = the next key
+    0x00, 0xE0, // clear the screen
+    0xF0, 0x29, // I = the digit for V0
+    0x61, 0x1C, // V1 = x
+    0x62, 0x0D, // V2 = y
+    0xD1, 0x25, // draw it
+    0x12, 0x02, // next key
+];
+
+#[cfg(feature = "bundled-roms")]
+const BEEP: [u8; 26] = [
+    0x00, 0xE0, // clear the screen
+    0xF0, 0x0A, // V0 = the next key
+    0x00, 0xE0, // clear the screen
+    0xF0, 0x29, // I = the digit for V0
+    0x61, 0x1C, // V1 = x
+    0x62, 0x0D, // V2 = y
+    0xD1, 0x25, // draw it
+    0x81, 0x00, // V1 = V0
+    0x71, 0x01, // V1 += 1
+    0x81, 0x1E, // V1 <<= 1
+    0x81, 0x1E, // V1 <<= 1
+    0xF1, 0x18, // beep for 4 to 64 frames
+    0x12, 0x02, // next key
+];
+
+#[cfg(feature = "bundled-roms")]
+pub fn builtin_rom(name: &str) -> Result<Vec<u8>, String> {
+    BUILTIN_ROMS
+        .iter()
+        .find(|rom| rom.name == name)
+        .map(|rom| rom.bytes.to_vec())
+        .ok_or_else(|| {
+            let roms: Vec<String> = BUILTIN_ROMS
+                .iter()
+                .map(|rom| format!("{} ({})", rom.name, rom.description))
+                .collect();
+            format!("no built in ROM {:?}, there are {}", name, roms.join(", "))
+        })
+}
+
+#[cfg(not(feature = "bundled-roms"))]
+pub fn builtin_rom(name: &str) -> Result<Vec<u8>, String> {
+    Err(format!(
+        "can't run {}: built without the \"bundled-roms\" feature",
+        name
+    ))
+}
+
+#[cfg(all(test, feature = "bundled-roms"))]
+mod tests {
+    use super::*;
+    use chip_8::{create_chip8, Chip8};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn run_frames(chip8: &mut Chip8, frames: usize) {
+        for _ in 0..frames {
+            for _ in 0..14 {
+                chip8.emulate_cycle().unwrap();
+            }
+            chip8.tick_timers();
+        }
+    }
+
+    fn lit_pixels(chip8: &Chip8) -> usize {
+        chip8.framebuffer().pixels().filter(|lit| *lit).count()
+    }
+
+    #[test]
+    fn test_builtin_roms() {
+        for rom in &BUILTIN_ROMS {
+            let mut chip8 = create_chip8();
+            chip8.load_rom_bytes(rom.bytes).unwrap();
+            run_frames(&mut chip8, 600);
+        }
+        assert_eq!(builtin_rom("keypad").unwrap(), KEYPAD);
+        assert!(builtin_rom("tetris")
+            .unwrap_err()
+            .starts_with("no built in ROM \"tetris\", there are pong ("));
+    }
+
+    #[test]
+    fn test_pong() {
+        let mut chip8 = create_chip8();
+        chip8.set_rng(Box::new(StdRng::seed_from_u64(1)));
+        chip8.load_rom_bytes(&PONG).unwrap();
+        run_frames(&mut chip8, 1);
+        // the paddle and the ball
+        assert_eq!(lit_pixels(&chip8), 7);
+        // holding 1 moves the paddle to the top
+        chip8.set_key(1, true);
+        run_frames(&mut chip8, 60);
+        assert_eq!(chip8.registers()[0], 0);
+        assert!(chip8.framebuffer().get(2, 0));
+        chip8.set_key(1, false);
+        // however long it runs, there's always the paddle and the ball
+        for _ in 0..20 {
+            run_frames(&mut chip8, 17);
+            // the ball may be between being erased and drawn again
+            assert!((6..=7).contains(&lit_pixels(&chip8)));
+            assert!((0..6).all(|y| chip8.framebuffer().get(2, y)));
+        }
+    }
+
+    #[test]
+    fn test_beep() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&BEEP).unwrap();
+        run_frames(&mut chip8, 1);
+        chip8.set_key(3, true);
+        run_frames(&mut chip8, 1);
+        chip8.set_key(3, false);
+        run_frames(&mut chip8, 1);
+        assert_eq!(chip8.sound_timer, 15);
+        // the 3 is on screen
+        assert!(lit_pixels(&chip8) > 0);
+    }
+}
@@ -0,0 +1,203 @@
+use chip_8::{Chip8, Hooks, Opcode, Timing};
+
+// the most it speeds a ROM up, times the configured speed
+const MAX_FACTOR: u64 = 8;
+// frames in a row that never got to their wait before it speeds up
+const STARVED_FRAMES: u32 = 3;
+// frames in a row that spent most of their time waiting before it slows down again
+const IDLE_FRAMES: u32 = 60;
+// a ROM that hasn't waited on the delay timer for this long isn't pacing itself with it
+const PACED_FRAMES: u32 = 60;
+
+// --auto-speed: games that pace themselves by polling the delay timer (FX07 in a loop until it
+// runs out) were tuned for whatever interpreter their author had. when such a game doesn't get
+// to its wait within a frame it's running slow, so the speed goes up a quarter. when it's spent
+// most of every frame waiting for a second, it comes back down a fifth, never below the
+// configured speed. attach with `Chip8::add_hook`, call end_frame after tick_timers
+pub struct SpeedCalibrator {
+    // the configured speed, None when calibrating is off for this ROM
+    base: Option<u64>,
+    // where FX07 last ran this frame
+    poll: Option<usize>,
+    instructions: u32,
+    // instructions run when it first polled the same FX07 twice, the rest of the frame was waiting
+    waited_at: Option<u32>,
+    since_wait: u32,
+    starved: u32,
+    idle: u32,
+}
+
+impl SpeedCalibrator {
+    pub fn new() -> SpeedCalibrator {
+        SpeedCalibrator {
+            base: None,
+            poll: None,
+            instructions: 0,
+            waited_at: None,
+            since_wait: PACED_FRAMES,
+            starved: 0,
+            idle: 0,
+        }
+    }
+
+    // a ROM was loaded with `speed`, or `None` when it isn't to be calibrated
+    pub fn reset(&mut self, base: Option<u64>) {
+        *self = SpeedCalibrator {
+            base,
+            ..SpeedCalibrator::new()
+        };
+    }
+
+    // after the frame's instructions and timer tick. returns the new speed when it changed
+    pub fn end_frame(&mut self, chip8: &mut Chip8) -> Option<u64> {
+        let (instructions, waited_at) = (self.instructions, self.waited_at);
+        self.poll = None;
+        self.instructions = 0;
+        self.waited_at = None;
+        // the speed means nothing to VIP timing, and a ROM waiting for a key isn't behind
+        let base = self.base.filter(|_| chip8.timing() == Timing::Flat)?;
+        if chip8.key_wait().is_some() {
+            return None;
+        }
+        let speed = chip8.speed();
+        let new_speed = match waited_at {
+            Some(waited_at) => {
+                self.since_wait = 0;
+                self.starved = 0;
+                self.idle = if waited_at * 2 < instructions {
+                    self.idle + 1
+                } else {
+                    0
+                };
+                if self.idle < IDLE_FRAMES {
+                    return None;
+                }
+                self.idle = 0;
+                (speed * 4 / 5).max(base)
+            }
+            None => {
+                self.since_wait = self.since_wait.saturating_add(1);
+                self.idle = 0;
+                if self.since_wait > PACED_FRAMES {
+                    self.starved = 0;
+                    return None;
+                }
+                self.starved += 1;
+                if self.starved < STARVED_FRAMES {
+                    return None;
+                }
+                self.starved = 0;
+                (speed * 5 / 4).min(base * MAX_FACTOR)
+            }
+        };
+        if new_speed == speed {
+            return None;
+        }
+        chip8.set_speed(new_speed);
+        Some(new_speed)
+    }
+}
+
+impl Hooks for SpeedCalibrator {
+    fn before_instruction(&mut self, chip8: &Chip8, opcode: &Opcode) {
+        self.instructions = self.instructions.saturating_add(1);
+        if self.base.is_none() || !matches!(opcode, Opcode::OP_FX07(_)) {
+            return;
+        }
+        let pc = chip8.pc();
+        if self.poll == Some(pc) && self.waited_at.is_none() {
+            self.waited_at = Some(self.instructions);
+        }
+        self.poll = Some(pc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // a frame's worth of a game: `work` instructions of its own, then waiting on the delay timer
+    // for what's left of the frame
+    fn frame(calibrator: &mut SpeedCalibrator, chip8: &mut Chip8, work: u64) -> Option<u64> {
+        let budget = chip8.speed() / 60;
+        for _ in 0..work.min(budget) {
+            calibrator.before_instruction(chip8, &Opcode::OP_7XKK(0, 1));
+        }
+        for _ in work.min(budget)..budget {
+            calibrator.before_instruction(chip8, &Opcode::OP_FX07(0));
+        }
+        calibrator.end_frame(chip8)
+    }
+
+    #[test]
+    fn test_calibration() {
+        let mut chip8 = create_chip8();
+        chip8.set_speed(600);
+        let mut calibrator = SpeedCalibrator::new();
+        // off until a ROM is loaded with it on
+        for _ in 0..10 {
+            assert_eq!(frame(&mut calibrator, &mut chip8, 50), None);
+        }
+
+        calibrator.reset(Some(600));
+        // it has to have seen a wait first
+        for _ in 0..10 {
+            assert_eq!(frame(&mut calibrator, &mut chip8, 50), None);
+        }
+        assert_eq!(frame(&mut calibrator, &mut chip8, 5), None);
+        // then every third frame that doesn't get to it is a step faster
+        assert_eq!(frame(&mut calibrator, &mut chip8, 50), None);
+        assert_eq!(frame(&mut calibrator, &mut chip8, 50), None);
+        assert_eq!(frame(&mut calibrator, &mut chip8, 50), Some(750));
+        assert_eq!(chip8.speed(), 750);
+        let mut speeds = vec![];
+        for _ in 0..30 {
+            speeds.extend(frame(&mut calibrator, &mut chip8, 50));
+        }
+        // 50 instructions a frame is 3000 a second, right where it stops
+        assert_eq!(speeds, [937, 1171, 1463, 1828, 2285, 2856, 3570]);
+        for _ in 0..IDLE_FRAMES - 1 {
+            assert_eq!(frame(&mut calibrator, &mut chip8, 20), None);
+        }
+        assert_eq!(frame(&mut calibrator, &mut chip8, 20), Some(2856));
+
+        // a wait in every frame, but never much time spent in it
+        for _ in 0..200 {
+            assert_eq!(frame(&mut calibrator, &mut chip8, 40), None);
+        }
+        // nothing to wait for at all, left at the speed it was
+        for _ in 0..200 {
+            frame(&mut calibrator, &mut chip8, 1000);
+        }
+        assert!(chip8.speed() <= 600 * MAX_FACTOR);
+        for _ in 0..1000 {
+            frame(&mut calibrator, &mut chip8, 0);
+        }
+        assert_eq!(chip8.speed(), 600);
+
+        chip8.set_timing(Timing::Vip);
+        calibrator.reset(Some(600));
+        frame(&mut calibrator, &mut chip8, 0);
+        for _ in 0..10 {
+            assert_eq!(frame(&mut calibrator, &mut chip8, 50), None);
+        }
+    }
+
+    #[test]
+    fn test_hook() {
+        // FX07 at 0x200 and a jump back to it
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0xF0, 0x07, 0x12, 0x00]).unwrap();
+        let calibrator = Rc::new(RefCell::new(SpeedCalibrator::new()));
+        calibrator.borrow_mut().reset(Some(chip8.speed()));
+        chip8.add_hook(Box::new(calibrator.clone()));
+        for _ in 0..10 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(calibrator.borrow().waited_at, Some(3));
+        assert_eq!(calibrator.borrow().instructions, 10);
+    }
+}
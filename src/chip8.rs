@@ -1,22 +1,698 @@
+/// the interpreter: the machine, how it's configured and the instructions it runs
+#[allow(clippy::module_inception)]
 pub mod chip8 {
-    use rand::{thread_rng, Rng};
-    use sdl2::keyboard::Keycode;
-    use std::fs::File;
-    use std::io::Read;
-    use std::path::Path;
+    use crate::framebuffer::FrameBuffer;
+    use crate::input::KeyEdge;
+    use rand::rngs::StdRng;
+    use rand::{Rng, RngCore, SeedableRng};
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::fmt;
+    use std::ops::Range;
+    use std::rc::Rc;
+    use std::str::FromStr;
     use std::time::Duration;
+    use tracing::{debug, trace, trace_span, warn};
 
-    const MEM_SIZE: usize = 4096;
-    const REGISTER_COUNT: usize = 16;
+    /// bytes of RAM, addresses go from 0 to 0xFFF
+    pub const MEM_SIZE: usize = 4096;
+    /// V0 to VF
+    pub const REGISTER_COUNT: usize = 16;
+    /// rows of the screen, except for Variant::Hires
     pub const DISPLAY_HEIGHT: usize = 32;
+    /// pixels in a row, for every variant
     pub const DISPLAY_WIDTH: usize = 64;
-    const STACK_SIZE: usize = 16;
+    /// rows of the screen for Variant::Hires
+    pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+    /// return addresses 2NNN can push before it's a Chip8Error::StackOverflow
+    pub const STACK_SIZE: usize = 16;
     const KEY_COUNT: usize = 16;
     const FONT_SIZE: usize = 80;
-    const PROGRAM_START_ADDRESS: usize = 0x0200;
+    // the interpreter and the font live below this, no layout loads ROMs there
+    const MIN_LOAD_ADDRESS: usize = 0x0200;
+    /// the biggest ROM any layout has room for, see Layout::max_rom_size for a given one
+    pub const MAX_ROM_SIZE: usize = MEM_SIZE - MIN_LOAD_ADDRESS;
+    /// instructions per second by default
     pub const CYCLE_FREQ: u64 = 840; // kind of a guess. game speed depends on this
-    pub const TICK_INTERVAL: Duration = Duration::from_millis(20);
+    /// ticks per second of the delay and sound timers, and frames per second of run_frame
+    pub const TIMER_FREQ: u64 = 60;
+    const AUDIO_PATTERN_SIZE: usize = 16;
+    const DEFAULT_PITCH: u8 = 64;
+    /// SCHIP has 8 of them, XO-CHIP 16
+    pub const RPL_FLAG_COUNT: usize = 16;
+    /// CHIP-8X colors are set for 8 pixel wide strips of a row
+    pub const CHIP8X_ZONE_WIDTH: usize = 8;
+    const CHIP8X_ZONE_HEIGHT: usize = 4;
+    // power-on colors of the VP-590 color board: red on dark blue
+    const CHIP8X_DEFAULT_FOREGROUND: u8 = 1;
+    const CHIP8X_DEFAULT_BACKGROUND: u8 = 0;
+    const CHIP8X_FOREGROUND_COUNT: u8 = 8;
+    const CHIP8X_BACKGROUND_COUNT: u8 = 4;
 
+    /// interpreters that need more than quirks: a different display or extra opcodes
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Variant {
+        Chip8,
+        /// the two-page hires interpreter for the VIP: 64x64 pixels, 0230 clears the screen
+        Hires,
+        /// the VP-590 color board: programs start at 0x300, 02A0 and BXYN set colors, 5XY1 adds
+        /// coordinates
+        Chip8x,
+    }
+
+    impl Variant {
+        pub fn name(self) -> &'static str {
+            match self {
+                Variant::Chip8 => "chip8",
+                Variant::Hires => "hires",
+                Variant::Chip8x => "chip8x",
+            }
+        }
+
+        /// where its programs are loaded and start, unless the machine is given another layout
+        pub fn layout(self) -> Layout {
+            match self {
+                Variant::Chip8x => Layout::CHIP8X,
+                _ => Layout::VIP,
+            }
+        }
+
+        pub fn display_height(self) -> usize {
+            match self {
+                Variant::Hires => HIRES_DISPLAY_HEIGHT,
+                _ => DISPLAY_HEIGHT,
+            }
+        }
+    }
+
+    impl FromStr for Variant {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Variant, String> {
+            [Variant::Chip8, Variant::Hires, Variant::Chip8x]
+                .iter()
+                .copied()
+                .find(|variant| variant.name() == name.to_ascii_lowercase())
+                .ok_or(format!(
+                    "unknown variant {}, expected chip8, hires or chip8x",
+                    name
+                ))
+        }
+    }
+
+    /// where ROMs are loaded in memory and the address they start running from. interpreters for
+    /// other machines moved them, the ETI-660's programs load and start at 0x600
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Layout {
+        pub load: usize,
+        pub start: usize,
+    }
+
+    impl Layout {
+        pub const VIP: Layout = Layout {
+            load: 0x200,
+            start: 0x200,
+        };
+        pub const CHIP8X: Layout = Layout {
+            load: 0x300,
+            start: 0x300,
+        };
+        pub const ETI660: Layout = Layout {
+            load: 0x600,
+            start: 0x600,
+        };
+
+        /// vip, chip8x or eti660
+        pub fn preset(name: &str) -> Result<Layout, String> {
+            match name.to_ascii_lowercase().as_str() {
+                "vip" => Ok(Layout::VIP),
+                "chip8x" => Ok(Layout::CHIP8X),
+                "eti660" => Ok(Layout::ETI660),
+                _ => Err(format!(
+                    "unknown layout {}, expected vip, chip8x or eti660",
+                    name
+                )),
+            }
+        }
+
+        /// the load address is above the interpreter and both are in memory
+        pub fn check(self) -> Result<(), String> {
+            if !(MIN_LOAD_ADDRESS..MEM_SIZE).contains(&self.load) {
+                return Err(format!(
+                    "the load address must be from {:#05X} to {:#05X}: {:#05X}",
+                    MIN_LOAD_ADDRESS,
+                    MEM_SIZE - 1,
+                    self.load
+                ));
+            }
+            if self.start >= MEM_SIZE {
+                return Err(format!(
+                    "the start address must be below {:#05X}: {:#05X}",
+                    MEM_SIZE, self.start
+                ));
+            }
+            Ok(())
+        }
+
+        /// the biggest ROM that fits from the load address on
+        pub fn max_rom_size(self) -> usize {
+            MEM_SIZE - self.load
+        }
+    }
+
+    /// what happens to reads and writes past the end of memory, e.g. FX55 with I near 0xFFF
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum MemoryPolicy {
+        /// around to the start, like the address lines of the real thing
+        Wrap,
+        /// stop with Chip8Error::MemoryFault, to catch ROMs (or interpreters) doing it by mistake
+        Fault,
+    }
+
+    impl MemoryPolicy {
+        pub fn name(self) -> &'static str {
+            match self {
+                MemoryPolicy::Wrap => "wrap",
+                MemoryPolicy::Fault => "fault",
+            }
+        }
+    }
+
+    impl FromStr for MemoryPolicy {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<MemoryPolicy, String> {
+            [MemoryPolicy::Wrap, MemoryPolicy::Fault]
+                .iter()
+                .copied()
+                .find(|policy| policy.name() == name.to_ascii_lowercase())
+                .ok_or(format!(
+                    "unknown memory policy {}, expected wrap or fault",
+                    name
+                ))
+        }
+    }
+
+    /// what 0NNN does. on the VIP it ran the 1802 machine code at NNN, which can't be done here.
+    /// ROMs mostly have them left over from setup code that's harmless to pass over
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum MachineCodePolicy {
+        /// go on to the next instruction, with a warning the first time for each address
+        Ignore,
+        /// stop with Chip8Error::MachineCode
+        Fault,
+    }
+
+    impl MachineCodePolicy {
+        pub fn name(self) -> &'static str {
+            match self {
+                MachineCodePolicy::Ignore => "ignore",
+                MachineCodePolicy::Fault => "fault",
+            }
+        }
+    }
+
+    impl FromStr for MachineCodePolicy {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<MachineCodePolicy, String> {
+            [MachineCodePolicy::Ignore, MachineCodePolicy::Fault]
+                .iter()
+                .copied()
+                .find(|policy| policy.name() == name.to_ascii_lowercase())
+                .ok_or(format!(
+                    "unknown machine code policy {}, expected ignore or fault",
+                    name
+                ))
+        }
+    }
+
+    /// what a word that isn't an instruction does when it's run, usually data the ROM jumped or
+    /// ran into. FX70, FX71 and FX72 count too when no peripheral takes them
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum UnknownOpcodePolicy {
+        /// stop with Chip8Error::UnknownOpcode
+        Fault,
+        /// go on to the next instruction as if it were a 0000
+        Skip,
+        /// skip it, with a warning the first time for each address
+        Warn,
+    }
+
+    impl UnknownOpcodePolicy {
+        pub fn name(self) -> &'static str {
+            match self {
+                UnknownOpcodePolicy::Fault => "fault",
+                UnknownOpcodePolicy::Skip => "skip",
+                UnknownOpcodePolicy::Warn => "warn",
+            }
+        }
+    }
+
+    impl FromStr for UnknownOpcodePolicy {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<UnknownOpcodePolicy, String> {
+            [
+                UnknownOpcodePolicy::Fault,
+                UnknownOpcodePolicy::Skip,
+                UnknownOpcodePolicy::Warn,
+            ]
+            .iter()
+            .copied()
+            .find(|policy| policy.name() == name.to_ascii_lowercase())
+            .ok_or(format!(
+                "no unknown opcode policy {}, expected fault, skip or warn",
+                name
+            ))
+        }
+    }
+
+    /// how long instructions take, which decides how many run in a frame
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Timing {
+        /// `speed` instructions a second, whatever they are
+        Flat,
+        /// every instruction takes about as long as in the COSMAC VIP interpreter, so a DXYN
+        /// costs as much as hundreds of 6XKKs and games run at the pace they were written for.
+        /// `speed` doesn't count
+        Vip,
+    }
+
+    impl Timing {
+        pub fn name(self) -> &'static str {
+            match self {
+                Timing::Flat => "flat",
+                Timing::Vip => "vip",
+            }
+        }
+    }
+
+    impl FromStr for Timing {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Timing, String> {
+            [Timing::Flat, Timing::Vip]
+                .iter()
+                .copied()
+                .find(|timing| timing.name() == name.to_ascii_lowercase())
+                .ok_or(format!("unknown timing {}, expected flat or vip", name))
+        }
+    }
+
+    // microseconds the VIP interpreter spends on an instruction, on average: 6XKK is the
+    // quickest, DXYN includes waiting for the display interrupt. instructions the VIP didn't
+    // have get a middling cost
+    fn vip_microseconds(opcode: &Opcode) -> u64 {
+        match opcode {
+            Opcode::OP_00E0 => 109,
+            Opcode::OP_00EE | Opcode::OP_1MMM(_) | Opcode::OP_2MMM(_) => 105,
+            Opcode::OP_BMMM(_) | Opcode::OP_BXYN(..) => 105,
+            Opcode::OP_3XKK(..) | Opcode::OP_4XKK(..) | Opcode::OP_AMMM(_) => 55,
+            Opcode::OP_5XY0(..) | Opcode::OP_9XY0(..) => 73,
+            Opcode::OP_EX9E(_) | Opcode::OP_EXA1(_) => 73,
+            Opcode::OP_6XKK(..) => 27,
+            Opcode::OP_7XKK(..) => 45,
+            Opcode::OP_8XY0(..)
+            | Opcode::OP_8XY1(..)
+            | Opcode::OP_8XY2(..)
+            | Opcode::OP_8XY3(..)
+            | Opcode::OP_8XY4(..)
+            | Opcode::OP_8XY5(..)
+            | Opcode::OP_8XY6(..)
+            | Opcode::OP_8XY7(..)
+            | Opcode::OP_8XYE(..) => 200,
+            Opcode::OP_CXKK(..) => 164,
+            Opcode::OP_DXYN(..) => 22734,
+            // FX0A is charged for every time it looks at the keypad
+            Opcode::OP_FX07(_) | Opcode::OP_FX0A(_) | Opcode::OP_FX15(_) | Opcode::OP_FX18(_) => 45,
+            Opcode::OP_FX1E(_) => 86,
+            Opcode::OP_FX29(_) => 91,
+            Opcode::OP_FX33(_) => 927,
+            Opcode::OP_FX55(_) | Opcode::OP_FX65(_) => 605,
+            _ => 100,
+        }
+    }
+
+    /// spreads a second's worth of instructions over its frames by the machine's Timing.
+    /// run_frame keeps one, frontends that call emulate_cycle themselves keep their own: `start`
+    /// it every frame, then run instructions while it `has_time`, `spend`ing after each
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct FrameBudget {
+        // in TIMER_FREQths of an instruction (Flat) or a microsecond (Vip)
+        left: i64,
+    }
+
+    impl FrameBudget {
+        /// the rest of the last frame carries over, what's less than an instruction or
+        /// microsecond and what an expensive instruction overspent
+        pub fn start(&mut self, chip8: &Chip8) {
+            let carried = if self.left > 0 {
+                self.left % TIMER_FREQ as i64
+            } else {
+                self.left
+            };
+            let per_second = match chip8.timing {
+                Timing::Flat => chip8.speed,
+                Timing::Vip => 1_000_000,
+            };
+            self.left = carried + per_second as i64;
+        }
+
+        pub fn has_time(&self) -> bool {
+            self.left >= TIMER_FREQ as i64
+        }
+
+        /// for the instruction emulate_cycle just ran, or looked at while waiting for a key
+        pub fn spend(&mut self, chip8: &Chip8) {
+            let cost = match chip8.timing {
+                Timing::Flat => 1,
+                Timing::Vip => vip_microseconds(&chip8.opcode),
+            };
+            self.left -= (cost * TIMER_FREQ) as i64;
+        }
+    }
+
+    /// behaviours that differ between interpreters. defaults match the original COSMAC VIP,
+    /// except where most ROMs written since expect otherwise
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Quirks {
+        /// sprites are cut off at the screen edges instead of wrapping around
+        pub clipping: bool,
+        pub memory: MemoryPolicy,
+        /// 8XY6/8XYE shift VY into VX like the VIP, instead of shifting VX in place like
+        /// CHIP-48 and SCHIP
+        pub shift_vy: bool,
+        /// FX55/FX65 leave I pointing past the last register, I + X + 1, like the VIP. modern
+        /// interpreters leave it alone
+        pub increment_i: bool,
+        /// BXNN jumps to XNN + VX like CHIP-48 and SCHIP, instead of BNNN to NNN + V0
+        pub jump_vx: bool,
+        /// 8XY1/8XY2/8XY3 set VF to 0, a side effect of how the VIP ran them
+        pub vf_reset: bool,
+        /// DXYN waits for the vertical blank like the VIP, so there's at most one sprite drawn
+        /// per frame. the frame boundary is tick_timers
+        pub display_wait: bool,
+        pub machine_code: MachineCodePolicy,
+        pub unknown_opcode: UnknownOpcodePolicy,
+    }
+
+    impl Default for Quirks {
+        fn default() -> Self {
+            Quirks {
+                clipping: true,
+                memory: MemoryPolicy::Wrap,
+                shift_vy: false,
+                increment_i: false,
+                jump_vx: false,
+                vf_reset: false,
+                display_wait: false,
+                machine_code: MachineCodePolicy::Ignore,
+                unknown_opcode: UnknownOpcodePolicy::Fault,
+            }
+        }
+    }
+
+    impl Quirks {
+        /// named sets: cosmac (the original VIP interpreter), schip (SCHIP 1.1) or modern (the
+        /// defaults, what most ROMs written since expect)
+        pub fn preset(name: &str) -> Result<Quirks, String> {
+            let quirks = match name.to_ascii_lowercase().as_str() {
+                "cosmac" => Quirks {
+                    shift_vy: true,
+                    increment_i: true,
+                    vf_reset: true,
+                    display_wait: true,
+                    ..Quirks::default()
+                },
+                "schip" => Quirks {
+                    jump_vx: true,
+                    ..Quirks::default()
+                },
+                "modern" => Quirks::default(),
+                _ => {
+                    return Err(format!(
+                        "unknown quirks {}, expected cosmac, schip or modern",
+                        name
+                    ))
+                }
+            };
+            Ok(quirks)
+        }
+    }
+
+    /// where FX0A is at: waiting for any key to go down, then for that key to come back up
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum KeyWait {
+        Press(usize),
+        Release(usize, u8),
+    }
+
+    /// everything needed to pick a game up where it was left, see Chip8::snapshot. keys, hooks
+    /// and the random number generator belong to the frontend and aren't in it
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Snapshot {
+        pub variant: Variant,
+        pub quirks: Quirks,
+        pub speed: u64,
+        pub memory: Vec<u8>,
+        pub registers: [u8; REGISTER_COUNT],
+        pub index: usize,
+        pub pc: usize,
+        /// return addresses, oldest first
+        pub stack: Vec<usize>,
+        pub delay_timer: u8,
+        pub sound_timer: u8,
+        pub audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+        pub pitch: u8,
+        pub rpl_flags: [u8; RPL_FLAG_COUNT],
+        pub framebuffer: FrameBuffer,
+        pub zone_colors: Vec<u8>,
+        pub background_color: u8,
+        pub key_wait: Option<KeyWait>,
+        pub exited: bool,
+    }
+
+    /// what emulate_cycle left the machine doing
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Status {
+        Running,
+        /// the program ran 00FD. nothing runs until the next reset
+        Exited,
+    }
+
+    /// what happened during a run_frame, in order
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Event {
+        /// the screen changed since the last frame
+        DisplayUpdated,
+        SoundStarted,
+        SoundStopped,
+        /// FX0A is waiting for a key, the frame ended early
+        WaitingForKey,
+        Exited,
+        /// the frame stopped at the failing instruction
+        Error(Chip8Error),
+    }
+
+    /// what came of a frame of run_frame or run_for
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct FrameOutput {
+        /// in the order they happened
+        pub events: Vec<Event>,
+        /// instructions run this frame
+        pub instructions: u64,
+        /// timer ticks, one for a run_frame that wasn't cut short by an exit or error
+        pub frames: u64,
+    }
+
+    /// how much run_for does before it returns
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum RunLimit {
+        /// emulated time: the whole frames it makes up, the rest counts toward the next call
+        Time(Duration),
+        /// instructions, stopping partway through a frame if that's where they run out
+        Cycles(u64),
+    }
+
+    impl FrameOutput {
+        pub fn contains(&self, event: &Event) -> bool {
+            self.events.contains(event)
+        }
+    }
+
+    /// how often the predecoded instructions were used, see Chip8::set_predecode
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct DecodeCacheStats {
+        /// instructions that were already decoded
+        pub hits: u64,
+        /// instructions decoded when they were run, memory written since or never decoded
+        pub misses: u64,
+    }
+
+    impl DecodeCacheStats {
+        pub fn hit_rate(&self) -> f64 {
+            match self.hits + self.misses {
+                0 => 0.0,
+                total => self.hits as f64 / total as f64,
+            }
+        }
+    }
+
+    /// why the machine stopped. everything but RomTooLarge happens running an instruction and
+    /// has its pc
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Chip8Error {
+        /// a call with all 16 stack slots in use. `stack` holds the return addresses, oldest first
+        StackOverflow { pc: usize, stack: Vec<usize> },
+        /// a return with nothing on the stack
+        StackUnderflow { pc: usize },
+        /// the ROM doesn't fit between the program start address and the end of memory
+        RomTooLarge { size: usize, max: usize },
+        /// an access past the end of memory with MemoryPolicy::Fault
+        MemoryFault { address: usize, pc: usize },
+        /// an instruction the variant doesn't have, with UnknownOpcodePolicy::Fault
+        UnknownOpcode { opcode: u16, pc: usize },
+        /// one it has but this interpreter doesn't do (yet)
+        Unsupported { opcode: u16, pc: usize },
+        /// a 0NNN call to 1802 machine code with MachineCodePolicy::Fault
+        MachineCode { address: usize, pc: usize },
+    }
+
+    impl fmt::Display for Chip8Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Chip8Error::StackOverflow { pc, stack } => {
+                    write!(f, "stack overflow at {:#05X}, call stack:", pc)?;
+                    for address in stack {
+                        write!(f, " {:#05X}", address)?;
+                    }
+                    Ok(())
+                }
+                Chip8Error::StackUnderflow { pc } => {
+                    write!(f, "stack underflow at {:#05X}: return without a call", pc)
+                }
+                Chip8Error::RomTooLarge { size, max } => {
+                    write!(f, "ROM is {} bytes, the maximum is {}", size, max)
+                }
+                Chip8Error::MemoryFault { address, pc } => {
+                    write!(f, "memory fault at {:#05X}: access to {:#X}", pc, address)
+                }
+                Chip8Error::UnknownOpcode { opcode, pc } => {
+                    write!(f, "unknown instruction {:04X} at {:#05X}", opcode, pc)
+                }
+                Chip8Error::Unsupported { opcode, pc } => {
+                    write!(
+                        f,
+                        "instruction {:04X} at {:#05X} isn't supported",
+                        opcode, pc
+                    )
+                }
+                Chip8Error::MachineCode { address, pc } => {
+                    write!(
+                        f,
+                        "call to 1802 machine code at {:#05X} from {:#05X}",
+                        address, pc
+                    )
+                }
+            }
+        }
+    }
+
+    impl Chip8Error {
+        /// where it went wrong, for the errors raised by an instruction
+        pub fn pc(&self) -> Option<usize> {
+            match self {
+                Chip8Error::StackOverflow { pc, .. }
+                | Chip8Error::StackUnderflow { pc }
+                | Chip8Error::MemoryFault { pc, .. }
+                | Chip8Error::UnknownOpcode { pc, .. }
+                | Chip8Error::Unsupported { pc, .. }
+                | Chip8Error::MachineCode { pc, .. } => Some(*pc),
+                Chip8Error::RomTooLarge { .. } => None,
+            }
+        }
+    }
+
+    impl std::error::Error for Chip8Error {}
+
+    /// lets external tools (profilers, debuggers, test harnesses) observe execution.
+    /// every method has an empty default so implementors only pick what they need
+    pub trait Hooks {
+        /// with pc still at the instruction
+        fn before_instruction(&mut self, _chip8: &Chip8, _opcode: &Opcode) {}
+        /// once the instruction has run, unless it failed
+        fn after_instruction(&mut self, _chip8: &Chip8, _opcode: &Opcode) {}
+        /// the framebuffer was changed by 00E0 or DXYN
+        fn on_display(&mut self, _chip8: &Chip8) {}
+        /// the buzzer started (true) or stopped (false)
+        fn on_sound(&mut self, _chip8: &Chip8, _playing: bool) {}
+        /// an instruction (FX33, FX55) stored `value` at `address`
+        fn on_memory_write(&mut self, _chip8: &Chip8, _address: usize, _value: u8) {}
+    }
+
+    // so the caller can keep a handle on a hook and read its results back later
+    impl<T: Hooks> Hooks for Rc<RefCell<T>> {
+        fn before_instruction(&mut self, chip8: &Chip8, opcode: &Opcode) {
+            self.borrow_mut().before_instruction(chip8, opcode);
+        }
+        fn after_instruction(&mut self, chip8: &Chip8, opcode: &Opcode) {
+            self.borrow_mut().after_instruction(chip8, opcode);
+        }
+        fn on_display(&mut self, chip8: &Chip8) {
+            self.borrow_mut().on_display(chip8);
+        }
+        fn on_sound(&mut self, chip8: &Chip8, playing: bool) {
+            self.borrow_mut().on_sound(chip8, playing);
+        }
+        fn on_memory_write(&mut self, chip8: &Chip8, address: usize, value: u8) {
+            self.borrow_mut().on_memory_write(chip8, address, value);
+        }
+    }
+
+    /// hardware beyond the stock machine: a clock, a serial console, GPIO pins. loads and stores
+    /// by instructions in the ranges a peripheral claims go to it instead of RAM, and it gets to
+    /// run the FX70, FX71 and FX72 instructions nothing else does. instructions are still only
+    /// fetched from RAM, and debuggers and snapshots only see RAM
+    pub trait Peripheral {
+        /// asked once, when it's attached
+        fn ranges(&self) -> Vec<Range<usize>> {
+            Vec::new()
+        }
+        fn read(&mut self, _address: usize) -> u8 {
+            0
+        }
+        fn write(&mut self, _address: usize, _value: u8) {}
+        /// FX70, FX71 or FX72 with VX, the new VX if it handles the instruction. the first
+        /// peripheral that does wins
+        fn instruction(&mut self, _opcode: Opcode, _vx: u8) -> Option<u8> {
+            None
+        }
+    }
+
+    impl<T: Peripheral> Peripheral for Rc<RefCell<T>> {
+        fn ranges(&self) -> Vec<Range<usize>> {
+            self.borrow().ranges()
+        }
+        fn read(&mut self, address: usize) -> u8 {
+            self.borrow_mut().read(address)
+        }
+        fn write(&mut self, address: usize, value: u8) {
+            self.borrow_mut().write(address, value);
+        }
+        fn instruction(&mut self, opcode: Opcode, vx: u8) -> Option<u8> {
+            self.borrow_mut().instruction(opcode, vx)
+        }
+    }
+
+    // a peripheral and the ranges it claimed
+    struct Attached {
+        ranges: Vec<Range<usize>>,
+        peripheral: Box<dyn Peripheral>,
+    }
+
+    /// the machine: memory, registers, timers, keys and screen, and how it's set up to run.
+    /// made with Chip8::builder or create_chip8
     #[allow(non_snake_case)]
     pub struct Chip8 {
         memory: [u8; MEM_SIZE],
@@ -25,133 +701,659 @@ pub mod chip8 {
         // index register
         I: usize,
         pc: usize,
-        // monochrome, so use bool
-        pub gfx: [bool; DISPLAY_HEIGHT * DISPLAY_WIDTH],
+        // DISPLAY_WIDTH wide and display_height() tall
+        gfx: FrameBuffer,
         delay_timer: u8,
+        /// the buzzer sounds while it's above 0
         pub sound_timer: u8,
+        /// XO-CHIP audio. None until a ROM loads a pattern with F002
+        pub audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+        /// XO-CHIP playback rate of audio_pattern, set by FX3A. 64 is 4000 samples a second
+        pub pitch: u8,
+        /// SCHIP "RPL user flags", written by FX75 and read back by FX85. they outlive the ROM
+        /// on the HP48, so the frontend persists them
+        pub rpl_flags: [u8; RPL_FLAG_COUNT],
         stack: [usize; STACK_SIZE],
         sp: usize,
         keys: [bool; KEY_COUNT],
         opcode: Opcode,
+        /// something on screen changed, see dirty_rows for which rows
         pub draw: bool,
-        wait_for_input: Option<usize>,
+        // decoded instruction at every address, None where it isn't known (yet)
+        decode_cache: Option<Vec<Option<Opcode>>>,
+        decode_cache_stats: DecodeCacheStats,
+        dirty_rows: Vec<bool>,
+        // FX0A in progress, nothing runs until it's done
+        key_wait: Option<KeyWait>,
+        exited: bool,
+        // a frame started since the last DXYN, and DXYN stalled waiting for one
+        vblank: bool,
+        waiting_for_vblank: bool,
+        // instructions per second for run_frame, and what's left of it for the frames to come
+        speed: u64,
+        timing: Timing,
+        frame_budget: FrameBudget,
+        // run_for stopped partway through a frame, the rest of its budget goes first next time
+        mid_frame: bool,
+        // time given to run_for that doesn't make up a whole frame yet
+        run_clock: Duration,
+        /// can be changed at any time, the next instruction goes by them
+        pub quirks: Quirks,
+        variant: Variant,
+        layout: Layout,
+        // CHIP-8X foreground color of every CHIP8X_ZONE_WIDTH pixel strip, row by row
+        zone_colors: Vec<u8>,
+        background_color: u8,
+        // 0NNN addresses already warned about
+        machine_code_calls: HashSet<usize>,
+        // addresses of unknown instructions already warned about
+        unknown_opcodes: HashSet<usize>,
+        hooks: Vec<Box<dyn Hooks>>,
+        peripherals: Vec<Attached>,
+        // source for CXKK, swap in a seeded one for reproducible runs
+        rng: Box<dyn RngCore>,
     }
 
     impl Chip8 {
-        pub fn load_rom(&mut self, file_path: &Path) {
-            let mut file = File::open(file_path).unwrap();
-            let mut file_contents: Vec<u8> = Vec::new();
-            let read_size = file.read_to_end(&mut file_contents).unwrap();
-            for i in 0..read_size {
-                self.memory[PROGRAM_START_ADDRESS + i] = file_contents[i];
+        /// a machine set up differently from create_chip8's
+        pub fn builder() -> Chip8Builder {
+            Chip8Builder::default()
+        }
+
+        /// called around every instruction from now on, after the hooks already added
+        pub fn add_hook(&mut self, hook: Box<dyn Hooks>) {
+            self.hooks.push(hook);
+        }
+
+        /// where ranges of two peripherals overlap, the one attached first gets the access
+        pub fn attach(&mut self, peripheral: Box<dyn Peripheral>) {
+            self.peripherals.push(Attached {
+                ranges: peripheral.ranges(),
+                peripheral,
+            });
+        }
+
+        /// anything attached with attach or Chip8Builder::peripheral
+        pub fn has_peripherals(&self) -> bool {
+            !self.peripherals.is_empty()
+        }
+
+        /// source for CXKK from now on, see Chip8Builder::rng
+        pub fn set_rng(&mut self, rng: Box<dyn RngCore>) {
+            self.rng = rng;
+        }
+
+        /// FNV-1a over the framebuffer, handy for comparing screens in tests
+        pub fn framebuffer_hash(&self) -> u64 {
+            let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+            for pixel in self.gfx.pixels() {
+                hash ^= pixel as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01B3);
             }
+            hash
         }
 
-        pub fn key_up(&mut self, keycode: Keycode) {
-            let mapped_keycode = Chip8::keymap(keycode);
-            match mapped_keycode {
-                None => {}
-                Some(pressed_key) => {
-                    self.keys[pressed_key as usize] = false;
-                }
+        /// FNV-1a over everything a ROM can see: memory, registers, stack, timers and the screen.
+        /// two machines that agree on it are running the same way
+        pub fn state_hash(&self) -> u64 {
+            let mut hash: u64 = 0xCBF2_9CE4_8422_2325;
+            let mut add = |byte: u8| {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01B3);
+            };
+            self.memory
+                .iter()
+                .chain(&self.V)
+                .for_each(|byte| add(*byte));
+            for value in [self.I, self.pc, self.sp]
+                .iter()
+                .chain(&self.stack[..self.sp])
+            {
+                value.to_le_bytes().iter().for_each(|byte| add(*byte));
             }
+            add(self.delay_timer);
+            add(self.sound_timer);
+            self.framebuffer_hash()
+                .to_le_bytes()
+                .iter()
+                .for_each(|byte| add(*byte));
+            hash
         }
 
-        pub fn key_down(&mut self, keycode: Keycode) {
-            let mapped_keycode = Chip8::keymap(keycode);
-            match mapped_keycode {
-                None => {} // pressed key is not in keymap. don't do anything
-                Some(pressed_key) => match self.wait_for_input {
-                    Some(x) => {
-                        self.V[x] = pressed_key;
-                        self.wait_for_input = None;
-                    }
-                    None => {
-                        self.keys[pressed_key as usize] = true;
-                    }
-                },
+        /// the state to come back to with restore, or to save with savestate::write
+        pub fn snapshot(&self) -> Snapshot {
+            Snapshot {
+                variant: self.variant,
+                quirks: self.quirks,
+                speed: self.speed,
+                memory: self.memory.to_vec(),
+                registers: self.V,
+                index: self.I,
+                pc: self.pc,
+                stack: self.stack().to_vec(),
+                delay_timer: self.delay_timer,
+                sound_timer: self.sound_timer,
+                audio_pattern: self.audio_pattern,
+                pitch: self.pitch,
+                rpl_flags: self.rpl_flags,
+                framebuffer: self.gfx.clone(),
+                zone_colors: self.zone_colors.clone(),
+                background_color: self.background_color,
+                key_wait: self.key_wait,
+                exited: self.exited,
             }
         }
 
-        fn keymap(keycode: Keycode) -> Option<u8> {
-            match keycode {
-                Keycode::X => Some(0x0),
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-                Keycode::Num4 => Some(0xC),
-                Keycode::Q => Some(0x4),
-                Keycode::W => Some(0x5),
-                Keycode::E => Some(0x6),
-                Keycode::R => Some(0xD),
-                Keycode::A => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-                Keycode::F => Some(0xE),
-                Keycode::Z => Some(0xA),
-                Keycode::C => Some(0xB),
-                Keycode::V => Some(0xF),
-                _ => None,
+        /// back to where the snapshot was taken. the snapshot is checked first and left alone
+        /// if it doesn't fit this machine, e.g. a screen of the wrong size
+        pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+            let height = snapshot.variant.display_height();
+            if snapshot.memory.len() != MEM_SIZE {
+                return Err(format!("{} bytes of memory", snapshot.memory.len()));
+            }
+            if snapshot.stack.len() > STACK_SIZE {
+                return Err(format!("{} return addresses", snapshot.stack.len()));
+            }
+            if snapshot.framebuffer.size() != (DISPLAY_WIDTH, height) {
+                let (width, height) = snapshot.framebuffer.size();
+                return Err(format!("a {}x{} screen", width, height));
+            }
+            if snapshot.zone_colors.len() != self.zone_colors.len() {
+                return Err(format!("{} color zones", snapshot.zone_colors.len()));
             }
+            if let Some(color) = snapshot
+                .zone_colors
+                .iter()
+                .find(|color| **color >= CHIP8X_FOREGROUND_COUNT)
+            {
+                return Err(format!("zone color {}", color));
+            }
+            if snapshot.background_color >= CHIP8X_BACKGROUND_COUNT {
+                return Err(format!("background color {}", snapshot.background_color));
+            }
+            // the layout isn't saved, a snapshot of the same variant keeps the one set
+            let layout = self.layout;
+            let same_variant = snapshot.variant == self.variant;
+            self.set_variant(snapshot.variant);
+            if same_variant {
+                self.layout = layout;
+            }
+            self.quirks = snapshot.quirks;
+            self.set_speed(snapshot.speed);
+            self.memory.copy_from_slice(&snapshot.memory);
+            self.V = snapshot.registers;
+            self.I = snapshot.index % MEM_SIZE;
+            self.pc = snapshot.pc % MEM_SIZE;
+            self.stack = [0; STACK_SIZE];
+            self.stack[..snapshot.stack.len()].copy_from_slice(&snapshot.stack);
+            self.sp = snapshot.stack.len();
+            self.delay_timer = snapshot.delay_timer;
+            self.sound_timer = snapshot.sound_timer;
+            self.audio_pattern = snapshot.audio_pattern;
+            self.pitch = snapshot.pitch;
+            self.rpl_flags = snapshot.rpl_flags;
+            self.gfx = snapshot.framebuffer.clone();
+            self.zone_colors = snapshot.zone_colors.clone();
+            self.background_color = snapshot.background_color;
+            self.key_wait = snapshot.key_wait;
+            self.exited = snapshot.exited;
+            self.keys = [false; KEY_COUNT];
+            self.vblank = false;
+            self.waiting_for_vblank = false;
+            self.frame_budget = FrameBudget::default();
+            self.mid_frame = false;
+            self.predecode();
+            self.mark_all_dirty();
+            trace!(pc = self.pc, "restored a snapshot");
+            Ok(())
         }
 
-        fn init_font(&mut self) {
-            // could we do this without allocating a new array? probably
-            let font: [u8; FONT_SIZE] = [
-                0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-                0x20, 0x60, 0x20, 0x20, 0x70, // 1
-                0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-                0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-                0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-                0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-                0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-                0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-                0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-                0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-                0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-                0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-                0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-                0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-                0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-                0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-            ];
-            for i in 0..FONT_SIZE {
-                self.memory[i] = font[i];
+        pub fn variant(&self) -> Variant {
+            self.variant
+        }
+
+        /// to be called before loading a ROM, it clears the screen and moves pc to where the
+        /// variant's programs start. the variant's layout replaces one set before
+        pub fn set_variant(&mut self, variant: Variant) {
+            self.variant = variant;
+            self.layout = variant.layout();
+            self.gfx = FrameBuffer::new(DISPLAY_WIDTH, variant.display_height());
+            self.dirty_rows = vec![true; variant.display_height()];
+            self.zone_colors =
+                vec![CHIP8X_DEFAULT_FOREGROUND; DISPLAY_WIDTH / CHIP8X_ZONE_WIDTH * DISPLAY_HEIGHT];
+            self.background_color = CHIP8X_DEFAULT_BACKGROUND;
+            self.pc = self.layout.start;
+            self.draw = true;
+            // decoding depends on the variant
+            self.predecode();
+        }
+
+        /// rows of the screen for the variant, the framebuffer is that tall
+        pub fn display_height(&self) -> usize {
+            self.variant.display_height()
+        }
+
+        pub fn layout(&self) -> Layout {
+            self.layout
+        }
+
+        /// load ROMs somewhere else than the variant does, or start them elsewhere. like
+        /// set_variant, to be called before loading a ROM, it moves pc to the start address
+        pub fn set_layout(&mut self, layout: Layout) {
+            self.layout = layout;
+            self.pc = layout.start;
+        }
+
+        /// CHIP-8X color index (0 to 7) of the pixel at x, y
+        pub fn foreground_color(&self, x: usize, y: usize) -> u8 {
+            self.zone_colors[y * DISPLAY_WIDTH / CHIP8X_ZONE_WIDTH + x / CHIP8X_ZONE_WIDTH]
+        }
+
+        /// CHIP-8X background color index (0 to 3)
+        pub fn background_color(&self) -> u8 {
+            self.background_color
+        }
+
+        pub fn pc(&self) -> usize {
+            self.pc
+        }
+
+        pub fn delay_timer(&self) -> u8 {
+            self.delay_timer
+        }
+
+        /// read-only views for debuggers and other tools
+        pub fn registers(&self) -> &[u8; REGISTER_COUNT] {
+            &self.V
+        }
+
+        pub fn index(&self) -> usize {
+            self.I
+        }
+
+        pub fn memory(&self) -> &[u8] {
+            &self.memory
+        }
+
+        /// return addresses, oldest first
+        pub fn stack(&self) -> &[usize] {
+            &self.stack[..self.sp]
+        }
+
+        /// the raw instruction at pc. a peek, so it wraps whatever the memory policy
+        pub fn next_instruction(&self) -> u16 {
+            let byte = |address: usize| self.memory[address % MEM_SIZE] as u16;
+            byte(self.pc) << 8 | byte(self.pc + 1)
+        }
+
+        fn run_hooks(&mut self, call: impl Fn(&mut dyn Hooks, &Chip8)) {
+            if self.hooks.is_empty() {
+                return;
             }
+            // hooks get a shared reference to the machine, so move them out while they run
+            let mut hooks = std::mem::take(&mut self.hooks);
+            for hook in hooks.iter_mut() {
+                call(hook.as_mut(), self);
+            }
+            self.hooks = hooks;
         }
 
-        // load 2 bytes starting at pc
-        fn fetch(&self) -> u16 {
-            (self.memory[self.pc] as u16).rotate_left(8) | self.memory[self.pc + 1] as u16
+        /// copies the ROM to the layout's load address. pc, the registers and the rest of memory
+        /// are left as they are, reset first for a clean start
+        pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+            let start = self.layout.load;
+            if rom.len() > self.layout.max_rom_size() {
+                return Err(Chip8Error::RomTooLarge {
+                    size: rom.len(),
+                    max: self.layout.max_rom_size(),
+                });
+            }
+            self.memory[start..start + rom.len()].copy_from_slice(rom);
+            // hires ROMs start with a jump over the part of the interpreter that lived in
+            // 0x260..0x2C0 on the VIP. we don't need it, so jump straight to the program
+            if self.variant == Variant::Hires && rom.starts_with(&[0x12, 0x60]) {
+                self.memory[start + 1] = 0xC0;
+            }
+            self.predecode();
+            debug!(
+                size = rom.len(),
+                variant = self.variant.name(),
+                "ROM loaded"
+            );
+            Ok(())
         }
 
-        fn execute(&mut self) {
-            // flag to be set when the next instruction is skipped
-            let mut skip_flag = false;
-            // flag to be set when an instruction sets pc
-            let mut jump_flag = false;
+        /// back to the power-on state. quirks, variant, layout, hooks and peripherals are kept
+        pub fn reset(&mut self) {
+            let mut fresh = create_chip8();
+            fresh.quirks = self.quirks;
+            fresh.speed = self.speed;
+            fresh.timing = self.timing;
+            fresh.set_variant(self.variant);
+            fresh.set_layout(self.layout);
+            std::mem::swap(&mut fresh.hooks, &mut self.hooks);
+            std::mem::swap(&mut fresh.peripherals, &mut self.peripherals);
+            std::mem::swap(&mut fresh.rng, &mut self.rng);
+            fresh.set_predecode(self.decode_cache.is_some());
+            *self = fresh;
+            self.mark_all_dirty();
+            debug!("reset");
+        }
 
-            match self.opcode {
-                Opcode::OP_0000 => {
-                    // NOOP
+        /// press or release chip-8 key 0 to F. host keys go through a Keypad and its keymap first,
+        /// scripts and replays call this directly
+        pub fn set_key(&mut self, key: u8, pressed: bool) {
+            let key = key & 0xF;
+            let was_pressed = self.keys[key as usize];
+            self.keys[key as usize] = pressed;
+            self.key_wait = match self.key_wait {
+                // keys that were already held when FX0A started don't count
+                Some(KeyWait::Press(x)) if pressed && !was_pressed => {
+                    Some(KeyWait::Release(x, key))
                 }
-                Opcode::OP_00E0 => {
-                    self.clear_screen();
-                }
-                Opcode::OP_00EE => {
-                    // return
-                    self.sp -= 1;
-                    self.pc = self.stack[self.sp] + 2;
-                    jump_flag = true;
+                Some(KeyWait::Release(x, waiting_for)) if !pressed && key == waiting_for => {
+                    self.V[x] = key;
+                    None
                 }
-                Opcode::OP_1MMM(mmm) => {
-                    // goto (not considered harmful}
-                    self.pc = mmm;
-                    jump_flag = true;
-                }
-                Opcode::OP_2MMM(mmm) => {
-                    // call subroutine
-                    self.stack[self.sp] = self.pc;
+                key_wait => key_wait,
+            };
+        }
+
+        /// a press or release from a Keypad
+        pub fn key_edge(&mut self, edge: KeyEdge) {
+            self.set_key(edge.key(), edge.pressed());
+        }
+
+        /// which keypad keys are held down, by key
+        pub fn keys(&self) -> [bool; KEY_COUNT] {
+            self.keys
+        }
+
+        /// Some while FX0A is waiting for a key
+        pub fn key_wait(&self) -> Option<KeyWait> {
+            self.key_wait
+        }
+
+        /// for tools that patch the running machine (scripts, debuggers). these don't notify hooks
+        pub fn set_register(&mut self, x: usize, value: u8) {
+            self.V[x & 0xF] = value;
+        }
+
+        /// addresses past the end wrap around. the instruction there is decoded again if it runs
+        pub fn set_memory(&mut self, address: usize, value: u8) {
+            self.memory[address % MEM_SIZE] = value;
+            self.invalidate(address % MEM_SIZE);
+        }
+
+        /// I, wrapped to memory like set_memory
+        pub fn set_index(&mut self, value: usize) {
+            self.I = value % MEM_SIZE;
+        }
+
+        /// the next instruction run is at `address`, wrapped to memory
+        pub fn set_pc(&mut self, address: usize) {
+            self.pc = address % MEM_SIZE;
+        }
+
+        /// frames above the new stack pointer keep their return addresses, so raising it again
+        /// brings them back
+        pub fn set_sp(&mut self, sp: usize) {
+            self.sp = sp.min(STACK_SIZE);
+        }
+
+        pub fn set_delay_timer(&mut self, value: u8) {
+            self.delay_timer = value;
+        }
+
+        fn reset_vf(&mut self) {
+            if self.quirks.vf_reset {
+                self.V[0xF] = 0;
+            }
+        }
+
+        // the flag goes in after the result, so it wins when VX is VF
+        fn set_with_flag(&mut self, x: usize, result: u8, flag: u8) {
+            self.V[x] = result;
+            self.V[0xF] = flag;
+        }
+
+        // the value 8XY6 and 8XYE shift
+        fn shift_source(&self, x: usize, y: usize) -> u8 {
+            if self.quirks.shift_vy {
+                self.V[y]
+            } else {
+                self.V[x]
+            }
+        }
+
+        // where an access to `address` ends up under the memory policy
+        fn resolve(&self, address: usize) -> Result<usize, Chip8Error> {
+            if address < MEM_SIZE {
+                return Ok(address);
+            }
+            match self.quirks.memory {
+                MemoryPolicy::Wrap => Ok(address % MEM_SIZE),
+                MemoryPolicy::Fault => Err(Chip8Error::MemoryFault {
+                    address,
+                    pc: self.pc,
+                }),
+            }
+        }
+
+        // the peripheral that claimed `address`, if any
+        fn peripheral_at(&mut self, address: usize) -> Option<&mut Box<dyn Peripheral>> {
+            self.peripherals
+                .iter_mut()
+                .find(|attached| attached.ranges.iter().any(|range| range.contains(&address)))
+                .map(|attached| &mut attached.peripheral)
+        }
+
+        // a load done by an instruction
+        fn load(&mut self, address: usize) -> Result<u8, Chip8Error> {
+            let address = self.resolve(address)?;
+            if let Some(peripheral) = self.peripheral_at(address) {
+                return Ok(peripheral.read(address));
+            }
+            Ok(self.memory[address])
+        }
+
+        // a store done by an instruction
+        fn store(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+            let address = self.resolve(address)?;
+            match self.peripheral_at(address) {
+                Some(peripheral) => peripheral.write(address, value),
+                None => {
+                    self.memory[address] = value;
+                    self.invalidate(address);
+                }
+            }
+            self.run_hooks(|hook, chip8| hook.on_memory_write(chip8, address, value));
+            Ok(())
+        }
+
+        fn init_font(&mut self) {
+            // could we do this without allocating a new array? probably
+            let font: [u8; FONT_SIZE] = [
+                0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+                0x20, 0x60, 0x20, 0x20, 0x70, // 1
+                0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+                0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+                0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+                0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+                0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+                0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+                0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+                0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+                0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+                0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+                0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+                0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+                0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+                0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+            ];
+            self.memory[..FONT_SIZE].copy_from_slice(&font);
+        }
+
+        /// decode every address up front and keep the results until the memory under them
+        /// changes. saves decoding the same instructions over and over, and the table doubles
+        /// as a disassembly
+        pub fn set_predecode(&mut self, enabled: bool) {
+            self.decode_cache = if enabled { Some(Vec::new()) } else { None };
+            self.decode_cache_stats = DecodeCacheStats::default();
+            self.predecode();
+        }
+
+        /// None without set_predecode
+        pub fn decode_cache_stats(&self) -> Option<DecodeCacheStats> {
+            self.decode_cache.as_ref().map(|_| self.decode_cache_stats)
+        }
+
+        /// the predecoded instruction at `address`, if there is one
+        pub fn predecoded(&self, address: usize) -> Option<Opcode> {
+            self.decode_cache.as_ref()?.get(address).copied().flatten()
+        }
+
+        fn predecode(&mut self) {
+            if let Some(cache) = &mut self.decode_cache {
+                let memory = &self.memory;
+                let variant = self.variant;
+                *cache = (0..MEM_SIZE)
+                    .map(|address| {
+                        let high = memory[address] as u16;
+                        let low = *memory.get(address + 1).unwrap_or(&0) as u16;
+                        try_decode(high << 8 | low, variant)
+                    })
+                    .collect();
+            }
+        }
+
+        // a byte belongs to the instruction starting on it and the one starting just before
+        fn invalidate(&mut self, address: usize) {
+            if let Some(cache) = &mut self.decode_cache {
+                cache[address] = None;
+                if address > 0 {
+                    cache[address - 1] = None;
+                }
+            }
+        }
+
+        fn decode_next(&mut self) -> Result<Opcode, Chip8Error> {
+            // a jump (BNNN) or running off the end can leave pc past the last address
+            self.pc = self.resolve(self.pc)?;
+            let pc = self.pc;
+            if let Some(opcode) = self.decode_cache.as_ref().and_then(|cache| cache[pc]) {
+                self.decode_cache_stats.hits += 1;
+                return Ok(opcode);
+            }
+            let instruction = self.fetch()?;
+            let opcode = match try_decode(instruction, self.variant) {
+                Some(opcode) => opcode,
+                // not cached, the policy might be different the next time it comes up
+                None => {
+                    self.unknown_opcode(instruction)?;
+                    return Ok(Opcode::OP_0000);
+                }
+            };
+            if let Some(cache) = &mut self.decode_cache {
+                self.decode_cache_stats.misses += 1;
+                cache[pc] = Some(opcode);
+            }
+            Ok(opcode)
+        }
+
+        // `opcode` at pc isn't an instruction: Ok if the policy says to pass over it
+        fn unknown_opcode(&mut self, opcode: u16) -> Result<(), Chip8Error> {
+            let pc = self.pc;
+            match self.quirks.unknown_opcode {
+                UnknownOpcodePolicy::Fault => Err(Chip8Error::UnknownOpcode { opcode, pc }),
+                UnknownOpcodePolicy::Skip => Ok(()),
+                UnknownOpcodePolicy::Warn => {
+                    if self.unknown_opcodes.insert(pc) {
+                        warn!("skipping unknown instruction {:04X} at {:#05X}", opcode, pc);
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        // for the instruction at pc
+        fn unsupported(&self) -> Chip8Error {
+            Chip8Error::Unsupported {
+                opcode: self.next_instruction(),
+                pc: self.pc,
+            }
+        }
+
+        // load 2 bytes starting at pc
+        fn fetch(&self) -> Result<u16, Chip8Error> {
+            let byte = |address: usize| -> Result<u16, Chip8Error> {
+                Ok(self.memory[self.resolve(address)?] as u16)
+            };
+            Ok(byte(self.pc)? << 8 | byte(self.pc + 1)?)
+        }
+
+        fn execute(&mut self) -> Result<(), Chip8Error> {
+            // flag to be set when the next instruction is skipped
+            let mut skip_flag = false;
+            // flag to be set when an instruction sets pc
+            let mut jump_flag = false;
+
+            match self.opcode {
+                Opcode::OP_0000 => {
+                    // NOOP
+                }
+                Opcode::OP_0NNN(address) => match self.quirks.machine_code {
+                    MachineCodePolicy::Ignore => {
+                        if self.machine_code_calls.insert(address) {
+                            warn!(
+                                "skipping call to 1802 machine code at {:#05X} from {:#05X}",
+                                address, self.pc
+                            );
+                        }
+                    }
+                    MachineCodePolicy::Fault => {
+                        return Err(Chip8Error::MachineCode {
+                            address,
+                            pc: self.pc,
+                        });
+                    }
+                },
+                Opcode::OP_00E0 => {
+                    self.clear_screen();
+                }
+                Opcode::OP_0230 => {
+                    self.clear_screen();
+                }
+                Opcode::OP_02A0 => {
+                    self.background_color = (self.background_color + 1) % CHIP8X_BACKGROUND_COUNT;
+                    self.mark_all_dirty();
+                }
+                Opcode::OP_00FD => {
+                    self.exited = true;
+                }
+                Opcode::OP_00EE => {
+                    // return
+                    if self.sp == 0 {
+                        return Err(Chip8Error::StackUnderflow { pc: self.pc });
+                    }
+                    self.sp -= 1;
+                    self.pc = self.stack[self.sp] + 2;
+                    jump_flag = true;
+                }
+                Opcode::OP_1MMM(mmm) => {
+                    // goto (not considered harmful}
+                    self.pc = mmm;
+                    jump_flag = true;
+                }
+                Opcode::OP_2MMM(mmm) => {
+                    // call subroutine
+                    if self.sp == STACK_SIZE {
+                        return Err(Chip8Error::StackOverflow {
+                            pc: self.pc,
+                            stack: self.stack.to_vec(),
+                        });
+                    }
+                    self.stack[self.sp] = self.pc;
                     self.sp += 1;
                     self.pc = mmm;
                     jump_flag = true
@@ -173,6 +1375,12 @@ pub mod chip8 {
                         skip_flag = true;
                     }
                 }
+                Opcode::OP_5XY1(x, y) => {
+                    // both nibbles are added separately and wrap at 8, for color zone coordinates
+                    let high = (self.V[x] >> 4).wrapping_add(self.V[y] >> 4) & 0x7;
+                    let low = (self.V[x] & 0xF).wrapping_add(self.V[y] & 0xF) & 0x7;
+                    self.V[x] = high << 4 | low;
+                }
                 Opcode::OP_6XKK(x, kk) => {
                     self.V[x] = kk;
                 }
@@ -185,39 +1393,39 @@ pub mod chip8 {
                 }
                 Opcode::OP_8XY1(x, y) => {
                     self.V[x] |= self.V[y];
+                    self.reset_vf();
                 }
                 Opcode::OP_8XY2(x, y) => {
                     self.V[x] &= self.V[y];
+                    self.reset_vf();
                 }
                 Opcode::OP_8XY3(x, y) => {
                     self.V[x] ^= self.V[y];
+                    self.reset_vf();
                 }
+                // the arithmetic ones read both operands first and write VF last, so with VF
+                // as VX the flag is what's left in it, like on the VIP
                 Opcode::OP_8XY4(x, y) => {
-                    let result = self.V[x].overflowing_add(self.V[y]);
-                    self.V[0xF] = result.1 as u8;
-                    self.V[x] = result.0;
+                    let (result, carry) = self.V[x].overflowing_add(self.V[y]);
+                    self.set_with_flag(x, result, carry as u8);
                 }
                 Opcode::OP_8XY5(x, y) => {
-                    let result = self.V[x].overflowing_sub(self.V[y]);
-                    self.V[0xF] = !result.1 as u8;
-                    self.V[x] = result.0;
+                    // VF = 1 when there's no borrow
+                    let (result, borrow) = self.V[x].overflowing_sub(self.V[y]);
+                    self.set_with_flag(x, result, !borrow as u8);
                 }
-                Opcode::OP_8X16(x) => {
-                    self.V[0xF] = self.V[x] & 1;
-                    self.V[x] = self.V[x] >> 1;
+                Opcode::OP_8XY6(x, y) => {
+                    let value = self.shift_source(x, y);
+                    self.set_with_flag(x, value >> 1, value & 1);
                 }
                 Opcode::OP_8XY7(x, y) => {
-                    let result = self.V[y].overflowing_sub(self.V[x]);
-                    self.V[0xF] = result.1 as u8;
-                    self.V[x] = result.0;
+                    // VX = VY - VX, with the same flag as 8XY5
+                    let (result, borrow) = self.V[y].overflowing_sub(self.V[x]);
+                    self.set_with_flag(x, result, !borrow as u8);
                 }
-                Opcode::OP_8X1E(x) => {
-                    if self.V[x] & 0x80 == 0x80 {
-                        self.V[0xF] = 1;
-                    } else {
-                        self.V[0xF] = 0;
-                    }
-                    self.V[x] = self.V[x] << 1;
+                Opcode::OP_8XYE(x, y) => {
+                    let value = self.shift_source(x, y);
+                    self.set_with_flag(x, value << 1, value >> 7);
                 }
                 Opcode::OP_9XY0(x, y) => {
                     if self.V[x] != self.V[y] {
@@ -228,44 +1436,56 @@ pub mod chip8 {
                     self.I = mmm;
                 }
                 Opcode::OP_BMMM(mmm) => {
-                    self.pc = mmm + (self.V[0] as usize);
+                    let offset = if self.quirks.jump_vx {
+                        self.V[mmm >> 8]
+                    } else {
+                        self.V[0]
+                    };
+                    self.pc = mmm + offset as usize;
                     jump_flag = true;
                 }
+                Opcode::OP_BXYN(x, y, n) => {
+                    self.set_zone_colors(x, self.V[y] % CHIP8X_FOREGROUND_COUNT, n);
+                }
                 Opcode::OP_CXKK(x, kk) => {
                     // AND kk w/ a random value
-                    let mut rng = thread_rng();
-                    let rnd: u8 = rng.gen_range(0..255);
+                    let rnd: u8 = self.rng.gen();
                     self.V[x] = rnd & kk;
                 }
                 Opcode::OP_DXYN(x, y, n) => {
-                    self.draw_sprite(x, y, n);
+                    self.draw_sprite(x, y, n)?;
                 }
                 Opcode::OP_EX9E(x) => {
-                    // skip if key[Vx] is down
-                    let key = self.V[x] as usize;
+                    // skip if key[Vx] is down. only the low nibble counts, like FX29
+                    let key = (self.V[x] & 0xF) as usize;
                     if self.keys[key] {
                         skip_flag = true;
                     }
                 }
                 Opcode::OP_EXA1(x) => {
-                    // skip if key[Vx] is down
-                    let key = self.V[x] as usize;
+                    // skip if key[Vx] is up
+                    let key = (self.V[x] & 0xF) as usize;
                     if !self.keys[key] {
                         skip_flag = true
                     }
                 }
-                Opcode::OP_F000 => {
-                    // TODO: implement
-                    // stop
-                    panic!("not implemented");
+                Opcode::OP_F000 => return Err(self.unsupported()),
+                Opcode::OP_F002 => {
+                    // load the 16 byte audio pattern buffer from I
+                    let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                    for (offset, byte) in pattern.iter_mut().enumerate() {
+                        *byte = self.load(self.I + offset)?;
+                    }
+                    self.audio_pattern = Some(pattern);
                 }
                 Opcode::OP_FX07(x) => {
                     // set VX to delay timer
                     self.V[x] = self.delay_timer;
                 }
                 Opcode::OP_FX0A(x) => {
-                    // wait for keypress and save value to Vx
-                    self.wait_for_input = Some(x);
+                    // wait for a key to be pressed and released, then save it to Vx. like the
+                    // VIP, the key counts on release
+                    self.key_wait = Some(KeyWait::Press(x));
                 }
                 Opcode::OP_FX15(x) => {
                     // set delay timer to VX
@@ -282,36 +1502,58 @@ pub mod chip8 {
                     self.I += self.V[x] as usize;
                 }
                 Opcode::OP_FX29(x) => {
-                    // set I to the memory address of the sprite for the hex digit in VX
-                    self.I = (self.V[x] * 5) as usize;
+                    // set I to the memory address of the sprite for the hex digit in VX. only
+                    // the low nibble counts, like on the VIP
+                    self.I = (self.V[x] & 0xF) as usize * 5;
+                }
+                Opcode::OP_FX3A(x) => {
+                    // set the audio pattern playback pitch
+                    self.pitch = self.V[x];
                 }
                 Opcode::OP_FX33(x) => {
                     // store BCD representation of V[x] at I..I + 2
-                    self.memory[self.I] = self.V[x] / 100;
-                    self.memory[self.I + 1] = (self.V[x] / 10) % 10;
-                    self.memory[self.I + 2] = self.V[x] % 10;
+                    self.store(self.I, self.V[x] / 100)?;
+                    self.store(self.I + 1, (self.V[x] / 10) % 10)?;
+                    self.store(self.I + 2, self.V[x] % 10)?;
                 }
 
                 Opcode::OP_FX55(x) => {
                     // dump registers
                     for reg_index in 0..=x {
-                        self.memory[self.I + reg_index] = self.V[reg_index];
+                        self.store(self.I + reg_index, self.V[reg_index])?;
+                    }
+                    if self.quirks.increment_i {
+                        self.I += x + 1;
                     }
                 }
                 Opcode::OP_FX65(x) => {
                     // load registers from memory
                     for reg_index in 0..=x {
-                        self.V[reg_index] = self.memory[self.I + reg_index];
+                        self.V[reg_index] = self.load(self.I + reg_index)?;
+                    }
+                    if self.quirks.increment_i {
+                        self.I += x + 1;
                     }
                 }
-                Opcode::OP_FX70(_x) => {
-                    panic!("not implemented");
+                Opcode::OP_FX75(x) => {
+                    // save V0..=VX to the flags
+                    self.rpl_flags[..=x].copy_from_slice(&self.V[..=x]);
                 }
-                Opcode::OP_FX71(_x) => {
-                    panic!("not implemented");
+                Opcode::OP_FX85(x) => {
+                    // and load them back
+                    self.V[..=x].copy_from_slice(&self.rpl_flags[..=x]);
                 }
-                Opcode::OP_FX72(_x) => {
-                    panic!("not implemented");
+                Opcode::OP_FX70(x) | Opcode::OP_FX71(x) | Opcode::OP_FX72(x) => {
+                    // only a peripheral knows what these do
+                    let (opcode, vx) = (self.opcode, self.V[x]);
+                    let value = self
+                        .peripherals
+                        .iter_mut()
+                        .find_map(|attached| attached.peripheral.instruction(opcode, vx));
+                    match value {
+                        Some(value) => self.V[x] = value,
+                        None => self.unknown_opcode(self.next_instruction())?,
+                    }
                 }
             }
 
@@ -322,87 +1564,492 @@ pub mod chip8 {
                     self.pc += 2;
                 }
             }
+            Ok(())
+        }
+
+        /// does nothing while FX0A waits for a key, or DXYN for the next frame with the
+        /// display_wait quirk. the timers keep running meanwhile
+        pub fn emulate_cycle(&mut self) -> Result<Status, Chip8Error> {
+            if self.exited {
+                return Ok(Status::Exited);
+            }
+            if self.key_wait.is_none() && !self.waiting_for_vblank {
+                self.opcode = self.decode_next()?;
+                let opcode = self.opcode;
+                trace!(pc = self.pc, ?opcode, "instruction");
+                if let Opcode::OP_DXYN(..) = opcode {
+                    if self.quirks.display_wait && !self.vblank {
+                        self.waiting_for_vblank = true;
+                        return Ok(Status::Running);
+                    }
+                    self.vblank = false;
+                }
+                let sound_was_playing = self.sound_timer > 0;
+                self.run_hooks(|hook, chip8| hook.before_instruction(chip8, &opcode));
+                self.execute()?;
+                self.run_hooks(|hook, chip8| hook.after_instruction(chip8, &opcode));
+                if matches!(opcode, Opcode::OP_00E0 | Opcode::OP_DXYN(..)) {
+                    self.run_hooks(|hook, chip8| hook.on_display(chip8));
+                }
+                self.notify_sound_change(sound_was_playing);
+            }
+            Ok(if self.exited {
+                Status::Exited
+            } else {
+                Status::Running
+            })
+        }
+
+        /// 00FD ran, the SCHIP exit. nothing runs after it
+        pub fn has_exited(&self) -> bool {
+            self.exited
+        }
+
+        /// nothing more runs this frame, the frontend can skip ahead to tick_timers
+        pub fn waiting_for_vblank(&self) -> bool {
+            self.waiting_for_vblank
+        }
+
+        /// instructions per second for run_frame under Timing::Flat
+        pub fn speed(&self) -> u64 {
+            self.speed
+        }
+
+        /// instructions per second run by run_frame
+        pub fn set_speed(&mut self, speed: u64) {
+            self.speed = speed;
+            self.frame_budget = FrameBudget::default();
+            self.mid_frame = false;
+        }
+
+        pub fn timing(&self) -> Timing {
+            self.timing
+        }
+
+        /// what's left of the current frame's budget is dropped
+        pub fn set_timing(&mut self, timing: Timing) {
+            self.timing = timing;
+            self.frame_budget = FrameBudget::default();
+            self.mid_frame = false;
+        }
+
+        /// one 60th of a second: speed / TIMER_FREQ instructions and a timer tick, and what came of
+        /// them, so a frontend doesn't have to poll `draw` and `sound_timer`. frontends that need
+        /// to step through single instructions (debuggers) use emulate_cycle and tick_timers.
+        /// consumes `draw`
+        pub fn run_frame(&mut self) -> FrameOutput {
+            let _frame = trace_span!("frame").entered();
+            self.run(1, u64::MAX)
+        }
+
+        /// a bounded amount of work and what came of it, for GUI event loops and async runtimes
+        /// that can't sit in a loop sleeping between frames. given the time since the last call it
+        /// keeps the same pace as run_frame at TIMER_FREQ. with a number of cycles it ticks the
+        /// timers whenever a frame's worth has run, and also returns once a frame goes by without
+        /// running any, as while waiting for a key. either way it stops at an exit or error
+        pub fn run_for(&mut self, limit: RunLimit) -> FrameOutput {
+            match limit {
+                RunLimit::Time(time) => {
+                    let frame = (Duration::from_secs(1) / TIMER_FREQ as u32).as_nanos();
+                    let clock = (self.run_clock + time).as_nanos();
+                    self.run_clock = Duration::from_nanos((clock % frame) as u64);
+                    self.run((clock / frame) as u64, u64::MAX)
+                }
+                RunLimit::Cycles(cycles) => self.run(u64::MAX, cycles),
+            }
+        }
+
+        // up to `frames` frames and `cycles` instructions, whichever runs out first
+        fn run(&mut self, frames: u64, mut cycles: u64) -> FrameOutput {
+            let mut output = FrameOutput::default();
+            let mut sound_playing = self.sound_timer > 0;
+            while output.frames < frames {
+                if !self.mid_frame {
+                    let mut budget = self.frame_budget;
+                    budget.start(self);
+                    self.frame_budget = budget;
+                    self.mid_frame = true;
+                }
+                let instructions = output.instructions;
+                while self.frame_budget.has_time() {
+                    if self.key_wait.is_some() || self.waiting_for_vblank {
+                        break;
+                    }
+                    if cycles == 0 {
+                        self.push_display_event(&mut output);
+                        return output;
+                    }
+                    let result = self.emulate_cycle();
+                    // a DXYN held back for the next frame didn't run, it costs nothing
+                    let held_back = self.waiting_for_vblank;
+                    if !held_back {
+                        let mut budget = self.frame_budget;
+                        budget.spend(self);
+                        self.frame_budget = budget;
+                    }
+                    self.push_sound_event(&mut output, &mut sound_playing);
+                    let stop = match result {
+                        Ok(Status::Running) if held_back => continue,
+                        Ok(Status::Running) => {
+                            output.instructions += 1;
+                            cycles -= 1;
+                            continue;
+                        }
+                        Ok(Status::Exited) => Event::Exited,
+                        Err(e) => Event::Error(e),
+                    };
+                    self.mid_frame = false;
+                    self.push_display_event(&mut output);
+                    output.events.push(stop);
+                    return output;
+                }
+                self.tick_timers();
+                self.mid_frame = false;
+                output.frames += 1;
+                self.push_sound_event(&mut output, &mut sound_playing);
+                self.push_display_event(&mut output);
+                // counting cycles, frames that run none would go on forever
+                if cycles != u64::MAX && output.instructions == instructions {
+                    break;
+                }
+            }
+            if self.key_wait.is_some() {
+                output.events.push(Event::WaitingForKey);
+            }
+            output
+        }
+
+        fn push_sound_event(&self, output: &mut FrameOutput, was_playing: &mut bool) {
+            let playing = self.sound_timer > 0;
+            if playing != *was_playing {
+                output.events.push(if playing {
+                    Event::SoundStarted
+                } else {
+                    Event::SoundStopped
+                });
+                *was_playing = playing;
+            }
+        }
+
+        fn push_display_event(&mut self, output: &mut FrameOutput) {
+            if self.draw {
+                self.draw = false;
+                output.events.push(Event::DisplayUpdated);
+            }
         }
 
-        pub fn emulate_cycle(&mut self) {
-            let raw_opcode = self.fetch();
-            self.opcode = decode(raw_opcode);
-            if self.wait_for_input == None {
-                self.execute();
+        fn notify_sound_change(&mut self, was_playing: bool) {
+            let playing = self.sound_timer > 0;
+            if playing != was_playing {
+                self.run_hooks(|hook, chip8| hook.on_sound(chip8, playing));
             }
         }
 
-        pub fn timer_tick(&mut self) {
-            // to be run every 20 ms (50 Hz)
-            // public so that timing can be handled by the main loop
+        /// pattern playback rate in bits per second, 4000 at the default pitch of 64
+        pub fn audio_playback_rate(&self) -> f32 {
+            4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+        }
+
+        /// the core has no clock of its own: the frontend calls this TIMER_FREQ times per
+        /// emulated second, so tests and headless runs can drive time however they like
+        pub fn tick_timers(&mut self) {
+            self.vblank = true;
+            self.waiting_for_vblank = false;
             if self.delay_timer > 0 {
                 self.delay_timer -= 1;
             }
             if self.sound_timer > 0 {
                 self.sound_timer -= 1;
+                self.notify_sound_change(true);
             }
         }
 
         fn clear_screen(&mut self) {
-            for i in 0..DISPLAY_HEIGHT * DISPLAY_WIDTH {
-                self.gfx[i] = false;
+            for row in 0..self.gfx.height() {
+                if !self.gfx.row_is_blank(row) {
+                    self.dirty_rows[row] = true;
+                }
             }
+            self.gfx.clear();
             self.draw = true
         }
 
-        fn draw_sprite(&mut self, x: usize, y: usize, n: u8) {
+        /// the screen, DISPLAY_WIDTH pixels wide and display_height rows high
+        pub fn framebuffer(&self) -> &FrameBuffer {
+            &self.gfx
+        }
+
+        /// for tools and tests that draw on the screen without running a ROM
+        pub fn set_pixel(&mut self, x: usize, y: usize, lit: bool) {
+            if y < self.gfx.height() {
+                self.gfx.set(x, y, lit);
+                self.dirty_rows[y] = true;
+                self.draw = true;
+            }
+        }
+
+        /// rows of the framebuffer that changed since the last clear_dirty_rows, so frontends
+        /// can redraw only those
+        pub fn dirty_rows(&self) -> impl Iterator<Item = usize> + '_ {
+            self.dirty_rows
+                .iter()
+                .enumerate()
+                .filter(|(_, dirty)| **dirty)
+                .map(|(row, _)| row)
+        }
+
+        /// call after rendering
+        pub fn clear_dirty_rows(&mut self) {
+            for dirty in self.dirty_rows.iter_mut() {
+                *dirty = false;
+            }
+        }
+
+        fn mark_all_dirty(&mut self) {
+            for dirty in self.dirty_rows.iter_mut() {
+                *dirty = true;
+            }
+            self.draw = true;
+        }
+
+        // BXY0 colors whole zones: the low nibble of VX is the first column of 8 pixels and the
+        // high nibble the last, VX+1 the same for rows of 4 pixels.
+        // BXYN colors n rows from row VX+1 in the column holding x coordinate VX
+        fn set_zone_colors(&mut self, x: usize, color: u8, n: u8) {
+            let columns = DISPLAY_WIDTH / CHIP8X_ZONE_WIDTH;
+            let (horizontal, vertical) = (self.V[x], self.V[(x + 1) % REGISTER_COUNT]);
+            let (column_range, row_range) = if n == 0 {
+                let first_row = (vertical & 0xF) as usize * CHIP8X_ZONE_HEIGHT;
+                let last_row = ((vertical >> 4) as usize + 1) * CHIP8X_ZONE_HEIGHT;
+                (
+                    (horizontal & 0xF) as usize..=(horizontal >> 4) as usize,
+                    first_row..last_row,
+                )
+            } else {
+                let column = horizontal as usize / CHIP8X_ZONE_WIDTH;
+                let first_row = vertical as usize;
+                (column..=column, first_row..first_row + n as usize)
+            };
+            for row in row_range.filter(|row| *row < DISPLAY_HEIGHT) {
+                for column in column_range.clone().filter(|column| *column < columns) {
+                    self.zone_colors[row * columns + column] = color;
+                }
+                self.dirty_rows[row] = true;
+            }
+            self.draw = true;
+        }
+
+        fn draw_sprite(&mut self, x: usize, y: usize, n: u8) -> Result<(), Chip8Error> {
             let mut collision = false;
+            // the starting position always wraps, only the sprite body is subject to clipping
+            let display_height = self.display_height();
+            let origin_x = self.V[x] as usize % DISPLAY_WIDTH;
+            let origin_y = self.V[y] as usize % display_height;
             for byte_index in 0..n as usize {
-                let byte = self.memory[self.I + byte_index];
-                'inner: for bit_index in 0..8 {
-                    let gfx_index = (self.V[y] as usize + byte_index) * DISPLAY_WIDTH
-                        + self.V[x] as usize
-                        + bit_index;
-                    if gfx_index >= DISPLAY_HEIGHT * DISPLAY_WIDTH {
-                        break 'inner;
+                let byte = self.load(self.I + byte_index)?;
+                let mut pixel_y = origin_y + byte_index;
+                if pixel_y >= display_height {
+                    if self.quirks.clipping {
+                        break;
                     }
-                    let bit_value = (byte >> (7 - bit_index as u32) & 1) != 0;
-                    if bit_value & self.gfx[gfx_index] {
-                        collision = true;
+                    pixel_y %= display_height;
+                }
+                for bit_index in 0..8 {
+                    let mut pixel_x = origin_x + bit_index;
+                    if pixel_x >= DISPLAY_WIDTH {
+                        if self.quirks.clipping {
+                            break;
+                        }
+                        pixel_x %= DISPLAY_WIDTH;
+                    }
+                    if (byte >> (7 - bit_index as u32) & 1) != 0 {
+                        collision |= self.gfx.toggle(pixel_x, pixel_y);
+                        self.dirty_rows[pixel_y] = true;
                     }
-                    self.gfx[gfx_index] = self.gfx[gfx_index] ^ bit_value;
                 }
             }
             self.V[0xF] = collision as u8;
             self.draw = true;
+            Ok(())
         }
     }
 
+    /// a CHIP-8 with the default quirks, the font loaded and nothing else in memory. rng is
+    /// seeded from the OS
     pub fn create_chip8() -> Chip8 {
         let mut instance = Chip8 {
             memory: [0; MEM_SIZE],
             V: [0; REGISTER_COUNT],
             I: 0,
-            pc: PROGRAM_START_ADDRESS,
-            gfx: [false; DISPLAY_HEIGHT * DISPLAY_WIDTH],
+            pc: Layout::VIP.start,
+            gfx: FrameBuffer::new(DISPLAY_WIDTH, DISPLAY_HEIGHT),
             delay_timer: 0,
             sound_timer: 0,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rpl_flags: [0; RPL_FLAG_COUNT],
             stack: [0; STACK_SIZE],
             sp: 0,
             keys: [false; KEY_COUNT],
             opcode: Opcode::OP_0000,
             draw: false,
-            wait_for_input: None,
+            dirty_rows: vec![false; DISPLAY_HEIGHT],
+            decode_cache: None,
+            decode_cache_stats: DecodeCacheStats::default(),
+            key_wait: None,
+            exited: false,
+            vblank: false,
+            waiting_for_vblank: false,
+            speed: CYCLE_FREQ,
+            timing: Timing::Flat,
+            frame_budget: FrameBudget::default(),
+            mid_frame: false,
+            run_clock: Duration::ZERO,
+            quirks: Quirks::default(),
+            variant: Variant::Chip8,
+            layout: Layout::VIP,
+            zone_colors: vec![
+                CHIP8X_DEFAULT_FOREGROUND;
+                DISPLAY_WIDTH / CHIP8X_ZONE_WIDTH * DISPLAY_HEIGHT
+            ],
+            background_color: CHIP8X_DEFAULT_BACKGROUND,
+            machine_code_calls: HashSet::new(),
+            unknown_opcodes: HashSet::new(),
+            hooks: Vec::new(),
+            peripherals: Vec::new(),
+            rng: Box::new(StdRng::from_entropy()),
         };
         instance.init_font();
         instance
     }
 
+    /// configures a machine before it starts, everything left out keeps the create_chip8 default:
+    ///
+    /// ```
+    /// use chip_8::{Chip8, Variant};
+    /// use rand::{rngs::StdRng, SeedableRng};
+    ///
+    /// let chip8 = Chip8::builder().variant(Variant::Hires).rng(StdRng::seed_from_u64(1)).build();
+    /// assert_eq!(chip8.display_height(), 64);
+    /// ```
+    #[derive(Default)]
+    pub struct Chip8Builder {
+        variant: Option<Variant>,
+        layout: Option<Layout>,
+        quirks: Option<Quirks>,
+        rng: Option<Box<dyn RngCore>>,
+        hooks: Vec<Box<dyn Hooks>>,
+        peripherals: Vec<Box<dyn Peripheral>>,
+        predecode: bool,
+        speed: Option<u64>,
+        timing: Option<Timing>,
+    }
+
+    impl Chip8Builder {
+        /// Variant::Chip8 by default
+        pub fn variant(mut self, variant: Variant) -> Self {
+            self.variant = Some(variant);
+            self
+        }
+
+        /// the variant's layout by default
+        pub fn layout(mut self, layout: Layout) -> Self {
+            self.layout = Some(layout);
+            self
+        }
+
+        /// Quirks::default by default
+        pub fn quirks(mut self, quirks: Quirks) -> Self {
+            self.quirks = Some(quirks);
+            self
+        }
+
+        /// source for CXKK, e.g. a seeded StdRng for reproducible runs
+        pub fn rng(mut self, rng: impl RngCore + 'static) -> Self {
+            self.rng = Some(Box::new(rng));
+            self
+        }
+
+        /// see Chip8::add_hook, can be given more than once
+        pub fn hook(mut self, hook: Box<dyn Hooks>) -> Self {
+            self.hooks.push(hook);
+            self
+        }
+
+        /// see Chip8::attach
+        pub fn peripheral(mut self, peripheral: Box<dyn Peripheral>) -> Self {
+            self.peripherals.push(peripheral);
+            self
+        }
+
+        /// see Chip8::set_predecode
+        pub fn predecode(mut self, enabled: bool) -> Self {
+            self.predecode = enabled;
+            self
+        }
+
+        /// instructions per second for Chip8::run_frame, CYCLE_FREQ by default
+        pub fn speed(mut self, speed: u64) -> Self {
+            self.speed = Some(speed);
+            self
+        }
+
+        /// Timing::Flat by default
+        pub fn timing(mut self, timing: Timing) -> Self {
+            self.timing = Some(timing);
+            self
+        }
+
+        /// the machine, with the font loaded and no ROM yet
+        pub fn build(self) -> Chip8 {
+            let mut chip8 = create_chip8();
+            if let Some(variant) = self.variant {
+                chip8.set_variant(variant);
+            }
+            if let Some(layout) = self.layout {
+                chip8.set_layout(layout);
+            }
+            if let Some(quirks) = self.quirks {
+                chip8.quirks = quirks;
+            }
+            if let Some(rng) = self.rng {
+                chip8.rng = rng;
+            }
+            chip8.hooks = self.hooks;
+            for peripheral in self.peripherals {
+                chip8.attach(peripheral);
+            }
+            chip8.set_predecode(self.predecode);
+            if let Some(speed) = self.speed {
+                chip8.set_speed(speed);
+            }
+            if let Some(timing) = self.timing {
+                chip8.set_timing(timing);
+            }
+            chip8
+        }
+    }
+
+    /// a decoded instruction, named after its encoding: X and Y are registers, KK a byte, N a
+    /// nibble and MMM an address
     #[allow(non_camel_case_types)]
-    enum Opcode {
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum Opcode {
         OP_0000,
         OP_00E0,
         OP_00EE,
+        /// SCHIP: exit the interpreter
+        OP_00FD,
+        /// call the 1802 machine code routine at NNN, COSMAC VIP only
+        OP_0NNN(usize),
+        OP_0230,
+        OP_02A0,
         OP_1MMM(usize),
         OP_2MMM(usize),
         OP_3XKK(usize, u8),
         OP_4XKK(usize, u8),
         OP_5XY0(usize, usize),
+        OP_5XY1(usize, usize),
         OP_6XKK(usize, u8),
         OP_7XKK(usize, u8),
         OP_8XY0(usize, usize),
@@ -411,34 +2058,45 @@ pub mod chip8 {
         OP_8XY3(usize, usize),
         OP_8XY4(usize, usize),
         OP_8XY5(usize, usize),
-        OP_8X16(usize),
+        OP_8XY6(usize, usize),
         OP_8XY7(usize, usize),
-        OP_8X1E(usize),
+        OP_8XYE(usize, usize),
         OP_9XY0(usize, usize),
         OP_AMMM(usize),
         OP_BMMM(usize),
+        OP_BXYN(usize, usize, u8),
         OP_CXKK(usize, u8),
         OP_DXYN(usize, usize, u8),
         OP_EX9E(usize),
         OP_EXA1(usize),
         OP_F000,
+        OP_F002,
         OP_FX07(usize),
         OP_FX0A(usize),
         OP_FX15(usize),
-        // OP_FX17(usize),
+        /// OP_FX17(usize),
         OP_FX18(usize),
         OP_FX1E(usize),
         OP_FX29(usize),
         OP_FX33(usize),
+        OP_FX3A(usize),
         OP_FX55(usize),
         OP_FX65(usize),
+        OP_FX75(usize),
+        OP_FX85(usize),
         OP_FX70(usize),
         OP_FX71(usize),
         OP_FX72(usize),
     }
 
-    fn decode(instruction: u16) -> Opcode {
-        match instruction & 0xF000 {
+    /// panics on instructions this variant doesn't know, see try_decode
+    pub fn decode(instruction: u16, variant: Variant) -> Opcode {
+        try_decode(instruction, variant).expect("unknown opcode")
+    }
+
+    /// None for words that aren't an instruction of this variant
+    pub fn try_decode(instruction: u16, variant: Variant) -> Option<Opcode> {
+        let opcode = match instruction & 0xF000 {
             0x0000 => {
                 if instruction == 0x0000 {
                     Opcode::OP_0000
@@ -446,8 +2104,14 @@ pub mod chip8 {
                     Opcode::OP_00E0
                 } else if instruction == 0x00EE {
                     Opcode::OP_00EE
+                } else if instruction == 0x00FD {
+                    Opcode::OP_00FD
+                } else if instruction == 0x0230 && variant == Variant::Hires {
+                    Opcode::OP_0230
+                } else if instruction == 0x02A0 && variant == Variant::Chip8x {
+                    Opcode::OP_02A0
                 } else {
-                    panic!()
+                    Opcode::OP_0NNN((instruction & 0x0FFF) as usize)
                 }
             }
             0x1000 => Opcode::OP_1MMM((instruction & 0x0FFF) as usize),
@@ -465,7 +2129,11 @@ pub mod chip8 {
                     let (x, y) = decode_xy(instruction);
                     Opcode::OP_5XY0(x, y)
                 }
-                _ => panic!("unknown opcode"),
+                0x0001 if variant == Variant::Chip8x => {
+                    let (x, y) = decode_xy(instruction);
+                    Opcode::OP_5XY1(x, y)
+                }
+                _ => return None,
             },
             0x6000 => {
                 let (x, kk) = decode_xkk(instruction);
@@ -501,27 +2169,31 @@ pub mod chip8 {
                     Opcode::OP_8XY5(x, y)
                 }
                 0x0006 => {
-                    let x = decode_x(instruction);
-                    Opcode::OP_8X16(x)
+                    let (x, y) = decode_xy(instruction);
+                    Opcode::OP_8XY6(x, y)
                 }
                 0x0007 => {
                     let (x, y) = decode_xy(instruction);
                     Opcode::OP_8XY7(x, y)
                 }
                 0x000E => {
-                    let x = decode_x(instruction);
-                    Opcode::OP_8X1E(x)
+                    let (x, y) = decode_xy(instruction);
+                    Opcode::OP_8XYE(x, y)
                 }
-                _ => panic!("unknown opcode"),
+                _ => return None,
             },
             0x9000 => match instruction & 0x000F {
                 0x0000 => {
                     let (x, y) = decode_xy(instruction);
                     Opcode::OP_9XY0(x, y)
                 }
-                _ => panic!("unknown opcode"),
+                _ => return None,
             },
             0xA000 => Opcode::OP_AMMM((instruction & 0x0FFF) as usize),
+            0xB000 if variant == Variant::Chip8x => {
+                let (x, y) = decode_xy(instruction);
+                Opcode::OP_BXYN(x, y, (instruction & 0x000F) as u8)
+            }
             0xB000 => Opcode::OP_BMMM((instruction & 0x0FFF) as usize),
             0xC000 => {
                 let (x, kk) = decode_xkk(instruction);
@@ -535,11 +2207,13 @@ pub mod chip8 {
             0xE000 => match instruction & 0x00FF {
                 0x009E => Opcode::OP_EX9E(decode_x(instruction)),
                 0x00A1 => Opcode::OP_EXA1(decode_x(instruction)),
-                _ => panic!("unknown opcode"),
+                _ => return None,
             },
             0xF000 => {
                 if instruction == 0xF000 {
                     Opcode::OP_F000
+                } else if instruction == 0xF002 {
+                    Opcode::OP_F002
                 } else {
                     match instruction & 0x00FF {
                         0x0007 => Opcode::OP_FX07(decode_x(instruction)),
@@ -550,17 +2224,21 @@ pub mod chip8 {
                         0x001E => Opcode::OP_FX1E(decode_x(instruction)),
                         0x0029 => Opcode::OP_FX29(decode_x(instruction)),
                         0x0033 => Opcode::OP_FX33(decode_x(instruction)),
+                        0x003A => Opcode::OP_FX3A(decode_x(instruction)),
                         0x0055 => Opcode::OP_FX55(decode_x(instruction)),
                         0x0065 => Opcode::OP_FX65(decode_x(instruction)),
+                        0x0075 => Opcode::OP_FX75(decode_x(instruction)),
+                        0x0085 => Opcode::OP_FX85(decode_x(instruction)),
                         0x0070 => Opcode::OP_FX70(decode_x(instruction)),
                         0x0071 => Opcode::OP_FX71(decode_x(instruction)),
                         0x0072 => Opcode::OP_FX72(decode_x(instruction)),
-                        _ => panic!("unknown opcode"),
+                        _ => return None,
                     }
                 }
             }
-            _ => panic!("unknown opcode"),
-        }
+            _ => return None,
+        };
+        Some(opcode)
     }
 
     fn decode_xkk(instruction: u16) -> (usize, u8) {
@@ -581,23 +2259,131 @@ pub mod chip8 {
     #[cfg(test)]
     mod tests {
         use crate::chip8;
+        use crate::chip8::chip8::Variant;
+        use std::cell::RefCell;
+        use std::rc::Rc;
 
-        #[test]
-        fn test_decode() {
-            let result = chip8::chip8::decode(0xA21A);
-            match result {
-                chip8::chip8::Opcode::OP_AMMM(mmm) => {
-                    assert_eq!(mmm, 0x21A);
+        // a machine to run single instructions on, set up and checked in one chain:
+        // TestMachine::new().v(1, 5).v(2, 3).exec(0x8124).assert_v(1, 8)
+        struct TestMachine {
+            chip8: chip8::chip8::Chip8,
+        }
+
+        impl TestMachine {
+            fn new() -> TestMachine {
+                TestMachine {
+                    chip8: chip8::chip8::create_chip8(),
                 }
-                _ => assert!(false, "wrong opcode parsed"),
             }
-            let result = chip8::chip8::decode(0x8F17);
-            match result {
-                chip8::chip8::Opcode::OP_8XY7(x, y) => {
-                    assert_eq!(x, 0xF);
+
+            fn variant(mut self, variant: Variant) -> TestMachine {
+                self.chip8.set_variant(variant);
+                self
+            }
+
+            fn quirks(mut self, set: impl FnOnce(&mut chip8::chip8::Quirks)) -> TestMachine {
+                set(&mut self.chip8.quirks);
+                self
+            }
+
+            fn v(mut self, x: usize, value: u8) -> TestMachine {
+                self.chip8.V[x] = value;
+                self
+            }
+
+            fn i(mut self, value: usize) -> TestMachine {
+                self.chip8.I = value;
+                self
+            }
+
+            fn mem(mut self, address: usize, bytes: &[u8]) -> TestMachine {
+                self.chip8.memory[address..address + bytes.len()].copy_from_slice(bytes);
+                self
+            }
+
+            fn key(mut self, key: u8, pressed: bool) -> TestMachine {
+                self.chip8.set_key(key, pressed);
+                self
+            }
+
+            // the instruction goes at pc too, for the errors that quote it
+            fn run(&mut self, instruction: u16) -> Result<(), chip8::chip8::Chip8Error> {
+                let pc = self.chip8.pc;
+                self.chip8.set_memory(pc, (instruction >> 8) as u8);
+                self.chip8.set_memory(pc + 1, instruction as u8);
+                self.chip8.opcode = chip8::chip8::decode(instruction, self.chip8.variant);
+                self.chip8.execute()
+            }
+
+            fn exec(mut self, instruction: u16) -> TestMachine {
+                if let Err(e) = self.run(instruction) {
+                    panic!("{:04X} failed: {}", instruction, e);
+                }
+                self
+            }
+
+            fn fails(mut self, instruction: u16) -> chip8::chip8::Chip8Error {
+                self.run(instruction).unwrap_err()
+            }
+
+            #[track_caller]
+            fn assert_v(self, x: usize, value: u8) -> TestMachine {
+                assert_eq!(self.chip8.V[x], value, "V{:X}", x);
+                self
+            }
+
+            #[track_caller]
+            fn assert_i(self, value: usize) -> TestMachine {
+                assert_eq!(self.chip8.I, value, "I");
+                self
+            }
+
+            #[track_caller]
+            fn assert_pc(self, value: usize) -> TestMachine {
+                assert_eq!(self.chip8.pc, value, "pc");
+                self
+            }
+
+            #[track_caller]
+            fn assert_mem(self, address: usize, bytes: &[u8]) -> TestMachine {
+                assert_eq!(
+                    self.chip8.memory[address..address + bytes.len()],
+                    *bytes,
+                    "memory at {:03X}",
+                    address
+                );
+                self
+            }
+
+            #[track_caller]
+            fn assert_pixel(self, x: usize, y: usize, lit: bool) -> TestMachine {
+                assert_eq!(self.chip8.framebuffer().get(x, y), lit, "pixel {},{}", x, y);
+                self
+            }
+
+            #[track_caller]
+            fn assert_stack(self, stack: &[usize]) -> TestMachine {
+                assert_eq!(self.chip8.stack(), stack, "stack");
+                self
+            }
+        }
+
+        #[test]
+        fn test_decode() {
+            let result = chip8::chip8::decode(0xA21A, Variant::Chip8);
+            match result {
+                chip8::chip8::Opcode::OP_AMMM(mmm) => {
+                    assert_eq!(mmm, 0x21A);
+                }
+                _ => panic!("wrong opcode parsed"),
+            }
+            let result = chip8::chip8::decode(0x8F17, Variant::Chip8);
+            match result {
+                chip8::chip8::Opcode::OP_8XY7(x, y) => {
+                    assert_eq!(x, 0xF);
                     assert_eq!(y, 0x1);
                 }
-                _ => assert!(false, "wrong opcode parsed"),
+                _ => panic!("wrong opcode parsed"),
             }
         }
 
@@ -606,13 +2392,13 @@ pub mod chip8 {
             let mut emulator = chip8::chip8::create_chip8();
             let x = 0;
             emulator.V[x] = 0x81;
-            emulator.opcode = chip8::chip8::Opcode::OP_8X16(x);
-            emulator.execute();
+            emulator.opcode = chip8::chip8::Opcode::OP_8XY6(x, x);
+            emulator.execute().unwrap();
             assert_eq!(emulator.V[x], 0x40);
             assert_eq!(emulator.V[0xF], 1);
 
             emulator.V[x] = 0xF0;
-            emulator.execute();
+            emulator.execute().unwrap();
             assert_eq!(emulator.V[x], 0x78);
             assert_eq!(emulator.V[0xF], 0);
 
@@ -620,11 +2406,133 @@ pub mod chip8 {
             emulator.opcode = chip8::chip8::Opcode::OP_8XY4(x, y);
             emulator.V[x] = 200;
             emulator.V[y] = 60;
-            emulator.execute();
+            emulator.execute().unwrap();
             assert_eq!(emulator.V[x], 4);
             assert_eq!(emulator.V[0xF], 1);
         }
 
+        #[test]
+        fn test_shift_quirk() {
+            let mut emulator = chip8::chip8::create_chip8();
+            // VX shifted in place
+            emulator.V[1] = 0x81;
+            emulator.V[2] = 0x04;
+            emulator.opcode = chip8::chip8::decode(0x8126, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[1], 0x40);
+            assert_eq!(emulator.V[0xF], 1);
+            emulator.opcode = chip8::chip8::decode(0x812E, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[1], 0x80);
+            assert_eq!(emulator.V[0xF], 0);
+
+            // VY shifted into VX, VY left alone
+            emulator.quirks.shift_vy = true;
+            emulator.opcode = chip8::chip8::decode(0x8126, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[1], 0x02);
+            assert_eq!(emulator.V[2], 0x04);
+            assert_eq!(emulator.V[0xF], 0);
+            emulator.V[2] = 0x81;
+            emulator.opcode = chip8::chip8::decode(0x812E, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[1], 0x02);
+            assert_eq!(emulator.V[2], 0x81);
+            assert_eq!(emulator.V[0xF], 1);
+        }
+
+        #[test]
+        fn test_vf_reset_quirk() {
+            let mut emulator = chip8::chip8::create_chip8();
+            for (instruction, result) in [(0x8011, 0x37), (0x8012, 0x21), (0x8013, 0x16)].iter() {
+                emulator.opcode = chip8::chip8::decode(*instruction, Variant::Chip8);
+                for vf_reset in [false, true].iter() {
+                    emulator.quirks.vf_reset = *vf_reset;
+                    emulator.V[0] = 0x23;
+                    emulator.V[1] = 0x35;
+                    emulator.V[0xF] = 9;
+                    emulator.execute().unwrap();
+                    assert_eq!(emulator.V[0], *result);
+                    assert_eq!(emulator.V[0xF], if *vf_reset { 0 } else { 9 });
+                }
+            }
+        }
+
+        #[test]
+        fn test_display_wait_quirk() {
+            // draw, V0 += 1, again
+            let rom = [0xD0, 0x01, 0x70, 0x01, 0x12, 0x00];
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.load_rom_bytes(&rom).unwrap();
+            for _ in 0..10 {
+                emulator.emulate_cycle().unwrap();
+            }
+            assert_eq!(emulator.V[0], 3);
+
+            emulator.reset();
+            emulator.quirks.display_wait = true;
+            emulator.load_rom_bytes(&rom).unwrap();
+            // the first draw waits for a frame to start
+            emulator.emulate_cycle().unwrap();
+            assert!(emulator.waiting_for_vblank());
+            for frame in 1..=3 {
+                emulator.tick_timers();
+                assert!(!emulator.waiting_for_vblank());
+                for _ in 0..10 {
+                    emulator.emulate_cycle().unwrap();
+                }
+                assert!(emulator.waiting_for_vblank());
+                assert_eq!(emulator.V[0], frame);
+                assert_eq!(emulator.pc(), 0x200);
+            }
+            // the held back draw isn't counted until the frame it runs in
+            assert_eq!(emulator.run_frame().instructions, 0);
+            assert_eq!(emulator.run_frame().instructions, 3);
+            assert_eq!(emulator.V[0], 4);
+        }
+
+        #[test]
+        fn test_quirk_presets() {
+            use chip8::chip8::Quirks;
+
+            let cosmac = Quirks::preset("COSMAC").unwrap();
+            assert!(cosmac.vf_reset && cosmac.shift_vy && cosmac.increment_i);
+            assert!(cosmac.display_wait);
+            assert!(cosmac.clipping && !cosmac.jump_vx);
+            let schip = Quirks::preset("schip").unwrap();
+            assert!(schip.jump_vx && !schip.vf_reset);
+            assert_eq!(Quirks::preset("modern"), Ok(Quirks::default()));
+            assert!(Quirks::preset("eti660").is_err());
+        }
+
+        #[test]
+        fn test_increment_i_quirk() {
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.V[..3].copy_from_slice(&[1, 2, 3]);
+            emulator.I = 0x300;
+            emulator.opcode = chip8::chip8::decode(0xF255, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.I, 0x300);
+            emulator.opcode = chip8::chip8::decode(0xF265, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.I, 0x300);
+
+            emulator.quirks.increment_i = true;
+            emulator.opcode = chip8::chip8::decode(0xF255, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.I, 0x303);
+            // the next store goes after the first
+            emulator.opcode = chip8::chip8::decode(0xF055, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.memory[0x300..0x304], [1, 2, 3, 1]);
+            assert_eq!(emulator.I, 0x304);
+            emulator.I = 0x301;
+            emulator.opcode = chip8::chip8::decode(0xF165, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[..2], [2, 3]);
+            assert_eq!(emulator.I, 0x303);
+        }
+
         #[test]
         fn test_draw() {
             let mut emulator = chip8::chip8::create_chip8();
@@ -637,18 +2545,1510 @@ pub mod chip8 {
             emulator.V[y] = 0;
 
             emulator.opcode = chip8::chip8::Opcode::OP_DXYN(x, y, 2);
-            emulator.execute();
-            assert_eq!(emulator.gfx[0], true);
-            assert_eq!(emulator.gfx[7], true);
-            assert_eq!(emulator.gfx[64], true);
-            assert_eq!(emulator.gfx[71], true);
+            emulator.execute().unwrap();
+            assert!(emulator.framebuffer().get(0, 0));
+            assert!(emulator.framebuffer().get(7, 0));
+            assert!(emulator.framebuffer().get(0, 1));
+            assert!(emulator.framebuffer().get(7, 1));
             assert_eq!(emulator.V[0xF], 0);
-            emulator.execute();
-            assert_eq!(emulator.gfx[0], false);
-            assert_eq!(emulator.gfx[7], false);
+            emulator.execute().unwrap();
+            assert!(!emulator.framebuffer().get(0, 0));
+            assert!(!emulator.framebuffer().get(7, 0));
 
-            assert_eq!(emulator.gfx[71], false);
+            assert!(!emulator.framebuffer().get(7, 1));
             assert_eq!(emulator.V[0xF], 1);
         }
+
+        #[test]
+        fn test_predecode() {
+            let mut emulator = chip8::chip8::Chip8::builder().predecode(true).build();
+            // 0x200: V0 += 1, 0x202: I = 0x206, 0x204: jump 0x200, 0x206: data
+            emulator
+                .load_rom_bytes(&[0x70, 0x01, 0xA2, 0x06, 0x12, 0x00, 0xFF, 0xFF])
+                .unwrap();
+            assert_eq!(
+                emulator.predecoded(0x200),
+                Some(chip8::chip8::Opcode::OP_7XKK(0, 1))
+            );
+            // data that doesn't decode is simply not in the table
+            assert_eq!(emulator.predecoded(0x206), None);
+            for _ in 0..6 {
+                emulator.emulate_cycle().unwrap();
+            }
+            let stats = emulator.decode_cache_stats().unwrap();
+            assert_eq!((stats.hits, stats.misses), (6, 0));
+
+            // self-modifying code: V0 += 1 becomes V0 += 5
+            emulator.set_memory(0x201, 0x05);
+            assert_eq!(emulator.predecoded(0x200), None);
+            assert_eq!(
+                emulator.predecoded(0x202),
+                Some(chip8::chip8::Opcode::OP_AMMM(0x206))
+            );
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.registers()[0], 7);
+            let stats = emulator.decode_cache_stats().unwrap();
+            assert_eq!((stats.hits, stats.misses), (6, 1));
+            assert_eq!(stats.hit_rate(), 6.0 / 7.0);
+
+            assert_eq!(chip8::chip8::create_chip8().decode_cache_stats(), None);
+        }
+
+        #[test]
+        fn test_setters() {
+            let mut emulator = chip8::chip8::create_chip8();
+            // call 0x204, which returns
+            emulator
+                .load_rom_bytes(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE])
+                .unwrap();
+            emulator.emulate_cycle().unwrap();
+            emulator.set_sp(0);
+            assert!(emulator.stack().is_empty());
+            assert!(emulator.emulate_cycle().is_err());
+            emulator.set_sp(100);
+            assert_eq!(emulator.stack().len(), chip8::chip8::STACK_SIZE);
+            emulator.set_sp(1);
+            assert_eq!(emulator.stack(), &[0x200]);
+
+            emulator.set_pc(0x204);
+            emulator.set_index(0x1234);
+            emulator.set_register(0x1F, 7);
+            emulator.set_delay_timer(9);
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.pc(), 0x202);
+            assert_eq!(emulator.index(), 0x234);
+            assert_eq!(emulator.registers()[0xF], 7);
+            assert_eq!(emulator.delay_timer(), 9);
+        }
+
+        #[test]
+        fn test_state_hash() {
+            let mut a = chip8::chip8::create_chip8();
+            let mut b = chip8::chip8::create_chip8();
+            assert_eq!(a.state_hash(), b.state_hash());
+            a.set_register(3, 1);
+            assert_ne!(a.state_hash(), b.state_hash());
+            b.set_register(3, 1);
+            b.set_pixel(0, 0, true);
+            assert_ne!(a.state_hash(), b.state_hash());
+        }
+
+        #[test]
+        fn test_snapshot() {
+            let mut emulator = chip8::chip8::create_chip8();
+            // call 0x204, which sets V0 and loops
+            emulator
+                .load_rom_bytes(&[0x22, 0x04, 0x00, 0x00, 0x60, 0x2A, 0x12, 0x06])
+                .unwrap();
+            emulator.emulate_cycle().unwrap();
+            emulator.set_pixel(3, 4, true);
+            emulator.set_delay_timer(30);
+            let snapshot = emulator.snapshot();
+            let hash = emulator.state_hash();
+            emulator.emulate_cycle().unwrap();
+            emulator.reset();
+            assert_ne!(emulator.state_hash(), hash);
+
+            emulator.restore(&snapshot).unwrap();
+            assert_eq!(emulator.state_hash(), hash);
+            assert_eq!(emulator.stack(), &[0x200]);
+            assert!(emulator.framebuffer().get(3, 4));
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.registers()[0], 0x2A);
+
+            let mut hires = snapshot.clone();
+            hires.variant = chip8::chip8::Variant::Hires;
+            assert!(emulator.restore(&hires).is_err());
+            assert_eq!(emulator.registers()[0], 0x2A);
+        }
+
+        #[test]
+        fn test_exit() {
+            use chip8::chip8::Status;
+
+            let mut emulator = chip8::chip8::create_chip8();
+            // V0 = 1, exit, V0 = 2
+            emulator
+                .load_rom_bytes(&[0x60, 0x01, 0x00, 0xFD, 0x60, 0x02])
+                .unwrap();
+            assert_eq!(emulator.emulate_cycle(), Ok(Status::Running));
+            assert_eq!(emulator.emulate_cycle(), Ok(Status::Exited));
+            assert!(emulator.has_exited());
+            assert_eq!(emulator.emulate_cycle(), Ok(Status::Exited));
+            assert_eq!(emulator.V[0], 1);
+
+            emulator.reset();
+            assert!(!emulator.has_exited());
+        }
+
+        #[test]
+        fn test_run_frame() {
+            use chip8::chip8::{Chip8, Chip8Error, Event};
+
+            // V0 = 2, sound timer = V0, clear the screen, exit
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator
+                .load_rom_bytes(&[0x60, 0x02, 0xF0, 0x18, 0x00, 0xE0, 0x00, 0xFD])
+                .unwrap();
+            let output = emulator.run_frame();
+            assert_eq!(
+                output.events,
+                [Event::SoundStarted, Event::DisplayUpdated, Event::Exited]
+            );
+            assert_eq!(output.instructions, 3);
+            assert!(!emulator.draw);
+
+            // wait for a key in V1, then loop. the timer runs out during the wait
+            emulator.reset();
+            emulator
+                .load_rom_bytes(&[0x60, 0x02, 0xF0, 0x18, 0xF1, 0x0A, 0x12, 0x06])
+                .unwrap();
+            let output = emulator.run_frame();
+            assert_eq!(
+                output.events,
+                [
+                    Event::SoundStarted,
+                    Event::DisplayUpdated,
+                    Event::WaitingForKey
+                ]
+            );
+            assert_eq!(output.instructions, 3);
+            let output = emulator.run_frame();
+            assert_eq!(output.events, [Event::SoundStopped, Event::WaitingForKey]);
+            assert_eq!(output.instructions, 0);
+
+            // return without a call
+            emulator.reset();
+            emulator.load_rom_bytes(&[0x00, 0xEE]).unwrap();
+            let output = emulator.run_frame();
+            assert!(output.contains(&Event::Error(Chip8Error::StackUnderflow { pc: 0x200 })));
+
+            // 1.5 instructions a frame: V0 += 1 in a loop
+            let mut emulator = Chip8::builder().speed(90).build();
+            emulator.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+            let instructions: Vec<u64> =
+                (0..4).map(|_| emulator.run_frame().instructions).collect();
+            assert_eq!(instructions, [1, 2, 1, 2]);
+            assert_eq!(emulator.V[0], 3);
+            assert_eq!(emulator.speed(), 90);
+        }
+
+        #[test]
+        fn test_frame_budget() {
+            use chip8::chip8::{Chip8, FrameBudget, Timing};
+
+            // what doesn't divide evenly carries over, so 500 a second really is 500
+            let chip8 = Chip8::builder().speed(500).build();
+            let mut budget = FrameBudget::default();
+            let frame = |budget: &mut FrameBudget, chip8: &Chip8| {
+                budget.start(chip8);
+                let mut instructions = 0;
+                while budget.has_time() {
+                    budget.spend(chip8);
+                    instructions += 1;
+                }
+                instructions
+            };
+            let second: Vec<u64> = (0..60).map(|_| frame(&mut budget, &chip8)).collect();
+            assert_eq!(second.iter().sum::<u64>(), 500);
+            assert!(second.iter().all(|cycles| *cycles == 8 || *cycles == 9));
+            assert_eq!(budget, FrameBudget::default());
+            assert_eq!(frame(&mut budget, &Chip8::builder().build()), 14);
+
+            // V0 = 1 (27us) and a jump back (105us) for a second, whatever the speed
+            let mut chip8 = Chip8::builder().speed(1).timing(Timing::Vip).build();
+            chip8.load_rom_bytes(&[0x60, 0x01, 0x12, 0x00]).unwrap();
+            let instructions: u64 = (0..60).map(|_| chip8.run_frame().instructions).sum();
+            let spent = instructions / 2 * (27 + 105) + instructions % 2 * 27;
+            assert!((1_000_000 - 105..1_000_000 + 105).contains(&spent));
+            // a sprite takes more than a frame, the next ones pay for what it overspent until
+            // one has no time left at all
+            let mut chip8 = Chip8::builder().timing(Timing::Vip).build();
+            chip8.load_rom_bytes(&[0xD0, 0x15, 0x12, 0x00]).unwrap();
+            let instructions: Vec<u64> = (0..4).map(|_| chip8.run_frame().instructions).collect();
+            assert_eq!(instructions, [1, 2, 2, 0]);
+            assert_eq!(chip8.timing(), Timing::Vip);
+            chip8.reset();
+            assert_eq!(chip8.timing(), Timing::Vip);
+        }
+
+        #[test]
+        fn test_run_for() {
+            use chip8::chip8::{Chip8, Event, RunLimit};
+            use std::time::Duration;
+
+            // 2 instructions a frame: V0 += 1 in a loop
+            let mut emulator = Chip8::builder().speed(120).build();
+            emulator.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+            // a third of a frame runs nothing, the rest carries over
+            let output = emulator.run_for(RunLimit::Time(Duration::from_millis(5)));
+            assert_eq!((output.frames, output.instructions), (0, 0));
+            let output = emulator.run_for(RunLimit::Time(Duration::from_millis(30)));
+            assert_eq!((output.frames, output.instructions), (2, 4));
+            assert_eq!(emulator.V[0], 2);
+
+            // a frame and a half, the rest of the frame goes first next time
+            let output = emulator.run_for(RunLimit::Cycles(3));
+            assert_eq!((output.frames, output.instructions), (1, 3));
+            let output = emulator.run_for(RunLimit::Cycles(1));
+            assert_eq!((output.frames, output.instructions), (1, 1));
+            assert_eq!(emulator.V[0], 4);
+
+            // waiting for a key, cycles don't run out but it returns after a frame that ran none
+            emulator.reset();
+            emulator
+                .load_rom_bytes(&[0x60, 0x02, 0xF0, 0x18, 0xF1, 0x0A])
+                .unwrap();
+            let output = emulator.run_for(RunLimit::Cycles(100));
+            assert_eq!((output.frames, output.instructions), (3, 3));
+            assert_eq!(
+                output.events,
+                [
+                    Event::SoundStarted,
+                    Event::DisplayUpdated,
+                    Event::SoundStopped,
+                    Event::WaitingForKey
+                ]
+            );
+
+            // stops at an exit
+            emulator.reset();
+            emulator.load_rom_bytes(&[0x00, 0xFD]).unwrap();
+            let output = emulator.run_for(RunLimit::Time(Duration::from_secs(1)));
+            assert_eq!(output.frames, 0);
+            assert!(output.contains(&Event::Exited));
+        }
+
+        #[test]
+        fn test_key_wait() {
+            use chip8::chip8::KeyWait;
+
+            let mut emulator = chip8::chip8::create_chip8();
+            // key 5 is already held. delay timer = 3, wait for a key in V2, V3 = 1
+            emulator.set_key(5, true);
+            let held: Vec<usize> = (0..16).filter(|key| emulator.keys()[*key]).collect();
+            assert_eq!(held, [5]);
+            emulator.V[0] = 3;
+            emulator
+                .load_rom_bytes(&[0xF0, 0x15, 0xF2, 0x0A, 0x63, 0x01])
+                .unwrap();
+            emulator.emulate_cycle().unwrap();
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.key_wait(), Some(KeyWait::Press(2)));
+
+            // nothing runs while waiting, but time goes on
+            emulator.emulate_cycle().unwrap();
+            emulator.tick_timers();
+            assert_eq!(emulator.pc(), 0x204);
+            assert_eq!(emulator.delay_timer(), 2);
+
+            // the held key has to be let go and pressed again, and only its release counts
+            emulator.set_key(5, false);
+            assert_eq!(emulator.key_wait(), Some(KeyWait::Press(2)));
+            emulator.set_key(7, true);
+            emulator.set_key(5, true);
+            emulator.set_key(5, false);
+            assert_eq!(emulator.key_wait(), Some(KeyWait::Release(2, 7)));
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.V[3], 0);
+
+            emulator.set_key(7, false);
+            assert_eq!(emulator.key_wait(), None);
+            assert_eq!(emulator.V[2], 7);
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.V[3], 1);
+        }
+
+        #[test]
+        fn test_dirty_rows() {
+            let mut emulator = chip8::chip8::create_chip8();
+            let dirty = |emulator: &chip8::chip8::Chip8| emulator.dirty_rows().collect::<Vec<_>>();
+            assert!(dirty(&emulator).is_empty());
+            // a sprite with an empty second row, drawn at y = 10
+            emulator.I = 0x300;
+            emulator.memory[0x300..0x303].copy_from_slice(&[0x80, 0x00, 0x01]);
+            emulator.V[1] = 10;
+            emulator.opcode = chip8::chip8::Opcode::OP_DXYN(0, 1, 3);
+            emulator.execute().unwrap();
+            assert_eq!(dirty(&emulator), vec![10, 12]);
+
+            emulator.clear_dirty_rows();
+            assert!(dirty(&emulator).is_empty());
+            // only rows that had something on them change when clearing
+            emulator.opcode = chip8::chip8::Opcode::OP_00E0;
+            emulator.execute().unwrap();
+            assert_eq!(dirty(&emulator), vec![10, 12]);
+
+            emulator.clear_dirty_rows();
+            emulator.set_variant(Variant::Hires);
+            assert_eq!(dirty(&emulator).len(), 64);
+        }
+
+        #[derive(Default)]
+        struct RecordingHook {
+            executed: Vec<usize>,
+            display_updates: usize,
+            sound_events: Vec<bool>,
+            memory_writes: Vec<(usize, u8)>,
+        }
+
+        impl chip8::chip8::Hooks for RecordingHook {
+            fn before_instruction(
+                &mut self,
+                chip8: &chip8::chip8::Chip8,
+                _opcode: &chip8::chip8::Opcode,
+            ) {
+                self.executed.push(chip8.pc());
+            }
+            fn on_display(&mut self, _chip8: &chip8::chip8::Chip8) {
+                self.display_updates += 1;
+            }
+            fn on_sound(&mut self, _chip8: &chip8::chip8::Chip8, playing: bool) {
+                self.sound_events.push(playing);
+            }
+            fn on_memory_write(&mut self, _chip8: &chip8::chip8::Chip8, address: usize, value: u8) {
+                self.memory_writes.push((address, value));
+            }
+        }
+
+        #[test]
+        fn test_hooks() {
+            let mut emulator = chip8::chip8::create_chip8();
+            let hook = Rc::new(RefCell::new(RecordingHook::default()));
+            emulator.add_hook(Box::new(hook.clone()));
+            // V0 = 1, sound timer = V0, clear screen, I = 0x300, store V0
+            let program = [0x60, 0x01, 0xF0, 0x18, 0x00, 0xE0, 0xA3, 0x00, 0xF0, 0x55];
+            emulator.memory[0x200..0x20A].copy_from_slice(&program);
+            for _ in 0..5 {
+                emulator.emulate_cycle().unwrap();
+            }
+            emulator.tick_timers();
+
+            let hook = hook.borrow();
+            assert_eq!(hook.executed, vec![0x200, 0x202, 0x204, 0x206, 0x208]);
+            assert_eq!(hook.display_updates, 1);
+            assert_eq!(hook.sound_events, vec![true, false]);
+            assert_eq!(hook.memory_writes, vec![(0x300, 1)]);
+        }
+
+        // a serial port: FX70 sends VX, FX71 receives into VX, stores to 0xF00 send
+        // too and loads from it count what was sent
+        #[derive(Default)]
+        struct Serial {
+            sent: Vec<u8>,
+            received: Vec<u8>,
+        }
+
+        impl chip8::chip8::Peripheral for Serial {
+            #[allow(clippy::single_range_in_vec_init)]
+            fn ranges(&self) -> Vec<std::ops::Range<usize>> {
+                vec![0xF00..0xF01]
+            }
+            fn read(&mut self, _address: usize) -> u8 {
+                self.sent.len() as u8
+            }
+            fn write(&mut self, _address: usize, value: u8) {
+                self.sent.push(value);
+            }
+            fn instruction(&mut self, opcode: chip8::chip8::Opcode, vx: u8) -> Option<u8> {
+                match opcode {
+                    chip8::chip8::Opcode::OP_FX70(_) => {
+                        self.sent.push(vx);
+                        Some(vx)
+                    }
+                    chip8::chip8::Opcode::OP_FX71(_) => Some(self.received.pop().unwrap_or(0)),
+                    _ => None,
+                }
+            }
+        }
+
+        #[test]
+        fn test_peripheral() {
+            use chip8::chip8::{Chip8, Chip8Error};
+
+            let serial = Rc::new(RefCell::new(Serial {
+                received: vec![0x42],
+                ..Serial::default()
+            }));
+            let mut emulator = Chip8::builder()
+                .peripheral(Box::new(serial.clone()))
+                .build();
+            // V0 = 7, send V0, receive into V1, I = 0xF00, store V0 and V1 there, load them back
+            let program = [
+                0x60, 0x07, 0xF0, 0x70, 0xF1, 0x71, 0xAF, 0x00, 0xF1, 0x55, 0xF1, 0x65, 0xF0, 0x72,
+            ];
+            emulator.load_rom_bytes(&program).unwrap();
+            for _ in 0..3 {
+                emulator.emulate_cycle().unwrap();
+            }
+            assert_eq!(emulator.V[1], 0x42);
+            emulator.emulate_cycle().unwrap();
+            emulator.emulate_cycle().unwrap();
+            // V0 went to the port, V1 to the RAM after it
+            assert_eq!(serial.borrow().sent, vec![0x07, 0x07]);
+            assert_eq!(emulator.memory[0xF00], 0);
+            assert_eq!(emulator.memory[0xF01], 0x42);
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.V[0], 2);
+            assert_eq!(emulator.V[1], 0x42);
+            // nothing handles FX72
+            assert_eq!(
+                emulator.emulate_cycle(),
+                Err(Chip8Error::UnknownOpcode {
+                    opcode: 0xF072,
+                    pc: 0x20C
+                })
+            );
+        }
+
+        #[test]
+        fn test_load_rom_bytes() {
+            let mut emulator = chip8::chip8::create_chip8();
+            let rom = vec![0xAB; chip8::chip8::MAX_ROM_SIZE];
+            emulator.load_rom_bytes(&rom).unwrap();
+            assert_eq!(emulator.memory[0x200], 0xAB);
+            assert_eq!(emulator.memory[0xFFF], 0xAB);
+
+            let rom = vec![0xAB; chip8::chip8::MAX_ROM_SIZE + 1];
+            assert_eq!(
+                emulator.load_rom_bytes(&rom),
+                Err(chip8::chip8::Chip8Error::RomTooLarge {
+                    size: 3585,
+                    max: 3584
+                })
+            );
+        }
+
+        #[test]
+        fn test_layout() {
+            use chip8::chip8::{Chip8, Chip8Error, Layout, Variant};
+
+            assert_eq!(Layout::preset("ETI660"), Ok(Layout::ETI660));
+            assert!(Layout::preset("eti").is_err());
+            assert!(Layout {
+                load: 0x100,
+                start: 0x100
+            }
+            .check()
+            .is_err());
+            assert!(Layout {
+                load: 0x600,
+                start: 0x1000
+            }
+            .check()
+            .is_err());
+
+            // loaded at 0x600, started past the header
+            let layout = Layout {
+                load: 0x600,
+                start: 0x604,
+            };
+            let mut emulator = Chip8::builder().layout(layout).build();
+            assert_eq!(emulator.pc(), 0x604);
+            emulator
+                .load_rom_bytes(&[0x00, 0x00, 0x00, 0x00, 0x60, 0x2A])
+                .unwrap();
+            assert_eq!(emulator.memory[0x604], 0x60);
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.V[0], 0x2A);
+            assert_eq!(
+                emulator.load_rom_bytes(&vec![0; 0xA01]),
+                Err(Chip8Error::RomTooLarge {
+                    size: 0xA01,
+                    max: 0xA00
+                })
+            );
+
+            // kept over resets and snapshots, the variant's own comes back with set_variant
+            emulator.reset();
+            assert_eq!((emulator.layout(), emulator.pc()), (layout, 0x604));
+            let snapshot = emulator.snapshot();
+            emulator.restore(&snapshot).unwrap();
+            assert_eq!(emulator.layout(), layout);
+            emulator.set_variant(Variant::Chip8x);
+            assert_eq!((emulator.layout(), emulator.pc()), (Layout::CHIP8X, 0x300));
+        }
+
+        #[test]
+        fn test_bad_instructions() {
+            use chip8::chip8::Chip8Error;
+
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.load_rom_bytes(&[0xE0, 0xFF, 0xF0, 0x00]).unwrap();
+            assert_eq!(
+                emulator.emulate_cycle(),
+                Err(Chip8Error::UnknownOpcode {
+                    opcode: 0xE0FF,
+                    pc: 0x200
+                })
+            );
+            emulator.set_pc(0x202);
+            assert_eq!(
+                emulator.emulate_cycle(),
+                Err(Chip8Error::Unsupported {
+                    opcode: 0xF000,
+                    pc: 0x202
+                })
+            );
+        }
+
+        // what fuzz/ does at length: whatever the ROM, the interpreter fails with an error
+        // rather than a panic
+        #[test]
+        fn test_random_roms() {
+            use chip8::chip8::{MachineCodePolicy, MemoryPolicy, UnknownOpcodePolicy};
+            use rand::rngs::StdRng;
+            use rand::{Rng, SeedableRng};
+
+            // a machine per round: variant, predecoding and the policies all take turns, and
+            // every 24 rounds go through each combination of the policies
+            let run = |rom: &[u8], round: usize, rng: &mut StdRng| {
+                let mut emulator = chip8::chip8::Chip8::builder()
+                    .variant([Variant::Chip8, Variant::Hires, Variant::Chip8x][round % 3])
+                    .predecode(round.is_multiple_of(2))
+                    .build();
+                emulator.quirks.memory = match round % 4 < 2 {
+                    true => MemoryPolicy::Wrap,
+                    false => MemoryPolicy::Fault,
+                };
+                // Fault stops at the first word that isn't code, the others run on past it
+                emulator.quirks.unknown_opcode = [
+                    UnknownOpcodePolicy::Fault,
+                    UnknownOpcodePolicy::Skip,
+                    UnknownOpcodePolicy::Warn,
+                ][round / 4 % 3];
+                emulator.quirks.machine_code = match round / 12 % 2 {
+                    0 => MachineCodePolicy::Fault,
+                    _ => MachineCodePolicy::Ignore,
+                };
+                emulator.load_rom_bytes(rom).unwrap();
+                for cycle in 0..1000 {
+                    if cycle % 14 == 0 {
+                        emulator.tick_timers();
+                        emulator.set_key(rng.gen(), rng.gen());
+                    }
+                    if emulator.emulate_cycle().is_err() {
+                        break;
+                    }
+                }
+            };
+
+            let mut rng = StdRng::seed_from_u64(1);
+            // instructions that index with a register or I, with VX = 0xFF and I at the very end
+            // of memory, looping. random ROMs rarely get that far before an error
+            for instruction in [
+                0xE09E, 0xE0A1, 0xF029, 0xF030, 0xF033, 0xFF55, 0xFF65, 0xF01E, 0xD01F,
+            ]
+            .iter()
+            {
+                for i in [0xFFEu16, 0xFFF].iter() {
+                    let rom = [
+                        0x60,
+                        0xFF,
+                        0xA0 | (i >> 8) as u8,
+                        *i as u8,
+                        (instruction >> 8) as u8,
+                        *instruction as u8,
+                        0x12,
+                        0x00,
+                    ];
+                    for round in 0..24 {
+                        run(&rom, round, &mut rng);
+                    }
+                }
+            }
+            for round in 0..200 {
+                let rom: Vec<u8> = (0..rng.gen_range(1..512)).map(|_| rng.gen()).collect();
+                run(&rom, round, &mut rng);
+            }
+        }
+
+        #[test]
+        fn test_key_past_f() {
+            // VX = 0xFF is key F, it used to index past the 16 keys
+            for (skip_op, skipped_when_down) in [(0x9E, true), (0xA1, false)].iter() {
+                for pressed in [false, true].iter() {
+                    let mut emulator = chip8::chip8::create_chip8();
+                    emulator
+                        .load_rom_bytes(&[0x60, 0xFF, 0xE0, *skip_op])
+                        .unwrap();
+                    emulator.set_key(0xF, *pressed);
+                    emulator.emulate_cycle().unwrap();
+                    emulator.emulate_cycle().unwrap();
+                    let skipped = pressed == skipped_when_down;
+                    assert_eq!(emulator.pc, if skipped { 0x206 } else { 0x204 });
+                }
+            }
+        }
+
+        #[test]
+        fn test_reset() {
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.quirks.clipping = false;
+            emulator.load_rom_bytes(&[0x60, 0x2A, 0x00, 0xE0]).unwrap();
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.V[0], 0x2A);
+            emulator.reset();
+            assert_eq!(emulator.V[0], 0);
+            assert_eq!(emulator.pc, 0x200);
+            assert_eq!(emulator.memory[0x200], 0);
+            // the font survives, settings too
+            assert_eq!(emulator.memory[0], 0xF0);
+            assert!(!emulator.quirks.clipping);
+        }
+
+        #[test]
+        fn test_hires() {
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.set_variant(Variant::Hires);
+            assert_eq!(emulator.framebuffer().size(), (64, 64));
+            emulator.load_rom_bytes(&[0x12, 0x60]).unwrap();
+            // straight past the interpreter
+            assert_eq!(emulator.memory[0x200..0x202], [0x12, 0xC0]);
+
+            // below the standard 32 rows
+            emulator.I = 0;
+            emulator.V[1] = 60;
+            emulator.opcode = chip8::chip8::Opcode::OP_DXYN(0, 1, 1);
+            emulator.execute().unwrap();
+            assert!(emulator.framebuffer().get(0, 60));
+            assert_eq!(
+                chip8::chip8::decode(0x0230, Variant::Hires),
+                chip8::chip8::Opcode::OP_0230
+            );
+            emulator.opcode = chip8::chip8::Opcode::OP_0230;
+            emulator.execute().unwrap();
+            assert!(emulator.framebuffer().pixels().all(|pixel| !pixel));
+
+            // the variant survives a reset
+            emulator.reset();
+            assert_eq!(emulator.variant(), Variant::Hires);
+            assert_eq!(emulator.framebuffer().size(), (64, 64));
+        }
+
+        #[test]
+        fn test_chip8x() {
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.set_variant(Variant::Chip8x);
+            assert_eq!(emulator.pc, 0x300);
+            emulator.load_rom_bytes(&[0xB1, 0x20]).unwrap();
+            assert_eq!(emulator.memory[0x300], 0xB1);
+            assert_eq!(
+                chip8::chip8::decode(0xB123, Variant::Chip8x),
+                chip8::chip8::Opcode::OP_BXYN(1, 2, 3)
+            );
+            assert_eq!(
+                chip8::chip8::decode(0xB123, Variant::Chip8),
+                chip8::chip8::Opcode::OP_BMMM(0x123)
+            );
+
+            // columns 1 to 2, zone rows 0 to 1 (pixel rows 0 to 7) in color 5
+            emulator.V[1] = 0x21;
+            emulator.V[2] = 0x10;
+            emulator.V[3] = 5;
+            emulator.opcode = chip8::chip8::Opcode::OP_BXYN(1, 3, 0);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.foreground_color(8, 0), 5);
+            assert_eq!(emulator.foreground_color(23, 7), 5);
+            assert_eq!(emulator.foreground_color(24, 7), 1);
+            assert_eq!(emulator.foreground_color(8, 8), 1);
+            // two pixel rows from row 20 in the column holding x = 40
+            emulator.V[1] = 40;
+            emulator.V[2] = 20;
+            emulator.opcode = chip8::chip8::Opcode::OP_BXYN(1, 3, 2);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.foreground_color(47, 21), 5);
+            assert_eq!(emulator.foreground_color(47, 22), 1);
+
+            emulator.opcode = chip8::chip8::Opcode::OP_02A0;
+            for _ in 0..5 {
+                emulator.execute().unwrap();
+            }
+            assert_eq!(emulator.background_color(), 1);
+
+            emulator.V[4] = 0x76;
+            emulator.V[5] = 0x13;
+            emulator.opcode = chip8::chip8::Opcode::OP_5XY1(4, 5);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[4], 0x01);
+        }
+
+        #[test]
+        fn test_rpl_flags() {
+            let mut emulator = chip8::chip8::create_chip8();
+            for i in 0..4 {
+                emulator.V[i] = 10 + i as u8;
+            }
+            emulator.opcode = chip8::chip8::decode(0xF275, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.rpl_flags[..4], [10, 11, 12, 0]);
+
+            emulator.V = [0; 16];
+            emulator.opcode = chip8::chip8::decode(0xF185, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[..3], [10, 11, 0]);
+        }
+
+        #[test]
+        fn test_variant_names() {
+            assert_eq!("CHIP8X".parse(), Ok(Variant::Chip8x));
+            assert!("schip".parse::<Variant>().is_err());
+        }
+
+        #[test]
+        fn test_builder() {
+            use rand::rngs::StdRng;
+            use rand::SeedableRng;
+
+            let random_byte = |chip8: &mut chip8::chip8::Chip8| {
+                // VA = random & 0xFF
+                chip8.load_rom_bytes(&[0xCA, 0xFF]).unwrap();
+                chip8.emulate_cycle().unwrap();
+                chip8.registers()[0xA]
+            };
+            let quirks = chip8::chip8::Quirks {
+                clipping: false,
+                ..Default::default()
+            };
+            let mut first = chip8::chip8::Chip8::builder()
+                .variant(Variant::Hires)
+                .quirks(quirks)
+                .rng(StdRng::seed_from_u64(7))
+                .build();
+            assert_eq!(first.variant(), Variant::Hires);
+            assert_eq!(first.quirks, quirks);
+            let mut second = chip8::chip8::Chip8::builder()
+                .rng(StdRng::seed_from_u64(7))
+                .build();
+            assert_eq!(second.variant(), Variant::Chip8);
+            assert_eq!(random_byte(&mut first), random_byte(&mut second));
+        }
+
+        #[test]
+        fn test_stack_errors() {
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.opcode = chip8::chip8::decode(0x00EE, Variant::Chip8);
+            assert_eq!(
+                emulator.execute(),
+                Err(chip8::chip8::Chip8Error::StackUnderflow { pc: 0x200 })
+            );
+
+            emulator.opcode = chip8::chip8::decode(0x2200, Variant::Chip8);
+            for _ in 0..16 {
+                emulator.execute().unwrap();
+            }
+            let error = emulator.execute().unwrap_err();
+            assert_eq!(
+                error,
+                chip8::chip8::Chip8Error::StackOverflow {
+                    pc: 0x200,
+                    stack: vec![0x200; 16]
+                }
+            );
+            assert_eq!(emulator.sp, 16);
+        }
+
+        #[test]
+        fn test_memory_policy() {
+            use chip8::chip8::{Chip8Error, MemoryPolicy};
+
+            let mut emulator = chip8::chip8::create_chip8();
+            // V0 = 1, V1 = 2 stored at 0xFFF and, wrapped around, 0x000
+            emulator.V[0] = 1;
+            emulator.V[1] = 2;
+            emulator.I = 0xFFF;
+            emulator.opcode = chip8::chip8::decode(0xF155, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.memory[0xFFF], 1);
+            assert_eq!(emulator.memory[0], 2);
+            emulator.opcode = chip8::chip8::decode(0xF165, Variant::Chip8);
+            emulator.V = [0; 16];
+            emulator.execute().unwrap();
+            assert_eq!(emulator.V[..2], [1, 2]);
+            // a jump past the end lands at the start, where V0 = 0x2A waits
+            emulator.V[0] = 0x10;
+            emulator.pc = 0x200;
+            emulator.load_rom_bytes(&[0xBF, 0xF8]).unwrap();
+            emulator.memory[0x008..0x00A].copy_from_slice(&[0x60, 0x2A]);
+            emulator.emulate_cycle().unwrap();
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.V[0], 0x2A);
+            assert_eq!(emulator.pc, 0x00A);
+
+            emulator.reset();
+            emulator.quirks.memory = MemoryPolicy::Fault;
+            emulator.I = 0xFFE;
+            emulator.pc = 0x2A0;
+            emulator.opcode = chip8::chip8::decode(0xD005, Variant::Chip8);
+            assert_eq!(
+                emulator.execute(),
+                Err(Chip8Error::MemoryFault {
+                    address: 0x1000,
+                    pc: 0x2A0
+                })
+            );
+            emulator.opcode = chip8::chip8::decode(0xF255, Variant::Chip8);
+            assert!(emulator.execute().is_err());
+            // nothing wrapped around onto the font
+            assert_eq!(emulator.memory[0], 0xF0);
+            emulator.pc = 0xFFF;
+            assert_eq!(
+                emulator.emulate_cycle(),
+                Err(Chip8Error::MemoryFault {
+                    address: 0x1000,
+                    pc: 0xFFF
+                })
+            );
+        }
+
+        #[test]
+        fn test_machine_code() {
+            use chip8::chip8::{Chip8Error, MachineCodePolicy, Opcode};
+
+            assert_eq!(
+                chip8::chip8::decode(0x0123, Variant::Chip8),
+                Opcode::OP_0NNN(0x123)
+            );
+            // only the hires interpreter has 0230
+            assert_eq!(
+                chip8::chip8::decode(0x0230, Variant::Chip8),
+                Opcode::OP_0NNN(0x230)
+            );
+            // call 0x123 and carry on to V0 = 7
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.load_rom_bytes(&[0x01, 0x23, 0x60, 0x07]).unwrap();
+            emulator.emulate_cycle().unwrap();
+            emulator.emulate_cycle().unwrap();
+            assert_eq!(emulator.V[0], 7);
+            assert!(emulator.machine_code_calls.contains(&0x123));
+
+            emulator.reset();
+            assert!(emulator.machine_code_calls.is_empty());
+            emulator.quirks.machine_code = MachineCodePolicy::Fault;
+            emulator.load_rom_bytes(&[0x01, 0x23, 0x60, 0x07]).unwrap();
+            let error = emulator.emulate_cycle().unwrap_err();
+            assert_eq!(
+                error,
+                Chip8Error::MachineCode {
+                    address: 0x123,
+                    pc: 0x200
+                }
+            );
+            assert_eq!(
+                error.to_string(),
+                "call to 1802 machine code at 0x123 from 0x200"
+            );
+            assert_eq!("FAULT".parse(), Ok(MachineCodePolicy::Fault));
+        }
+
+        #[test]
+        fn test_unknown_opcode() {
+            use chip8::chip8::{Chip8Error, UnknownOpcodePolicy};
+
+            // E0FF isn't an instruction, and nothing is attached to take F070
+            let rom = [0xE0, 0xFF, 0xF0, 0x70, 0x60, 0x07];
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.load_rom_bytes(&rom).unwrap();
+            let error = emulator.emulate_cycle().unwrap_err();
+            assert_eq!(
+                error,
+                Chip8Error::UnknownOpcode {
+                    opcode: 0xE0FF,
+                    pc: 0x200
+                }
+            );
+            assert_eq!(error.to_string(), "unknown instruction E0FF at 0x200");
+
+            for policy in [UnknownOpcodePolicy::Skip, UnknownOpcodePolicy::Warn] {
+                emulator.reset();
+                emulator.quirks.unknown_opcode = policy;
+                emulator.load_rom_bytes(&rom).unwrap();
+                for _ in 0..3 {
+                    emulator.emulate_cycle().unwrap();
+                }
+                assert_eq!(emulator.V[0], 7);
+                assert_eq!(emulator.pc(), 0x206);
+                let warned = policy == UnknownOpcodePolicy::Warn;
+                assert_eq!(emulator.unknown_opcodes.contains(&0x200), warned);
+                assert_eq!(emulator.unknown_opcodes.contains(&0x202), warned);
+            }
+            assert_eq!("Warn".parse(), Ok(UnknownOpcodePolicy::Warn));
+            assert!("nop".parse::<UnknownOpcodePolicy>().is_err());
+        }
+
+        #[test]
+        fn test_audio_pattern() {
+            let mut emulator = chip8::chip8::create_chip8();
+            assert!(emulator.audio_pattern.is_none());
+            assert_eq!(emulator.audio_playback_rate(), 4000.0);
+            emulator.I = 0x300;
+            for i in 0..16 {
+                emulator.memory[0x300 + i] = i as u8;
+            }
+            emulator.opcode = chip8::chip8::decode(0xF002, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.audio_pattern.unwrap()[15], 15);
+
+            emulator.V[3] = 112;
+            emulator.opcode = chip8::chip8::decode(0xF33A, Variant::Chip8);
+            emulator.execute().unwrap();
+            assert_eq!(emulator.pitch, 112);
+            assert_eq!(emulator.audio_playback_rate(), 8000.0);
+        }
+
+        #[test]
+        fn test_draw_clipping() {
+            let mut emulator = chip8::chip8::create_chip8();
+            let (x, y) = (0, 1);
+            emulator.I = 0x300;
+            emulator.memory[0x300] = 0xFF;
+            emulator.memory[0x301] = 0xFF;
+            emulator.V[x] = 60;
+            emulator.V[y] = 31;
+            emulator.opcode = chip8::chip8::Opcode::OP_DXYN(x, y, 2);
+            emulator.execute().unwrap();
+            assert!(emulator.framebuffer().get(63, 31));
+            // nothing spills onto the left edge or the top row
+            assert!(!emulator.framebuffer().get(0, 0));
+            assert!(!emulator.framebuffer().get(0, 31));
+
+            let mut emulator = chip8::chip8::create_chip8();
+            emulator.quirks.clipping = false;
+            emulator.I = 0x300;
+            emulator.memory[0x300] = 0xFF;
+            emulator.memory[0x301] = 0xFF;
+            emulator.V[x] = 60;
+            emulator.V[y] = 31;
+            emulator.opcode = chip8::chip8::Opcode::OP_DXYN(x, y, 2);
+            emulator.execute().unwrap();
+            assert!(emulator.framebuffer().get(63, 31));
+            assert!(emulator.framebuffer().get(3, 31));
+            assert!(emulator.framebuffer().get(3, 0));
+            assert!(!emulator.framebuffer().get(4, 0));
+        }
+
+        #[test]
+        fn test_flow_opcodes() {
+            TestMachine::new().exec(0x0000).assert_pc(0x202);
+            TestMachine::new().exec(0x1ABC).assert_pc(0xABC);
+            TestMachine::new()
+                .exec(0x2400)
+                .assert_pc(0x400)
+                .assert_stack(&[0x200])
+                .exec(0x2600)
+                .assert_stack(&[0x200, 0x400])
+                .exec(0x00EE)
+                .assert_pc(0x402)
+                .exec(0x00EE)
+                .assert_pc(0x202)
+                .assert_stack(&[]);
+            let machine = TestMachine::new().exec(0x00FD);
+            assert!(machine.chip8.has_exited());
+        }
+
+        #[test]
+        fn test_skip_opcodes() {
+            // each one, once when it skips and once when it doesn't
+            let cases = [
+                (0x3342, 0x42, 0x00, 0x204),
+                (0x3341, 0x42, 0x00, 0x202),
+                (0x4341, 0x42, 0x00, 0x204),
+                (0x4342, 0x42, 0x00, 0x202),
+                (0x5340, 0x42, 0x42, 0x204),
+                (0x5340, 0x42, 0x43, 0x202),
+                (0x9340, 0x42, 0x43, 0x204),
+                (0x9340, 0x42, 0x42, 0x202),
+            ];
+            for (instruction, v3, v4, pc) in cases.iter() {
+                TestMachine::new()
+                    .v(3, *v3)
+                    .v(4, *v4)
+                    .exec(*instruction)
+                    .assert_pc(*pc);
+            }
+        }
+
+        #[test]
+        fn test_register_opcodes() {
+            TestMachine::new()
+                .exec(0x6A2B)
+                .assert_v(0xA, 0x2B)
+                .exec(0x7A05)
+                .assert_v(0xA, 0x30)
+                .exec(0x8BA0)
+                .assert_v(0xB, 0x30);
+            // 7XKK wraps around without a carry
+            TestMachine::new()
+                .v(0, 0xFF)
+                .v(0xF, 7)
+                .exec(0x7002)
+                .assert_v(0, 1)
+                .assert_v(0xF, 7);
+        }
+
+        #[test]
+        fn test_logic_opcodes() {
+            for vf_reset in [false, true].iter() {
+                let vf = if *vf_reset { 0 } else { 9 };
+                for (instruction, result) in [(0x8121, 0x3E), (0x8122, 0x08), (0x8123, 0x36)].iter()
+                {
+                    TestMachine::new()
+                        .quirks(|quirks| quirks.vf_reset = *vf_reset)
+                        .v(1, 0x0C)
+                        .v(2, 0x3A)
+                        .v(0xF, 9)
+                        .exec(*instruction)
+                        .assert_v(1, *result)
+                        .assert_v(2, 0x3A)
+                        .assert_v(0xF, vf);
+                }
+                // VF as the destination: the reset, when there is one, comes last
+                TestMachine::new()
+                    .quirks(|quirks| quirks.vf_reset = *vf_reset)
+                    .v(0xF, 0x0C)
+                    .v(2, 0x3A)
+                    .exec(0x8F21)
+                    .assert_v(0xF, if *vf_reset { 0 } else { 0x3E });
+            }
+        }
+
+        #[test]
+        fn test_carry_and_borrow() {
+            // 8XY4: VF = 1 on a carry
+            let cases = [
+                (200, 60, 4, 1),
+                (100, 50, 150, 0),
+                (0xFF, 1, 0, 1),
+                (0x80, 0x7F, 0xFF, 0),
+            ];
+            for (x, y, result, carry) in cases.iter() {
+                TestMachine::new()
+                    .v(1, *x)
+                    .v(2, *y)
+                    .exec(0x8124)
+                    .assert_v(1, *result)
+                    .assert_v(2, *y)
+                    .assert_v(0xF, *carry);
+            }
+            // 8XY5: VF = 1 when there's no borrow, equal values included
+            let cases = [(5, 3, 2, 1), (3, 5, 0xFE, 0), (5, 5, 0, 1), (0, 0xFF, 1, 0)];
+            for (x, y, result, no_borrow) in cases.iter() {
+                TestMachine::new()
+                    .v(1, *x)
+                    .v(2, *y)
+                    .exec(0x8125)
+                    .assert_v(1, *result)
+                    .assert_v(0xF, *no_borrow);
+            }
+            // 8XY7: VX = VY - VX, with the 8XY5 flag
+            TestMachine::new()
+                .v(1, 3)
+                .v(2, 5)
+                .exec(0x8127)
+                .assert_v(1, 2)
+                .assert_v(2, 5)
+                .assert_v(0xF, 1);
+            TestMachine::new()
+                .v(1, 5)
+                .v(2, 3)
+                .exec(0x8127)
+                .assert_v(1, 0xFE)
+                .assert_v(0xF, 0);
+            TestMachine::new()
+                .v(1, 5)
+                .v(2, 5)
+                .exec(0x8127)
+                .assert_v(1, 0)
+                .assert_v(0xF, 1);
+        }
+
+        // what the flags test in the Timendus suite (and corax89's opcode test before it)
+        // checks: the result and VF of each arithmetic instruction, and with VF itself as VX
+        // the flag has to be what's left in VF
+        #[test]
+        fn test_flag_semantics() {
+            // instruction with VX = V1 and VY = V2, VX, VY, result, flag
+            let cases = [
+                (0x8124, 0x10, 0x20, 0x30, 0),
+                (0x8124, 0xFF, 0x02, 0x01, 1),
+                (0x8125, 0x30, 0x20, 0x10, 1),
+                (0x8125, 0x20, 0x30, 0xF0, 0),
+                (0x8125, 0x20, 0x20, 0x00, 1),
+                (0x8127, 0x20, 0x30, 0x10, 1),
+                (0x8127, 0x30, 0x20, 0xF0, 0),
+                (0x8127, 0x20, 0x20, 0x00, 1),
+                (0x8126, 0x0A, 0x00, 0x05, 0),
+                (0x8126, 0x0B, 0x00, 0x05, 1),
+                (0x812E, 0x41, 0x00, 0x82, 0),
+                (0x812E, 0xC1, 0x00, 0x82, 1),
+            ];
+            for (instruction, x, y, result, flag) in cases.iter() {
+                TestMachine::new()
+                    .v(1, *x)
+                    .v(2, *y)
+                    .v(0xF, 0x55)
+                    .exec(*instruction)
+                    .assert_v(1, *result)
+                    .assert_v(2, *y)
+                    .assert_v(0xF, *flag);
+                // the same with VF as VX
+                TestMachine::new()
+                    .v(0xF, *x)
+                    .v(2, *y)
+                    .exec(*instruction | 0x0F00)
+                    .assert_v(2, *y)
+                    .assert_v(0xF, *flag);
+            }
+        }
+
+        #[test]
+        fn test_vf_operands() {
+            // VF as the second operand is read before the flag overwrites it
+            TestMachine::new()
+                .v(0, 0xFF)
+                .v(0xF, 1)
+                .exec(0x80F4)
+                .assert_v(0, 0)
+                .assert_v(0xF, 1);
+            TestMachine::new()
+                .v(0, 10)
+                .v(0xF, 3)
+                .exec(0x80F5)
+                .assert_v(0, 7)
+                .assert_v(0xF, 1);
+            TestMachine::new()
+                .quirks(|quirks| quirks.shift_vy = true)
+                .v(0xF, 0x03)
+                .exec(0x80F6)
+                .assert_v(0, 0x01)
+                .assert_v(0xF, 1);
+            TestMachine::new()
+                .quirks(|quirks| quirks.shift_vy = true)
+                .v(0xF, 0x40)
+                .exec(0x80FE)
+                .assert_v(0, 0x80)
+                .assert_v(0xF, 0);
+            // and as a sprite coordinate, before the collision flag
+            TestMachine::new()
+                .v(0xF, 5)
+                .exec(0xDFF1)
+                .assert_pixel(5, 5, true)
+                .assert_pixel(4, 5, false)
+                .assert_v(0xF, 0);
+        }
+
+        #[test]
+        fn test_shift_opcodes() {
+            for shift_vy in [false, true].iter() {
+                let (source, other) = if *shift_vy { (2, 1) } else { (1, 2) };
+                let setup = || {
+                    TestMachine::new()
+                        .quirks(|quirks| quirks.shift_vy = *shift_vy)
+                        .v(source, 0x81)
+                        .v(other, 0x7E)
+                };
+                setup()
+                    .exec(0x8126)
+                    .assert_v(1, 0x40)
+                    .assert_v(0xF, 1)
+                    .assert_v(2, if *shift_vy { 0x81 } else { 0x7E });
+                setup()
+                    .exec(0x812E)
+                    .assert_v(1, 0x02)
+                    .assert_v(0xF, 1)
+                    .assert_v(2, if *shift_vy { 0x81 } else { 0x7E });
+            }
+            TestMachine::new()
+                .v(1, 0x7E)
+                .exec(0x812E)
+                .assert_v(1, 0xFC)
+                .assert_v(0xF, 0);
+        }
+
+        #[test]
+        fn test_index_opcodes() {
+            TestMachine::new()
+                .exec(0xA123)
+                .assert_i(0x123)
+                .v(0xF, 9)
+                .v(0, 0x10)
+                .exec(0xF01E)
+                .assert_i(0x133)
+                .assert_v(0xF, 9);
+            // the font starts at 0, 5 bytes a digit
+            TestMachine::new()
+                .v(4, 0xA)
+                .exec(0xF429)
+                .assert_i(50)
+                .assert_mem(50, &[0xF0, 0x90, 0xF0, 0x90, 0x90]);
+            TestMachine::new().v(4, 0x3A).exec(0xF429).assert_i(50);
+            TestMachine::new().v(4, 0xFF).exec(0xF429).assert_i(75);
+        }
+
+        #[test]
+        fn test_jump_opcodes() {
+            // BNNN jumps to NNN + V0, or with jump_vx to XNN + VX
+            let setup = |jump_vx: bool| {
+                TestMachine::new()
+                    .quirks(|quirks| quirks.jump_vx = jump_vx)
+                    .v(0, 0x10)
+                    .v(3, 0x20)
+            };
+            setup(false).exec(0xB345).assert_pc(0x355);
+            setup(true).exec(0xB345).assert_pc(0x365);
+            setup(true).exec(0xB045).assert_pc(0x055);
+        }
+
+        #[test]
+        fn test_random_opcode() {
+            TestMachine::new().v(0, 0xFF).exec(0xC000).assert_v(0, 0);
+            let mut machine = TestMachine::new();
+            for _ in 0..50 {
+                machine = machine.exec(0xC00F);
+                assert_eq!(machine.chip8.V[0] & 0xF0, 0);
+            }
+        }
+
+        #[test]
+        fn test_draw_opcodes() {
+            // one pixel at 3, 4, then the same again erases it with a collision
+            let machine = TestMachine::new()
+                .i(0x300)
+                .mem(0x300, &[0x80, 0x01])
+                .v(0, 3)
+                .v(1, 4)
+                .v(0xF, 9)
+                .exec(0xD011)
+                .assert_pixel(3, 4, true)
+                .assert_v(0xF, 0)
+                .exec(0xD011)
+                .assert_pixel(3, 4, false)
+                .assert_v(0xF, 1)
+                .exec(0xD012)
+                .assert_pixel(3, 4, true)
+                .assert_pixel(10, 5, true)
+                .assert_v(0xF, 0);
+            assert!(machine.chip8.draw);
+            machine
+                .exec(0x00E0)
+                .assert_pixel(3, 4, false)
+                .assert_pixel(10, 5, false);
+
+            // the origin wraps whatever the clipping quirk says
+            for clipping in [false, true].iter() {
+                TestMachine::new()
+                    .quirks(|quirks| quirks.clipping = *clipping)
+                    .i(0x300)
+                    .mem(0x300, &[0x80])
+                    .v(0, 64 + 2)
+                    .v(1, 32 + 1)
+                    .exec(0xD011)
+                    .assert_pixel(2, 1, true);
+            }
+        }
+
+        #[test]
+        fn test_key_opcodes() {
+            for pressed in [false, true].iter() {
+                let setup = || TestMachine::new().v(2, 0xB).key(0xB, *pressed);
+                setup()
+                    .exec(0xE29E)
+                    .assert_pc(if *pressed { 0x204 } else { 0x202 });
+                setup()
+                    .exec(0xE2A1)
+                    .assert_pc(if *pressed { 0x202 } else { 0x204 });
+            }
+            // FX0A stores the key once it is released
+            let machine = TestMachine::new().key(3, true).exec(0xF40A);
+            assert_eq!(
+                machine.chip8.key_wait(),
+                Some(chip8::chip8::KeyWait::Press(4))
+            );
+            let machine = machine.key(3, false).key(7, true).key(7, false);
+            assert_eq!(machine.chip8.key_wait(), None);
+            machine.assert_v(4, 7).assert_pc(0x202);
+            // past F only the low nibble counts
+            for vx in [0x1B, 0xFB, 0xFF].iter() {
+                let key = vx & 0xF;
+                for pressed in [false, true].iter() {
+                    let setup = || TestMachine::new().v(2, *vx).key(key, *pressed);
+                    setup()
+                        .exec(0xE29E)
+                        .assert_pc(if *pressed { 0x204 } else { 0x202 });
+                    setup()
+                        .exec(0xE2A1)
+                        .assert_pc(if *pressed { 0x202 } else { 0x204 });
+                }
+            }
+        }
+
+        #[test]
+        fn test_timer_opcodes() {
+            let machine = TestMachine::new().v(2, 9).exec(0xF215).exec(0xF218);
+            assert_eq!(machine.chip8.delay_timer, 9);
+            assert_eq!(machine.chip8.sound_timer, 9);
+            let mut machine = machine.exec(0xF307).assert_v(3, 9);
+            machine.chip8.tick_timers();
+            machine.exec(0xF307).assert_v(3, 8);
+        }
+
+        #[test]
+        fn test_memory_opcodes() {
+            TestMachine::new()
+                .i(0x300)
+                .v(0, 254)
+                .exec(0xF033)
+                .assert_mem(0x300, &[2, 5, 4])
+                .v(0, 7)
+                .exec(0xF033)
+                .assert_mem(0x300, &[0, 0, 7])
+                .assert_i(0x300);
+            for increment_i in [false, true].iter() {
+                let next = if *increment_i { 0x310 } else { 0x300 };
+                TestMachine::new()
+                    .quirks(|quirks| quirks.increment_i = *increment_i)
+                    .i(0x300)
+                    .mem(0x300, &[0xAA; 17])
+                    .v(0, 1)
+                    .v(0xF, 0xF)
+                    .exec(0xFF55)
+                    .assert_mem(
+                        0x300,
+                        &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xF, 0xAA],
+                    )
+                    .assert_i(next)
+                    .i(0x300)
+                    .mem(0x300, &[5, 6, 7])
+                    .exec(0xF165)
+                    .assert_v(0, 5)
+                    .assert_v(1, 6)
+                    .assert_v(2, 0)
+                    .assert_i(if *increment_i { 0x302 } else { 0x300 });
+            }
+            // the RPL user flags
+            TestMachine::new()
+                .v(0, 1)
+                .v(1, 2)
+                .v(2, 3)
+                .exec(0xF175)
+                .v(0, 0)
+                .v(1, 0)
+                .v(2, 0)
+                .exec(0xF285)
+                .assert_v(0, 1)
+                .assert_v(1, 2)
+                .assert_v(2, 0);
+        }
+
+        #[test]
+        fn test_memory_opcodes_at_the_end() {
+            use chip8::chip8::{Chip8Error, MemoryPolicy};
+
+            // wrapping around to the start of memory
+            TestMachine::new()
+                .i(0xFFE)
+                .v(0, 254)
+                .exec(0xF033)
+                .assert_mem(0xFFE, &[2, 5])
+                .assert_mem(0, &[4]);
+            let machine = TestMachine::new()
+                .i(0xFFF)
+                .v(0, 1)
+                .v(1, 2)
+                .v(0xF, 3)
+                .exec(0xFF55)
+                .assert_mem(0xFFF, &[1])
+                .assert_mem(0, &[2])
+                .assert_mem(14, &[3]);
+            machine
+                .mem(0xFFF, &[7])
+                .mem(0, &[8])
+                .exec(0xF165)
+                .assert_v(0, 7)
+                .assert_v(1, 8);
+
+            // or stopping at the first byte past it
+            let faulting =
+                || TestMachine::new().quirks(|quirks| quirks.memory = MemoryPolicy::Fault);
+            let fault = Chip8Error::MemoryFault {
+                address: 0x1000,
+                pc: 0x200,
+            };
+            assert_eq!(faulting().i(0xFFE).fails(0xF033), fault);
+            assert_eq!(faulting().i(0xFFF).fails(0xF155), fault);
+            assert_eq!(faulting().i(0xFFF).fails(0xF165), fault);
+            // what does fit is fine
+            faulting()
+                .i(0xFFF)
+                .v(0, 9)
+                .exec(0xF055)
+                .assert_mem(0xFFF, &[9])
+                .exec(0xF065)
+                .assert_v(0, 9);
+        }
+
+        #[test]
+        fn test_audio_opcodes() {
+            let machine = TestMachine::new()
+                .i(0x300)
+                .mem(0x300, &[0x0F; 16])
+                .exec(0xF002)
+                .v(5, 64)
+                .exec(0xF53A);
+            assert_eq!(machine.chip8.audio_pattern, Some([0x0F; 16]));
+            assert_eq!(machine.chip8.pitch, 64);
+        }
+
+        #[test]
+        fn test_variant_opcodes() {
+            // hires clears with 0230
+            TestMachine::new()
+                .variant(Variant::Hires)
+                .i(0)
+                .exec(0xD001)
+                .assert_pixel(0, 0, true)
+                .exec(0x0230)
+                .assert_pixel(0, 0, false);
+
+            // CHIP-8X: 02A0 cycles the background, 5XY1 adds nibbles modulo 8, BXYN colors
+            let machine = TestMachine::new().variant(Variant::Chip8x).exec(0x02A0);
+            assert_eq!(machine.chip8.background_color(), 1);
+            let machine = machine
+                .v(0, 0x37)
+                .v(1, 0x25)
+                .exec(0x5011)
+                .assert_v(0, 0x54)
+                .assert_v(1, 0x25)
+                .v(0, 8)
+                .v(1, 2)
+                .v(2, 5)
+                .exec(0xB021);
+            assert_eq!(machine.chip8.foreground_color(8, 2), 5);
+            assert_ne!(machine.chip8.foreground_color(0, 2), 5);
+            assert_ne!(machine.chip8.foreground_color(8, 3), 5);
+        }
+
+        #[test]
+        fn test_unsupported_opcodes() {
+            use chip8::chip8::Chip8Error;
+
+            assert_eq!(
+                TestMachine::new().fails(0xF000),
+                Chip8Error::Unsupported {
+                    opcode: 0xF000,
+                    pc: 0x200
+                }
+            );
+            // without a peripheral to take them these aren't instructions at all
+            for instruction in [0xF070, 0xF171, 0xF272].iter() {
+                assert_eq!(
+                    TestMachine::new().fails(*instruction),
+                    Chip8Error::UnknownOpcode {
+                        opcode: *instruction,
+                        pc: 0x200
+                    }
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,36 @@
+use crate::rom;
+
+use std::path::Path;
+
+use chip_8::Variant;
+
+// the analyze subcommand, returns the exit code
+pub fn analyze(path: &Path, variant: Option<Variant>) -> i32 {
+    let rom = match rom::read_rom(path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let variant =
+        variant.unwrap_or_else(|| rom::guess_variant(&rom).parse().unwrap_or(Variant::Chip8));
+    let info = match rom::validate_rom(&rom, variant, variant.layout()) {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    println!(
+        "{}: sha1 {}, analyzed as {}",
+        path.display(),
+        info.sha1,
+        variant.name()
+    );
+    for warning in &info.warnings {
+        println!("warning: {}", warning);
+    }
+    print!("{}", chip_8::analyze(&rom, variant));
+    0
+}
@@ -0,0 +1,29 @@
+use std::fs;
+use std::path::Path;
+
+// the asm subcommand, returns the exit code
+pub fn assemble(source_path: &Path, output: Option<&Path>) -> i32 {
+    let source = match fs::read_to_string(source_path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("couldn't read {}: {}", source_path.display(), e);
+            return 1;
+        }
+    };
+    let rom = match chip_8::asm::compile(&source) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}: {}", source_path.display(), e);
+            return 1;
+        }
+    };
+    let output = output
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| source_path.with_extension("ch8"));
+    if let Err(e) = fs::write(&output, &rom) {
+        eprintln!("couldn't write {}: {}", output.display(), e);
+        return 1;
+    }
+    println!("{}: {} bytes", output.display(), rom.len());
+    0
+}
@@ -0,0 +1,24 @@
+// the audio-devices subcommand, returns the exit code. only SDL's audio is started, there's no
+// window
+pub fn audio_devices() -> i32 {
+    let audio_subsystem = match sdl2::init().and_then(|sdl_context| sdl_context.audio()) {
+        Ok(audio_subsystem) => audio_subsystem,
+        Err(e) => {
+            eprintln!("couldn't start SDL audio: {}", e);
+            return 1;
+        }
+    };
+    let count = match audio_subsystem.num_audio_playback_devices() {
+        Some(count) => count,
+        None => {
+            eprintln!("SDL can't tell which audio devices there are");
+            return 1;
+        }
+    };
+    for index in 0..count {
+        if let Ok(name) = audio_subsystem.audio_playback_device_name(index) {
+            println!("{}", name);
+        }
+    }
+    0
+}
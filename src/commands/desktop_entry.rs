@@ -0,0 +1,43 @@
+use crate::{desktop, persistence};
+
+use std::fs;
+
+// the install-desktop-entry subcommand, returns the exit code
+pub fn install_desktop_entry() -> i32 {
+    if !cfg!(target_os = "linux") {
+        eprintln!("desktop entries are only written on Linux");
+        return 1;
+    }
+    let data_dir = match persistence::data_dir() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("nowhere to install to, neither XDG_DATA_HOME nor HOME is set");
+            return 1;
+        }
+    };
+    let exe = match std::env::current_exe().and_then(fs::canonicalize) {
+        Ok(exe) => exe,
+        Err(e) => {
+            eprintln!("couldn't tell where the emulator is: {}", e);
+            return 1;
+        }
+    };
+    match desktop::install(&data_dir, &exe) {
+        Ok(written) => {
+            for path in written {
+                println!("wrote {}", path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+    for program in desktop::refresh(&data_dir) {
+        println!(
+            "{} failed or isn't installed, the desktop may only notice after logging in again",
+            program
+        );
+    }
+    0
+}
@@ -0,0 +1,95 @@
+use crate::launch::load_symbols;
+use crate::rom;
+
+use std::path::Path;
+
+use chip_8::analysis::opcode_name;
+use chip_8::{Opcode, Symbols, Variant};
+
+// the disasm subcommand, returns the exit code
+pub fn disasm(path: &Path, variant: Option<Variant>, symbols: Option<&Path>) -> i32 {
+    let rom = match rom::read_rom(path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let symbols = match symbols.map(load_symbols).transpose() {
+        Ok(symbols) => symbols.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let variant =
+        variant.unwrap_or_else(|| rom::guess_variant(&rom).parse().unwrap_or(Variant::Chip8));
+    print!("{}", disassemble(&rom, variant, &symbols));
+    0
+}
+
+// a line for every instruction the analysis reaches, with the address it jumps to or loads named
+// if the symbols have it, and a line for every byte in between
+fn disassemble(rom: &[u8], variant: Variant, symbols: &Symbols) -> String {
+    let analysis = chip_8::analyze(rom, variant);
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let address = analysis.start + offset;
+        if let Some(name) = symbols.name(address) {
+            listing += &format!("{}:\n", name);
+        }
+        let word = rom
+            .get(offset..offset + 2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]));
+        let opcode = word
+            .filter(|_| analysis.code.contains(&address))
+            .and_then(|word| chip_8::try_decode(word, variant));
+        match (word, opcode) {
+            (Some(word), Some(opcode)) => {
+                let target = match opcode {
+                    Opcode::OP_1MMM(target) | Opcode::OP_2MMM(target) | Opcode::OP_AMMM(target) => {
+                        symbols.name(target)
+                    }
+                    _ => None,
+                };
+                listing += &format!("  {:#05X}  {:04X}  {}", address, word, opcode_name(&opcode));
+                if let Some(target) = target {
+                    listing += &format!(" {}", target);
+                }
+                listing.push('\n');
+                offset += 2;
+            }
+            _ => {
+                listing += &format!("  {:#05X}  {:02X}    data\n", address, rom[offset]);
+                offset += 1;
+            }
+        }
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble() {
+        // call a subroutine that loads a sprite and returns, then loop, with the sprite after
+        let rom = [0x22, 0x04, 0x12, 0x02, 0xA2, 0x08, 0x00, 0xEE, 0xF0, 0x90];
+        let mut symbols = Symbols::default();
+        symbols.insert("draw", 0x204);
+        symbols.insert("sprite", 0x208);
+        assert_eq!(
+            disassemble(&rom, Variant::Chip8, &symbols),
+            "  0x200  2204  2MMM draw\n\
+             \x20 0x202  1202  1MMM\n\
+             draw:\n\
+             \x20 0x204  A208  AMMM sprite\n\
+             \x20 0x206  00EE  00EE\n\
+             sprite:\n\
+             \x20 0x208  F0    data\n\
+             \x20 0x209  90    data\n"
+        );
+    }
+}
@@ -0,0 +1,16 @@
+use crate::{Cli, BIN_NAME};
+
+use std::io::Write;
+
+use clap::CommandFactory;
+
+// the man subcommand, returns the exit code
+pub fn man_page(out: &mut dyn Write) -> i32 {
+    match clap_mangen::Man::new(Cli::command().name(BIN_NAME)).render(out) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("couldn't write the man page: {}", e);
+            1
+        }
+    }
+}
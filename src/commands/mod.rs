@@ -0,0 +1,9 @@
+// the subcommands that don't start the emulator, each returns the exit code
+pub mod analyze;
+pub mod asm;
+pub mod audio_devices;
+pub mod desktop_entry;
+pub mod disasm;
+pub mod man;
+pub mod state_info;
+pub mod trace_diff;
@@ -0,0 +1,42 @@
+use std::fs;
+use std::path::Path;
+
+use chip_8::savestate;
+
+// the state-info subcommand, returns the exit code
+pub fn state_info(path: &Path) -> i32 {
+    let file = match fs::read(path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("couldn't read {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    let (header, snapshot) = match savestate::read(&file) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    println!("{}: {} bytes", path.display(), file.len());
+    print!("{}", header);
+    println!("speed: {} instructions per second", snapshot.speed);
+    println!(
+        "pc {:#05X}, I {:#05X}, {} return addresses on the stack",
+        snapshot.pc,
+        snapshot.index,
+        snapshot.stack.len()
+    );
+    println!(
+        "delay timer {}, sound timer {}",
+        snapshot.delay_timer, snapshot.sound_timer
+    );
+    if snapshot.key_wait.is_some() {
+        println!("waiting for a key");
+    }
+    if snapshot.exited {
+        println!("the ROM has exited");
+    }
+    0
+}
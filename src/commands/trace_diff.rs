@@ -0,0 +1,71 @@
+use crate::trace::Comparison;
+use crate::{rom, trace};
+
+use std::path::Path;
+
+use chip_8::{Chip8Builder, Variant};
+
+// the trace-diff subcommand, returns the exit code: 0 if the whole reference matched
+pub fn trace_diff(
+    rom_path: &Path,
+    reference_path: &Path,
+    variant: Option<Variant>,
+    builder: Chip8Builder,
+    context: usize,
+) -> i32 {
+    let rom = match rom::read_rom(rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let reference = match trace::read_reference(reference_path) {
+        Ok(reference) => reference,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let variant =
+        variant.unwrap_or_else(|| rom::guess_variant(&rom).parse().unwrap_or(Variant::Chip8));
+    let mut chip8 = builder.variant(variant).build();
+    if let Err(e) = chip8.load_rom_bytes(&rom) {
+        eprintln!("couldn't load ROM: {}", e);
+        return 1;
+    }
+    match trace::compare(&mut chip8, &reference, context) {
+        Comparison::Matched(steps) => {
+            println!(
+                "all {} steps of {} matched",
+                steps,
+                reference_path.display()
+            );
+            0
+        }
+        Comparison::Diverged {
+            step,
+            differences,
+            context,
+        } => {
+            println!("diverged at step {}:", step);
+            for difference in differences {
+                println!("  {}", difference);
+            }
+            println!();
+            for line in context {
+                println!("{}", line);
+            }
+            1
+        }
+        Comparison::Stopped { step, reason } => {
+            println!(
+                "stopped after {} of {} steps matched: {}",
+                step,
+                reference.len(),
+                reason
+            );
+            1
+        }
+    }
+}
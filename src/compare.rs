@@ -0,0 +1,219 @@
+use crate::patches::Patches;
+use chip_8::frontend::{AudioSink, Display, InputEvent, InputSource};
+use chip_8::{Chip8, Event, HostKey, Keypad};
+
+use std::time::Instant;
+use tracing::{error, info, warn};
+
+const SIDES: [&str; 2] = ["left", "right"];
+
+// the same ROM on two machines configured differently (usually the quirks), side by side with
+// the same keys pressed on both, to see where they go separate ways. a machine that stops
+// with an error or exits stays on its last picture while the other keeps going
+pub struct Compare {
+    machines: [Chip8; 2],
+    rom: Vec<u8>,
+    patches: Patches,
+    stopped: [bool; 2],
+    keypad: Keypad,
+}
+
+impl Compare {
+    // both machines with the ROM and patches loaded, the keys go through `keypad`'s keymap
+    pub fn new(machines: [Chip8; 2], rom: Vec<u8>, patches: Patches, keypad: Keypad) -> Compare {
+        Compare {
+            machines,
+            rom,
+            patches,
+            stopped: [false; 2],
+            keypad,
+        }
+    }
+
+    // runs until the window is closed. returns the exit code, 1 if either machine stopped with an
+    // error along the way
+    pub fn run<D: Display, A: AudioSink, I: InputSource>(
+        &mut self,
+        display: &mut D,
+        audio: &mut A,
+        input: &mut I,
+    ) -> i32 {
+        let frame_interval = crate::runloop::freq_to_period_duration(chip_8::TIMER_FREQ);
+        let mut next_frame = Instant::now();
+        let mut redraw = true;
+        let mut exit_code = 0;
+
+        loop {
+            let mut instructions = 0;
+            for (side, chip8) in self.machines.iter_mut().enumerate() {
+                if self.stopped[side] {
+                    continue;
+                }
+                let output = chip8.run_frame();
+                if side == 0 {
+                    instructions = output.instructions;
+                }
+                for event in output.events {
+                    match event {
+                        Event::DisplayUpdated => redraw = true,
+                        // only the left one is heard
+                        Event::SoundStarted if side == 0 => audio.set_playing(true),
+                        Event::SoundStopped if side == 0 => audio.set_playing(false),
+                        Event::Exited => {
+                            info!("{}: the ROM exited", SIDES[side]);
+                            self.stopped[side] = true;
+                        }
+                        Event::Error(e) => {
+                            error!("{}: emulation error: {}", SIDES[side], e);
+                            self.stopped[side] = true;
+                            exit_code = 1;
+                        }
+                        _ => {}
+                    }
+                }
+                self.patches.apply_freezes(chip8);
+            }
+            if display.end_frame(instructions) {
+                redraw = true;
+            }
+
+            for event in input.poll() {
+                match event {
+                    InputEvent::Quit => {
+                        audio.set_playing(false);
+                        return exit_code;
+                    }
+                    InputEvent::Reset => {
+                        audio.set_playing(false);
+                        self.restart();
+                        redraw = true;
+                    }
+                    InputEvent::ToggleFullscreen => {
+                        display.toggle_fullscreen();
+                        redraw = true;
+                    }
+                    InputEvent::CycleScaling => {
+                        display.cycle_scaling();
+                        redraw = true;
+                    }
+                    InputEvent::Redraw => redraw = true,
+                    InputEvent::KeyDown(HostKey::M) if !self.keypad.is_mapped(HostKey::M) => {
+                        audio.toggle_mute()
+                    }
+                    InputEvent::KeyDown(host_key) | InputEvent::KeyUp(host_key) => {
+                        let edge = match event {
+                            InputEvent::KeyDown(_) => self.keypad.key_down(host_key),
+                            _ => self.keypad.key_up(host_key),
+                        };
+                        if let Some(edge) = edge {
+                            self.machines
+                                .iter_mut()
+                                .for_each(|chip8| chip8.key_edge(edge));
+                        }
+                    }
+                    // one ROM at a time, no frame advance or overlay, and held keys don't repeat
+                    _ => {}
+                }
+            }
+            if redraw {
+                let [left, right] = &mut self.machines;
+                if let Err(e) = display.present_pair(left, right) {
+                    warn!("couldn't draw: {}", e);
+                }
+                left.clear_dirty_rows();
+                right.clear_dirty_rows();
+                redraw = false;
+            }
+
+            crate::runloop::wait_for_next_frame(&mut next_frame, frame_interval);
+        }
+    }
+
+    // the machines as run, left then right
+    pub fn into_machines(self) -> [Chip8; 2] {
+        self.machines
+    }
+
+    // both machines back to the start of the ROM, keeping their configuration
+    fn restart(&mut self) {
+        for chip8 in self.machines.iter_mut() {
+            chip8.reset();
+            // it was loaded once already, so it fits
+            chip8.load_rom_bytes(&self.rom).unwrap();
+            self.patches.apply(chip8);
+        }
+        self.stopped = [false; 2];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::Quirks;
+    use std::collections::VecDeque;
+
+    #[derive(Default)]
+    struct MockDisplay {
+        pairs: usize,
+    }
+
+    impl Display for MockDisplay {
+        fn present(&mut self, _chip8: &Chip8) -> Result<(), String> {
+            unreachable!("compare mode shows both machines")
+        }
+
+        fn present_pair(&mut self, _left: &Chip8, _right: &Chip8) -> Result<(), String> {
+            self.pairs += 1;
+            Ok(())
+        }
+    }
+
+    struct MockAudio;
+
+    impl AudioSink for MockAudio {
+        fn set_playing(&mut self, _playing: bool) {}
+    }
+
+    struct MockInput {
+        frames: VecDeque<Vec<InputEvent>>,
+    }
+
+    impl InputSource for MockInput {
+        fn poll(&mut self) -> Vec<InputEvent> {
+            self.frames.pop_front().unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        // V0 = 3, V1 = 5, V0 >>= 1 (which shifts V1 with shift_vy), wait for a key in V2, then
+        // return without a call
+        let rom = vec![0x60, 0x03, 0x61, 0x05, 0x80, 0x16, 0xF2, 0x0A, 0x00, 0xEE];
+        let cosmac = Quirks::preset("cosmac").unwrap();
+        let machines = [Quirks::default(), cosmac].map(|quirks| {
+            let mut chip8 = Chip8::builder().quirks(quirks).build();
+            chip8.load_rom_bytes(&rom).unwrap();
+            chip8
+        });
+        let mut compare = Compare::new(machines, rom, Patches::default(), Keypad::new());
+        let mut display = MockDisplay::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![],
+                vec![InputEvent::KeyDown(HostKey::Num1)],
+                vec![InputEvent::KeyUp(HostKey::Num1)],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        assert_eq!(compare.run(&mut display, &mut MockAudio, &mut input), 1);
+
+        let [left, right] = &compare.machines;
+        assert_eq!(left.registers()[0], 1);
+        assert_eq!(right.registers()[0], 2);
+        // both got the key and then failed the same way
+        assert_eq!([left.registers()[2], right.registers()[2]], [1, 1]);
+        assert_eq!(compare.stopped, [true, true]);
+        assert_eq!(display.pairs, 1);
+    }
+}
@@ -0,0 +1,740 @@
+use crate::audio::Waveform;
+use crate::debug_server::parse_number;
+use crate::display::{Blend, Filter, Scaling, MAX_BLEND_FRAMES, MAX_PERSISTENCE_MS};
+use crate::keymaps::{self, KeymapPreset};
+use crate::persistence;
+use crate::sdl;
+use crate::title::{self, DEFAULT_TITLE};
+use chip_8::{HostKey, Layout, Macro, Quirks, Timing, Variant, CYCLE_FREQ, MEM_SIZE};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+
+const DEFAULT_SCALE_FACTOR: u32 = 6;
+const DEFAULT_BEEP_FREQ: f32 = 440.0;
+const DEFAULT_VOLUME: f32 = 0.25;
+const DEFAULT_SAMPLE_RATE: i32 = 44100;
+// about 12ms at 44.1kHz. leaving it to SDL can mean 4096 samples and a beep that starts late
+const DEFAULT_BUFFER_SAMPLES: u16 = 512;
+
+// everything is optional so that the global file, per-ROM overrides and CLI flags can be layered
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // initial window size, the window can be resized afterwards
+    pub scale_factor: Option<u32>,
+    pub fullscreen: Option<bool>,
+    // pace the main loop by the monitor's refresh instead of sleeping
+    pub vsync: Option<bool>,
+    // faint lines between the chip-8 pixels, when they're big enough
+    pub grid: Option<bool>,
+    // the window title, {rom}, {variant}, {speed}, {fps} and {state} are filled in
+    pub title: Option<String>,
+    // what {rom} in the title calls the ROM, for the per-ROM sections. the file name otherwise
+    pub name: Option<String>,
+    // integer, fit or stretch
+    pub scaling: Option<Scaling>,
+    // older name for scaling = "integer"
+    pub integer_scaling: Option<bool>,
+    pub filter: Option<Filter>,
+    // frames combined against flicker, 1 is off
+    pub blend: Option<usize>,
+    pub blend_mode: Option<Blend>,
+    // milliseconds switched off pixels take to fade out, 0 is off
+    pub persistence: Option<u32>,
+    // where RPL flags are kept, ~/.local/share/chip8/saves by default
+    pub save_dir: Option<PathBuf>,
+    // instructions per second
+    pub speed: Option<u64>,
+    // speed up (never below speed) games that wait on the delay timer but don't get to it in a frame
+    pub auto_speed: Option<bool>,
+    // flat or vip
+    pub timing: Option<String>,
+    // chip8, hires or chip8x
+    pub variant: Option<String>,
+    // vip, chip8x or eti660, where the ROM is loaded and run from. defaults to the variant's
+    pub layout: Option<String>,
+    // override the layout's addresses, the start address follows the load address unless set
+    pub load_address: Option<usize>,
+    pub start_address: Option<usize>,
+    // RAM saved on exit and restored on load, for games that keep high scores there. an
+    // inclusive range like "0x3F0-0x3FF"
+    pub persist_memory: Option<String>,
+    pub palette: PaletteConfig,
+    pub quirks: QuirksConfig,
+    // qwerty, left-hand, arrows or numpad, what the keymap starts from
+    pub keymap_preset: Option<KeymapPreset>,
+    // host key name (as understood by SDL) -> chip-8 key
+    pub keymap: HashMap<String, u8>,
+    // host key name -> keypad keys it presses by itself
+    pub macros: HashMap<String, MacroConfig>,
+    pub audio: AudioConfig,
+    // per-ROM overrides, keyed by ROM file name or the lowercase hex SHA-1 of the ROM
+    pub roms: HashMap<String, Config>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PaletteConfig {
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirksConfig {
+    pub clipping: Option<bool>,
+    // wrap or fault
+    pub memory: Option<String>,
+    pub shift_vy: Option<bool>,
+    pub increment_i: Option<bool>,
+    pub jump_vx: Option<bool>,
+    pub vf_reset: Option<bool>,
+    pub display_wait: Option<bool>,
+    // ignore or fault
+    pub machine_code: Option<String>,
+    // fault, skip or warn
+    pub unknown_opcode: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MacroConfig {
+    // steps like "4+6*10 _ 5", see Macro::parse
+    pub sequence: String,
+    // over and over while the key is held, for rapid fire
+    pub repeat: bool,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    pub beep_freq: Option<f32>,
+    pub volume: Option<f32>,
+    pub waveform: Option<Waveform>,
+    // samples per second asked for, the device may give another
+    pub sample_rate: Option<i32>,
+    // samples in SDL's buffer, a power of two. smaller is lower latency, too small crackles
+    pub buffer_samples: Option<u16>,
+    // output device name as SDL lists it, the system default if not set
+    pub device: Option<String>,
+}
+
+// fully resolved settings, ready to be used by the frontend
+pub struct Settings {
+    pub scale_factor: u32,
+    pub fullscreen: bool,
+    pub vsync: bool,
+    pub grid: bool,
+    pub title: String,
+    pub rom_name: Option<String>,
+    pub scaling: Scaling,
+    pub filter: Filter,
+    pub blend: usize,
+    pub blend_mode: Blend,
+    pub persistence: u32,
+    // None if there's nowhere to save to
+    pub save_dir: Option<PathBuf>,
+    pub speed: u64,
+    pub auto_speed: bool,
+    pub timing: Timing,
+    pub variant: Variant,
+    pub layout: Layout,
+    pub persist_memory: Option<Range<usize>>,
+    pub foreground: Color,
+    pub background: Color,
+    pub quirks: Quirks,
+    // the preset with the config's keys on top, and just the config's to put on another preset
+    pub keymap: HashMap<HostKey, u8>,
+    pub keymap_preset: KeymapPreset,
+    pub keymap_overrides: HashMap<HostKey, u8>,
+    pub macros: HashMap<HostKey, Macro>,
+    pub beep_freq: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+    pub sample_rate: i32,
+    pub buffer_samples: u16,
+    pub audio_device: Option<String>,
+}
+
+impl Config {
+    // a missing file is not an error, it just means everything is left at its default
+    pub fn load(path: &Path) -> Result<Config, String> {
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        Config::parse(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Config, String> {
+        toml::from_str(contents).map_err(|e| e.to_string())
+    }
+
+    // values set in `other` win
+    pub fn merge(&mut self, other: &Config) {
+        if other.scale_factor.is_some() {
+            self.scale_factor = other.scale_factor;
+        }
+        if other.fullscreen.is_some() {
+            self.fullscreen = other.fullscreen;
+        }
+        if other.vsync.is_some() {
+            self.vsync = other.vsync;
+        }
+        if other.grid.is_some() {
+            self.grid = other.grid;
+        }
+        if other.title.is_some() {
+            self.title = other.title.clone();
+        }
+        if other.name.is_some() {
+            self.name = other.name.clone();
+        }
+        // whichever was given last wins
+        if other.scaling.is_some() {
+            self.scaling = other.scaling;
+            self.integer_scaling = None;
+        }
+        if other.integer_scaling.is_some() {
+            self.integer_scaling = other.integer_scaling;
+            self.scaling = None;
+        }
+        if other.filter.is_some() {
+            self.filter = other.filter;
+        }
+        if other.blend.is_some() {
+            self.blend = other.blend;
+        }
+        if other.blend_mode.is_some() {
+            self.blend_mode = other.blend_mode;
+        }
+        if other.persistence.is_some() {
+            self.persistence = other.persistence;
+        }
+        if other.save_dir.is_some() {
+            self.save_dir = other.save_dir.clone();
+        }
+        if other.speed.is_some() {
+            self.speed = other.speed;
+        }
+        if other.auto_speed.is_some() {
+            self.auto_speed = other.auto_speed;
+        }
+        if other.timing.is_some() {
+            self.timing = other.timing.clone();
+        }
+        if other.variant.is_some() {
+            self.variant = other.variant.clone();
+        }
+        if other.layout.is_some() {
+            self.layout = other.layout.clone();
+        }
+        if other.load_address.is_some() {
+            self.load_address = other.load_address;
+        }
+        if other.start_address.is_some() {
+            self.start_address = other.start_address;
+        }
+        if other.persist_memory.is_some() {
+            self.persist_memory = other.persist_memory.clone();
+        }
+        if other.palette.foreground.is_some() {
+            self.palette.foreground = other.palette.foreground.clone();
+        }
+        if other.palette.background.is_some() {
+            self.palette.background = other.palette.background.clone();
+        }
+        if other.quirks.clipping.is_some() {
+            self.quirks.clipping = other.quirks.clipping;
+        }
+        if other.quirks.memory.is_some() {
+            self.quirks.memory = other.quirks.memory.clone();
+        }
+        if other.quirks.shift_vy.is_some() {
+            self.quirks.shift_vy = other.quirks.shift_vy;
+        }
+        if other.quirks.increment_i.is_some() {
+            self.quirks.increment_i = other.quirks.increment_i;
+        }
+        if other.quirks.jump_vx.is_some() {
+            self.quirks.jump_vx = other.quirks.jump_vx;
+        }
+        if other.quirks.vf_reset.is_some() {
+            self.quirks.vf_reset = other.quirks.vf_reset;
+        }
+        if other.quirks.display_wait.is_some() {
+            self.quirks.display_wait = other.quirks.display_wait;
+        }
+        if other.quirks.machine_code.is_some() {
+            self.quirks.machine_code = other.quirks.machine_code.clone();
+        }
+        if other.quirks.unknown_opcode.is_some() {
+            self.quirks.unknown_opcode = other.quirks.unknown_opcode.clone();
+        }
+        if other.keymap_preset.is_some() {
+            self.keymap_preset = other.keymap_preset;
+        }
+        for (key, value) in &other.keymap {
+            self.keymap.insert(key.clone(), *value);
+        }
+        for (key, value) in &other.macros {
+            self.macros.insert(key.clone(), value.clone());
+        }
+        if other.audio.beep_freq.is_some() {
+            self.audio.beep_freq = other.audio.beep_freq;
+        }
+        if other.audio.volume.is_some() {
+            self.audio.volume = other.audio.volume;
+        }
+        if other.audio.waveform.is_some() {
+            self.audio.waveform = other.audio.waveform;
+        }
+        if other.audio.sample_rate.is_some() {
+            self.audio.sample_rate = other.audio.sample_rate;
+        }
+        if other.audio.buffer_samples.is_some() {
+            self.audio.buffer_samples = other.audio.buffer_samples;
+        }
+        if other.audio.device.is_some() {
+            self.audio.device = other.audio.device.clone();
+        }
+    }
+
+    // apply the overrides for a ROM, if there are any. the ones for its hash win over the ones
+    // for its file name, they follow the game wherever it's copied to
+    pub fn for_rom(&self, rom_path: Option<&Path>, rom: Option<&[u8]>) -> Config {
+        let mut config = self.clone();
+        let by_name = rom_path
+            .and_then(Path::file_name)
+            .and_then(|name| self.roms.get(name.to_string_lossy().as_ref()));
+        let by_hash = rom.and_then(|rom| self.roms.get(&persistence::rom_hash(rom)));
+        for rom_overrides in by_name.into_iter().chain(by_hash) {
+            config.merge(rom_overrides);
+        }
+        config
+    }
+
+    pub fn resolve(&self) -> Result<Settings, String> {
+        let mut keymap_overrides = HashMap::new();
+        for (name, value) in &self.keymap {
+            let host_key =
+                key_by_name(name).ok_or(format!("unknown key name in keymap: {}", name))?;
+            if *value > 0xF {
+                return Err(format!(
+                    "keymap value for {} is not a chip-8 key: {}",
+                    name, value
+                ));
+            }
+            keymap_overrides.insert(host_key, *value);
+        }
+        let keymap_preset = self.keymap_preset.unwrap_or(KeymapPreset::Qwerty);
+        let mut macros = HashMap::new();
+        for (name, value) in &self.macros {
+            let host_key =
+                key_by_name(name).ok_or(format!("unknown key name in macros: {}", name))?;
+            let parsed = Macro::parse(&value.sequence, value.repeat)
+                .map_err(|e| format!("macro for {}: {}", name, e))?;
+            macros.insert(host_key, parsed);
+        }
+        let variant = match &self.variant {
+            Some(name) => name.parse()?,
+            None => Variant::Chip8,
+        };
+        let default_layout = match &self.layout {
+            Some(name) => Layout::preset(name)?,
+            None => variant.layout(),
+        };
+        let load = self.load_address.unwrap_or(default_layout.load);
+        let layout = Layout {
+            load,
+            start: match (self.start_address, self.load_address) {
+                (Some(start), _) => start,
+                (None, Some(_)) => load,
+                (None, None) => default_layout.start,
+            },
+        };
+        layout.check()?;
+        let timing = match &self.timing {
+            Some(name) => name.parse()?,
+            None => Timing::Flat,
+        };
+        let title = self.title.as_deref().unwrap_or(DEFAULT_TITLE);
+        title::check_template(title)?;
+        let blend = self.blend.unwrap_or(1);
+        if !(1..=MAX_BLEND_FRAMES).contains(&blend) {
+            return Err(format!(
+                "blend must be between 1 and {} frames: {}",
+                MAX_BLEND_FRAMES, blend
+            ));
+        }
+        let persistence = self.persistence.unwrap_or(0);
+        if persistence > MAX_PERSISTENCE_MS {
+            return Err(format!(
+                "persistence must be at most {} ms: {}",
+                MAX_PERSISTENCE_MS, persistence
+            ));
+        }
+        let quirks = Quirks {
+            clipping: self.quirks.clipping.unwrap_or(Quirks::default().clipping),
+            memory: match &self.quirks.memory {
+                Some(name) => name.parse()?,
+                None => Quirks::default().memory,
+            },
+            shift_vy: self.quirks.shift_vy.unwrap_or(Quirks::default().shift_vy),
+            increment_i: self
+                .quirks
+                .increment_i
+                .unwrap_or(Quirks::default().increment_i),
+            jump_vx: self.quirks.jump_vx.unwrap_or(Quirks::default().jump_vx),
+            vf_reset: self.quirks.vf_reset.unwrap_or(Quirks::default().vf_reset),
+            display_wait: self
+                .quirks
+                .display_wait
+                .unwrap_or(Quirks::default().display_wait),
+            machine_code: match &self.quirks.machine_code {
+                Some(name) => name.parse()?,
+                None => Quirks::default().machine_code,
+            },
+            unknown_opcode: match &self.quirks.unknown_opcode {
+                Some(name) => name.parse()?,
+                None => Quirks::default().unknown_opcode,
+            },
+        };
+        let sample_rate = self.audio.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+        if !(8000..=192000).contains(&sample_rate) {
+            return Err(format!(
+                "sample_rate must be between 8000 and 192000: {}",
+                sample_rate
+            ));
+        }
+        let buffer_samples = self.audio.buffer_samples.unwrap_or(DEFAULT_BUFFER_SAMPLES);
+        if !buffer_samples.is_power_of_two() || !(16..=16384).contains(&buffer_samples) {
+            return Err(format!(
+                "buffer_samples must be a power of two from 16 to 16384: {}",
+                buffer_samples
+            ));
+        }
+        let persist_memory = self
+            .persist_memory
+            .as_deref()
+            .map(parse_memory_range)
+            .transpose()?;
+        Ok(Settings {
+            scale_factor: self.scale_factor.unwrap_or(DEFAULT_SCALE_FACTOR),
+            fullscreen: self.fullscreen.unwrap_or(false),
+            vsync: self.vsync.unwrap_or(false),
+            grid: self.grid.unwrap_or(false),
+            title: title.to_string(),
+            rom_name: self.name.clone(),
+            scaling: match (self.scaling, self.integer_scaling) {
+                (Some(scaling), _) => scaling,
+                (None, Some(true)) => Scaling::Integer,
+                _ => Scaling::Fit,
+            },
+            filter: self.filter.unwrap_or(Filter::None),
+            blend,
+            blend_mode: self.blend_mode.unwrap_or(Blend::Or),
+            persistence,
+            save_dir: self.save_dir.clone().or_else(persistence::default_save_dir),
+            speed: self.speed.unwrap_or(CYCLE_FREQ),
+            auto_speed: self.auto_speed.unwrap_or(false),
+            timing,
+            variant,
+            layout,
+            persist_memory,
+            foreground: parse_color(self.palette.foreground.as_deref().unwrap_or("#FFFFFF"))?,
+            background: parse_color(self.palette.background.as_deref().unwrap_or("#000000"))?,
+            quirks,
+            keymap: keymaps::keymap(keymap_preset, &keymap_overrides),
+            keymap_preset,
+            keymap_overrides,
+            macros,
+            beep_freq: self.audio.beep_freq.unwrap_or(DEFAULT_BEEP_FREQ),
+            volume: self.audio.volume.unwrap_or(DEFAULT_VOLUME),
+            waveform: self.audio.waveform.unwrap_or(Waveform::Square),
+            sample_rate,
+            buffer_samples,
+            audio_device: self.audio.device.clone(),
+        })
+    }
+}
+
+// $XDG_CONFIG_HOME/chip8/config.toml, falling back to ~/.config/chip8/config.toml
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("chip8").join("config.toml"))
+}
+
+// "0x3F0-0x3FF", both ends included
+fn parse_memory_range(range: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("invalid memory range: {}", range);
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let start = parse_number(start.trim()).map_err(|_| invalid())?;
+    let end = parse_number(end.trim()).map_err(|_| invalid())?;
+    if start > end || end >= MEM_SIZE {
+        return Err(invalid());
+    }
+    Ok(start..end + 1)
+}
+
+// the names SDL gives keys, the ones HostKey doesn't have by name are still looked up through SDL
+fn key_by_name(name: &str) -> Option<HostKey> {
+    HostKey::from_name(name).or_else(|| Keycode::from_name(name).map(sdl::host_key))
+}
+
+// "#RRGGBB"
+fn parse_color(color: &str) -> Result<Color, String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let value = u32::from_str_radix(hex, 16);
+    match value {
+        Ok(value) if hex.len() == 6 => Ok(Color::RGB(
+            (value >> 16) as u8,
+            (value >> 8) as u8,
+            value as u8,
+        )),
+        _ => Err(format!("invalid color: {}", color)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::{MachineCodePolicy, MemoryPolicy, UnknownOpcodePolicy};
+
+    #[test]
+    fn test_parse_and_merge() {
+        let config = Config::parse(
+            r##"
+            scale_factor = 10
+            speed = 500
+            integer_scaling = true
+            filter = "crt"
+
+            [palette]
+            foreground = "#33FF66"
+
+            [keymap]
+            Space = 5
+
+            [macros]
+            F = { sequence = "5*2 _*2", repeat = true }
+
+            [audio]
+            waveform = "triangle"
+            buffer_samples = 256
+            device = "USB Audio"
+
+            [roms."pong.ch8"]
+            speed = 1000
+            timing = "vip"
+            blend = 2
+            keymap_preset = "left-hand"
+            variant = "hires"
+            save_dir = "/tmp/pong"
+            quirks = { clipping = false, memory = "fault", shift_vy = true, increment_i = true, jump_vx = true, vf_reset = true, display_wait = true, machine_code = "fault", unknown_opcode = "warn" }
+            "##,
+        )
+        .unwrap();
+        let global = config.resolve().unwrap();
+        assert_eq!(global.scale_factor, 10);
+        assert_eq!(global.speed, 500);
+        assert_eq!(global.scaling, Scaling::Integer);
+        assert!(!global.fullscreen);
+        assert_eq!(global.filter, Filter::Crt);
+        assert_eq!(global.foreground, Color::RGB(0x33, 0xFF, 0x66));
+        assert_eq!(global.background, Color::RGB(0, 0, 0));
+        assert_eq!(global.keymap.get(&HostKey::Space), Some(&5));
+        assert_eq!(global.keymap.get(&HostKey::X), Some(&0));
+        assert_eq!(
+            global.macros.get(&HostKey::F),
+            Some(&Macro::parse("5*2 _*2", true).unwrap())
+        );
+        assert!(global.quirks.clipping);
+        assert_eq!(global.waveform, Waveform::Triangle);
+        assert_eq!(global.sample_rate, 44100);
+        assert_eq!(global.buffer_samples, 256);
+        assert_eq!(global.audio_device.as_deref(), Some("USB Audio"));
+
+        let pong = config
+            .for_rom(Some(Path::new("roms/pong.ch8")), None)
+            .resolve()
+            .unwrap();
+        assert_eq!(pong.speed, 1000);
+        assert_eq!(pong.blend, 2);
+        // the preset, with the global keymap on top
+        assert_eq!(pong.keymap_preset, KeymapPreset::LeftHand);
+        assert_eq!(pong.keymap.get(&HostKey::W), Some(&2));
+        assert_eq!(pong.keymap.get(&HostKey::Space), Some(&5));
+        assert_eq!(global.keymap_preset, KeymapPreset::Qwerty);
+        assert_eq!(pong.variant, Variant::Hires);
+        assert_eq!(global.variant, Variant::Chip8);
+        assert_eq!(pong.timing, Timing::Vip);
+        assert_eq!(global.timing, Timing::Flat);
+        assert_eq!(pong.save_dir, Some(PathBuf::from("/tmp/pong")));
+        assert_eq!(global.blend, 1);
+        assert_eq!(pong.scale_factor, 10);
+        assert!(!pong.quirks.clipping);
+        assert_eq!(pong.quirks.memory, MemoryPolicy::Fault);
+        assert_eq!(pong.quirks.machine_code, MachineCodePolicy::Fault);
+        assert_eq!(global.quirks.machine_code, MachineCodePolicy::Ignore);
+        assert_eq!(pong.quirks.unknown_opcode, UnknownOpcodePolicy::Warn);
+        assert_eq!(global.quirks.unknown_opcode, UnknownOpcodePolicy::Fault);
+        assert_eq!(global.quirks.memory, MemoryPolicy::Wrap);
+        assert!(pong.quirks.shift_vy);
+        assert!(!global.quirks.shift_vy);
+        assert!(pong.quirks.increment_i);
+        assert!(!global.quirks.increment_i);
+        assert!(pong.quirks.jump_vx);
+        assert!(!global.quirks.jump_vx);
+        assert!(pong.quirks.vf_reset);
+        assert!(!global.quirks.vf_reset);
+        assert!(pong.quirks.display_wait);
+        assert!(!global.quirks.display_wait);
+
+        let cli = Config {
+            speed: Some(42),
+            ..Config::default()
+        };
+        let mut layered = config.for_rom(Some(Path::new("pong.ch8")), None);
+        layered.merge(&cli);
+        assert_eq!(layered.resolve().unwrap().speed, 42);
+        // integer_scaling from the file gives way to --scaling
+        layered.merge(&Config {
+            scaling: Some(Scaling::Stretch),
+            ..Config::default()
+        });
+        assert_eq!(layered.resolve().unwrap().scaling, Scaling::Stretch);
+        assert_eq!(Config::default().resolve().unwrap().scaling, Scaling::Fit);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(Config::parse("bogus = 1").is_err());
+        let bad_color = Config::parse("[palette]\nforeground = \"#12345\"").unwrap();
+        assert!(bad_color.resolve().is_err());
+        let bad_key = Config::parse("[keymap]\nNotAKey = 1").unwrap();
+        assert!(bad_key.resolve().is_err());
+        let bad_value = Config::parse("[keymap]\nSpace = 16").unwrap();
+        assert!(bad_value.resolve().is_err());
+        let bad_macro = Config::parse("[macros]\nF = { sequence = \"5 G\" }").unwrap();
+        assert!(bad_macro.resolve().is_err());
+        assert!(Config::parse("[audio]\nwaveform = \"sawtooth\"").is_err());
+        assert!(Config::parse("filter = \"blur\"").is_err());
+        assert!(Config::parse("blend = 0").unwrap().resolve().is_err());
+        assert!(Config::parse("persistence = 10000")
+            .unwrap()
+            .resolve()
+            .is_err());
+        for audio in [
+            "buffer_samples = 500",
+            "buffer_samples = 8",
+            "sample_rate = 1000",
+        ]
+        .iter()
+        {
+            let bad_audio = Config::parse(&format!("[audio]\n{}", audio)).unwrap();
+            assert!(bad_audio.resolve().is_err(), "{}", audio);
+        }
+        let bad_variant = Config::parse("variant = \"schip\"").unwrap();
+        assert!(bad_variant.resolve().is_err());
+        for layout in [
+            "layout = \"eti\"",
+            "load_address = 0x100",
+            "load_address = 0x1000",
+            "start_address = 0x1000",
+        ]
+        .iter()
+        {
+            let bad_layout = Config::parse(layout).unwrap();
+            assert!(bad_layout.resolve().is_err(), "{}", layout);
+        }
+        let bad_title = Config::parse("title = \"{rom} {ips}\"").unwrap();
+        assert!(bad_title.resolve().is_err());
+        let bad_policy = Config::parse("[quirks]\nmemory = \"ignore\"").unwrap();
+        assert!(bad_policy.resolve().is_err());
+        let bad_policy = Config::parse("[quirks]\nunknown_opcode = \"nop\"").unwrap();
+        assert!(bad_policy.resolve().is_err());
+        for range in ["0x3FF-0x3F0", "0x3F0", "0xFF0-0x1000", "a-b"].iter() {
+            let bad_range = Config::parse(&format!("persist_memory = \"{}\"", range)).unwrap();
+            assert!(bad_range.resolve().is_err(), "{}", range);
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = |toml: &str| Config::parse(toml).unwrap().resolve().unwrap().layout;
+        assert_eq!(layout(""), Layout::VIP);
+        assert_eq!(layout("variant = \"chip8x\""), Layout::CHIP8X);
+        assert_eq!(layout("layout = \"eti660\""), Layout::ETI660);
+        assert_eq!(
+            layout("load_address = 0x400"),
+            Layout {
+                load: 0x400,
+                start: 0x400
+            }
+        );
+        assert_eq!(
+            layout("layout = \"eti660\"\nstart_address = 0x200"),
+            Layout {
+                load: 0x600,
+                start: 0x200
+            }
+        );
+    }
+
+    #[test]
+    fn test_rom_hash_overrides() {
+        let rom = [0x12, 0x00];
+        let config = Config::parse(&format!(
+            r##"
+            auto_speed = true
+
+            [roms."game.ch8"]
+            speed = 1000
+            blend = 3
+            auto_speed = false
+
+            [roms."{}"]
+            speed = 2000
+            name = "Pong (1 player)"
+            persist_memory = "0x3F0 - 0x3FF"
+            keymap = {{ Space = 5 }}
+            "##,
+            persistence::rom_hash(&rom)
+        ))
+        .unwrap();
+        let game = config
+            .for_rom(Some(Path::new("game.ch8")), Some(&rom))
+            .resolve()
+            .unwrap();
+        assert_eq!(game.speed, 2000);
+        assert_eq!(game.blend, 3);
+        assert!(!game.auto_speed);
+        assert_eq!(game.persist_memory, Some(0x3F0..0x400));
+        assert_eq!(game.keymap.get(&HostKey::Space), Some(&5));
+        // renamed, still the same game
+        let renamed = config
+            .for_rom(Some(Path::new("copy.ch8")), Some(&rom))
+            .resolve()
+            .unwrap();
+        assert_eq!(renamed.speed, 2000);
+        assert_eq!(renamed.blend, 1);
+        assert!(renamed.auto_speed);
+        assert_eq!(renamed.rom_name.as_deref(), Some("Pong (1 player)"));
+        let other = config.for_rom(None, Some(&[0x13, 0x00])).resolve().unwrap();
+        assert_eq!(other.persist_memory, None);
+        assert_eq!(other.speed, CYCLE_FREQ);
+    }
+}
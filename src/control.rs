@@ -0,0 +1,509 @@
+use crate::statedump::{base64, StateDump};
+use crate::thumbnail;
+use chip_8::{Chip8, Debugger, KeyEdge};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+#[cfg(unix)]
+use std::io::{ErrorKind, Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// the method was fine but couldn't be done
+const FAILED: i64 = -32000;
+// a client is dropped when a request line gets longer than this, or when it leaves more than
+// MAX_UNSENT bytes of responses unread
+const MAX_LINE: usize = 64 * 1024;
+const MAX_UNSENT: usize = 16 * 1024 * 1024;
+
+// --control-socket: JSON-RPC 2.0 over a Unix socket, a request a line and a response a line, for
+// launchers and test runners to drive the emulator. one client at a time.
+//
+//   load {"path": ...}                    run another ROM
+//   pause, resume, step {"count": N}      like the debug server's
+//   key {"key": 0-15, "pressed": bool}    press or let go of a keypad key
+//   screenshot {"path": ...}              the screen as a PNG, to the file or in the result as
+//                                         base64 {"png": ...} without a path
+//   state                                 the --dump-state-json object, memory as base64
+#[cfg(unix)]
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    client: Option<Client>,
+}
+
+#[cfg(unix)]
+struct Client {
+    stream: UnixStream,
+    // bytes received that don't make a complete line yet
+    pending: Vec<u8>,
+    // responses the client hasn't taken yet, sent as it reads them
+    unsent: Vec<u8>,
+}
+
+// what the main loop has to do for a request, the rest is done by the socket itself
+#[derive(Debug, PartialEq)]
+pub enum Control {
+    Open(PathBuf),
+    Key(KeyEdge),
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    // a notification without one gets no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct LoadParams {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct StepParams {
+    #[serde(default = "one")]
+    count: usize,
+}
+
+#[derive(Deserialize)]
+struct KeyParams {
+    key: u8,
+    #[serde(default = "yes")]
+    pressed: bool,
+}
+
+#[derive(Deserialize)]
+struct ScreenshotParams {
+    path: Option<PathBuf>,
+}
+
+fn one() -> usize {
+    1
+}
+
+fn yes() -> bool {
+    true
+}
+
+#[cfg(unix)]
+impl ControlSocket {
+    pub fn bind(path: &Path) -> Result<ControlSocket, String> {
+        // left behind by an instance that didn't get to clean up, nothing's listening on it
+        if path.exists() && UnixStream::connect(path).is_err() {
+            fs::remove_file(path)
+                .map_err(|e| format!("couldn't remove {}: {}", path.display(), e))?;
+        }
+        let listener = UnixListener::bind(path)
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            })
+            .map_err(|e| format!("couldn't listen on {}: {}", path.display(), e))?;
+        Ok(ControlSocket {
+            listener,
+            path: path.to_path_buf(),
+            client: None,
+        })
+    }
+
+    // accept a client and answer what it sent. never blocks, so it can be called from the main
+    // loop
+    pub fn poll(&mut self, chip8: &mut Chip8, debugger: &mut Debugger) -> Vec<Control> {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    self.client = Some(Client {
+                        stream,
+                        pending: Vec::new(),
+                        unsent: Vec::new(),
+                    });
+                }
+            }
+        }
+        let mut controls = Vec::new();
+        let connected = match &mut self.client {
+            Some(client) => client.poll(chip8, debugger, &mut controls).is_ok(),
+            None => return controls,
+        };
+        if !connected {
+            self.client = None;
+        }
+        controls
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+impl Client {
+    // an error means the client went away
+    fn poll(
+        &mut self,
+        chip8: &mut Chip8,
+        debugger: &mut Debugger,
+        controls: &mut Vec<Control>,
+    ) -> std::io::Result<()> {
+        let mut buffer = [0; 512];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(length) => self.pending.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                if let Some(response) = respond(&line, chip8, debugger, controls) {
+                    writeln!(self.unsent, "{}", response)?;
+                }
+            }
+            if self.pending.len() > MAX_LINE || self.unsent.len() > MAX_UNSENT {
+                return Err(ErrorKind::InvalidData.into());
+            }
+        }
+        self.flush()
+    }
+
+    // as much of the unsent responses as the socket takes without blocking, the rest waits for
+    // the next poll
+    fn flush(&mut self) -> std::io::Result<()> {
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(length) => {
+                    self.unsent.drain(..length);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+// no Unix sockets to listen on
+#[cfg(not(unix))]
+pub struct ControlSocket;
+
+#[cfg(not(unix))]
+impl ControlSocket {
+    pub fn bind(path: &Path) -> Result<ControlSocket, String> {
+        Err(format!(
+            "can't listen on {}: control sockets need Unix sockets",
+            path.display()
+        ))
+    }
+
+    pub fn poll(&mut self, _chip8: &mut Chip8, _debugger: &mut Debugger) -> Vec<Control> {
+        Vec::new()
+    }
+}
+
+// the response line to a request line, None for a notification
+fn respond(
+    line: &[u8],
+    chip8: &mut Chip8,
+    debugger: &mut Debugger,
+    controls: &mut Vec<Control>,
+) -> Option<Value> {
+    let request = match serde_json::from_slice::<Value>(line) {
+        Ok(request) => request,
+        Err(e) => return Some(error(Value::Null, PARSE_ERROR, &e.to_string())),
+    };
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let request = match serde_json::from_value::<Request>(request) {
+        Ok(request) if request.jsonrpc == "2.0" => request,
+        Ok(_) => return Some(error(id, INVALID_REQUEST, "jsonrpc has to be \"2.0\"")),
+        Err(e) => return Some(error(id, INVALID_REQUEST, &e.to_string())),
+    };
+    let result = execute(&request.method, request.params, chip8, debugger, controls);
+    let id = request.id?;
+    Some(match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err((code, message)) => error(id, code, &message),
+    })
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, (i64, String)> {
+    // leaving them out is the same as {}
+    let params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+fn execute(
+    method: &str,
+    params_value: Value,
+    chip8: &mut Chip8,
+    debugger: &mut Debugger,
+    controls: &mut Vec<Control>,
+) -> Result<Value, (i64, String)> {
+    match method {
+        "load" => {
+            let LoadParams { path } = params(params_value)?;
+            if !path.exists() {
+                return Err((FAILED, format!("no such file {}", path.display())));
+            }
+            controls.push(Control::Open(path));
+        }
+        "pause" => debugger.pause(chip8),
+        "resume" => debugger.resume(chip8),
+        "step" => {
+            let StepParams { count } = params(params_value)?;
+            debugger.step(chip8, count);
+        }
+        "key" => {
+            let KeyParams { key, pressed } = params(params_value)?;
+            if key as usize >= chip8.keys().len() {
+                return Err((INVALID_PARAMS, format!("no key {}", key)));
+            }
+            controls.push(Control::Key(if pressed {
+                KeyEdge::Press(key)
+            } else {
+                KeyEdge::Release(key)
+            }));
+        }
+        "screenshot" => {
+            let ScreenshotParams { path } = params(params_value)?;
+            let png = thumbnail::encode_png(chip8.framebuffer());
+            match path {
+                Some(path) => fs::write(&path, png)
+                    .map_err(|e| (FAILED, format!("couldn't write {}: {}", path.display(), e)))?,
+                None => return Ok(json!({ "png": base64(&png) })),
+            }
+        }
+        "state" => return Ok(serde_json::to_value(StateDump::new(chip8, true)).unwrap()),
+        method => return Err((METHOD_NOT_FOUND, format!("no method {}", method))),
+    }
+    Ok(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+
+    fn call(line: &str, chip8: &mut Chip8, debugger: &mut Debugger) -> (Value, Vec<Control>) {
+        let mut controls = Vec::new();
+        let response = respond(line.as_bytes(), chip8, debugger, &mut controls);
+        (response.unwrap_or(Value::Null), controls)
+    }
+
+    #[test]
+    fn test_methods() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        let mut debugger = Debugger::new();
+
+        let (response, _) = call(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "pause"}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response, json!({"jsonrpc": "2.0", "id": 1, "result": null}));
+        assert!(debugger.is_paused());
+        call(
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "resume"}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert!(!debugger.is_paused());
+
+        let (response, controls) = call(
+            r#"{"jsonrpc": "2.0", "id": "k", "method": "key", "params": {"key": 10}}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response["id"], "k");
+        assert_eq!(controls, [Control::Key(KeyEdge::Press(0xA))]);
+        let (_, controls) = call(
+            r#"{"jsonrpc": "2.0", "method": "key", "params": {"key": 10, "pressed": false}}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(controls, [Control::Key(KeyEdge::Release(0xA))]);
+        let (response, controls) = call(
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "key", "params": {"key": 16}}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        assert!(controls.is_empty());
+
+        let (response, _) = call(
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "state"}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response["result"]["pc"], 0x200);
+        assert!(response["result"]["memory"].is_string());
+        let (response, _) = call(
+            r#"{"jsonrpc": "2.0", "id": 5, "method": "screenshot"}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert!(response["result"]["png"]
+            .as_str()
+            .unwrap()
+            .starts_with("iVBORw0KGgo"));
+
+        let (response, controls) = call(
+            r#"{"jsonrpc": "2.0", "id": 6, "method": "load", "params": {"path": "Cargo.toml"}}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response["result"], Value::Null);
+        assert_eq!(controls, [Control::Open(PathBuf::from("Cargo.toml"))]);
+        let (response, _) = call(
+            r#"{"jsonrpc": "2.0", "id": 7, "method": "load", "params": {}}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut chip8 = create_chip8();
+        let mut debugger = Debugger::new();
+        let (response, _) = call("{not json", &mut chip8, &mut debugger);
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+        assert_eq!(response["id"], Value::Null);
+        let (response, _) = call(
+            r#"{"jsonrpc": "1.0", "id": 1, "method": "pause"}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+        let (response, _) = call(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "fly"}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+        // notifications get no response, even when they fail
+        let (response, _) = call(
+            r#"{"jsonrpc": "2.0", "method": "fly"}"#,
+            &mut chip8,
+            &mut debugger,
+        );
+        assert_eq!(response, Value::Null);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket() {
+        use std::io::{BufRead, BufReader};
+
+        let path = std::env::temp_dir().join(format!("chip8-control-{}.sock", std::process::id()));
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut chip8 = create_chip8();
+        let mut debugger = Debugger::new();
+        let mut client = UnixStream::connect(&path).unwrap();
+        writeln!(
+            client,
+            r#"{{"jsonrpc": "2.0", "id": 1, "method": "key", "params": {{"key": 5}}}}"#
+        )
+        .unwrap();
+        let mut controls = Vec::new();
+        for _ in 0..100 {
+            controls.extend(socket.poll(&mut chip8, &mut debugger));
+            if !controls.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(controls, [Control::Key(KeyEdge::Press(5))]);
+        let mut response = String::new();
+        BufReader::new(&client).read_line(&mut response).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["result"], Value::Null);
+
+        // a second instance can't take it over while this one runs
+        assert!(ControlSocket::bind(&path).is_err());
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_slow_clients() {
+        use std::io::{BufRead, BufReader};
+        use std::sync::mpsc;
+        use std::thread;
+
+        let path = std::env::temp_dir().join(format!("chip8-slow-{}.sock", std::process::id()));
+        let mut socket = ControlSocket::bind(&path).unwrap();
+        let mut chip8 = create_chip8();
+        let mut debugger = Debugger::new();
+
+        // more state than the socket holds at once, read back while the emulator keeps polling
+        let mut client = UnixStream::connect(&path).unwrap();
+        let requests: String = (0..100)
+            .map(|id| format!(r#"{{"jsonrpc": "2.0", "id": {}, "method": "state"}}"#, id) + "\n")
+            .collect();
+        client.write_all(requests.as_bytes()).unwrap();
+        let (sender, responses) = mpsc::channel();
+        let reader = BufReader::new(client.try_clone().unwrap());
+        thread::spawn(move || {
+            for line in reader.lines().take(100) {
+                let response: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                sender.send(response["id"].as_u64().unwrap()).unwrap();
+            }
+        });
+        let mut ids = Vec::new();
+        for _ in 0..500 {
+            socket.poll(&mut chip8, &mut debugger);
+            ids.extend(responses.try_iter());
+            if ids.len() == 100 {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(ids, (0..100).collect::<Vec<_>>());
+        assert!(socket.client.is_some());
+        drop(client);
+
+        // a line that never ends
+        let mut client = UnixStream::connect(&path).unwrap();
+        let writer = thread::spawn(move || {
+            let _ = client.write_all(&vec![b'x'; MAX_LINE * 2]);
+        });
+        for _ in 0..500 {
+            socket.poll(&mut chip8, &mut debugger);
+            if socket.client.is_none() && writer.is_finished() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(socket.client.is_none());
+    }
+}
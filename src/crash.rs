@@ -0,0 +1,196 @@
+use crate::persistence;
+use crate::thumbnail;
+use chip_8::analysis::opcode_name;
+use chip_8::debugger::{InstructionTracer, TraceEntry};
+use chip_8::{savestate, try_decode, Chip8, Chip8Error};
+
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// instructions run before the error that go in the report
+const TRACE_LENGTH: usize = 64;
+// instructions disassembled before and after the one that failed
+const DISASSEMBLY_WINDOW: usize = 8;
+
+// --crash-report DIR: remembers the last instructions run, and when the machine stops with an
+// error writes everything needed to look into it to a directory of its own in DIR: a report
+// with the error, ROM, settings, registers, the code around it and how it got there, a save
+// state of the machine as it stopped and a picture of the screen
+pub struct CrashReporter {
+    dir: PathBuf,
+    tracer: Rc<RefCell<InstructionTracer>>,
+}
+
+impl CrashReporter {
+    pub fn attach(dir: &Path, chip8: &mut Chip8) -> CrashReporter {
+        let tracer = Rc::new(RefCell::new(InstructionTracer::new(TRACE_LENGTH)));
+        chip8.add_hook(Box::new(tracer.clone()));
+        CrashReporter {
+            dir: dir.to_path_buf(),
+            tracer,
+        }
+    }
+
+    // returns the directory it went in
+    pub fn write(
+        &self,
+        chip8: &Chip8,
+        rom: Option<&[u8]>,
+        error: &Chip8Error,
+    ) -> Result<PathBuf, String> {
+        let hash = rom.map(persistence::rom_hash);
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let name = format!(
+            "crash-{}-{}",
+            hash.as_deref().map_or("none", |hash| &hash[..8]),
+            seconds
+        );
+        let dir = self.dir.join(name);
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+        let trace: Vec<TraceEntry> = self.tracer.borrow().entries().copied().collect();
+        let files = [
+            (
+                "report.txt",
+                report(chip8, hash.as_deref(), error, &trace).into_bytes(),
+            ),
+            (
+                "state.sav",
+                savestate::write(&chip8.snapshot(), hash.as_deref(), true),
+            ),
+            ("screen.png", thumbnail::encode_png(chip8.framebuffer())),
+        ];
+        for (name, contents) in files {
+            let path = dir.join(name);
+            fs::write(&path, contents)
+                .map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+        }
+        Ok(dir)
+    }
+}
+
+fn report(
+    chip8: &Chip8,
+    rom_hash: Option<&str>,
+    error: &Chip8Error,
+    trace: &[TraceEntry],
+) -> String {
+    let mut report = String::new();
+    let pc = error.pc().unwrap_or_else(|| chip8.pc());
+    let registers: Vec<String> = chip8
+        .registers()
+        .iter()
+        .map(|value| format!("{:02X}", value))
+        .collect();
+    let mut stack: Vec<String> = chip8
+        .stack()
+        .iter()
+        .map(|address| format!("{:#05X}", address))
+        .collect();
+    if stack.is_empty() {
+        stack.push("empty".to_string());
+    }
+    // writing to a String can't fail
+    writeln!(report, "error: {}", error).unwrap();
+    writeln!(report, "emulator: chip-8 {}", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(report, "rom sha-1: {}", rom_hash.unwrap_or("none")).unwrap();
+    writeln!(report, "variant: {}", chip8.variant().name()).unwrap();
+    writeln!(report, "speed: {} instructions a second", chip8.speed()).unwrap();
+    writeln!(report, "quirks: {:?}", chip8.quirks).unwrap();
+    writeln!(
+        report,
+        "pc={:#05X} i={:#05X} dt={} st={} v={}",
+        chip8.pc(),
+        chip8.index(),
+        chip8.delay_timer(),
+        chip8.sound_timer,
+        registers.join(",")
+    )
+    .unwrap();
+    writeln!(report, "stack: {}", stack.join(" ")).unwrap();
+
+    writeln!(report, "\ncode around {:#05X}:", pc).unwrap();
+    let memory = chip8.memory();
+    let start = pc.saturating_sub(DISASSEMBLY_WINDOW * 2);
+    let end = (pc + DISASSEMBLY_WINDOW * 2).min(memory.len() - 2);
+    for address in (start..=end).step_by(2) {
+        let instruction = u16::from_be_bytes([memory[address], memory[address + 1]]);
+        let marker = if address == pc { '>' } else { ' ' };
+        writeln!(
+            report,
+            "{} {:#05X}  {:04X}  {}",
+            marker,
+            address,
+            instruction,
+            name(instruction, chip8)
+        )
+        .unwrap();
+    }
+
+    writeln!(report, "\nlast {} instructions, oldest first:", trace.len()).unwrap();
+    for entry in trace {
+        let registers: Vec<String> = entry
+            .registers
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect();
+        writeln!(
+            report,
+            "  {:#05X}  {:04X}  {:<5} i={:#05X} v={}",
+            entry.pc,
+            entry.instruction,
+            name(entry.instruction, chip8),
+            entry.index,
+            registers.join(",")
+        )
+        .unwrap();
+    }
+    report
+}
+
+// "8XY4", or "????" for data that isn't an instruction
+fn name(instruction: u16, chip8: &Chip8) -> String {
+    try_decode(instruction, chip8.variant())
+        .map(|opcode| opcode_name(&opcode))
+        .unwrap_or_else(|| "????".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crash_report() {
+        let dir = std::env::temp_dir().join(format!("chip8-crash-{}", std::process::id()));
+        // V0 += 1, call 0x206 and come back to jump to its return
+        let rom = [0x70, 0x01, 0x22, 0x06, 0x12, 0x08, 0x00, 0xEE, 0x00, 0xEE];
+        let mut chip8 = Chip8::builder().build();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let reporter = CrashReporter::attach(&dir, &mut chip8);
+        let error = loop {
+            if let Err(e) = chip8.emulate_cycle() {
+                break e;
+            }
+        };
+        let written = reporter.write(&chip8, Some(&rom), &error).unwrap();
+        assert!(written.starts_with(&dir));
+
+        let report = fs::read_to_string(written.join("report.txt")).unwrap();
+        assert!(report.starts_with("error: stack underflow at 0x208"));
+        assert!(report.contains(&persistence::rom_hash(&rom)));
+        assert!(report.contains("> 0x208  00EE  00EE\n"));
+        assert!(report.contains("  0x200  7001  7XKK"));
+        assert!(report.contains("last 5 instructions, oldest first:"));
+        let state = fs::read(written.join("state.sav")).unwrap();
+        assert_eq!(savestate::read(&state).unwrap().1.registers[0], 1);
+        let screen = fs::read(written.join("screen.png")).unwrap();
+        assert!(thumbnail::decode_png(&screen).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
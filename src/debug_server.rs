@@ -0,0 +1,866 @@
+use crate::patches::Patches;
+use chip_8::debugger::{self, CallEvent, Debugger, EventBreakpoint, Stop};
+use chip_8::expression::Expression;
+use chip_8::{Chip8, STACK_SIZE};
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+// longest memory dump a single `mem` command returns
+const MAX_DUMP_LENGTH: usize = 256;
+// call and return events `calls` lists without a count
+const DEFAULT_CALLS: usize = 16;
+
+// a line based text protocol for driving the debugger from other programs, one client at a
+// time. every command gets one line back, starting with "error:" if it failed. stops are
+// reported as they happen with "stopped <reason> <address>".
+//
+//   break ADDR, break ADDR if COND, break if COND, delete ADDR, breakpoints
+//   clear ID, conditions
+//   break on EVENT, delete on EVENT, events (EVENT is draw, sound, key or key K)
+//   continue, step [N], pause, reverse-step [N], reverse-continue
+//   regs, mem ADDR [LEN], stack, backtrace, calls [N], pixel X Y
+//   set REG VALUE, poke ADDR VALUE
+//   patch ADDR VALUE, freeze ADDR [VALUE], unfreeze ADDR, patches
+//
+// numbers are decimal or 0x prefixed hex. addresses can also be names from the symbol file
+pub struct DebugServer {
+    listener: TcpListener,
+    client: Option<Client>,
+}
+
+struct Client {
+    stream: TcpStream,
+    // bytes received that don't make a complete line yet
+    pending: Vec<u8>,
+}
+
+impl DebugServer {
+    pub fn bind(address: SocketAddr) -> Result<DebugServer, String> {
+        let listener = TcpListener::bind(address)
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            })
+            .map_err(|e| format!("couldn't listen on {}: {}", address, e))?;
+        Ok(DebugServer {
+            listener,
+            client: None,
+        })
+    }
+
+    // accept a client, run the commands it sent and report stops. never blocks, so it can be
+    // called from the main loop
+    pub fn poll(&mut self, chip8: &mut Chip8, debugger: &mut Debugger, patches: &mut Patches) {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    self.client = Some(Client {
+                        stream,
+                        pending: Vec::new(),
+                    });
+                }
+            }
+        }
+        let connected = match &mut self.client {
+            Some(client) => client.poll(chip8, debugger, patches).is_ok(),
+            None => return,
+        };
+        if !connected {
+            self.client = None;
+        }
+    }
+}
+
+impl Client {
+    // an error means the client went away
+    fn poll(
+        &mut self,
+        chip8: &mut Chip8,
+        debugger: &mut Debugger,
+        patches: &mut Patches,
+    ) -> std::io::Result<()> {
+        let mut buffer = [0; 512];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(length) => self.pending.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        while let Some(end) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let reply = execute(&String::from_utf8_lossy(&line), chip8, debugger, patches);
+            writeln!(self.stream, "{}", reply)?;
+        }
+        if let Some(stop) = debugger.take_stop() {
+            let (reason, address) = match stop {
+                Stop::Breakpoint(address) => ("breakpoint", address),
+                Stop::Condition(address) => ("condition", address),
+                Stop::Event(address) => ("event", address),
+                Stop::Step(address) => ("step", address),
+                Stop::Pause(address) => ("pause", address),
+            };
+            writeln!(self.stream, "stopped {} {:#05X}", reason, address)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_number(text: &str) -> Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("not a number: {}", text))
+}
+
+// a symbol, or a number
+fn parse_address(text: &str, debugger: &Debugger) -> Result<usize, String> {
+    match debugger.symbols().address(text) {
+        Some(address) => Ok(address),
+        None => parse_number(text).map_err(|_| format!("not an address or symbol: {}", text)),
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_number(text)?;
+    if value > 0xFF {
+        return Err(format!("{} doesn't fit in a byte", text));
+    }
+    Ok(value as u8)
+}
+
+// draw, sound, key or key K
+fn parse_event(words: &[&str]) -> Result<EventBreakpoint, String> {
+    match words {
+        ["draw"] => Ok(EventBreakpoint::Draw),
+        ["sound"] => Ok(EventBreakpoint::Sound),
+        ["key"] => Ok(EventBreakpoint::Key(None)),
+        ["key", key] => match parse_number(key)? {
+            key @ 0..=0xF => Ok(EventBreakpoint::Key(Some(key as u8))),
+            _ => Err(format!("no key {}, they go from 0 to 0xF", key)),
+        },
+        _ => Err(format!(
+            "unknown event: {}, expected draw, sound, key or key K",
+            words.join(" ")
+        )),
+    }
+}
+
+// run one command and return the reply line
+pub fn execute(
+    line: &str,
+    chip8: &mut Chip8,
+    debugger: &mut Debugger,
+    patches: &mut Patches,
+) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let result = match words.as_slice() {
+        ["break", "if", condition @ ..] => {
+            Expression::parse(&condition.join(" "), debugger.symbols())
+                .map(|condition| format!("ok {}", debugger.add_condition(condition)))
+        }
+        ["break", "on", event @ ..] => parse_event(event).map(|event| {
+            debugger.add_event_breakpoint(event);
+            "ok".to_string()
+        }),
+        ["break", address, "if", condition @ ..] => {
+            parse_address(address, debugger).and_then(|address| {
+                let condition = Expression::parse(&condition.join(" "), debugger.symbols())?;
+                debugger.add_conditional_breakpoint(address, condition);
+                Ok("ok".to_string())
+            })
+        }
+        ["break", address] => parse_address(address, debugger).map(|address| {
+            debugger.add_breakpoint(address);
+            "ok".to_string()
+        }),
+        ["delete", "on", event @ ..] => parse_event(event).and_then(|event| {
+            if debugger.remove_event_breakpoint(event) {
+                Ok("ok".to_string())
+            } else {
+                Err(format!("no breakpoint on {}", event))
+            }
+        }),
+        ["events"] => Ok(debugger
+            .event_breakpoints()
+            .map(|event| event.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")),
+        ["delete", address] => parse_address(address, debugger).and_then(|address| {
+            if debugger.remove_breakpoint(address) {
+                Ok("ok".to_string())
+            } else {
+                Err(format!("no breakpoint at {:#05X}", address))
+            }
+        }),
+        ["breakpoints"] => Ok(debugger
+            .breakpoints()
+            .map(|address| format!("{:#05X}", address))
+            .collect::<Vec<_>>()
+            .join(" ")),
+        ["clear", id] => parse_number(id.trim_start_matches('#')).and_then(|id| {
+            if debugger.remove_condition(id) {
+                Ok("ok".to_string())
+            } else {
+                Err(format!("no condition #{}", id))
+            }
+        }),
+        ["conditions"] => Ok(conditions(debugger)),
+        ["continue"] | ["c"] => {
+            debugger.resume(chip8);
+            Ok("ok".to_string())
+        }
+        ["step"] | ["s"] => {
+            debugger.step(chip8, 1);
+            Ok("ok".to_string())
+        }
+        ["step", count] | ["s", count] => parse_number(count).map(|count| {
+            debugger.step(chip8, count);
+            "ok".to_string()
+        }),
+        ["reverse-step"] | ["rs"] => debugger.reverse_step(chip8, 1).map(|_| "ok".to_string()),
+        ["reverse-step", count] | ["rs", count] => parse_number(count)
+            .and_then(|count| debugger.reverse_step(chip8, count))
+            .map(|_| "ok".to_string()),
+        ["reverse-continue"] | ["rc"] => debugger.reverse_continue(chip8).map(|_| "ok".to_string()),
+        ["pause"] => {
+            debugger.pause(chip8);
+            Ok("ok".to_string())
+        }
+        ["regs"] => Ok(registers(chip8)),
+        ["mem", address] => {
+            parse_address(address, debugger).and_then(|address| dump(chip8, address, 16))
+        }
+        ["mem", address, length] => parse_address(address, debugger).and_then(|address| {
+            let length = parse_number(length)?;
+            dump(chip8, address, length)
+        }),
+        ["stack"] => Ok(chip8
+            .stack()
+            .iter()
+            .map(|address| format!("{:#05X}", address))
+            .collect::<Vec<_>>()
+            .join(" ")),
+        ["backtrace"] | ["bt"] => Ok(backtrace(chip8, debugger)),
+        ["calls"] => Ok(calls(debugger, DEFAULT_CALLS)),
+        ["calls", count] => parse_number(count).map(|count| calls(debugger, count)),
+        ["pixel", x, y] => parse_number(x).and_then(|x| {
+            let y = parse_number(y)?;
+            let (width, height) = chip8.framebuffer().size();
+            if x >= width || y >= height {
+                return Err(format!(
+                    "{},{} is off the {}x{} screen",
+                    x, y, width, height
+                ));
+            }
+            Ok(pixel_writers(debugger, x, y))
+        }),
+        ["set", register, value] => parse_number(value)
+            .and_then(|value| set_register(chip8, register, value))
+            .map(|_| {
+                debugger.clear_history();
+                "ok".to_string()
+            }),
+        ["poke", address, value] => parse_address(address, debugger).and_then(|address| {
+            if address >= chip8.memory().len() {
+                return Err(format!("{:#05X} is out of memory", address));
+            }
+            chip8.set_memory(address, parse_byte(value)?);
+            debugger.clear_history();
+            Ok("ok".to_string())
+        }),
+        ["patch", address, value] => parse_address(address, debugger).and_then(|address| {
+            patches.add_patch(chip8, address, parse_byte(value)?)?;
+            debugger.clear_history();
+            Ok("ok".to_string())
+        }),
+        ["freeze", address] => parse_address(address, debugger).and_then(|address| {
+            // keep whatever value is there now
+            let value = *chip8
+                .memory()
+                .get(address)
+                .ok_or_else(|| format!("{:#05X} is out of memory", address))?;
+            patches.add_freeze(chip8, address, value)?;
+            debugger.clear_history();
+            Ok("ok".to_string())
+        }),
+        ["freeze", address, value] => parse_address(address, debugger).and_then(|address| {
+            patches.add_freeze(chip8, address, parse_byte(value)?)?;
+            debugger.clear_history();
+            Ok("ok".to_string())
+        }),
+        ["unfreeze", address] => parse_address(address, debugger).and_then(|address| {
+            if patches.remove_freeze(address) {
+                Ok("ok".to_string())
+            } else {
+                Err(format!("{:#05X} isn't frozen", address))
+            }
+        }),
+        ["patches"] => Ok(patches
+            .patches()
+            .map(|(address, value)| format!("patch {:#05X}={:02X}", address, value))
+            .chain(
+                patches
+                    .freezes()
+                    .map(|(address, value)| format!("freeze {:#05X}={:02X}", address, value)),
+            )
+            .collect::<Vec<_>>()
+            .join(" ")),
+        [] => Err("empty command".to_string()),
+        _ => Err(format!("unknown command: {}", line.trim())),
+    };
+    result.unwrap_or_else(|e| format!("error: {}", e))
+}
+
+// v0-vf, i, pc, sp, dt or st, in any case
+fn set_register(chip8: &mut Chip8, register: &str, value: usize) -> Result<(), String> {
+    let register = register.to_ascii_lowercase();
+    let limit = match register.as_str() {
+        "i" | "pc" => chip8.memory().len() - 1,
+        "sp" => STACK_SIZE,
+        _ => 0xFF,
+    };
+    if value > limit {
+        return Err(format!("{} can't be more than {:#X}", register, limit));
+    }
+    match register.as_str() {
+        "i" => chip8.set_index(value),
+        "pc" => chip8.set_pc(value),
+        "sp" => chip8.set_sp(value),
+        "dt" => chip8.set_delay_timer(value as u8),
+        "st" => chip8.sound_timer = value as u8,
+        _ => {
+            let x = register
+                .strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| usize::from_str_radix(x, 16).ok())
+                .ok_or_else(|| format!("unknown register: {}", register))?;
+            chip8.set_register(x, value as u8);
+        }
+    }
+    Ok(())
+}
+
+fn registers(chip8: &Chip8) -> String {
+    let v: Vec<String> = chip8
+        .registers()
+        .iter()
+        .map(|value| format!("{:02X}", value))
+        .collect();
+    format!(
+        "pc={:#05X} i={:#05X} sp={} dt={} st={} v={}",
+        chip8.pc(),
+        chip8.index(),
+        chip8.stack().len(),
+        chip8.delay_timer(),
+        chip8.sound_timer,
+        v.join(",")
+    )
+}
+
+// "#ID: COND" for the ones anywhere, "ADDR: COND" for conditional breakpoints
+fn conditions(debugger: &Debugger) -> String {
+    let anywhere = debugger
+        .conditions()
+        .map(|(id, condition)| format!("#{}: {}", id, condition));
+    let breakpoints = debugger.breakpoints().filter_map(|address| {
+        debugger
+            .breakpoint_condition(address)
+            .map(|condition| format!("{:#05X}: {}", address, condition))
+    });
+    anywhere.chain(breakpoints).collect::<Vec<_>>().join(", ")
+}
+
+// innermost first, each subroutine with the address it was called from
+fn backtrace(chip8: &Chip8, debugger: &Debugger) -> String {
+    debugger::call_stack(chip8)
+        .iter()
+        .rev()
+        .map(|frame| {
+            format!(
+                "{}@{:#05X}",
+                debugger.label(frame.subroutine),
+                frame.call_site
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn calls(debugger: &Debugger, count: usize) -> String {
+    debugger
+        .calls(count)
+        .iter()
+        .map(|event| match event {
+            CallEvent::Call { from, to, depth } => {
+                format!("{} call {:#05X} {}", depth, from, debugger.label(*to))
+            }
+            CallEvent::Return { from, to, depth } => {
+                format!("{} return {:#05X} {:#05X}", depth, from, to)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// the DXYNs that last changed a pixel, newest first: where each ran, I, where the sprite went and
+// its bytes
+pub fn pixel_writers(debugger: &Debugger, x: usize, y: usize) -> String {
+    debugger
+        .pixel_writers(x, y)
+        .iter()
+        .map(|draw| {
+            let sprite: Vec<String> = draw.sprite.iter().map(|b| format!("{:02X}", b)).collect();
+            format!(
+                "{:#05X} i={:#05X} at {},{} sprite {}",
+                draw.pc,
+                draw.index,
+                draw.x,
+                draw.y,
+                sprite.join(" ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn dump(chip8: &Chip8, address: usize, length: usize) -> Result<String, String> {
+    let memory = chip8.memory();
+    if length > MAX_DUMP_LENGTH {
+        return Err(format!("at most {} bytes at a time", MAX_DUMP_LENGTH));
+    }
+    let end = match address
+        .checked_add(length)
+        .filter(|end| *end <= memory.len())
+    {
+        Some(end) => end,
+        None => {
+            return Err(format!(
+                "{:#05X} is out of memory",
+                address.saturating_add(length) - 1
+            ))
+        }
+    };
+    Ok(memory[address..end]
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::{create_chip8, Symbols};
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_commands() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        assert_eq!(
+            execute("break 0x202", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(
+            execute("break 600", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(
+            execute("breakpoints", &mut chip8, &mut debugger, &mut patches),
+            "0x202 0x258"
+        );
+        assert_eq!(
+            execute("delete 0x300", &mut chip8, &mut debugger, &mut patches),
+            "error: no breakpoint at 0x300"
+        );
+        assert_eq!(
+            execute("mem 0x200 4", &mut chip8, &mut debugger, &mut patches),
+            "60 2A 12 02"
+        );
+        assert!(
+            execute("mem 0xFFF 2", &mut chip8, &mut debugger, &mut patches).starts_with("error:")
+        );
+        assert_eq!(
+            execute(
+                "mem 0xFFFFFFFFFFFFFFFF 2",
+                &mut chip8,
+                &mut debugger,
+                &mut patches
+            ),
+            "error: 0xFFFFFFFFFFFFFFFE is out of memory"
+        );
+        assert!(execute("regs", &mut chip8, &mut debugger, &mut patches)
+            .starts_with("pc=0x200 i=0x000 sp=0"));
+        assert_eq!(
+            execute("jump 0x200", &mut chip8, &mut debugger, &mut patches),
+            "error: unknown command: jump 0x200"
+        );
+        assert_eq!(
+            execute("pause", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn test_condition_commands() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        assert_eq!(
+            execute(
+                "break if pc == 0x2F0 && v[4] > 10",
+                &mut chip8,
+                &mut debugger,
+                &mut patches
+            ),
+            "ok 1"
+        );
+        assert_eq!(
+            execute(
+                "break 0x202 if v0 == 0x2A",
+                &mut chip8,
+                &mut debugger,
+                &mut patches
+            ),
+            "ok"
+        );
+        assert!(
+            execute("break if v0 ==", &mut chip8, &mut debugger, &mut patches)
+                .starts_with("error:")
+        );
+        assert_eq!(
+            execute("conditions", &mut chip8, &mut debugger, &mut patches),
+            "#1: pc == 0x2F0 && v[4] > 10, 0x202: v0 == 0x2A"
+        );
+        assert_eq!(
+            execute("breakpoints", &mut chip8, &mut debugger, &mut patches),
+            "0x202"
+        );
+        for _ in 0..3 {
+            if debugger.before_cycle(&chip8) {
+                chip8.emulate_cycle().unwrap();
+            }
+        }
+        assert_eq!(debugger.take_stop(), Some(Stop::Breakpoint(0x202)));
+        assert_eq!(
+            execute("clear #1", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(
+            execute("clear 1", &mut chip8, &mut debugger, &mut patches),
+            "error: no condition #1"
+        );
+    }
+
+    #[test]
+    fn test_event_commands() {
+        // V0 = 1, draw, back to the start
+        let mut chip8 = create_chip8();
+        chip8
+            .load_rom_bytes(&[0x60, 0x01, 0xD0, 0x05, 0x12, 0x00])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        for command in ["break on draw", "break on key 0xA", "break on sound"] {
+            assert_eq!(
+                execute(command, &mut chip8, &mut debugger, &mut patches),
+                "ok"
+            );
+        }
+        assert_eq!(
+            execute("break on key 16", &mut chip8, &mut debugger, &mut patches),
+            "error: no key 16, they go from 0 to 0xF"
+        );
+        assert!(
+            execute("break on pixel", &mut chip8, &mut debugger, &mut patches)
+                .starts_with("error: unknown event")
+        );
+        assert_eq!(
+            execute("delete on sound", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(
+            execute("delete on sound", &mut chip8, &mut debugger, &mut patches),
+            "error: no breakpoint on sound"
+        );
+        assert_eq!(
+            execute("events", &mut chip8, &mut debugger, &mut patches),
+            "draw, key A"
+        );
+        for _ in 0..3 {
+            if debugger.before_cycle(&chip8) {
+                chip8.emulate_cycle().unwrap();
+            }
+        }
+        assert_eq!(debugger.take_stop(), Some(Stop::Event(0x202)));
+    }
+
+    #[test]
+    fn test_reverse_commands() {
+        // V0 += 1 forever
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        assert_eq!(
+            execute("rs", &mut chip8, &mut debugger, &mut patches),
+            "error: the history isn't being recorded"
+        );
+        debugger.record_history(&mut chip8);
+        for _ in 0..20 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(chip8.registers()[0], 10);
+        assert_eq!(
+            execute("reverse-step 5", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(chip8.registers()[0], 8);
+        assert_eq!(debugger.take_stop(), Some(Stop::Step(0x202)));
+        assert_eq!(
+            execute(
+                "break 0x200 if v0 == 3",
+                &mut chip8,
+                &mut debugger,
+                &mut patches
+            ),
+            "ok"
+        );
+        assert_eq!(
+            execute("reverse-continue", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(chip8.registers()[0], 3);
+        assert_eq!(debugger.take_stop(), Some(Stop::Breakpoint(0x200)));
+        // the past can't be replayed with the edit in it
+        execute("set v0 0", &mut chip8, &mut debugger, &mut patches);
+        assert!(execute("rs", &mut chip8, &mut debugger, &mut patches).starts_with("error:"));
+    }
+
+    #[test]
+    fn test_call_commands() {
+        // call 0x204, which calls 0x208 and loops there
+        let mut chip8 = create_chip8();
+        chip8
+            .load_rom_bytes(&[0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0x00, 0x12, 0x08])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        assert_eq!(
+            execute("calls", &mut chip8, &mut debugger, &mut patches),
+            ""
+        );
+        debugger.trace_calls(&mut chip8);
+        for _ in 0..4 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(
+            execute("bt", &mut chip8, &mut debugger, &mut patches),
+            "sub_208@0x204 sub_204@0x200"
+        );
+        assert_eq!(
+            execute("calls", &mut chip8, &mut debugger, &mut patches),
+            "1 call 0x200 sub_204, 2 call 0x204 sub_208"
+        );
+        assert_eq!(
+            execute("calls 1", &mut chip8, &mut debugger, &mut patches),
+            "2 call 0x204 sub_208"
+        );
+
+        debugger.set_symbols(Symbols::parse("outer 0x204\ninner 0x208").unwrap());
+        assert_eq!(
+            execute("bt", &mut chip8, &mut debugger, &mut patches),
+            "inner@0x204 outer@0x200"
+        );
+        assert_eq!(
+            execute("break inner", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), vec![0x208]);
+        assert_eq!(
+            execute("mem outer 2", &mut chip8, &mut debugger, &mut patches),
+            "22 08"
+        );
+        assert_eq!(
+            execute("delete nowhere", &mut chip8, &mut debugger, &mut patches),
+            "error: not an address or symbol: nowhere"
+        );
+    }
+
+    #[test]
+    fn test_pixel_command() {
+        // I = 0x206, draw the sprite there at 0, 0, loop forever
+        let mut chip8 = create_chip8();
+        chip8
+            .load_rom_bytes(&[0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0x80])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        debugger.trace_draws(&mut chip8);
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(
+            execute("pixel 0 0", &mut chip8, &mut debugger, &mut patches),
+            "0x202 i=0x206 at 0,0 sprite 80"
+        );
+        assert_eq!(
+            execute("pixel 1 0", &mut chip8, &mut debugger, &mut patches),
+            ""
+        );
+        assert_eq!(
+            execute("pixel 1", &mut chip8, &mut debugger, &mut patches),
+            "error: unknown command: pixel 1"
+        );
+        assert_eq!(
+            execute("pixel 64 0", &mut chip8, &mut debugger, &mut patches),
+            "error: 64,0 is off the 64x32 screen"
+        );
+        assert_eq!(
+            execute(
+                "pixel 1 0xFFFFFFFFFFFFFFFF",
+                &mut chip8,
+                &mut debugger,
+                &mut patches
+            ),
+            "error: 1,18446744073709551615 is off the 64x32 screen"
+        );
+    }
+
+    #[test]
+    fn test_edit_commands() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        for command in [
+            "set v3 0x1f",
+            "set VF 1",
+            "set i 0x300",
+            "set pc 0x202",
+            "set dt 60",
+            "set st 2",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                execute(command, &mut chip8, &mut debugger, &mut patches),
+                "ok",
+                "{}",
+                command
+            );
+        }
+        assert!(execute("regs", &mut chip8, &mut debugger, &mut patches)
+            .starts_with("pc=0x202 i=0x300 sp=0 dt=60 st=2 v=00,00,00,1F,"));
+        assert_eq!(chip8.registers()[0xF], 1);
+        assert_eq!(
+            execute("set v3 256", &mut chip8, &mut debugger, &mut patches),
+            "error: v3 can't be more than 0xFF"
+        );
+        assert_eq!(
+            execute("set sp 17", &mut chip8, &mut debugger, &mut patches),
+            "error: sp can't be more than 0x10"
+        );
+        assert_eq!(
+            execute("set v10 1", &mut chip8, &mut debugger, &mut patches),
+            "error: unknown register: v10"
+        );
+        assert_eq!(
+            execute("poke 0x300 0xAA", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(chip8.memory()[0x300], 0xAA);
+        // unlike a patch it isn't remembered
+        assert_eq!(
+            execute("patches", &mut chip8, &mut debugger, &mut patches),
+            ""
+        );
+        assert!(
+            execute("poke 0x1000 1", &mut chip8, &mut debugger, &mut patches).starts_with("error:")
+        );
+    }
+
+    #[test]
+    fn test_patch_commands() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        assert_eq!(
+            execute("patch 0x201 0x10", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(chip8.memory()[0x201], 0x10);
+        assert_eq!(
+            execute("freeze 0x202", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(
+            execute("freeze 0x300 7", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(
+            execute("patches", &mut chip8, &mut debugger, &mut patches),
+            "patch 0x201=10 freeze 0x202=12 freeze 0x300=07"
+        );
+        assert!(
+            execute("patch 0x300 256", &mut chip8, &mut debugger, &mut patches)
+                .starts_with("error:")
+        );
+        assert!(
+            execute("freeze 0x1000", &mut chip8, &mut debugger, &mut patches).starts_with("error:")
+        );
+        assert_eq!(
+            execute("unfreeze 0x300", &mut chip8, &mut debugger, &mut patches),
+            "ok"
+        );
+        assert_eq!(
+            execute("unfreeze 0x300", &mut chip8, &mut debugger, &mut patches),
+            "error: 0x300 isn't frozen"
+        );
+    }
+
+    #[test]
+    fn test_server() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+        let mut debugger = Debugger::new();
+        let mut patches = Patches::default();
+        let mut server = DebugServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = server.listener.local_addr().unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"break 0x202\n").unwrap();
+        client.flush().unwrap();
+
+        let mut reader = BufReader::new(client.try_clone().unwrap());
+        let mut line = String::new();
+        // the server never blocks, so keep polling until the reply is there
+        for _ in 0..1000 {
+            server.poll(&mut chip8, &mut debugger, &mut patches);
+            if debugger.breakpoints().count() == 1 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "ok\n");
+
+        // the breakpoint is hit and reported without being asked
+        for _ in 0..2 {
+            if debugger.before_cycle(&chip8) {
+                chip8.emulate_cycle().unwrap();
+            }
+        }
+        server.poll(&mut chip8, &mut debugger, &mut patches);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(line, "stopped breakpoint 0x202\n");
+    }
+}
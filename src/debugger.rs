@@ -0,0 +1,1098 @@
+use crate::chip8::chip8::{try_decode, Chip8, Hooks, Opcode, Snapshot, REGISTER_COUNT};
+use crate::expression::Expression;
+use crate::framebuffer::FrameBuffer;
+use crate::symbols::Symbols;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::rc::Rc;
+
+// call and return events kept for `Debugger::calls`
+const CALL_LOG_LENGTH: usize = 256;
+// DXYNs remembered for every pixel by `Debugger::pixel_writers`
+const DRAWS_PER_PIXEL: usize = 4;
+// instructions between the snapshots `History` takes, going back replays at most this many
+const SNAPSHOT_INTERVAL: u64 = 256;
+// snapshots `History` keeps, so reverse stepping goes back 16K instructions or more
+const HISTORY_SNAPSHOTS: usize = 64;
+
+/// what the debugger lets the machine do
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunState {
+    Running,
+    Paused,
+    /// run this many more instructions, then pause
+    Stepping(usize),
+}
+
+/// why the machine stopped, for whoever is driving the debugger
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    Breakpoint(usize),
+    /// one of the conditions that aren't tied to an address came true
+    Condition(usize),
+    /// about to run an instruction one of the event breakpoints is after
+    Event(usize),
+    Step(usize),
+    Pause(usize),
+}
+
+/// kinds of instructions to stop at wherever they are, to find the drawing, sound or input code
+/// in a ROM without knowing its addresses. stops before the instruction runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventBreakpoint {
+    /// DXYN
+    Draw,
+    /// FX18 setting the sound timer
+    Sound,
+    /// EX9E or EXA1 checking the key, any key when None. FX0A waiting for one only counts as
+    /// any key, which one isn't known until it's pressed
+    Key(Option<u8>),
+}
+
+impl EventBreakpoint {
+    pub fn matches(self, chip8: &Chip8, opcode: &Opcode) -> bool {
+        match (self, opcode) {
+            (EventBreakpoint::Draw, Opcode::OP_DXYN(..)) => true,
+            (EventBreakpoint::Sound, Opcode::OP_FX18(_)) => true,
+            (EventBreakpoint::Key(None), Opcode::OP_FX0A(_)) => true,
+            (EventBreakpoint::Key(key), Opcode::OP_EX9E(x) | Opcode::OP_EXA1(x)) => {
+                key.map_or(true, |key| chip8.registers()[*x] == key)
+            }
+            _ => false,
+        }
+    }
+}
+
+// "draw", "sound", "key" or "key 5", what the debug server takes
+impl fmt::Display for EventBreakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventBreakpoint::Draw => write!(f, "draw"),
+            EventBreakpoint::Sound => write!(f, "sound"),
+            EventBreakpoint::Key(None) => write!(f, "key"),
+            EventBreakpoint::Key(Some(key)) => write!(f, "key {:X}", key),
+        }
+    }
+}
+
+/// a subroutine on the call stack
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    /// where the 2MMM that called it is
+    pub call_site: usize,
+    pub subroutine: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CallEvent {
+    /// depth is the number of frames on the stack after the call, or before the return
+    Call {
+        from: usize,
+        to: usize,
+        depth: usize,
+    },
+    Return {
+        from: usize,
+        to: usize,
+        depth: usize,
+    },
+}
+
+/// the subroutines being run, outermost first. the callee is read back from the 2MMM at the call
+/// site, so it's wrong if the ROM has overwritten that since
+pub fn call_stack(chip8: &Chip8) -> Vec<Frame> {
+    let memory = chip8.memory();
+    chip8
+        .stack()
+        .iter()
+        .map(|&call_site| {
+            let low = *memory.get(call_site + 1).unwrap_or(&0) as usize;
+            Frame {
+                call_site,
+                subroutine: (memory[call_site] as usize & 0x0F) << 8 | low,
+            }
+        })
+        .collect()
+}
+
+/// logs every 2MMM and 00EE. attach with `Chip8::add_hook`, or let `Debugger::trace_calls` do it
+pub struct CallTracer {
+    events: VecDeque<CallEvent>,
+    capacity: usize,
+    // pc of the instruction being run, a return only knows where it went afterwards
+    pc: usize,
+}
+
+impl CallTracer {
+    /// keeps the last `capacity` events
+    pub fn new(capacity: usize) -> CallTracer {
+        CallTracer {
+            events: VecDeque::new(),
+            capacity,
+            pc: 0,
+        }
+    }
+
+    /// oldest first
+    pub fn events(&self) -> impl Iterator<Item = &CallEvent> {
+        self.events.iter()
+    }
+
+    fn push(&mut self, event: CallEvent) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+impl Hooks for CallTracer {
+    fn before_instruction(&mut self, chip8: &Chip8, _opcode: &Opcode) {
+        self.pc = chip8.pc();
+    }
+
+    fn after_instruction(&mut self, chip8: &Chip8, opcode: &Opcode) {
+        match opcode {
+            Opcode::OP_2MMM(_) => self.push(CallEvent::Call {
+                from: self.pc,
+                to: chip8.pc(),
+                depth: chip8.stack().len(),
+            }),
+            Opcode::OP_00EE => self.push(CallEvent::Return {
+                from: self.pc,
+                to: chip8.pc(),
+                depth: chip8.stack().len() + 1,
+            }),
+            _ => {}
+        }
+    }
+}
+
+/// an instruction about to run and the registers it found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub pc: usize,
+    pub instruction: u16,
+    pub index: usize,
+    pub registers: [u8; REGISTER_COUNT],
+}
+
+/// keeps the last instructions run, e.g. to see how a ROM got to where it crashed. attach with
+/// `Chip8::add_hook`
+pub struct InstructionTracer {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl InstructionTracer {
+    /// keeps the last `capacity` instructions
+    pub fn new(capacity: usize) -> InstructionTracer {
+        InstructionTracer {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+}
+
+impl Hooks for InstructionTracer {
+    fn before_instruction(&mut self, chip8: &Chip8, _opcode: &Opcode) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        let memory = chip8.memory();
+        let pc = chip8.pc();
+        let high = memory[pc] as u16;
+        let low = *memory.get(pc + 1).unwrap_or(&0) as u16;
+        self.entries.push_back(TraceEntry {
+            pc,
+            instruction: high << 8 | low,
+            index: chip8.index(),
+            registers: *chip8.registers(),
+        });
+    }
+}
+
+/// a DXYN as it ran
+#[derive(Clone, Debug, PartialEq)]
+pub struct Draw {
+    pub pc: usize,
+    pub index: usize,
+    /// VX and VY, where the sprite went
+    pub x: u8,
+    pub y: u8,
+    /// the N bytes at I
+    pub sprite: Vec<u8>,
+}
+
+/// remembers which DXYNs changed every pixel. attach with `Chip8::add_hook`, or let
+/// `Debugger::trace_draws` do it
+pub struct DrawTracker {
+    width: usize,
+    // newest first, for every pixel row by row
+    writers: Vec<VecDeque<Rc<Draw>>>,
+    // the DXYN being run and the screen before it
+    pending: Option<(Rc<Draw>, FrameBuffer)>,
+}
+
+impl DrawTracker {
+    pub fn new() -> DrawTracker {
+        DrawTracker {
+            width: 0,
+            writers: Vec::new(),
+            pending: None,
+        }
+    }
+
+    /// the DXYNs that last switched the pixel on or off, newest first
+    pub fn writers(&self, x: usize, y: usize) -> impl Iterator<Item = &Draw> {
+        let pixel = (x < self.width)
+            .then(|| y.checked_mul(self.width)?.checked_add(x))
+            .flatten()
+            .and_then(|index| self.writers.get(index));
+        pixel.into_iter().flatten().map(|draw| draw.as_ref())
+    }
+}
+
+impl Default for DrawTracker {
+    fn default() -> Self {
+        DrawTracker::new()
+    }
+}
+
+impl Hooks for DrawTracker {
+    fn before_instruction(&mut self, chip8: &Chip8, opcode: &Opcode) {
+        if let Opcode::OP_DXYN(x, y, n) = *opcode {
+            let memory = chip8.memory();
+            let sprite = (0..n as usize)
+                .map(|row| memory[(chip8.index() + row) % memory.len()])
+                .collect();
+            let draw = Draw {
+                pc: chip8.pc(),
+                index: chip8.index(),
+                x: chip8.registers()[x],
+                y: chip8.registers()[y],
+                sprite,
+            };
+            self.pending = Some((Rc::new(draw), chip8.framebuffer().clone()));
+        }
+    }
+
+    fn after_instruction(&mut self, chip8: &Chip8, _opcode: &Opcode) {
+        let (draw, before) = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let screen = chip8.framebuffer();
+        let (width, height) = screen.size();
+        // another variant, what was known is for a screen that's gone
+        if self.width != width || self.writers.len() != width * height {
+            self.width = width;
+            self.writers = vec![VecDeque::new(); width * height];
+        }
+        for y in 0..height {
+            for x in 0..width {
+                if screen.get(x, y) != before.get(x, y) {
+                    let writers = &mut self.writers[y * width + x];
+                    writers.truncate(DRAWS_PER_PIXEL - 1);
+                    writers.push_front(draw.clone());
+                }
+            }
+        }
+    }
+}
+
+// what running an instruction again wouldn't bring back: random numbers, keys and the timers.
+// taken before every instruction, with pc and V as they were whatever the keys did to them
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Moment {
+    pc: usize,
+    registers: [u8; REGISTER_COUNT],
+    delay_timer: u8,
+    sound_timer: u8,
+}
+
+impl Moment {
+    fn of(chip8: &Chip8) -> Moment {
+        Moment {
+            pc: chip8.pc(),
+            registers: *chip8.registers(),
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer,
+        }
+    }
+
+    fn apply(&self, chip8: &mut Chip8) {
+        chip8.set_pc(self.pc);
+        for (x, value) in self.registers.iter().enumerate() {
+            chip8.set_register(x, *value);
+        }
+        chip8.set_delay_timer(self.delay_timer);
+        chip8.sound_timer = self.sound_timer;
+    }
+}
+
+/// the recent past for reverse stepping: a snapshot every SNAPSHOT_INTERVAL instructions and a
+/// moment for every one since the oldest, to replay from the nearest snapshot. attach with
+/// `Debugger::record_history`
+pub struct History {
+    // instructions run since it was attached
+    step: u64,
+    // with the step they were taken before, oldest first
+    snapshots: VecDeque<(u64, Snapshot)>,
+    // one for every step from the oldest snapshot on
+    moments: VecDeque<Moment>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            step: 0,
+            snapshots: VecDeque::new(),
+            moments: VecDeque::new(),
+        }
+    }
+
+    /// the step the next instruction is
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// the furthest back it can go, None before anything ran
+    pub fn oldest(&self) -> Option<u64> {
+        self.snapshots.front().map(|(step, _)| *step)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.moments.clear();
+    }
+
+    // the machine as it was before step `to`, replayed from the last snapshot before `from` on
+    // a machine of its own, set up like `like`, so the hooks on the real one don't see anything
+    // twice. `visit` sees the machine before every step on the way, `to` included
+    fn replay(
+        &self,
+        like: &Chip8,
+        from: u64,
+        to: u64,
+        mut visit: impl FnMut(u64, &Chip8),
+    ) -> Result<Chip8, String> {
+        // what a peripheral answered the first time can't be asked again
+        if like.has_peripherals() {
+            return Err("can't go back with peripherals attached".to_string());
+        }
+        let oldest = self
+            .oldest()
+            .filter(|oldest| *oldest <= from && to < self.step);
+        let (oldest, (start, snapshot)) = oldest
+            .zip(self.snapshots.iter().rev().find(|(step, _)| *step <= from))
+            .ok_or_else(|| format!("step {} isn't in the history", to))?;
+        // the snapshot has the quirks and speed, and keeps the layout of the same variant
+        let mut chip8 = Chip8::builder()
+            .variant(like.variant())
+            .layout(like.layout())
+            .timing(like.timing())
+            .build();
+        chip8.restore(snapshot)?;
+        for step in *start..=to {
+            // FX0A let go of. whatever the key was, V comes from the moment
+            if chip8.key_wait().is_some() {
+                chip8.set_key(0, true);
+                chip8.set_key(0, false);
+            }
+            self.moments[(step - oldest) as usize].apply(&mut chip8);
+            visit(step, &chip8);
+            if step == to {
+                break;
+            }
+            chip8.emulate_cycle().map_err(|e| e.to_string())?;
+            // a DXYN waiting for the display, it ran after the next timer tick
+            if chip8.waiting_for_vblank() {
+                chip8.tick_timers();
+                chip8.emulate_cycle().map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(chip8)
+    }
+
+    // forget what came after `step`, the machine is back there
+    fn truncate(&mut self, step: u64) {
+        while self
+            .snapshots
+            .back()
+            .is_some_and(|(start, _)| *start > step)
+        {
+            self.snapshots.pop_back();
+        }
+        if let Some(oldest) = self.oldest() {
+            self.moments.truncate((step - oldest) as usize);
+        }
+        self.step = step;
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+impl Hooks for History {
+    fn before_instruction(&mut self, chip8: &Chip8, _opcode: &Opcode) {
+        let due = self
+            .snapshots
+            .back()
+            .map_or(true, |(start, _)| self.step - start >= SNAPSHOT_INTERVAL);
+        if due {
+            if self.snapshots.len() == HISTORY_SNAPSHOTS {
+                if let Some((dropped, _)) = self.snapshots.pop_front() {
+                    let oldest = self.oldest().unwrap_or(self.step);
+                    self.moments.drain(..(oldest - dropped) as usize);
+                }
+            }
+            self.snapshots.push_back((self.step, chip8.snapshot()));
+        }
+        self.moments.push_back(Moment::of(chip8));
+        self.step += 1;
+    }
+}
+
+/// breakpoints and run control, independent of any frontend. call `before_cycle` ahead of
+/// every `emulate_cycle` and skip the cycle when it says so
+pub struct Debugger {
+    // a conditional breakpoint only stops when its condition is true
+    breakpoints: BTreeMap<usize, Option<Expression>>,
+    // checked before every instruction, by id
+    conditions: BTreeMap<usize, Expression>,
+    next_condition: usize,
+    events: BTreeSet<EventBreakpoint>,
+    state: RunState,
+    // set when resuming from a breakpoint so the same breakpoint doesn't stop us again
+    resume_from: Option<usize>,
+    stop: Option<Stop>,
+    calls: Option<Rc<RefCell<CallTracer>>>,
+    draws: Option<Rc<RefCell<DrawTracker>>>,
+    history: Option<Rc<RefCell<History>>>,
+    symbols: Symbols,
+}
+
+impl Debugger {
+    /// running, with no breakpoints
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            conditions: BTreeMap::new(),
+            next_condition: 1,
+            events: BTreeSet::new(),
+            state: RunState::Running,
+            resume_from: None,
+            stop: None,
+            calls: None,
+            draws: None,
+            history: None,
+            symbols: Symbols::default(),
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// the name a subroutine is shown under, made up when the symbols don't have one
+    pub fn label(&self, address: usize) -> String {
+        match self.symbols.name(address) {
+            Some(name) => name.to_string(),
+            None => format!("sub_{:03X}", address),
+        }
+    }
+
+    /// start logging calls and returns on `chip8`. the hook stays through `Chip8::reset`
+    pub fn trace_calls(&mut self, chip8: &mut Chip8) {
+        if self.calls.is_none() {
+            let tracer = Rc::new(RefCell::new(CallTracer::new(CALL_LOG_LENGTH)));
+            chip8.add_hook(Box::new(tracer.clone()));
+            self.calls = Some(tracer);
+        }
+    }
+
+    /// the last `count` calls and returns, oldest first. empty without `trace_calls`
+    pub fn calls(&self, count: usize) -> Vec<CallEvent> {
+        match &self.calls {
+            Some(tracer) => {
+                let tracer = tracer.borrow();
+                let skip = tracer.events.len().saturating_sub(count);
+                tracer.events().skip(skip).copied().collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// start remembering which DXYNs drew every pixel on `chip8`. the hook stays through
+    /// `Chip8::reset`
+    pub fn trace_draws(&mut self, chip8: &mut Chip8) {
+        if self.draws.is_none() {
+            let tracker = Rc::new(RefCell::new(DrawTracker::new()));
+            chip8.add_hook(Box::new(tracker.clone()));
+            self.draws = Some(tracker);
+        }
+    }
+
+    /// the DXYNs that last changed pixel (x, y), newest first. empty without `trace_draws`
+    pub fn pixel_writers(&self, x: usize, y: usize) -> Vec<Draw> {
+        match &self.draws {
+            Some(tracker) => tracker.borrow().writers(x, y).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// start keeping the recent past of `chip8` for `reverse_step` and `reverse_continue`. the
+    /// hook stays through `Chip8::reset`, `clear_history` after one
+    pub fn record_history(&mut self, chip8: &mut Chip8) {
+        if self.history.is_none() {
+            let history = Rc::new(RefCell::new(History::new()));
+            chip8.add_hook(Box::new(history.clone()));
+            self.history = Some(history);
+        }
+    }
+
+    /// after the machine was changed in a way replaying wouldn't repeat: a reset, a loaded
+    /// state or an edit
+    pub fn clear_history(&mut self) {
+        if let Some(history) = &self.history {
+            history.borrow_mut().clear();
+        }
+    }
+
+    /// go back `count` instructions and pause there
+    pub fn reverse_step(&mut self, chip8: &mut Chip8, count: usize) -> Result<(), String> {
+        let history = self.history()?;
+        let (step, oldest) = {
+            let history = history.borrow();
+            (history.step(), history.oldest().unwrap_or(history.step()))
+        };
+        if count as u64 > step - oldest {
+            return Err(format!("can only go back {} instructions", step - oldest));
+        }
+        self.go_back(chip8, &history, step - count as u64)?;
+        self.stop = Some(Stop::Step(chip8.pc()));
+        Ok(())
+    }
+
+    /// go back to the last instruction a breakpoint, condition or event breakpoint would have
+    /// stopped at and pause there
+    pub fn reverse_continue(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let history = self.history()?;
+        let mut last = None;
+        {
+            let history = history.borrow();
+            if let Some(oldest) = history.oldest().filter(|oldest| *oldest < history.step()) {
+                history.replay(chip8, oldest, history.step() - 1, |step, past| {
+                    if let Some(stop) = self.stop_at(past) {
+                        last = Some((step, stop));
+                    }
+                })?;
+            }
+        }
+        let (step, stop) = last.ok_or("nothing to stop at in the history")?;
+        self.go_back(chip8, &history, step)?;
+        self.stop = Some(stop);
+        Ok(())
+    }
+
+    fn history(&self) -> Result<Rc<RefCell<History>>, String> {
+        self.history
+            .clone()
+            .ok_or_else(|| "the history isn't being recorded".to_string())
+    }
+
+    fn go_back(
+        &mut self,
+        chip8: &mut Chip8,
+        history: &RefCell<History>,
+        step: u64,
+    ) -> Result<(), String> {
+        let past = history.borrow().replay(chip8, step, step, |_, _| {})?;
+        chip8.restore(&past.snapshot())?;
+        history.borrow_mut().truncate(step);
+        self.state = RunState::Paused;
+        self.resume_from = None;
+        Ok(())
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address, None);
+    }
+
+    pub fn add_conditional_breakpoint(&mut self, address: usize, condition: Expression) {
+        self.breakpoints.insert(address, Some(condition));
+    }
+
+    /// false if there was no breakpoint there
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.keys().copied()
+    }
+
+    pub fn breakpoint_condition(&self, address: usize) -> Option<&Expression> {
+        self.breakpoints.get(&address)?.as_ref()
+    }
+
+    /// stop wherever `condition` comes true. returns the id to remove it with
+    pub fn add_condition(&mut self, condition: Expression) -> usize {
+        let id = self.next_condition;
+        self.next_condition += 1;
+        self.conditions.insert(id, condition);
+        id
+    }
+
+    pub fn remove_condition(&mut self, id: usize) -> bool {
+        self.conditions.remove(&id).is_some()
+    }
+
+    pub fn conditions(&self) -> impl Iterator<Item = (usize, &Expression)> {
+        self.conditions
+            .iter()
+            .map(|(id, condition)| (*id, condition))
+    }
+
+    pub fn add_event_breakpoint(&mut self, event: EventBreakpoint) {
+        self.events.insert(event);
+    }
+
+    pub fn remove_event_breakpoint(&mut self, event: EventBreakpoint) -> bool {
+        self.events.remove(&event)
+    }
+
+    pub fn event_breakpoints(&self) -> impl Iterator<Item = EventBreakpoint> + '_ {
+        self.events.iter().copied()
+    }
+
+    pub fn state(&self) -> RunState {
+        self.state
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self, chip8: &Chip8) {
+        if self.state != RunState::Paused {
+            self.state = RunState::Paused;
+            self.stop = Some(Stop::Pause(chip8.pc()));
+        }
+    }
+
+    pub fn resume(&mut self, chip8: &Chip8) {
+        self.state = RunState::Running;
+        self.resume_from = Some(chip8.pc());
+    }
+
+    pub fn step(&mut self, chip8: &Chip8, count: usize) {
+        if count > 0 {
+            self.state = RunState::Stepping(count);
+            self.resume_from = Some(chip8.pc());
+        }
+    }
+
+    /// whether the next instruction should run
+    pub fn before_cycle(&mut self, chip8: &Chip8) -> bool {
+        let pc = chip8.pc();
+        let resuming = self.resume_from.take() == Some(pc);
+        match self.state {
+            RunState::Paused => return false,
+            RunState::Stepping(0) => {
+                self.state = RunState::Paused;
+                self.stop = Some(Stop::Step(pc));
+                return false;
+            }
+            RunState::Stepping(count) => self.state = RunState::Stepping(count - 1),
+            RunState::Running => {}
+        }
+        if resuming {
+            return true;
+        }
+        let stop = self.stop_at(chip8);
+        if stop.is_some() {
+            self.state = RunState::Paused;
+            self.stop = stop;
+            return false;
+        }
+        true
+    }
+
+    // what, if anything, stops the machine before its next instruction
+    fn stop_at(&self, chip8: &Chip8) -> Option<Stop> {
+        let pc = chip8.pc();
+        match self.breakpoints.get(&pc) {
+            Some(None) => Some(Stop::Breakpoint(pc)),
+            Some(Some(condition)) if condition.is_true(chip8) => Some(Stop::Breakpoint(pc)),
+            _ if self
+                .conditions
+                .values()
+                .any(|condition| condition.is_true(chip8)) =>
+            {
+                Some(Stop::Condition(pc))
+            }
+            _ if self.stops_for_event(chip8) => Some(Stop::Event(pc)),
+            _ => None,
+        }
+    }
+
+    fn stops_for_event(&self, chip8: &Chip8) -> bool {
+        if self.events.is_empty() {
+            return false;
+        }
+        match try_decode(chip8.next_instruction(), chip8.variant()) {
+            Some(opcode) => self
+                .events
+                .iter()
+                .any(|event| event.matches(chip8, &opcode)),
+            None => false,
+        }
+    }
+
+    /// the last stop, once
+    pub fn take_stop(&mut self) -> Option<Stop> {
+        self.stop.take()
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::chip8::{create_chip8, Layout, Peripheral, Timing};
+
+    // V0 += 1 forever
+    fn counting_machine() -> Chip8 {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip8
+    }
+
+    fn run(debugger: &mut Debugger, chip8: &mut Chip8, cycles: usize) {
+        for _ in 0..cycles {
+            if debugger.before_cycle(chip8) {
+                chip8.emulate_cycle().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut chip8 = counting_machine();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x202);
+        run(&mut debugger, &mut chip8, 10);
+        assert_eq!(debugger.take_stop(), Some(Stop::Breakpoint(0x202)));
+        assert_eq!(debugger.take_stop(), None);
+        assert_eq!(chip8.registers()[0], 1);
+
+        // continuing runs past the breakpoint once and stops on the next lap
+        debugger.resume(&chip8);
+        run(&mut debugger, &mut chip8, 10);
+        assert_eq!(debugger.take_stop(), Some(Stop::Breakpoint(0x202)));
+        assert_eq!(chip8.registers()[0], 2);
+
+        assert!(debugger.remove_breakpoint(0x202));
+        assert!(!debugger.remove_breakpoint(0x202));
+        debugger.resume(&chip8);
+        run(&mut debugger, &mut chip8, 10);
+        assert_eq!(debugger.take_stop(), None);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let symbols = Symbols::default();
+        let mut chip8 = counting_machine();
+        let mut debugger = Debugger::new();
+        // only on the third lap
+        let condition = Expression::parse("v0 == 2", &symbols).unwrap();
+        debugger.add_conditional_breakpoint(0x200, condition.clone());
+        assert_eq!(debugger.breakpoint_condition(0x200), Some(&condition));
+        run(&mut debugger, &mut chip8, 20);
+        assert_eq!(debugger.take_stop(), Some(Stop::Breakpoint(0x200)));
+        assert_eq!(chip8.registers()[0], 2);
+
+        debugger.remove_breakpoint(0x200);
+        let id = debugger.add_condition(Expression::parse("v0 >= 5", &symbols).unwrap());
+        debugger.resume(&chip8);
+        run(&mut debugger, &mut chip8, 20);
+        assert_eq!(debugger.take_stop(), Some(Stop::Condition(0x202)));
+        assert_eq!(chip8.registers()[0], 5);
+        // still true, resuming only gets past the instruction it stopped on
+        debugger.resume(&chip8);
+        run(&mut debugger, &mut chip8, 20);
+        assert_eq!(debugger.take_stop(), Some(Stop::Condition(0x200)));
+        assert_eq!(chip8.registers()[0], 5);
+
+        assert_eq!(debugger.conditions().count(), 1);
+        assert!(debugger.remove_condition(id));
+        assert!(!debugger.remove_condition(id));
+        debugger.resume(&chip8);
+        run(&mut debugger, &mut chip8, 20);
+        assert_eq!(debugger.take_stop(), None);
+    }
+
+    #[test]
+    fn test_event_breakpoints() {
+        // V0 = 5, skip if key V0 is up, V1 = 3, draw, beep for V1, back to the start
+        let rom = [
+            0x60, 0x05, 0xE0, 0xA1, 0x61, 0x03, 0xD0, 0x15, 0xF1, 0x18, 0x12, 0x00,
+        ];
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.add_event_breakpoint(EventBreakpoint::Key(Some(4)));
+        run(&mut debugger, &mut chip8, 20);
+        assert_eq!(debugger.take_stop(), None);
+
+        debugger.add_event_breakpoint(EventBreakpoint::Key(Some(5)));
+        debugger.add_event_breakpoint(EventBreakpoint::Sound);
+        chip8.reset();
+        chip8.load_rom_bytes(&rom).unwrap();
+        run(&mut debugger, &mut chip8, 20);
+        assert_eq!(debugger.take_stop(), Some(Stop::Event(0x202)));
+        debugger.resume(&chip8);
+        run(&mut debugger, &mut chip8, 20);
+        // the key is up, so V1 = 3 was skipped
+        assert_eq!(debugger.take_stop(), Some(Stop::Event(0x208)));
+        assert_eq!(chip8.registers()[1], 0);
+
+        assert!(debugger.remove_event_breakpoint(EventBreakpoint::Sound));
+        assert!(!debugger.remove_event_breakpoint(EventBreakpoint::Draw));
+        debugger.add_event_breakpoint(EventBreakpoint::Draw);
+        assert_eq!(
+            debugger
+                .event_breakpoints()
+                .map(|event| event.to_string())
+                .collect::<Vec<_>>(),
+            ["draw", "key 4", "key 5"]
+        );
+    }
+
+    #[test]
+    fn test_instruction_tracer() {
+        let mut chip8 = counting_machine();
+        let tracer = Rc::new(RefCell::new(InstructionTracer::new(3)));
+        chip8.add_hook(Box::new(tracer.clone()));
+        for _ in 0..5 {
+            chip8.emulate_cycle().unwrap();
+        }
+        let tracer = tracer.borrow();
+        let entries: Vec<_> = tracer.entries().collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!((entries[0].pc, entries[0].instruction), (0x200, 0x7001));
+        // the registers from before it ran
+        assert_eq!(entries[0].registers[0], 1);
+        assert_eq!((entries[1].pc, entries[1].instruction), (0x202, 0x1200));
+        assert_eq!(entries[2].registers[0], 2);
+    }
+
+    #[test]
+    fn test_call_stack() {
+        // call 0x206, which calls 0x20A, which returns twice
+        let mut chip8 = create_chip8();
+        chip8
+            .load_rom_bytes(&[
+                0x22, 0x06, 0x12, 0x04, 0x00, 0x00, 0x22, 0x0A, 0x00, 0xEE, 0x00, 0xEE,
+            ])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        debugger.trace_calls(&mut chip8);
+        run(&mut debugger, &mut chip8, 2);
+        assert_eq!(
+            call_stack(&chip8),
+            vec![
+                Frame {
+                    call_site: 0x200,
+                    subroutine: 0x206
+                },
+                Frame {
+                    call_site: 0x206,
+                    subroutine: 0x20A
+                },
+            ]
+        );
+        assert_eq!(debugger.label(0x20A), "sub_20A");
+        let mut symbols = Symbols::default();
+        symbols.insert("draw", 0x20A);
+        debugger.set_symbols(symbols);
+        assert_eq!(debugger.label(0x20A), "draw");
+
+        run(&mut debugger, &mut chip8, 3);
+        assert!(call_stack(&chip8).is_empty());
+        assert_eq!(
+            debugger.calls(3),
+            vec![
+                CallEvent::Call {
+                    from: 0x206,
+                    to: 0x20A,
+                    depth: 2
+                },
+                CallEvent::Return {
+                    from: 0x20A,
+                    to: 0x208,
+                    depth: 2
+                },
+                CallEvent::Return {
+                    from: 0x208,
+                    to: 0x202,
+                    depth: 1
+                },
+            ]
+        );
+        assert_eq!(debugger.calls(10).len(), 4);
+    }
+
+    #[test]
+    fn test_step_and_pause() {
+        let mut chip8 = counting_machine();
+        let mut debugger = Debugger::new();
+        debugger.pause(&chip8);
+        assert_eq!(debugger.take_stop(), Some(Stop::Pause(0x200)));
+        run(&mut debugger, &mut chip8, 5);
+        assert_eq!(chip8.pc(), 0x200);
+
+        debugger.step(&chip8, 3);
+        run(&mut debugger, &mut chip8, 10);
+        assert_eq!(debugger.take_stop(), Some(Stop::Step(0x202)));
+        assert_eq!(chip8.registers()[0], 2);
+        assert!(debugger.is_paused());
+    }
+    #[test]
+    fn test_pixel_writers() {
+        // I = 0x20A, V1 = 2, draw the 2 pixel wide sprite at 2, 2 twice, loop forever
+        let mut chip8 = create_chip8();
+        chip8
+            .load_rom_bytes(&[
+                0xA2, 0x0A, 0x61, 0x02, 0xD1, 0x11, 0xD1, 0x11, 0x12, 0x08, 0xC0,
+            ])
+            .unwrap();
+        let mut debugger = Debugger::new();
+        assert!(debugger.pixel_writers(2, 2).is_empty());
+        debugger.trace_draws(&mut chip8);
+        for _ in 0..3 {
+            chip8.emulate_cycle().unwrap();
+        }
+        let first = Draw {
+            pc: 0x204,
+            index: 0x20A,
+            x: 2,
+            y: 2,
+            sprite: vec![0xC0],
+        };
+        assert_eq!(debugger.pixel_writers(3, 2), vec![first.clone()]);
+        assert!(debugger.pixel_writers(4, 2).is_empty());
+        assert!(debugger.pixel_writers(100, 2).is_empty());
+
+        // erased again, by the one after it
+        chip8.emulate_cycle().unwrap();
+        let writers = debugger.pixel_writers(2, 2);
+        assert_eq!(writers.len(), 2);
+        assert_eq!(writers[0].pc, 0x206);
+        assert_eq!(writers[1], first);
+    }
+
+    #[test]
+    fn test_reverse_step() {
+        // V1 = random, V0 += 1, both stored at 0x300, around again
+        let rom = [0xC1, 0xFF, 0x70, 0x01, 0xA3, 0x00, 0xF1, 0x55, 0x12, 0x00];
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let mut debugger = Debugger::new();
+        assert!(debugger.reverse_step(&mut chip8, 1).is_err());
+        debugger.record_history(&mut chip8);
+        // the machine before every instruction, to compare with
+        let mut past = Vec::new();
+        for _ in 0..600 {
+            past.push(chip8.snapshot());
+            run(&mut debugger, &mut chip8, 1);
+        }
+
+        debugger.reverse_step(&mut chip8, 1).unwrap();
+        assert_eq!(chip8.snapshot(), past[599]);
+        assert!(debugger.is_paused());
+        assert_eq!(debugger.take_stop(), Some(Stop::Step(chip8.pc())));
+        // back across a snapshot or two
+        debugger.reverse_step(&mut chip8, 300).unwrap();
+        assert_eq!(chip8.snapshot(), past[299]);
+        assert_eq!(
+            debugger.reverse_step(&mut chip8, 300),
+            Err("can only go back 299 instructions".to_string())
+        );
+
+        // running on from there takes another way, with other random numbers
+        past.truncate(299);
+        debugger.resume(&chip8);
+        for _ in 0..10 {
+            past.push(chip8.snapshot());
+            run(&mut debugger, &mut chip8, 1);
+        }
+        debugger.reverse_step(&mut chip8, 7).unwrap();
+        assert_eq!(chip8.snapshot(), past[302]);
+
+        // the last time V0 was 25 at 0x208
+        let symbols = Symbols::default();
+        debugger
+            .add_conditional_breakpoint(0x208, Expression::parse("v0 == 25", &symbols).unwrap());
+        debugger.reverse_continue(&mut chip8).unwrap();
+        assert_eq!(chip8.snapshot(), past[124]);
+        assert_eq!(debugger.take_stop(), Some(Stop::Breakpoint(0x208)));
+        debugger.remove_breakpoint(0x208);
+        assert!(debugger.reverse_continue(&mut chip8).is_err());
+
+        debugger.clear_history();
+        assert!(debugger.reverse_step(&mut chip8, 1).is_err());
+    }
+
+    #[test]
+    fn test_reverse_step_elsewhere() {
+        // V0 += 1 and around again, loaded and started at 0x600
+        let layout = Layout::ETI660;
+        let mut chip8 = Chip8::builder().layout(layout).timing(Timing::Vip).build();
+        chip8.load_rom_bytes(&[0x70, 0x01, 0x16, 0x00]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.record_history(&mut chip8);
+        let mut past = Vec::new();
+        for _ in 0..10 {
+            past.push(chip8.snapshot());
+            run(&mut debugger, &mut chip8, 1);
+        }
+        debugger.reverse_step(&mut chip8, 4).unwrap();
+        assert_eq!(chip8.snapshot(), past[6]);
+        assert_eq!(chip8.layout(), layout);
+        assert_eq!(chip8.pc(), 0x600);
+
+        // a peripheral's answers can't be asked for again
+        struct Nothing;
+        impl Peripheral for Nothing {}
+        chip8.attach(Box::new(Nothing));
+        debugger.resume(&chip8);
+        run(&mut debugger, &mut chip8, 2);
+        assert_eq!(
+            debugger.reverse_step(&mut chip8, 1),
+            Err("can't go back with peripherals attached".to_string())
+        );
+        assert!(debugger.reverse_continue(&mut chip8).is_err());
+    }
+}
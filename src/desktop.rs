@@ -0,0 +1,157 @@
+use crate::icon;
+use crate::rom::ROM_EXTENSIONS;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// what ROM files are registered as
+const MIME_TYPE: &str = "application/x-chip8-rom";
+const DESKTOP_FILE: &str = "chip-8.desktop";
+const ICON_NAME: &str = "chip-8";
+const ICON_SIZE: usize = 64;
+
+// install-desktop-entry: writes a menu entry that runs `exe`, a MIME type for ROM files and the
+// icon for both under `data_dir` (~/.local/share). returns the files written
+pub fn install(data_dir: &Path, exe: &Path) -> Result<Vec<PathBuf>, String> {
+    let files = [
+        (
+            data_dir.join("applications").join(DESKTOP_FILE),
+            desktop_entry(exe).into_bytes(),
+        ),
+        (
+            data_dir
+                .join("mime")
+                .join("packages")
+                .join(format!("{}.xml", ICON_NAME)),
+            mime_info().into_bytes(),
+        ),
+        (
+            data_dir
+                .join("icons")
+                .join("hicolor")
+                .join(format!("{0}x{0}", ICON_SIZE))
+                .join("apps")
+                .join(format!("{}.png", ICON_NAME)),
+            icon::encode_png(ICON_SIZE),
+        ),
+    ];
+    let mut written = Vec::new();
+    for (path, contents) in files {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+        }
+        fs::write(&path, contents)
+            .map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+// have the desktop pick up what `install` wrote and open ROMs with the emulator. the tools
+// come with most desktops, returns the ones that failed or aren't there
+pub fn refresh(data_dir: &Path) -> Vec<String> {
+    let mime_dir = data_dir.join("mime");
+    let applications_dir = data_dir.join("applications");
+    let commands: [(&str, Vec<&std::ffi::OsStr>); 3] = [
+        ("update-mime-database", vec![mime_dir.as_os_str()]),
+        (
+            "update-desktop-database",
+            vec![applications_dir.as_os_str()],
+        ),
+        (
+            "xdg-mime",
+            vec![
+                "default".as_ref(),
+                DESKTOP_FILE.as_ref(),
+                MIME_TYPE.as_ref(),
+            ],
+        ),
+    ];
+    commands
+        .iter()
+        .filter(|(program, args)| {
+            !Command::new(program)
+                .args(args)
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+        .map(|(program, _)| program.to_string())
+        .collect()
+}
+
+fn desktop_entry(exe: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=CHIP-8\n\
+         GenericName=CHIP-8 Emulator\n\
+         Comment=Play CHIP-8, SUPER-CHIP and CHIP-8X games\n\
+         Exec={} %f\n\
+         Icon={}\n\
+         Terminal=false\n\
+         Categories=Game;Emulator;\n\
+         MimeType={};\n",
+        exec_quote(&exe.to_string_lossy()),
+        ICON_NAME,
+        MIME_TYPE
+    )
+}
+
+fn mime_info() -> String {
+    let globs: String = ROM_EXTENSIONS
+        .iter()
+        .map(|extension| format!("    <glob pattern=\"*.{}\"/>\n", extension))
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <mime-info xmlns=\"http://www.freedesktop.org/standards/shared-mime-info\">\n  \
+           <mime-type type=\"{}\">\n    \
+             <comment>CHIP-8 ROM</comment>\n    \
+             <icon name=\"{}\"/>\n\
+         {}  \
+           </mime-type>\n\
+         </mime-info>\n",
+        MIME_TYPE, ICON_NAME, globs
+    )
+}
+
+// a path as an Exec argument: quoted, with the quoting's escapes escaped again for the
+// desktop file's strings, and % doubled so it isn't taken for a field code
+fn exec_quote(path: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        match c {
+            '"' | '`' | '$' => quoted += &format!("\\\\{}", c),
+            '\\' => quoted += "\\\\\\\\",
+            '%' => quoted += "%%",
+            c => quoted.push(c),
+        }
+    }
+    quoted + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install() {
+        let dir = std::env::temp_dir().join(format!("chip8-desktop-{}", std::process::id()));
+        let written = install(&dir, Path::new("/opt/my games/chip-8")).unwrap();
+        assert_eq!(written.len(), 3);
+        let entry = fs::read_to_string(dir.join("applications/chip-8.desktop")).unwrap();
+        assert!(entry.contains("\nExec=\"/opt/my games/chip-8\" %f\n"));
+        assert!(entry.contains("\nMimeType=application/x-chip8-rom;\n"));
+        let mime = fs::read_to_string(dir.join("mime/packages/chip-8.xml")).unwrap();
+        assert!(mime.contains("  <mime-type type=\"application/x-chip8-rom\">\n"));
+        assert!(mime.contains("    <glob pattern=\"*.ch8\"/>\n    <glob pattern=\"*.c8\"/>\n"));
+        assert!(mime.ends_with("/>\n  </mime-type>\n</mime-info>\n"));
+        let icon = fs::read(dir.join("icons/hicolor/64x64/apps/chip-8.png")).unwrap();
+        assert!(icon.starts_with(b"\x89PNG"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(exec_quote("/a\\b/$x/100%"), "\"/a\\\\\\\\b/\\\\$x/100%%\"");
+    }
+}
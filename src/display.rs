@@ -0,0 +1,705 @@
+use chip_8::{Chip8, Variant, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+use clap::ValueEnum;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{BlendMode, Texture, WindowCanvas};
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+// how much of a pixel's brightness is left after each timer tick once it's switched off
+const GLOW_DECAY: f32 = 0.6;
+// below this a fading pixel is considered off
+const GLOW_CUTOFF: f32 = 0.05;
+// how long a chip-8 frame is, what --persistence fades by
+const FRAME_MS: f32 = 1000.0 / 60.0;
+pub const MAX_PERSISTENCE_MS: u32 = 5000;
+// darkness of the gap between rows, 0 to 255
+const SCANLINE_ALPHA: u8 = 96;
+// the grid lines between pixels, faint against both the foreground and the background
+const GRID_COLOR: Color = Color::RGBA(128, 128, 128, 64);
+// the least window pixels a chip-8 pixel needs for a grid, any smaller and it's all lines
+const MIN_GRID_CELL: u32 = 4;
+// how much darker the corners are than the center with the crt filter
+const VIGNETTE_STRENGTH: f32 = 0.3;
+pub const MAX_BLEND_FRAMES: usize = 16;
+// what a display without any scaling has, and what window sizes are meant for
+const STANDARD_DPI: f32 = 96.0;
+// the VP-590 colors by CHIP-8X color index
+const CHIP8X_FOREGROUND_COLORS: [Color; 8] = [
+    Color::RGB(0x00, 0x00, 0x00), // black
+    Color::RGB(0xFF, 0x00, 0x00), // red
+    Color::RGB(0x00, 0x00, 0xFF), // blue
+    Color::RGB(0xFF, 0x00, 0xFF), // violet
+    Color::RGB(0x00, 0xFF, 0x00), // green
+    Color::RGB(0xFF, 0xFF, 0x00), // yellow
+    Color::RGB(0x00, 0xFF, 0xFF), // aqua
+    Color::RGB(0xFF, 0xFF, 0xFF), // white
+];
+const CHIP8X_BACKGROUND_COLORS: [Color; 4] = [
+    Color::RGB(0x00, 0x00, 0x80), // dark blue
+    Color::RGB(0x00, 0x00, 0x00), // black
+    Color::RGB(0x00, 0x80, 0x00), // green
+    Color::RGB(0x80, 0x00, 0x00), // red
+];
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    None,
+    // dark gaps between the rows
+    Scanlines,
+    // switched off pixels fade out over a few frames instead of disappearing, which hides most
+    // of the flicker from XOR drawing
+    Glow,
+    // glow and scanlines with the edges shaded a bit darker
+    Crt,
+}
+
+impl Filter {
+    fn glow(self) -> bool {
+        matches!(self, Filter::Glow | Filter::Crt)
+    }
+
+    fn scanlines(self) -> bool {
+        matches!(self, Filter::Scanlines | Filter::Crt)
+    }
+}
+
+// how the last few frames are combined with --blend
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Blend {
+    // a pixel lit in any of them is lit, sprites look solid
+    Or,
+    // a pixel's brightness is the fraction of frames it was lit in
+    Average,
+}
+
+// how the chip-8 screen fills the window
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaling {
+    // whole multiples only, every chip-8 pixel the same size. leaves a border
+    Integer,
+    // as large as fits with the aspect ratio kept, letterboxed
+    Fit,
+    // the whole window, pixels come out wider or taller than they should
+    Stretch,
+}
+
+impl Scaling {
+    pub fn name(self) -> &'static str {
+        match self {
+            Scaling::Integer => "integer",
+            Scaling::Fit => "fit",
+            Scaling::Stretch => "stretch",
+        }
+    }
+
+    // for switching between them at runtime
+    pub fn next(self) -> Scaling {
+        match self {
+            Scaling::Integer => Scaling::Fit,
+            Scaling::Fit => Scaling::Stretch,
+            Scaling::Stretch => Scaling::Integer,
+        }
+    }
+}
+
+// post-processing between the chip-8 framebuffer and the window: the frame is turned into RGB
+// pixels, uploaded to the top left of a texture (big enough for every variant) and stretched
+// over the viewport
+pub struct Screen {
+    pub filter: Filter,
+    // size of the chip-8 display in pixels
+    width: usize,
+    height: usize,
+    lit: Vec<bool>,
+    // CHIP-8X foreground color index of every pixel and the background color index
+    chip8x_colors: Option<(Vec<u8>, u8)>,
+    // brightness of every pixel, 0.0 to 1.0
+    intensity: Vec<f32>,
+    // frames to blend, 1 turns blending off
+    blend_frames: usize,
+    blend: Blend,
+    // the last blend_frames complete frames, newest last
+    history: VecDeque<Vec<bool>>,
+    // --persistence: how bright the phosphor behind every pixel still is, and what's left of
+    // it after each frame. None when it's off
+    phosphor: Option<(Vec<f32>, f32)>,
+    // nothing copied yet, take the whole framebuffer instead of just the dirty rows
+    fresh: bool,
+}
+
+impl Screen {
+    pub fn new(filter: Filter, blend_frames: usize, blend: Blend) -> Screen {
+        Screen {
+            filter,
+            width: DISPLAY_WIDTH,
+            height: DISPLAY_HEIGHT,
+            lit: vec![false; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            chip8x_colors: None,
+            intensity: vec![0.0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            blend_frames: blend_frames.clamp(1, MAX_BLEND_FRAMES),
+            blend,
+            history: VecDeque::new(),
+            phosphor: None,
+            fresh: true,
+        }
+    }
+
+    // switched off pixels fade out exponentially, down to nothing after `ms` milliseconds of
+    // chip-8 time, like the phosphor of the original displays. 0 is off
+    pub fn persistence(mut self, ms: u32) -> Screen {
+        self.phosphor = (ms > 0).then(|| {
+            let decay = GLOW_CUTOFF.powf(FRAME_MS / ms as f32);
+            (vec![0.0; self.width * self.height], decay)
+        });
+        self
+    }
+
+    // take the current frame. with glow, pixels that were switched off keep their brightness
+    // until they fade
+    pub fn update(&mut self, chip8: &Chip8) {
+        let height = chip8.display_height();
+        if height != self.height {
+            // a different variant, start over
+            let phosphor = self.phosphor.take();
+            *self = Screen::new(self.filter, self.blend_frames, self.blend);
+            self.height = height;
+            self.lit = vec![false; self.width * height];
+            self.intensity = vec![0.0; self.width * height];
+            self.phosphor = phosphor.map(|(_, decay)| (vec![0.0; self.width * height], decay));
+        }
+        self.chip8x_colors = if chip8.variant() == Variant::Chip8x {
+            let foreground = (0..self.width * self.height)
+                .map(|i| chip8.foreground_color(i % self.width, i / self.width))
+                .collect();
+            Some((foreground, chip8.background_color()))
+        } else {
+            None
+        };
+        let glow = self.filter.glow();
+        // only rows the chip-8 touched since the last frame need copying
+        let rows: Vec<usize> = if self.fresh {
+            self.fresh = false;
+            (0..height).collect()
+        } else {
+            chip8.dirty_rows().collect()
+        };
+        for row in rows {
+            for (x, on) in chip8.framebuffer().row(row).enumerate() {
+                let i = row * self.width + x;
+                self.lit[i] = on;
+                if on {
+                    self.intensity[i] = 1.0;
+                    if let Some((phosphor, _)) = &mut self.phosphor {
+                        phosphor[i] = 1.0;
+                    }
+                } else if !glow {
+                    self.intensity[i] = 0.0;
+                }
+            }
+        }
+    }
+
+    // to be called at the timer rate, once per chip-8 frame. returns true when the picture
+    // changed without the chip-8 drawing anything, so the frontend knows to redraw
+    pub fn tick(&mut self) -> bool {
+        let fading = self.fade() | self.decay();
+        if self.blend_frames < 2 {
+            return fading;
+        }
+        if self.history.len() == self.blend_frames {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.lit.clone());
+        true
+    }
+
+    fn fade(&mut self) -> bool {
+        if !self.filter.glow() {
+            return false;
+        }
+        let mut fading = false;
+        for (intensity, &lit) in self.intensity.iter_mut().zip(&self.lit) {
+            if !lit && *intensity > 0.0 {
+                *intensity *= GLOW_DECAY;
+                if *intensity < GLOW_CUTOFF {
+                    *intensity = 0.0;
+                }
+                fading = true;
+            }
+        }
+        fading
+    }
+
+    // the phosphor of switched off pixels loses some of its brightness, lit ones stay at full
+    fn decay(&mut self) -> bool {
+        let (phosphor, decay) = match &mut self.phosphor {
+            Some(phosphor) => phosphor,
+            None => return false,
+        };
+        let mut fading = false;
+        for (level, &lit) in phosphor.iter_mut().zip(&self.lit) {
+            if !lit && *level > 0.0 {
+                *level *= *decay;
+                if *level < GLOW_CUTOFF {
+                    *level = 0.0;
+                }
+                fading = true;
+            }
+        }
+        fading
+    }
+
+    // RGB24 pixel data for the texture
+    pub fn pixels(&self, foreground: Color, background: Color) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.lit.len() * 3);
+        for (i, &intensity) in self.intensity.iter().enumerate() {
+            // once blending has frames to work with, the picture only changes at frame boundaries,
+            // so pixels erased and redrawn mid-frame don't show. glow still fades pixels out
+            let intensity = if self.history.is_empty() {
+                intensity
+            } else if self.lit[i] {
+                self.blended(i)
+            } else {
+                self.blended(i).max(intensity)
+            };
+            // the phosphor glows on under whatever the filters make of the pixel
+            let intensity = match &self.phosphor {
+                Some((phosphor, _)) => intensity.max(phosphor[i]),
+                None => intensity,
+            };
+            let shade = if self.filter == Filter::Crt {
+                vignette(i % self.width, i / self.width, (self.width, self.height))
+            } else {
+                1.0
+            };
+            // CHIP-8X ROMs pick their own colors
+            let (foreground, background) = match &self.chip8x_colors {
+                Some((zones, background)) => (
+                    CHIP8X_FOREGROUND_COLORS[zones[i] as usize],
+                    CHIP8X_BACKGROUND_COLORS[*background as usize],
+                ),
+                None => (foreground, background),
+            };
+            for (fg, bg) in [
+                (foreground.r, background.r),
+                (foreground.g, background.g),
+                (foreground.b, background.b),
+            ] {
+                let value = bg as f32 + (fg as f32 - bg as f32) * intensity;
+                pixels.push((value * shade).round() as u8);
+            }
+        }
+        pixels
+    }
+
+    // the frames in the history combined
+    fn blended(&self, i: usize) -> f32 {
+        let lit = self.history.iter().filter(|frame| frame[i]).count();
+        match self.blend {
+            Blend::Or if lit > 0 => 1.0,
+            Blend::Or => 0.0,
+            Blend::Average => lit as f32 / self.history.len() as f32,
+        }
+    }
+
+    pub fn draw(
+        &self,
+        canvas: &mut WindowCanvas,
+        texture: &mut Texture,
+        viewport: Rect,
+        foreground: Color,
+        background: Color,
+    ) -> Result<(), String> {
+        let source = Rect::new(0, 0, self.width as u32, self.height as u32);
+        texture
+            .update(source, &self.pixels(foreground, background), self.width * 3)
+            .map_err(|e| e.to_string())?;
+        canvas.copy(texture, source, viewport)?;
+        if self.filter.scanlines() {
+            canvas.set_blend_mode(BlendMode::Blend);
+            canvas.set_draw_color(Color::RGBA(0, 0, 0, SCANLINE_ALPHA));
+            for y in 0..self.height {
+                // the bottom third of every row, at least one window pixel
+                let row = pixel_rect(viewport, 0, y, (self.width, self.height));
+                let height = (row.height() / 3).max(1);
+                canvas.fill_rect(Rect::new(
+                    viewport.x(),
+                    row.bottom() - height as i32,
+                    viewport.width(),
+                    height,
+                ))?;
+            }
+            canvas.set_blend_mode(BlendMode::None);
+        }
+        Ok(())
+    }
+
+    // width and height of the chip-8 display, as of the last update
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+// --grid: a line along the left and top edge of every chip-8 pixel but the first, drawn over
+// the screen
+pub fn draw_grid(
+    canvas: &mut WindowCanvas,
+    viewport: Rect,
+    size: (usize, usize),
+) -> Result<(), String> {
+    let lines = grid_lines(viewport, size);
+    if lines.is_empty() {
+        return Ok(());
+    }
+    canvas.set_blend_mode(BlendMode::Blend);
+    canvas.set_draw_color(GRID_COLOR);
+    for (start, end) in lines {
+        canvas.draw_line(start, end)?;
+    }
+    canvas.set_blend_mode(BlendMode::None);
+    Ok(())
+}
+
+// none when the pixels are too small for lines between them
+fn grid_lines(viewport: Rect, (width, height): (usize, usize)) -> Vec<(Point, Point)> {
+    if viewport.width() < width as u32 * MIN_GRID_CELL
+        || viewport.height() < height as u32 * MIN_GRID_CELL
+    {
+        return Vec::new();
+    }
+    let columns = (1..width).map(|x| {
+        let left = pixel_rect(viewport, x, 0, (width, height)).left();
+        (
+            Point::new(left, viewport.top()),
+            Point::new(left, viewport.bottom() - 1),
+        )
+    });
+    let rows = (1..height).map(|y| {
+        let top = pixel_rect(viewport, 0, y, (width, height)).top();
+        (
+            Point::new(viewport.left(), top),
+            Point::new(viewport.right() - 1, top),
+        )
+    });
+    columns.chain(rows).collect()
+}
+
+// 1.0 in the center falling off towards the corners
+fn vignette(x: usize, y: usize, (width, height): (usize, usize)) -> f32 {
+    let dx = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+    let dy = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+    1.0 - VIGNETTE_STRENGTH * (dx * dx + dy * dy) / 2.0
+}
+
+// the largest area with the aspect ratio of a `width` x `height` display that fits the window,
+// centered so the rest is letterboxed. with integer scaling each chip-8 pixel is a whole number
+// of window pixels, stretching ignores the aspect ratio and takes all of it
+pub fn viewport(
+    output_width: u32,
+    output_height: u32,
+    (width, height): (usize, usize),
+    scaling: Scaling,
+) -> Rect {
+    let (width, height) = (width as u32, height as u32);
+    let (viewport_width, viewport_height) = match scaling {
+        Scaling::Integer => {
+            let scale = (output_width / width).min(output_height / height).max(1);
+            (width * scale, height * scale)
+        }
+        Scaling::Stretch => (output_width, output_height),
+        // narrower than the display, bars above and below
+        Scaling::Fit if output_width * height < output_height * width => {
+            (output_width, output_width * height / width)
+        }
+        Scaling::Fit => (output_height * width / height, output_height),
+    };
+    Rect::new(
+        (output_width as i32 - viewport_width as i32) / 2,
+        (output_height as i32 - viewport_height as i32) / 2,
+        viewport_width.max(1),
+        viewport_height.max(1),
+    )
+}
+
+// the window size for `size` on a display with `dpi` dots per inch. where the system scales
+// windows itself (macOS, Wayland) the drawable area is already bigger than the window and it's
+// left alone. elsewhere it grows by how much denser than the usual 96 DPI the display is, so
+// the scale factor looks the same on a 4K monitor as on an old one
+pub fn hidpi_window_size(
+    (width, height): (u32, u32),
+    drawable_width: u32,
+    dpi: Option<f32>,
+) -> (u32, u32) {
+    let scale = match dpi {
+        Some(dpi) if drawable_width <= width && dpi > STANDARD_DPI => dpi / STANDARD_DPI,
+        _ => return (width, height),
+    };
+    (
+        (width as f32 * scale).round() as u32,
+        (height as f32 * scale).round() as u32,
+    )
+}
+
+// the window split into `count` columns of equal width, with a viewport in each, left to right
+pub fn column_viewports(
+    output_width: u32,
+    output_height: u32,
+    size: (usize, usize),
+    count: u32,
+    scaling: Scaling,
+) -> Vec<Rect> {
+    let column_width = output_width / count;
+    (0..count)
+        .map(|column| {
+            let mut viewport = viewport(column_width, output_height, size, scaling);
+            viewport.offset((column * column_width) as i32, 0);
+            viewport
+        })
+        .collect()
+}
+
+// the chip-8 pixel at window position (x, y), None outside the viewport
+pub fn pixel_at(
+    viewport: Rect,
+    (width, height): (usize, usize),
+    x: i32,
+    y: i32,
+) -> Option<(usize, usize)> {
+    if !viewport.contains_point((x, y)) {
+        return None;
+    }
+    let column = (x - viewport.x()) as usize * width / viewport.width() as usize;
+    let row = (y - viewport.y()) as usize * height / viewport.height() as usize;
+    Some((column, row))
+}
+
+// the window area covered by chip-8 pixel (x, y). edges are rounded so that neighbouring pixels
+// meet without gaps when the scale isn't a whole number
+fn pixel_rect(viewport: Rect, x: usize, y: usize, (width, height): (usize, usize)) -> Rect {
+    let left = viewport.width() as usize * x / width;
+    let right = viewport.width() as usize * (x + 1) / width;
+    let top = viewport.height() as usize * y / height;
+    let bottom = viewport.height() as usize * (y + 1) / height;
+    Rect::new(
+        viewport.x() + left as i32,
+        viewport.y() + top as i32,
+        (right - left).max(1) as u32,
+        (bottom - top).max(1) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+
+    const STANDARD: (usize, usize) = (DISPLAY_WIDTH, DISPLAY_HEIGHT);
+
+    #[test]
+    fn test_viewport() {
+        // exact fit
+        assert_eq!(
+            viewport(640, 320, STANDARD, Scaling::Fit),
+            Rect::new(0, 0, 640, 320)
+        );
+        // 16:9 is narrower than 2:1, bars above and below
+        assert_eq!(
+            viewport(3840, 2160, STANDARD, Scaling::Fit),
+            Rect::new(0, 120, 3840, 1920)
+        );
+        // very wide window, bars on the sides
+        assert_eq!(
+            viewport(1000, 300, STANDARD, Scaling::Fit),
+            Rect::new(200, 0, 600, 300)
+        );
+        // integer scaling leaves a border instead of stretching
+        assert_eq!(
+            viewport(1000, 600, STANDARD, Scaling::Integer),
+            Rect::new(20, 60, 960, 480)
+        );
+        // never smaller than one pixel per chip-8 pixel
+        assert_eq!(
+            viewport(10, 10, STANDARD, Scaling::Integer),
+            Rect::new(-27, -11, 64, 32)
+        );
+        // hires is square
+        assert_eq!(
+            viewport(1000, 600, (64, 64), Scaling::Fit),
+            Rect::new(200, 0, 600, 600)
+        );
+        assert_eq!(
+            viewport(1000, 600, STANDARD, Scaling::Stretch),
+            Rect::new(0, 0, 1000, 600)
+        );
+    }
+
+    #[test]
+    fn test_hidpi_window_size() {
+        // a normal display, or no idea
+        assert_eq!(hidpi_window_size((640, 320), 640, Some(96.0)), (640, 320));
+        assert_eq!(hidpi_window_size((640, 320), 640, None), (640, 320));
+        // 4K at 200%
+        assert_eq!(hidpi_window_size((640, 320), 640, Some(192.0)), (1280, 640));
+        // a Retina Mac already gives the window twice the pixels
+        assert_eq!(hidpi_window_size((640, 320), 1280, Some(220.0)), (640, 320));
+    }
+
+    #[test]
+    fn test_pixel_at() {
+        let viewport = Rect::new(10, 20, 640, 320);
+        assert_eq!(pixel_at(viewport, STANDARD, 10, 20), Some((0, 0)));
+        assert_eq!(pixel_at(viewport, STANDARD, 29, 39), Some((1, 1)));
+        assert_eq!(pixel_at(viewport, STANDARD, 649, 339), Some((63, 31)));
+        assert_eq!(pixel_at(viewport, STANDARD, 650, 30), None);
+        assert_eq!(pixel_at(viewport, STANDARD, 5, 30), None);
+    }
+
+    #[test]
+    fn test_column_viewports() {
+        assert_eq!(
+            column_viewports(1280, 320, STANDARD, 2, Scaling::Fit),
+            [Rect::new(0, 0, 640, 320), Rect::new(640, 0, 640, 320)]
+        );
+        // a taller window, bars above and below both
+        assert_eq!(
+            column_viewports(1280, 600, STANDARD, 2, Scaling::Integer),
+            [Rect::new(0, 140, 640, 320), Rect::new(640, 140, 640, 320)]
+        );
+    }
+
+    // the first pixel of the screen after showing a frame where only it is (or isn't) lit
+    fn first_pixel(screen: &mut Screen, lit: bool) -> u8 {
+        let mut chip8 = create_chip8();
+        chip8.set_pixel(0, 0, lit);
+        screen.update(&chip8);
+        screen.pixels(Color::WHITE, Color::BLACK)[0]
+    }
+
+    #[test]
+    fn test_glow() {
+        let mut screen = Screen::new(Filter::Glow, 1, Blend::Or);
+        assert_eq!(first_pixel(&mut screen, true), 255);
+        // switched off but still fully bright until the next tick
+        assert_eq!(first_pixel(&mut screen, false), 255);
+        assert!(screen.fade());
+        assert_eq!(screen.pixels(Color::WHITE, Color::BLACK)[0], 153);
+        while screen.fade() {}
+        assert_eq!(screen.pixels(Color::WHITE, Color::BLACK)[0], 0);
+
+        // without glow pixels go straight off
+        let mut screen = Screen::new(Filter::Scanlines, 1, Blend::Or);
+        first_pixel(&mut screen, true);
+        assert_eq!(first_pixel(&mut screen, false), 0);
+        assert!(!screen.fade());
+    }
+
+    #[test]
+    fn test_persistence() {
+        // 100ms is six frames to fade out over, works alongside blending
+        let mut screen = Screen::new(Filter::None, 2, Blend::Or).persistence(100);
+        assert_eq!(first_pixel(&mut screen, true), 255);
+        // erased mid-frame, the phosphor still glows
+        assert_eq!(first_pixel(&mut screen, false), 255);
+        let mut levels = Vec::new();
+        while screen.decay() {
+            levels.push(screen.phosphor.as_ref().unwrap().0[0]);
+        }
+        assert!(levels.windows(2).all(|pair| pair[1] < pair[0]));
+        // down to the cutoff after six, whether rounding takes one more to reach nothing
+        assert!(levels[0] > 0.6 && levels[4] > GLOW_CUTOFF);
+        assert!(levels.len() <= 7 && levels[levels.len() - 1] == 0.0);
+
+        // carried over to a different variant
+        let mut chip8 = create_chip8();
+        chip8.set_variant(Variant::Hires);
+        screen.update(&chip8);
+        assert_eq!(screen.phosphor.unwrap().0.len(), 64 * 64);
+
+        let mut off = Screen::new(Filter::None, 1, Blend::Or).persistence(0);
+        first_pixel(&mut off, true);
+        assert_eq!(first_pixel(&mut off, false), 0);
+        assert!(!off.tick());
+    }
+
+    #[test]
+    fn test_blend() {
+        // the sprite is drawn, ticked, erased and drawn again: the usual flicker
+        let frames = [true, false, true, false];
+        let mut or = Screen::new(Filter::None, 2, Blend::Or);
+        let mut average = Screen::new(Filter::None, 4, Blend::Average);
+        for lit in frames {
+            first_pixel(&mut or, lit);
+            assert!(or.tick());
+            first_pixel(&mut average, lit);
+            assert!(average.tick());
+        }
+        // erased in the last frame but lit in the one before
+        assert_eq!(or.history.len(), 2);
+        assert_eq!(or.pixels(Color::WHITE, Color::BLACK)[0], 255);
+        // lit in two of the last four, drawing mid-frame doesn't show until the next tick
+        assert_eq!(average.history.len(), 4);
+        assert_eq!(first_pixel(&mut average, true), 128);
+
+        // blending off keeps no history
+        let mut plain = Screen::new(Filter::None, 1, Blend::Or);
+        first_pixel(&mut plain, true);
+        assert!(!plain.tick());
+        assert_eq!(first_pixel(&mut plain, false), 0);
+    }
+
+    #[test]
+    fn test_variants() {
+        let mut screen = Screen::new(Filter::None, 2, Blend::Or);
+        let mut chip8 = create_chip8();
+        screen.update(&chip8);
+        screen.tick();
+        chip8.set_variant(Variant::Hires);
+        screen.update(&chip8);
+        assert_eq!(screen.size(), (64, 64));
+        assert!(screen.history.is_empty());
+        assert_eq!(screen.pixels(Color::WHITE, Color::BLACK).len(), 64 * 64 * 3);
+
+        // CHIP-8X ignores the palette: lit pixels are red on dark blue at power-on
+        chip8.set_variant(Variant::Chip8x);
+        chip8.set_pixel(0, 0, true);
+        screen.update(&chip8);
+        let pixels = screen.pixels(Color::WHITE, Color::BLACK);
+        assert_eq!(pixels[..6], [0xFF, 0x00, 0x00, 0x00, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn test_vignette() {
+        let center = vignette(DISPLAY_WIDTH / 2, DISPLAY_HEIGHT / 2, STANDARD);
+        let corner = vignette(0, 0, STANDARD);
+        assert!(center > 0.99);
+        assert!(corner < center && corner > 1.0 - VIGNETTE_STRENGTH);
+    }
+
+    #[test]
+    fn test_grid_lines() {
+        // 3 window pixels a chip-8 pixel is too small
+        assert!(grid_lines(Rect::new(0, 0, 192, 96), STANDARD).is_empty());
+        let lines = grid_lines(Rect::new(10, 20, 640, 320), STANDARD);
+        assert_eq!(lines.len(), 63 + 31);
+        assert_eq!(lines[0], (Point::new(20, 20), Point::new(20, 339)));
+        assert_eq!(lines[63], (Point::new(10, 30), Point::new(649, 30)));
+    }
+
+    #[test]
+    fn test_pixel_rect() {
+        let viewport = Rect::new(10, 20, 100, 50);
+        assert_eq!(
+            pixel_rect(viewport, 0, 0, STANDARD),
+            Rect::new(10, 20, 1, 1)
+        );
+        // the last pixel ends exactly at the viewport edge
+        let last = pixel_rect(viewport, DISPLAY_WIDTH - 1, DISPLAY_HEIGHT - 1, STANDARD);
+        assert_eq!(last.right(), viewport.right());
+        assert_eq!(last.bottom(), viewport.bottom());
+    }
+}
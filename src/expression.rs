@@ -0,0 +1,341 @@
+use crate::chip8::chip8::{Chip8, MEM_SIZE};
+use crate::symbols::Symbols;
+
+use std::fmt;
+
+/// conditions for the debugger, like `pc == 0x2F0 && v[4] > 10`. values are the machine's
+/// state when the condition is checked:
+///
+/// ```text
+///   pc, i, sp, dt, st     registers
+///   v0-vf, v[EXPR]        V registers
+///   m[EXPR], mem[EXPR]    a byte of memory
+///   names                 addresses from the symbol file
+/// ```
+///
+/// with the operators of rust, at rust's precedence: unary - and !, then * + - & ^ |, then the
+/// comparisons, && and ||. anything that isn't 0 is true
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(i64),
+    Pc,
+    Index,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    Register(Box<Node>),
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Multiply,
+    Add,
+    Subtract,
+    BitAnd,
+    BitXor,
+    BitOr,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+// higher binds tighter
+fn binary_op(token: &str) -> Option<(BinaryOp, u8)> {
+    let op = match token {
+        "*" => (BinaryOp::Multiply, 7),
+        "+" => (BinaryOp::Add, 6),
+        "-" => (BinaryOp::Subtract, 6),
+        "&" => (BinaryOp::BitAnd, 5),
+        "^" => (BinaryOp::BitXor, 4),
+        "|" => (BinaryOp::BitOr, 3),
+        "==" => (BinaryOp::Equal, 2),
+        "!=" => (BinaryOp::NotEqual, 2),
+        "<" => (BinaryOp::Less, 2),
+        "<=" => (BinaryOp::LessEqual, 2),
+        ">" => (BinaryOp::Greater, 2),
+        ">=" => (BinaryOp::GreaterEqual, 2),
+        "&&" => (BinaryOp::And, 1),
+        "||" => (BinaryOp::Or, 0),
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "&&", "||", "<", ">", "!", "+", "-", "*", "&", "|", "^", "(", ")", "[",
+    "]",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        let first = rest.chars().next().unwrap();
+        let length = if first.is_ascii_alphanumeric() || first == '_' {
+            let length = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            let word = &rest[..length];
+            if first.is_ascii_digit() {
+                let number = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                tokens.push(Token::Number(
+                    number.map_err(|_| format!("not a number: {}", word))?,
+                ));
+            } else {
+                tokens.push(Token::Name(word.to_string()));
+            }
+            length
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(*symbol))
+                .ok_or_else(|| format!("unexpected {}", first))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(found)) if found == symbol => Ok(()),
+            _ => Err(format!("expected {}", symbol)),
+        }
+    }
+
+    // operators at least as tight as `min_precedence`, left to right
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Symbol(symbol)) = self.tokens.get(self.position) {
+            let (op, precedence) = match binary_op(symbol) {
+                Some((op, precedence)) if precedence >= min_precedence => (op, precedence),
+                _ => break,
+            };
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Symbol("!")) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Symbol("-")) => Ok(Node::Negate(Box::new(self.unary()?))),
+            Some(Token::Symbol("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Name(name)) => self.name(&name),
+            Some(Token::Symbol(symbol)) => Err(format!("unexpected {}", symbol)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+
+    fn name(&mut self, name: &str) -> Result<Node, String> {
+        let lowercase = name.to_ascii_lowercase();
+        let node = match lowercase.as_str() {
+            "pc" => Node::Pc,
+            "i" => Node::Index,
+            "sp" => Node::Sp,
+            "dt" => Node::DelayTimer,
+            "st" => Node::SoundTimer,
+            "v" | "m" | "mem" => {
+                self.expect("[")?;
+                let inner = Box::new(self.binary(0)?);
+                self.expect("]")?;
+                if lowercase == "v" {
+                    Node::Register(inner)
+                } else {
+                    Node::Memory(inner)
+                }
+            }
+            _ => match (lowercase.strip_prefix('v'), self.symbols.address(name)) {
+                (Some(x), _) if x.len() == 1 && u8::from_str_radix(x, 16).is_ok() => {
+                    let x = i64::from_str_radix(x, 16).unwrap();
+                    Node::Register(Box::new(Node::Number(x)))
+                }
+                (_, Some(address)) => Node::Number(address as i64),
+                _ => return Err(format!("unknown name: {}", name)),
+            },
+        };
+        Ok(node)
+    }
+}
+
+impl Node {
+    fn evaluate(&self, chip8: &Chip8) -> i64 {
+        match self {
+            Node::Number(number) => *number,
+            Node::Pc => chip8.pc() as i64,
+            Node::Index => chip8.index() as i64,
+            Node::Sp => chip8.stack().len() as i64,
+            Node::DelayTimer => chip8.delay_timer() as i64,
+            Node::SoundTimer => chip8.sound_timer as i64,
+            Node::Register(x) => chip8.registers()[(x.evaluate(chip8) & 0xF) as usize] as i64,
+            Node::Memory(address) => {
+                let address = address.evaluate(chip8).rem_euclid(MEM_SIZE as i64);
+                chip8.memory()[address as usize] as i64
+            }
+            Node::Not(node) => (node.evaluate(chip8) == 0) as i64,
+            Node::Negate(node) => node.evaluate(chip8).wrapping_neg(),
+            Node::Binary(op, left, right) => {
+                let left = left.evaluate(chip8);
+                // && and || don't look further than they need to
+                match op {
+                    BinaryOp::And if left == 0 => return 0,
+                    BinaryOp::Or if left != 0 => return 1,
+                    _ => {}
+                }
+                let right = right.evaluate(chip8);
+                match op {
+                    BinaryOp::Multiply => left.wrapping_mul(right),
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Subtract => left.wrapping_sub(right),
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::BitXor => left ^ right,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::Equal => (left == right) as i64,
+                    BinaryOp::NotEqual => (left != right) as i64,
+                    BinaryOp::Less => (left < right) as i64,
+                    BinaryOp::LessEqual => (left <= right) as i64,
+                    BinaryOp::Greater => (left > right) as i64,
+                    BinaryOp::GreaterEqual => (left >= right) as i64,
+                    BinaryOp::And | BinaryOp::Or => (right != 0) as i64,
+                }
+            }
+        }
+    }
+}
+
+impl Expression {
+    /// names that aren't registers are looked up in `symbols`
+    pub fn parse(source: &str, symbols: &Symbols) -> Result<Expression, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            symbols,
+        };
+        let root = parser.binary(0)?;
+        if let Some(token) = parser.next() {
+            return Err(format!("unexpected {:?} at the end", token));
+        }
+        Ok(Expression {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    /// the value on `chip8` as it is now
+    pub fn evaluate(&self, chip8: &Chip8) -> i64 {
+        self.root.evaluate(chip8)
+    }
+
+    pub fn is_true(&self, chip8: &Chip8) -> bool {
+        self.evaluate(chip8) != 0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::chip8::create_chip8;
+
+    fn evaluate(source: &str, chip8: &Chip8) -> i64 {
+        Expression::parse(source, &Symbols::default())
+            .unwrap()
+            .evaluate(chip8)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut chip8 = create_chip8();
+        // V4 = 12, I = 0x300, then loop
+        chip8
+            .load_rom_bytes(&[0x64, 0x0C, 0xA3, 0x00, 0x12, 0x04])
+            .unwrap();
+        chip8.emulate_cycle().unwrap();
+        chip8.emulate_cycle().unwrap();
+        chip8.set_memory(0x301, 0x55);
+        assert_eq!(evaluate("pc == 0x204 && v[4] > 10", &chip8), 1);
+        assert_eq!(evaluate("pc == 0x204 && v4 > 12", &chip8), 0);
+        assert_eq!(evaluate("i >= 0x300", &chip8), 1);
+        assert_eq!(evaluate("m[i + 1]", &chip8), 0x55);
+        assert_eq!(evaluate("mem[0x200] == 0x64 || m[0]", &chip8), 1);
+        assert_eq!(evaluate("v[2 + 2] & 0x0F", &chip8), 12);
+        assert_eq!(evaluate("1 + 2 * 3", &chip8), 7);
+        assert_eq!(evaluate("(1 + 2) * 3", &chip8), 9);
+        assert_eq!(evaluate("-1 < 0", &chip8), 1);
+        assert_eq!(evaluate("!(sp == 0)", &chip8), 0);
+        assert_eq!(evaluate("vF | 0x10 ^ 3", &chip8), 0x13);
+        assert_eq!(evaluate("dt + st", &chip8), 0);
+    }
+
+    #[test]
+    fn test_symbols_and_errors() {
+        let symbols = Symbols::parse("lives 0x3F0").unwrap();
+        let expression = Expression::parse(" m[lives] == 0 ", &symbols).unwrap();
+        assert_eq!(expression.to_string(), "m[lives] == 0");
+        assert!(expression.is_true(&create_chip8()));
+
+        for source in [
+            "", "pc ==", "pc = 1", "v[1", "lives", "1 2", "0x", "v10", "(pc",
+        ]
+        .iter()
+        {
+            assert!(
+                Expression::parse(source, &Symbols::default()).is_err(),
+                "{}",
+                source
+            );
+        }
+    }
+}
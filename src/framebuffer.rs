@@ -0,0 +1,139 @@
+// bits per word of the packed rows
+const WORD_BITS: usize = 64;
+
+/// the chip-8 screen, one bit per pixel. every row is packed into 64 pixel words, leftmost pixel
+/// in the lowest bit. the size depends on the variant, so ask rather than assume 64x32
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    words_per_row: usize,
+    words: Vec<u64>,
+}
+
+impl FrameBuffer {
+    /// all pixels off
+    pub fn new(width: usize, height: usize) -> FrameBuffer {
+        let words_per_row = width.div_ceil(WORD_BITS);
+        FrameBuffer {
+            width,
+            height,
+            words_per_row,
+            words: vec![0; words_per_row * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// (width, height)
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    // word index and bit mask of a pixel
+    fn locate(&self, x: usize, y: usize) -> (usize, u64) {
+        (y * self.words_per_row + x / WORD_BITS, 1 << (x % WORD_BITS))
+    }
+
+    /// pixels outside the screen are off
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let (word, mask) = self.locate(x, y);
+        self.words[word] & mask != 0
+    }
+
+    /// writes outside the screen are ignored
+    pub fn set(&mut self, x: usize, y: usize, lit: bool) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let (word, mask) = self.locate(x, y);
+        if lit {
+            self.words[word] |= mask;
+        } else {
+            self.words[word] &= !mask;
+        }
+    }
+
+    /// flip a pixel the way sprites are drawn. returns true if it was lit, i.e. sprites collided
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let was_lit = self.get(x, y);
+        self.set(x, y, !was_lit);
+        was_lit
+    }
+
+    pub fn clear(&mut self) {
+        for word in self.words.iter_mut() {
+            *word = 0;
+        }
+    }
+
+    pub fn row_is_blank(&self, y: usize) -> bool {
+        let start = y * self.words_per_row;
+        self.words[start..start + self.words_per_row]
+            .iter()
+            .all(|word| *word == 0)
+    }
+
+    /// the pixels of row `y`, left to right
+    pub fn row(&self, y: usize) -> impl Iterator<Item = bool> + '_ {
+        (0..self.width).map(move |x| self.get(x, y))
+    }
+
+    /// top to bottom
+    pub fn iter_rows(&self) -> impl Iterator<Item = impl Iterator<Item = bool> + '_> + '_ {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    /// every pixel, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = bool> + '_ {
+        self.iter_rows().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixels() {
+        let mut framebuffer = FrameBuffer::new(128, 64);
+        assert_eq!(framebuffer.size(), (128, 64));
+        assert_eq!(framebuffer.words.len(), 2 * 64);
+        framebuffer.set(0, 0, true);
+        framebuffer.set(127, 63, true);
+        assert!(framebuffer.get(0, 0));
+        assert!(framebuffer.get(127, 63));
+        assert!(!framebuffer.get(1, 0));
+        assert!(!framebuffer.get(128, 0));
+        framebuffer.set(200, 200, true);
+        assert_eq!(framebuffer.pixels().filter(|lit| *lit).count(), 2);
+
+        assert!(framebuffer.toggle(0, 0));
+        assert!(!framebuffer.toggle(64, 1));
+        assert!(framebuffer.get(64, 1));
+        assert!(framebuffer.row_is_blank(0));
+        assert!(!framebuffer.row_is_blank(1));
+        assert_eq!(framebuffer.row(1).position(|lit| lit), Some(64));
+        assert_eq!(framebuffer.iter_rows().count(), 64);
+
+        framebuffer.clear();
+        assert!(framebuffer.pixels().all(|lit| !lit));
+    }
+
+    #[test]
+    fn test_odd_width() {
+        let mut framebuffer = FrameBuffer::new(70, 2);
+        framebuffer.set(69, 1, true);
+        assert_eq!(framebuffer.row(1).count(), 70);
+        assert_eq!(framebuffer.row(1).last(), Some(true));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::config::Settings;
+use crate::display::Screen;
+use crate::thumbnail;
+use chip_8::Chip8;
+
+use sdl2::pixels::Color;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+    // the picture as the window has it before scaling: palette, filter, blending, persistence
+    Png,
+    // the bare chip-8 screen, a bit a pixel, for what the ROM drew whatever it's shown with
+    Pbm,
+}
+
+impl DumpFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            DumpFormat::Png => "png",
+            DumpFormat::Pbm => "pbm",
+        }
+    }
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<DumpFormat, String> {
+        [DumpFormat::Png, DumpFormat::Pbm]
+            .iter()
+            .copied()
+            .find(|format| format.name() == name.to_ascii_lowercase())
+            .ok_or(format!(
+                "unknown frame dump format {}, expected png or pbm",
+                name
+            ))
+    }
+}
+
+// --dump-frames: every `every`th frame the machine runs, written to `dir` as
+// frame-000042.png, numbered from 0. for comparing against pictures known to be right
+pub struct FrameDump {
+    dir: PathBuf,
+    every: u64,
+    format: DumpFormat,
+    frame: u64,
+    // its own, so the window's F-key toggles don't end up in the pictures
+    screen: Screen,
+    foreground: Color,
+    background: Color,
+}
+
+impl FrameDump {
+    pub fn create(
+        dir: &Path,
+        every: u64,
+        format: DumpFormat,
+        settings: &Settings,
+    ) -> Result<FrameDump, String> {
+        if every == 0 {
+            return Err("--every has to be at least 1".to_string());
+        }
+        fs::create_dir_all(dir).map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+        Ok(FrameDump {
+            dir: dir.to_path_buf(),
+            every,
+            format,
+            frame: 0,
+            screen: Screen::new(settings.filter, settings.blend, settings.blend_mode)
+                .persistence(settings.persistence),
+            foreground: settings.foreground,
+            background: settings.background,
+        })
+    }
+
+    // once a frame, after the timers ticked
+    pub fn frame(&mut self, chip8: &Chip8) -> Result<(), String> {
+        self.screen.update(chip8);
+        self.screen.tick();
+        let frame = self.frame;
+        self.frame += 1;
+        if frame % self.every != 0 {
+            return Ok(());
+        }
+        let (width, height) = self.screen.size();
+        let contents = match self.format {
+            DumpFormat::Png => thumbnail::encode_rgb_png(
+                width,
+                height,
+                &self.screen.pixels(self.foreground, self.background),
+            ),
+            DumpFormat::Pbm => pbm(chip8),
+        };
+        let path = self
+            .dir
+            .join(format!("frame-{:06}.{}", frame, self.format.name()));
+        fs::write(&path, contents).map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+    }
+}
+
+// binary PBM: a bit a pixel, rows padded to whole bytes, set for lit (which PBM shows black)
+fn pbm(chip8: &Chip8) -> Vec<u8> {
+    let framebuffer = chip8.framebuffer();
+    let (width, height) = (chip_8::DISPLAY_WIDTH, chip8.display_height());
+    let mut pbm = format!("P4\n{} {}\n", width, height).into_bytes();
+    for y in 0..height {
+        let mut row = vec![0u8; width.div_ceil(8)];
+        for x in (0..width).filter(|x| framebuffer.get(*x, y)) {
+            row[x / 8] |= 0x80 >> (x % 8);
+        }
+        pbm.extend(row);
+    }
+    pbm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use chip_8::create_chip8;
+
+    #[test]
+    fn test_frame_dump() {
+        let dir = std::env::temp_dir().join(format!("chip8-frames-{}", std::process::id()));
+        let settings = Config::default().resolve().unwrap();
+        let mut chip8 = create_chip8();
+        chip8.set_pixel(0, 0, true);
+        chip8.set_pixel(9, 1, true);
+
+        let mut dump = FrameDump::create(&dir, 2, DumpFormat::Pbm, &settings).unwrap();
+        for _ in 0..3 {
+            dump.frame(&chip8).unwrap();
+        }
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["frame-000000.pbm", "frame-000002.pbm"]);
+        let pbm = fs::read(dir.join("frame-000000.pbm")).unwrap();
+        assert!(pbm.starts_with(b"P4\n64 32\n"));
+        // 8 bytes a row
+        assert_eq!(pbm.len(), 9 + 8 * 32);
+        assert_eq!(pbm[9..11], [0x80, 0]);
+        assert_eq!(pbm[17..19], [0, 0x40]);
+
+        let mut dump = FrameDump::create(&dir, 1, DumpFormat::Png, &settings).unwrap();
+        dump.frame(&chip8).unwrap();
+        assert!(fs::read(dir.join("frame-000000.png"))
+            .unwrap()
+            .starts_with(b"\x89PNG"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(FrameDump::create(&dir, 0, DumpFormat::Png, &settings).is_err());
+        assert_eq!("PBM".parse(), Ok(DumpFormat::Pbm));
+        assert!("gif".parse::<DumpFormat>().is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! what a frontend plugs into the main loop. the SDL frontend implements all three, a terminal
+//! or web frontend would bring its own, and tests can use mocks that record what they get
+
+use crate::chip8::chip8::Chip8;
+use crate::framebuffer::FrameBuffer;
+
+use crate::input::HostKey;
+
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// where the picture goes
+pub trait Display {
+    /// show the current picture. called when `chip8.draw` is set, which is cleared afterwards
+    fn present(&mut self, chip8: &Chip8) -> Result<(), String>;
+
+    /// compare mode: two machines running the same ROM, side by side. frontends that can only
+    /// show one show the left one
+    fn present_pair(&mut self, left: &Chip8, _right: &Chip8) -> Result<(), String> {
+        self.present(left)
+    }
+
+    /// the sprite viewer: memory from `address` on read as 8 pixel wide sprites `height` rows
+    /// high. frontends without one keep showing the screen
+    fn present_sprites(
+        &mut self,
+        chip8: &Chip8,
+        _address: usize,
+        _height: usize,
+    ) -> Result<(), String> {
+        self.present(chip8)
+    }
+
+    /// the save slot picker: what's in each slot, None for empty ones, and whether a slot
+    /// picked now is saved to rather than loaded. frontends without one keep showing the screen
+    fn present_slots(
+        &mut self,
+        chip8: &Chip8,
+        _slots: &[Option<SaveSlot>],
+        _saving: bool,
+    ) -> Result<(), String> {
+        self.present(chip8)
+    }
+
+    /// the keymap picker, with the preset at `selected` marked. frontends without one keep
+    /// showing the screen
+    fn present_keymaps(&mut self, chip8: &Chip8, _selected: usize) -> Result<(), String> {
+        self.present(chip8)
+    }
+
+    /// the memory heatmap: a color for every address, for how much it was read, written and run.
+    /// frontends without one keep showing the screen
+    fn present_heatmap(&mut self, chip8: &Chip8, _colors: &[Rgb]) -> Result<(), String> {
+        self.present(chip8)
+    }
+
+    /// once per chip-8 frame, with the number of instructions run in it. returns true to
+    /// redraw even though the chip-8 didn't draw anything, e.g. for effects that fade out
+    fn end_frame(&mut self, _instructions: u64) -> bool {
+        false
+    }
+
+    /// the window title, filled in from the config's template about once a second
+    fn set_title(&mut self, _title: &str) {}
+
+    /// the machine stopped with an error
+    fn show_error(&mut self, _message: &str) {}
+
+    fn toggle_fullscreen(&mut self) {}
+
+    fn toggle_overlay(&mut self) {}
+
+    /// the registers and the next instructions over the game
+    fn toggle_hud(&mut self) {}
+
+    /// lines between the chip-8 pixels
+    fn toggle_grid(&mut self) {}
+
+    /// the keypad drawn over the screen, once it's been touched
+    fn show_touch_keypad(&mut self) {}
+
+    /// the next way of fitting the screen to the window
+    fn cycle_scaling(&mut self) {}
+
+    /// the chip-8 pixel under a point of the window, as given by InputEvent::Click
+    fn pixel_at(&self, _x: i32, _y: i32) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+/// a color, for what the frontend is handed to draw other than the chip-8's own pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+}
+
+/// a filled save slot: when it was saved and the screen at the time
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveSlot {
+    pub saved: SystemTime,
+    pub thumbnail: FrameBuffer,
+}
+
+/// where the sound goes
+pub trait AudioSink {
+    /// the buzzer, on while the sound timer runs
+    fn set_playing(&mut self, playing: bool);
+
+    /// the sound timer while the buzzer is on, every frame. sinks that buffer ahead can use it
+    /// to stop on time by themselves
+    fn set_sound_timer(&mut self, _ticks: u8) {}
+
+    /// XO-CHIP audio: a 1-bit 128 sample waveform and the rate to play it at
+    fn set_pattern(&mut self, _pattern: [u8; 16], _playback_rate: f32) {}
+
+    /// silence the buzzer, or let it be heard again
+    fn toggle_mute(&mut self) {}
+}
+
+/// what an InputSource reports, in the order it happened
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    /// host keys, mapped to the chip-8 keypad by a `Keypad`. frontends turn their own key events
+    /// into HostKeys, like sdl.rs does
+    KeyDown(HostKey),
+    KeyUp(HostKey),
+    /// a key held down long enough to repeat. menus scroll with it, the keypad ignores it
+    KeyRepeat(HostKey),
+    /// a finger put down on a touchscreen or moved, and lifted. the id tells fingers apart, x
+    /// and y are from 0.0 to 1.0 across and down the window
+    Finger(i64, f32, f32),
+    FingerUp(i64),
+    /// load another ROM
+    Open(PathBuf),
+    /// start the ROM over
+    Reset,
+    /// switch between running freely and only running the frames asked for with AdvanceFrame
+    ToggleFrameAdvance,
+    /// run exactly one more frame, switching to frame advance first if needed
+    AdvanceFrame,
+    /// the picture was lost, e.g. the window was resized or uncovered
+    Redraw,
+    ToggleFullscreen,
+    ToggleOverlay,
+    ToggleHud,
+    ToggleGrid,
+    CycleScaling,
+    /// open or close the sprite viewer
+    ToggleSprites,
+    /// open or close the save slot picker
+    ToggleSlots,
+    /// open or close the keymap picker
+    ToggleKeymaps,
+    /// show the memory heatmap instead of the screen, or the screen again
+    ToggleHeatmap,
+    /// the left mouse button, in window coordinates
+    Click(i32, i32),
+    Quit,
+}
+
+/// where the keys, mouse and window events come from
+pub trait InputSource {
+    /// everything that happened since the last call
+    fn poll(&mut self) -> Vec<InputEvent>;
+}
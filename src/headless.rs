@@ -0,0 +1,52 @@
+use chip_8::frontend::{AudioSink, Display, InputEvent, InputSource};
+use chip_8::Chip8;
+
+// the frontend for --headless: nothing is shown or heard and no keys are pressed, the ROM just
+// runs. `frames` stops it after that many, otherwise it goes on until it exits or fails
+pub struct Headless {
+    frames: Option<u64>,
+}
+
+impl Headless {
+    pub fn new(frames: Option<u64>) -> Headless {
+        Headless { frames }
+    }
+}
+
+impl Display for Headless {
+    fn present(&mut self, _chip8: &Chip8) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl AudioSink for Headless {
+    fn set_playing(&mut self, _playing: bool) {}
+}
+
+// polled once a frame
+impl InputSource for Headless {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        match &mut self.frames {
+            Some(0) => vec![InputEvent::Quit],
+            Some(frames) => {
+                *frames -= 1;
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_limit() {
+        let mut headless = Headless::new(Some(2));
+        assert!(headless.poll().is_empty());
+        assert!(headless.poll().is_empty());
+        assert_eq!(headless.poll(), vec![InputEvent::Quit]);
+        assert!(Headless::new(None).poll().is_empty());
+    }
+}
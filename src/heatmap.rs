@@ -0,0 +1,159 @@
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::thumbnail;
+use chip_8::frontend::Rgb;
+use chip_8::MEM_SIZE;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+
+// addresses in a row of the map, 64 rows of them are the whole 4K
+pub const WIDTH: usize = 64;
+// the least brightness of an address that was used at all, so one read doesn't get lost
+const MIN_LEVEL: f64 = 64.0;
+
+// how often every address was read, written and run, collected by the profiler
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heat {
+    pub reads: Vec<u64>,
+    pub writes: Vec<u64>,
+    pub executes: Vec<u64>,
+}
+
+impl Heat {
+    pub fn new() -> Heat {
+        Heat {
+            reads: vec![0; MEM_SIZE],
+            writes: vec![0; MEM_SIZE],
+            executes: vec![0; MEM_SIZE],
+        }
+    }
+
+    // a color for every address: green where it was run, red where written, blue where read.
+    // code that writes itself comes out yellow, sprite data blue. brightness goes with the log
+    // of the count next to the busiest address, or a hot loop would leave everything else dark
+    pub fn colors(&self) -> Vec<Rgb> {
+        let max = |counts: &[u64]| counts.iter().copied().max().unwrap_or(0);
+        let (reads, writes, executes) = (max(&self.reads), max(&self.writes), max(&self.executes));
+        (0..MEM_SIZE)
+            .map(|address| {
+                Rgb::new(
+                    level(self.writes[address], writes),
+                    level(self.executes[address], executes),
+                    level(self.reads[address], reads),
+                )
+            })
+            .collect()
+    }
+
+    // the map as an RGB PNG, every address a `scale` pixels square
+    pub fn encode_png(&self, scale: usize) -> Vec<u8> {
+        let colors = self.colors();
+        let size = WIDTH * scale;
+        let mut pixels = Vec::with_capacity(size * size * 3);
+        for y in 0..size {
+            for x in 0..size {
+                let color = colors[y / scale * WIDTH + x / scale];
+                pixels.extend_from_slice(&[color.r, color.g, color.b]);
+            }
+        }
+        thumbnail::encode_rgb_png(size, size, &pixels)
+    }
+}
+
+impl Default for Heat {
+    fn default() -> Self {
+        Heat::new()
+    }
+}
+
+fn level(count: u64, max: u64) -> u8 {
+    if count == 0 {
+        return 0;
+    }
+    let fraction = (count as f64).ln_1p() / (max as f64).ln_1p();
+    (MIN_LEVEL + (255.0 - MIN_LEVEL) * fraction).round() as u8
+}
+
+// the F9 view: a title, the map with the address of every 8th row on the left and what the
+// colors mean underneath
+pub fn draw(
+    canvas: &mut WindowCanvas,
+    colors: &[Color],
+    foreground: Color,
+    background: Color,
+) -> Result<(), String> {
+    // in units of one address
+    let label_width = 4 * (GLYPH_WIDTH + 1) as usize;
+    let line_height = GLYPH_HEIGHT as usize + 2;
+    let units_wide = 1 + label_width + WIDTH + 1;
+    let units_high = 1 + line_height + WIDTH + 1 + line_height;
+    let (width, height) = canvas.output_size()?;
+    let pixel_size = (width / units_wide as u32)
+        .min(height / units_high as u32)
+        .max(1);
+    let unit = |units: usize| (units as u32 * pixel_size) as i32;
+
+    canvas.set_draw_color(background);
+    canvas.clear();
+    font::draw_text(canvas, "MEMORY", unit(1), unit(1), pixel_size, foreground)?;
+    let top = 1 + line_height;
+    for row in (0..WIDTH).step_by(8) {
+        let label = format!("{:03X}", row * WIDTH);
+        font::draw_text(
+            canvas,
+            &label,
+            unit(1),
+            unit(top + row),
+            pixel_size,
+            foreground,
+        )?;
+    }
+    let left = 1 + label_width;
+    for (address, color) in colors.iter().enumerate() {
+        canvas.set_draw_color(*color);
+        canvas.fill_rect(Rect::new(
+            unit(left + address % WIDTH),
+            unit(top + address / WIDTH),
+            pixel_size,
+            pixel_size,
+        ))?;
+    }
+    let mut x = unit(left);
+    let y = unit(top + WIDTH + 1);
+    for (text, color) in [
+        ("RUN", Color::RGB(0, 255, 0)),
+        ("WRITE", Color::RGB(255, 0, 0)),
+        ("READ", Color::RGB(0, 0, 255)),
+    ] {
+        font::draw_text(canvas, text, x, y, pixel_size, color)?;
+        x += (font::text_width(text, pixel_size) + (GLYPH_WIDTH + 1) * pixel_size) as i32;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors() {
+        let mut heat = Heat::new();
+        heat.executes[0x200] = 1000;
+        heat.executes[0x201] = 1000;
+        heat.executes[0x202] = 1;
+        heat.writes[0x202] = 5;
+        heat.reads[0x300] = 2;
+        let colors = heat.colors();
+        assert_eq!(colors[0x200], Rgb::new(0, 255, 0));
+        // run once and written, self-modifying code
+        assert_eq!(colors[0x202], Rgb::new(255, 83, 0));
+        assert_eq!(colors[0x300], Rgb::new(0, 0, 255));
+        assert_eq!(colors[0x301], Rgb::new(0, 0, 0));
+
+        let png = heat.encode_png(2);
+        assert!(png.starts_with(b"\x89PNG"));
+        // 128 pixels wide and high
+        assert_eq!(png[16..24], [0, 0, 0, 128, 0, 0, 0, 128]);
+    }
+}
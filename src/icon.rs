@@ -0,0 +1,83 @@
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::thumbnail;
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::surface::Surface;
+use sdl2::video::Window;
+use tracing::warn;
+
+// the icon is drawn on a grid this many units wide and high: "C8" in the overlay font, two
+// units a font pixel, on a dark screen
+const UNITS: usize = 16;
+const FOREGROUND: [u8; 3] = [0x33, 0xFF, 0x66];
+const BACKGROUND: [u8; 3] = [0x10, 0x10, 0x10];
+const WINDOW_ICON_SIZE: usize = 64;
+
+// whether unit (x, y) is part of the text
+fn lit(x: usize, y: usize) -> bool {
+    let (left, top) = (1, 3);
+    let glyph_units = 2 * (GLYPH_WIDTH as usize + 1);
+    if x < left || y < top {
+        return false;
+    }
+    let (x, y) = (x - left, y - top);
+    let (index, column, row) = (x / glyph_units, x % glyph_units / 2, y / 2);
+    match "C8".chars().nth(index) {
+        Some(c) if column < GLYPH_WIDTH as usize && row < GLYPH_HEIGHT as usize => {
+            font::glyph(c)[row] >> (GLYPH_WIDTH as usize - 1 - column) & 1 == 1
+        }
+        _ => false,
+    }
+}
+
+// RGB, `size` pixels square, row by row
+fn pixels(size: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(size * size * 3);
+    for y in 0..size {
+        for x in 0..size {
+            let lit = lit(x * UNITS / size, y * UNITS / size);
+            pixels.extend_from_slice(if lit { &FOREGROUND } else { &BACKGROUND });
+        }
+    }
+    pixels
+}
+
+pub fn encode_png(size: usize) -> Vec<u8> {
+    thumbnail::encode_rgb_png(size, size, &pixels(size))
+}
+
+// the window manager shows a generic one otherwise
+pub fn set_window_icon(window: &mut Window) {
+    let size = WINDOW_ICON_SIZE;
+    let mut pixels = pixels(size);
+    let pitch = size as u32 * 3;
+    let surface = Surface::from_data(
+        &mut pixels,
+        size as u32,
+        size as u32,
+        pitch,
+        PixelFormatEnum::RGB24,
+    );
+    match surface {
+        Ok(surface) => window.set_icon(surface),
+        Err(e) => warn!("no window icon: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixels() {
+        let pixels = pixels(UNITS);
+        let lit = |x: usize, y: usize| pixels[(y * UNITS + x) * 3..][..3] == FOREGROUND;
+        // the top of the C and the 8, not the corner of the C or the gap between them
+        assert!(lit(3, 3) && lit(4, 4));
+        assert!(!lit(1, 3));
+        assert!(!lit(7, 3));
+        assert!(lit(9, 3) && lit(14, 12));
+        assert!(!lit(0, 0) && !lit(15, 15));
+        assert!(encode_png(32).starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+
+/// a chip-8 key going down or up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyEdge {
+    Press(u8),
+    Release(u8),
+}
+
+impl KeyEdge {
+    pub fn key(self) -> u8 {
+        match self {
+            KeyEdge::Press(key) | KeyEdge::Release(key) => key,
+        }
+    }
+
+    pub fn pressed(self) -> bool {
+        matches!(self, KeyEdge::Press(_))
+    }
+}
+
+/// a key on the host keyboard, by what's printed on it. frontends turn their own key events into
+/// these (sdl.rs does it for SDL's), so keymaps, macros and menus work the same whatever the
+/// frontend
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HostKey {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Num0,
+    Num1,
+    Num2,
+    Num3,
+    Num4,
+    Num5,
+    Num6,
+    Num7,
+    Num8,
+    Num9,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Kp0,
+    Kp1,
+    Kp2,
+    Kp3,
+    Kp4,
+    Kp5,
+    Kp6,
+    Kp7,
+    Kp8,
+    Kp9,
+    KpPlus,
+    KpMinus,
+    KpMultiply,
+    KpDivide,
+    KpPeriod,
+    KpEnter,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    Return,
+    Escape,
+    Backspace,
+    Tab,
+    Space,
+    LShift,
+    RShift,
+    LCtrl,
+    RCtrl,
+    LAlt,
+    RAlt,
+    Minus,
+    Equals,
+    Plus,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+    Semicolon,
+    Quote,
+    Comma,
+    Period,
+    Slash,
+    Backquote,
+    /// anything else, by the frontend's own code for it
+    Other(i32),
+}
+
+// the names the config file uses, SDL's
+const HOST_KEY_NAMES: [(HostKey, &str); 97] = [
+    (HostKey::A, "A"),
+    (HostKey::B, "B"),
+    (HostKey::C, "C"),
+    (HostKey::D, "D"),
+    (HostKey::E, "E"),
+    (HostKey::F, "F"),
+    (HostKey::G, "G"),
+    (HostKey::H, "H"),
+    (HostKey::I, "I"),
+    (HostKey::J, "J"),
+    (HostKey::K, "K"),
+    (HostKey::L, "L"),
+    (HostKey::M, "M"),
+    (HostKey::N, "N"),
+    (HostKey::O, "O"),
+    (HostKey::P, "P"),
+    (HostKey::Q, "Q"),
+    (HostKey::R, "R"),
+    (HostKey::S, "S"),
+    (HostKey::T, "T"),
+    (HostKey::U, "U"),
+    (HostKey::V, "V"),
+    (HostKey::W, "W"),
+    (HostKey::X, "X"),
+    (HostKey::Y, "Y"),
+    (HostKey::Z, "Z"),
+    (HostKey::Num0, "0"),
+    (HostKey::Num1, "1"),
+    (HostKey::Num2, "2"),
+    (HostKey::Num3, "3"),
+    (HostKey::Num4, "4"),
+    (HostKey::Num5, "5"),
+    (HostKey::Num6, "6"),
+    (HostKey::Num7, "7"),
+    (HostKey::Num8, "8"),
+    (HostKey::Num9, "9"),
+    (HostKey::F1, "F1"),
+    (HostKey::F2, "F2"),
+    (HostKey::F3, "F3"),
+    (HostKey::F4, "F4"),
+    (HostKey::F5, "F5"),
+    (HostKey::F6, "F6"),
+    (HostKey::F7, "F7"),
+    (HostKey::F8, "F8"),
+    (HostKey::F9, "F9"),
+    (HostKey::F10, "F10"),
+    (HostKey::F11, "F11"),
+    (HostKey::F12, "F12"),
+    (HostKey::Kp0, "Keypad 0"),
+    (HostKey::Kp1, "Keypad 1"),
+    (HostKey::Kp2, "Keypad 2"),
+    (HostKey::Kp3, "Keypad 3"),
+    (HostKey::Kp4, "Keypad 4"),
+    (HostKey::Kp5, "Keypad 5"),
+    (HostKey::Kp6, "Keypad 6"),
+    (HostKey::Kp7, "Keypad 7"),
+    (HostKey::Kp8, "Keypad 8"),
+    (HostKey::Kp9, "Keypad 9"),
+    (HostKey::KpPlus, "Keypad +"),
+    (HostKey::KpMinus, "Keypad -"),
+    (HostKey::KpMultiply, "Keypad *"),
+    (HostKey::KpDivide, "Keypad /"),
+    (HostKey::KpPeriod, "Keypad ."),
+    (HostKey::KpEnter, "Keypad Enter"),
+    (HostKey::Up, "Up"),
+    (HostKey::Down, "Down"),
+    (HostKey::Left, "Left"),
+    (HostKey::Right, "Right"),
+    (HostKey::Home, "Home"),
+    (HostKey::End, "End"),
+    (HostKey::PageUp, "PageUp"),
+    (HostKey::PageDown, "PageDown"),
+    (HostKey::Insert, "Insert"),
+    (HostKey::Delete, "Delete"),
+    (HostKey::Return, "Return"),
+    (HostKey::Escape, "Escape"),
+    (HostKey::Backspace, "Backspace"),
+    (HostKey::Tab, "Tab"),
+    (HostKey::Space, "Space"),
+    (HostKey::LShift, "Left Shift"),
+    (HostKey::RShift, "Right Shift"),
+    (HostKey::LCtrl, "Left Ctrl"),
+    (HostKey::RCtrl, "Right Ctrl"),
+    (HostKey::LAlt, "Left Alt"),
+    (HostKey::RAlt, "Right Alt"),
+    (HostKey::Minus, "-"),
+    (HostKey::Equals, "="),
+    (HostKey::Plus, "+"),
+    (HostKey::LeftBracket, "["),
+    (HostKey::RightBracket, "]"),
+    (HostKey::Backslash, "\\"),
+    (HostKey::Semicolon, ";"),
+    (HostKey::Quote, "'"),
+    (HostKey::Comma, ","),
+    (HostKey::Period, "."),
+    (HostKey::Slash, "/"),
+    (HostKey::Backquote, "`"),
+];
+
+impl HostKey {
+    /// not case sensitive, None for keys without a name here
+    pub fn from_name(name: &str) -> Option<HostKey> {
+        HOST_KEY_NAMES
+            .iter()
+            .find(|(_, known)| known.eq_ignore_ascii_case(name))
+            .map(|(key, _)| *key)
+    }
+
+    pub fn name(self) -> Option<&'static str> {
+        HOST_KEY_NAMES
+            .iter()
+            .find(|(key, _)| *key == self)
+            .map(|(_, name)| *name)
+    }
+}
+
+/// the keypad's 4x4 on 1234 QWER ASDF ZXCV
+pub fn default_keymap() -> HashMap<HostKey, u8> {
+    let mut keymap = HashMap::new();
+    keymap.insert(HostKey::X, 0x0);
+    keymap.insert(HostKey::Num1, 0x1);
+    keymap.insert(HostKey::Num2, 0x2);
+    keymap.insert(HostKey::Num3, 0x3);
+    keymap.insert(HostKey::Num4, 0xC);
+    keymap.insert(HostKey::Q, 0x4);
+    keymap.insert(HostKey::W, 0x5);
+    keymap.insert(HostKey::E, 0x6);
+    keymap.insert(HostKey::R, 0xD);
+    keymap.insert(HostKey::A, 0x7);
+    keymap.insert(HostKey::S, 0x8);
+    keymap.insert(HostKey::D, 0x9);
+    keymap.insert(HostKey::F, 0xE);
+    keymap.insert(HostKey::Z, 0xA);
+    keymap.insert(HostKey::C, 0xB);
+    keymap.insert(HostKey::V, 0xF);
+    keymap
+}
+
+/// turns host key events into chip-8 keypad edges, for every frontend to feed the machine (or
+/// netplay, or a recording) the same way. a key that is already down doesn't press again, and
+/// when several host keys share a keypad key it's only released with the last of them
+pub struct Keypad {
+    // host key -> keypad key
+    keymap: HashMap<HostKey, u8>,
+    // host keys held down and the keypad key each one pressed
+    held: HashMap<HostKey, u8>,
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Keypad::with_keymap(default_keymap())
+    }
+}
+
+impl Keypad {
+    /// with the default keymap
+    pub fn new() -> Keypad {
+        Keypad::default()
+    }
+
+    pub fn with_keymap(keymap: HashMap<HostKey, u8>) -> Keypad {
+        Keypad {
+            keymap,
+            held: HashMap::new(),
+        }
+    }
+
+    pub fn keymap(&self) -> &HashMap<HostKey, u8> {
+        &self.keymap
+    }
+
+    /// keys held down stay down until they're let go
+    pub fn set_keymap(&mut self, keymap: HashMap<HostKey, u8>) {
+        self.keymap = keymap;
+    }
+
+    /// whether the key belongs to the game, so the frontend leaves it alone
+    pub fn is_mapped(&self, host_key: HostKey) -> bool {
+        self.keymap.contains_key(&host_key)
+    }
+
+    /// None for keys that aren't mapped or don't change anything
+    pub fn key_down(&mut self, host_key: HostKey) -> Option<KeyEdge> {
+        let key = *self.keymap.get(&host_key)?;
+        if self.held.contains_key(&host_key) {
+            return None;
+        }
+        let already_down = self.is_down(key);
+        self.held.insert(host_key, key);
+        (!already_down).then_some(KeyEdge::Press(key))
+    }
+
+    /// the keypad key released is the one pressed, even if the keymap changed meanwhile
+    pub fn key_up(&mut self, host_key: HostKey) -> Option<KeyEdge> {
+        let key = self.held.remove(&host_key)?;
+        (!self.is_down(key)).then_some(KeyEdge::Release(key))
+    }
+
+    /// whether any host key is holding keypad key `key` down
+    pub fn is_down(&self, key: u8) -> bool {
+        self.held.values().any(|held| *held == key)
+    }
+}
+
+/// the keypad as it was on the COSMAC VIP, row by row. the window is split up like it for
+/// touchscreens
+pub const TOUCH_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// the keypad key under a finger, x and y from 0.0 to 1.0 across and down the window
+pub fn touch_key(x: f32, y: f32) -> u8 {
+    let cell = |at: f32| ((at * 4.0) as usize).min(3);
+    TOUCH_LAYOUT[cell(y)][cell(x)]
+}
+
+/// fingers on a touchscreen, each holding down the keypad key under it. like Keypad, a key two
+/// fingers are on is only released with the last of them
+#[derive(Default)]
+pub struct Touches {
+    held: HashMap<i64, u8>,
+}
+
+impl Touches {
+    pub fn new() -> Touches {
+        Touches::default()
+    }
+
+    /// a finger put down or moved to `at`, or lifted with None. sliding onto another key lets
+    /// go of the one it was on
+    pub fn touch(&mut self, finger: i64, at: Option<(f32, f32)>) -> Vec<KeyEdge> {
+        let before = match at {
+            Some((x, y)) => self.held.insert(finger, touch_key(x, y)),
+            None => self.held.remove(&finger),
+        };
+        let after = self.held.get(&finger).copied();
+        let mut edges = Vec::new();
+        if before == after {
+            return edges;
+        }
+        if let Some(key) = before.filter(|key| self.holding(*key) == 0) {
+            edges.push(KeyEdge::Release(key));
+        }
+        if let Some(key) = after.filter(|key| self.holding(*key) == 1) {
+            edges.push(KeyEdge::Press(key));
+        }
+        edges
+    }
+
+    fn holding(&self, key: u8) -> usize {
+        self.held.values().filter(|held| **held == key).count()
+    }
+}
+
+/// a host key that presses keypad keys by itself, from the config's `[macros]`: turbo buttons,
+/// combos, a hand for keys that are hard to hold
+#[derive(Clone, Debug, PartialEq)]
+pub struct Macro {
+    // the keypad keys held (a bit for each) and for how many frames
+    steps: Vec<(u16, u32)>,
+    // over and over for as long as the host key is held, or once through when it's pressed
+    repeat: bool,
+}
+
+impl Macro {
+    /// steps separated by spaces: keypad keys joined with + (or _ for none), and *N to hold
+    /// them for N frames instead of one. "5 _" is rapid fire 5, "4+6*10" holds 4 and 6 together
+    pub fn parse(sequence: &str, repeat: bool) -> Result<Macro, String> {
+        let steps = sequence
+            .split_whitespace()
+            .map(|step| {
+                let (keys, frames) = match step.split_once('*') {
+                    Some((keys, frames)) => (
+                        keys,
+                        frames
+                            .parse()
+                            .ok()
+                            .filter(|frames| *frames > 0)
+                            .ok_or(format!("not a number of frames: {}", frames))?,
+                    ),
+                    None => (step, 1),
+                };
+                if keys == "_" {
+                    return Ok((0, frames));
+                }
+                let mut mask = 0;
+                for key in keys.split('+') {
+                    let key = u8::from_str_radix(key, 16)
+                        .ok()
+                        .filter(|key| *key <= 0xF)
+                        .ok_or(format!("not a chip-8 key: {}", key))?;
+                    mask |= 1 << key;
+                }
+                Ok((mask, frames))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if steps.is_empty() {
+            return Err("no steps in the sequence".to_string());
+        }
+        Ok(Macro { steps, repeat })
+    }
+}
+
+/// plays the macros, a frame at a time. the keypad keys they press and release come out as
+/// edges like the ones from Keypad
+#[derive(Default)]
+pub struct Macros {
+    macros: HashMap<HostKey, Macro>,
+    // the host keys of the macros running, the step each one is at and the frames into it
+    playing: Vec<(HostKey, usize, u32)>,
+    // the keypad keys the macros have down
+    down: u16,
+}
+
+impl Macros {
+    pub fn new(macros: HashMap<HostKey, Macro>) -> Macros {
+        Macros {
+            macros,
+            ..Macros::default()
+        }
+    }
+
+    /// host keys with a macro don't go to the keymap
+    pub fn is_macro(&self, host_key: HostKey) -> bool {
+        self.macros.contains_key(&host_key)
+    }
+
+    pub fn key_down(&mut self, host_key: HostKey) {
+        if self.is_macro(host_key) && !self.playing.iter().any(|(held, ..)| *held == host_key) {
+            self.playing.push((host_key, 0, 0));
+        }
+    }
+
+    /// a repeating macro stops with its key, one that isn't plays to the end
+    pub fn key_up(&mut self, host_key: HostKey) {
+        let macros = &self.macros;
+        self.playing
+            .retain(|(held, ..)| *held != host_key || !macros[held].repeat);
+    }
+
+    /// once at the start of every frame, before it runs
+    pub fn frame(&mut self) -> Vec<KeyEdge> {
+        let macros = &self.macros;
+        let mut wanted = 0;
+        self.playing.retain_mut(|(host_key, step, frame)| {
+            let steps = &macros[host_key].steps;
+            if *step == steps.len() {
+                if !macros[host_key].repeat {
+                    return false;
+                }
+                *step = 0;
+            }
+            let (keys, frames) = steps[*step];
+            wanted |= keys;
+            *frame += 1;
+            if *frame == frames {
+                *step += 1;
+                *frame = 0;
+            }
+            true
+        });
+        let edges = (0..16)
+            .filter(|key| (wanted ^ self.down) >> key & 1 == 1)
+            .map(|key| match wanted >> key & 1 {
+                1 => KeyEdge::Press(key),
+                _ => KeyEdge::Release(key),
+            })
+            .collect();
+        self.down = wanted;
+        edges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edges() {
+        let mut keymap = default_keymap();
+        keymap.insert(HostKey::Up, 0x5);
+        let mut keypad = Keypad::with_keymap(keymap);
+        assert_eq!(keypad.key_down(HostKey::W), Some(KeyEdge::Press(5)));
+        // held down, or another key for the same one
+        assert_eq!(keypad.key_down(HostKey::W), None);
+        assert_eq!(keypad.key_down(HostKey::Up), None);
+        assert_eq!(keypad.key_up(HostKey::W), None);
+        assert!(keypad.is_down(5));
+        assert_eq!(keypad.key_up(HostKey::Up), Some(KeyEdge::Release(5)));
+        assert_eq!(keypad.key_up(HostKey::Up), None);
+        assert!(!keypad.is_down(5));
+
+        assert_eq!(keypad.key_down(HostKey::P), None);
+        assert_eq!(keypad.key_down(HostKey::X), Some(KeyEdge::Press(0)));
+        keypad.set_keymap(HashMap::new());
+        assert!(!keypad.is_mapped(HostKey::X));
+        assert_eq!(keypad.key_up(HostKey::X), Some(KeyEdge::Release(0)));
+    }
+
+    #[test]
+    fn test_host_key_names() {
+        assert_eq!(HostKey::from_name("q"), Some(HostKey::Q));
+        assert_eq!(HostKey::from_name("Keypad 7"), Some(HostKey::Kp7));
+        assert_eq!(HostKey::from_name("left shift"), Some(HostKey::LShift));
+        assert_eq!(HostKey::from_name("nope"), None);
+        assert_eq!(HostKey::Num4.name(), Some("4"));
+        assert_eq!(HostKey::Other(7).name(), None);
+    }
+
+    #[test]
+    fn test_touches() {
+        assert_eq!(touch_key(0.0, 0.0), 0x1);
+        assert_eq!(touch_key(0.3, 0.3), 0x5);
+        assert_eq!(touch_key(1.0, 1.0), 0xF);
+        let mut touches = Touches::new();
+        assert_eq!(touches.touch(1, Some((0.3, 0.3))), [KeyEdge::Press(5)]);
+        assert!(touches.touch(1, Some((0.4, 0.4))).is_empty());
+        // a second finger on the same key, then the first slides off it
+        assert!(touches.touch(2, Some((0.3, 0.3))).is_empty());
+        assert_eq!(touches.touch(1, Some((0.6, 0.3))), [KeyEdge::Press(6)]);
+        assert_eq!(touches.touch(2, None), [KeyEdge::Release(5)]);
+        assert_eq!(
+            touches.touch(1, Some((0.1, 0.9))),
+            [KeyEdge::Release(6), KeyEdge::Press(0xA)]
+        );
+        assert_eq!(touches.touch(1, None), [KeyEdge::Release(0xA)]);
+        assert!(touches.touch(1, None).is_empty());
+    }
+
+    #[test]
+    fn test_macros() {
+        assert_eq!(
+            Macro::parse("4+6*2 _ a", false),
+            Ok(Macro {
+                steps: vec![(0x50, 2), (0, 1), (0x400, 1)],
+                repeat: false
+            })
+        );
+        assert!(Macro::parse("", false).is_err());
+        assert!(Macro::parse("10", false).is_err());
+        assert!(Macro::parse("5*0", false).is_err());
+
+        let mut macros = Macros::new(HashMap::from([
+            (HostKey::F, Macro::parse("5 _", true).unwrap()),
+            (HostKey::G, Macro::parse("4+6*2 6", false).unwrap()),
+        ]));
+        assert!(macros.is_macro(HostKey::F) && !macros.is_macro(HostKey::H));
+        assert!(macros.frame().is_empty());
+
+        // rapid fire for as long as F is held
+        macros.key_down(HostKey::F);
+        assert_eq!(macros.frame(), [KeyEdge::Press(5)]);
+        assert_eq!(macros.frame(), [KeyEdge::Release(5)]);
+        macros.key_down(HostKey::F);
+        assert_eq!(macros.frame(), [KeyEdge::Press(5)]);
+        macros.key_up(HostKey::F);
+        assert_eq!(macros.frame(), [KeyEdge::Release(5)]);
+        assert!(macros.frame().is_empty());
+
+        // the combo plays to the end even when G is let go at once
+        macros.key_down(HostKey::G);
+        macros.key_up(HostKey::G);
+        assert_eq!(macros.frame(), [KeyEdge::Press(4), KeyEdge::Press(6)]);
+        assert!(macros.frame().is_empty());
+        assert_eq!(macros.frame(), [KeyEdge::Release(4)]);
+        assert_eq!(macros.frame(), [KeyEdge::Release(6)]);
+        assert!(macros.frame().is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::font::{self, GLYPH_HEIGHT};
+use chip_8::{default_keymap, HostKey};
+
+use clap::ValueEnum;
+use sdl2::pixels::Color;
+use sdl2::render::WindowCanvas;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// ready made keymaps, the config's [keymap] goes on top of the one picked. several host keys
+// can press the same keypad key, the arrows one keeps the qwerty keys as well
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeymapPreset {
+    // the keypad's 4x4 on 1234 QWER ASDF ZXCV
+    Qwerty,
+    // the keys most games move with around WASD, everything in reach of the left hand
+    LeftHand,
+    // qwerty with the arrow keys on 2 4 8 6 and space on 5
+    Arrows,
+    // laid out on the numeric keypad in the keypad's order, 1 2 3 on the top row
+    Numpad,
+}
+
+pub const PRESETS: [KeymapPreset; 4] = [
+    KeymapPreset::Qwerty,
+    KeymapPreset::LeftHand,
+    KeymapPreset::Arrows,
+    KeymapPreset::Numpad,
+];
+
+impl KeymapPreset {
+    pub fn name(self) -> &'static str {
+        match self {
+            KeymapPreset::Qwerty => "qwerty",
+            KeymapPreset::LeftHand => "left-hand",
+            KeymapPreset::Arrows => "arrows",
+            KeymapPreset::Numpad => "numpad",
+        }
+    }
+
+    // for the picker, in what the font has
+    fn description(self) -> &'static str {
+        match self {
+            KeymapPreset::Qwerty => "1234 QWER ASDF ZXCV",
+            KeymapPreset::LeftHand => "WASD 2 4 8 6  SPACE 5  QEZC 1 3 7 9",
+            KeymapPreset::Arrows => "QWERTY AND ARROWS 2 4 8 6  SPACE 5",
+            KeymapPreset::Numpad => "789 456 123 AS THE KEYPAD 123 456 789",
+        }
+    }
+
+    pub fn keymap(self) -> HashMap<HostKey, u8> {
+        let keys: &[(HostKey, u8)] = match self {
+            KeymapPreset::Qwerty => return default_keymap(),
+            KeymapPreset::LeftHand => &[
+                (HostKey::W, 0x2),
+                (HostKey::A, 0x4),
+                (HostKey::S, 0x8),
+                (HostKey::D, 0x6),
+                (HostKey::Space, 0x5),
+                (HostKey::Q, 0x1),
+                (HostKey::E, 0x3),
+                (HostKey::Z, 0x7),
+                (HostKey::C, 0x9),
+                (HostKey::X, 0x0),
+                (HostKey::Num1, 0xA),
+                (HostKey::Num2, 0xB),
+                (HostKey::R, 0xC),
+                (HostKey::F, 0xD),
+                (HostKey::V, 0xE),
+                (HostKey::G, 0xF),
+            ],
+            KeymapPreset::Arrows => &[
+                (HostKey::Up, 0x2),
+                (HostKey::Left, 0x4),
+                (HostKey::Down, 0x8),
+                (HostKey::Right, 0x6),
+                (HostKey::Space, 0x5),
+            ],
+            // what doesn't fit goes to / and *
+            KeymapPreset::Numpad => &[
+                (HostKey::Kp7, 0x1),
+                (HostKey::Kp8, 0x2),
+                (HostKey::Kp9, 0x3),
+                (HostKey::KpMinus, 0xC),
+                (HostKey::Kp4, 0x4),
+                (HostKey::Kp5, 0x5),
+                (HostKey::Kp6, 0x6),
+                (HostKey::KpPlus, 0xD),
+                (HostKey::Kp1, 0x7),
+                (HostKey::Kp2, 0x8),
+                (HostKey::Kp3, 0x9),
+                (HostKey::KpEnter, 0xE),
+                (HostKey::KpDivide, 0xA),
+                (HostKey::Kp0, 0x0),
+                (HostKey::KpPeriod, 0xB),
+                (HostKey::KpMultiply, 0xF),
+            ],
+        };
+        let mut keymap = match self {
+            KeymapPreset::Arrows => default_keymap(),
+            _ => HashMap::new(),
+        };
+        keymap.extend(keys.iter().copied());
+        keymap
+    }
+}
+
+// the preset with the config's own keys on top
+pub fn keymap(preset: KeymapPreset, overrides: &HashMap<HostKey, u8>) -> HashMap<HostKey, u8> {
+    let mut keymap = preset.keymap();
+    keymap.extend(overrides.iter().map(|(host_key, key)| (*host_key, *key)));
+    keymap
+}
+
+// the F11 keymap picker: up and down (or a number key) pick a preset, enter switches to it.
+// the machine is paused while it's open
+pub struct KeymapPicker {
+    pub selected: usize,
+}
+
+impl KeymapPicker {
+    pub fn new(current: KeymapPreset) -> KeymapPicker {
+        KeymapPicker {
+            selected: PRESETS
+                .iter()
+                .position(|preset| *preset == current)
+                .unwrap_or(0),
+        }
+    }
+
+    // the preset to switch to once it's picked, None while choosing
+    pub fn key_down(&mut self, host_key: HostKey) -> Option<KeymapPreset> {
+        match host_key {
+            HostKey::Up => {
+                self.selected = self.selected.checked_sub(1).unwrap_or(PRESETS.len() - 1)
+            }
+            HostKey::Down => self.selected = (self.selected + 1) % PRESETS.len(),
+            HostKey::Return | HostKey::KpEnter => return Some(PRESETS[self.selected]),
+            HostKey::Num1 => return Some(PRESETS[0]),
+            HostKey::Num2 => return Some(PRESETS[1]),
+            HostKey::Num3 => return Some(PRESETS[2]),
+            HostKey::Num4 => return Some(PRESETS[3]),
+            _ => {}
+        }
+        None
+    }
+
+    pub fn draw(
+        canvas: &mut WindowCanvas,
+        selected: usize,
+        foreground: Color,
+        background: Color,
+    ) -> Result<(), String> {
+        // in units of one font pixel: a title line, a gap and a line for every preset
+        let lines: Vec<String> = PRESETS
+            .iter()
+            .enumerate()
+            .map(|(i, preset)| {
+                let marker = if i == selected { ">" } else { " " };
+                format!(
+                    "{} {} {:10} {}",
+                    marker,
+                    i + 1,
+                    preset.name(),
+                    preset.description()
+                )
+            })
+            .collect();
+        let title = "KEYMAP: UP DOWN OR 1-4 THEN ENTER";
+        let line_height = GLYPH_HEIGHT + 2;
+        let units_wide = 4 + lines
+            .iter()
+            .map(|line| font::text_width(line, 1))
+            .chain([font::text_width(title, 1)])
+            .max()
+            .unwrap_or(0);
+        let units_high = 4 + line_height * (lines.len() as u32 + 2);
+        let (width, height) = canvas.output_size()?;
+        let pixel_size = (width / units_wide).min(height / units_high).max(1);
+        let unit = |units: u32| (units * pixel_size) as i32;
+
+        canvas.set_draw_color(background);
+        canvas.clear();
+        font::draw_text(canvas, title, unit(2), unit(2), pixel_size, foreground)?;
+        for (i, line) in lines.iter().enumerate() {
+            let y = 2 + line_height * (i as u32 + 2);
+            font::draw_text(canvas, line, unit(2), unit(y), pixel_size, foreground)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        for preset in PRESETS {
+            let keymap = preset.keymap();
+            // every keypad key can be pressed
+            for key in 0..16 {
+                assert!(keymap.values().any(|value| *value == key), "{:?}", preset);
+            }
+        }
+        assert_eq!(KeymapPreset::Qwerty.keymap(), default_keymap());
+        // two host keys on the same keypad key
+        let arrows = KeymapPreset::Arrows.keymap();
+        assert_eq!(arrows.get(&HostKey::Up), Some(&0x2));
+        assert_eq!(arrows.get(&HostKey::Num2), Some(&0x2));
+        assert_eq!(KeymapPreset::Numpad.keymap().get(&HostKey::Kp7), Some(&0x1));
+
+        let overrides = HashMap::from([(HostKey::Space, 0xF)]);
+        let keymap = keymap(KeymapPreset::LeftHand, &overrides);
+        assert_eq!(keymap.get(&HostKey::Space), Some(&0xF));
+        assert_eq!(keymap.get(&HostKey::W), Some(&0x2));
+    }
+
+    #[test]
+    fn test_picker() {
+        let mut picker = KeymapPicker::new(KeymapPreset::Arrows);
+        assert_eq!(picker.selected, 2);
+        assert_eq!(picker.key_down(HostKey::Down), None);
+        assert_eq!(picker.key_down(HostKey::Down), None);
+        assert_eq!(picker.selected, 0);
+        assert_eq!(picker.key_down(HostKey::Up), None);
+        assert_eq!(picker.key_down(HostKey::Return), Some(KeymapPreset::Numpad));
+        assert_eq!(picker.key_down(HostKey::Num2), Some(KeymapPreset::LeftHand));
+        assert_eq!(picker.key_down(HostKey::Num5), None);
+        assert_eq!(picker.key_down(HostKey::Escape), None);
+    }
+}
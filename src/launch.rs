@@ -0,0 +1,625 @@
+use crate::audio::{Beeper, ToneGenerator};
+use crate::compare::Compare;
+use crate::config::{self, Config, Settings};
+use crate::control::ControlSocket;
+use crate::crash::CrashReporter;
+use crate::debug_server::DebugServer;
+use crate::framedump::{DumpFormat, FrameDump};
+use crate::headless::Headless;
+use crate::menu::Menu;
+use crate::netplay::Netplay;
+use crate::patches::Patches;
+use crate::profiler::Profiler;
+use crate::replay::{Player, Recorder};
+use crate::runloop::{pick_rom, run, Exit};
+use crate::scripting::Script;
+use crate::sdl::{SdlAudio, SdlDisplay, SdlInput};
+use crate::session::{
+    check_rom, open_saves, persist_flags, resolve_settings, resume, save_state, Autosave, Session,
+    SPLASH_ROM,
+};
+use crate::smc::SmcDetector;
+use crate::thumbnail::Previews;
+use crate::trace::{TraceFormat, TraceWriter};
+use crate::watch::Watcher;
+use crate::RunArgs;
+use crate::{builtin, display, icon, rom, statedump, threaded};
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+use chip_8::{Chip8, Debugger, Keypad, Macros, Quirks, Symbols, Touches};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use sdl2::audio::{AudioDevice, AudioSpecDesired};
+use sdl2::render::WindowCanvas;
+use sdl2::video::WindowPos;
+use sdl2::{Sdl, VideoSubsystem};
+
+// the emulator as run, debug, record and play start it, from the ROM and config to the window
+// closing. returns the exit code
+pub fn launch(args: RunArgs, paused: bool) -> i32 {
+    const HEATMAP_SCALE: usize = 4;
+
+    let rom = match (&args.url, &args.rom_path, &args.builtin) {
+        (Some(url), _, _) => Some(rom::download_rom(url)),
+        (None, Some(path), _) => Some(rom::read_rom(path)),
+        (None, None, Some(name)) => Some(builtin::builtin_rom(name)),
+        (None, None, None) => None,
+    };
+    let rom = match rom.transpose() {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let config_path = args.config.clone().or_else(config::default_config_path);
+    let config = match config_path {
+        Some(path) => Config::load(&path),
+        None => Ok(Config::default()),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("config error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let settings = match resolve_settings(&config, args.rom_path.as_deref(), rom.as_deref(), &args)
+    {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("config error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(rom) = &rom {
+        let name = match (&args.url, &args.rom_path, &args.builtin) {
+            (Some(url), _, _) => url.clone(),
+            (None, Some(path), _) => path.display().to_string(),
+            (None, None, Some(name)) => name.clone(),
+            (None, None, None) => unreachable!(),
+        };
+        if let Err(e) = check_rom(&name, rom, settings.variant, settings.layout) {
+            eprintln!("{}: {}", name, e);
+            std::process::exit(1);
+        }
+    }
+    // without a ROM the game picker lists the ones in --dir or the current directory
+    let mut menu = match (&rom, &args.dir) {
+        (Some(_), _) => None,
+        (None, Some(dir)) => match rom::scan_roms(dir) {
+            Ok(roms) if roms.is_empty() => {
+                eprintln!("no ROMs in {}", dir.display());
+                std::process::exit(1);
+            }
+            Ok(roms) => Some(Menu::new(roms)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        (None, None) => rom::scan_roms(Path::new("."))
+            .ok()
+            .filter(|roms| !roms.is_empty())
+            .map(Menu::new),
+    };
+    let needs_rom = args.compare.is_some()
+        || args.host.is_some()
+        || args.connect.is_some()
+        || args.headless
+        || args.record.is_some()
+        || args.replay.is_some()
+        || args.threaded;
+    if needs_rom && rom.is_none() {
+        eprintln!(
+            "--compare, --host, --connect, --headless, --record, --replay and --threaded need a ROM"
+        );
+        std::process::exit(1);
+    }
+    // the window is created once, so these can't change when another ROM is loaded
+    let scale_factor = settings.scale_factor;
+    let fullscreen = settings.fullscreen;
+
+    let mut chip8 = Chip8::builder()
+        .variant(settings.variant)
+        .layout(settings.layout)
+        .quirks(settings.quirks)
+        .speed(settings.speed)
+        .timing(settings.timing)
+        .predecode(true)
+        .build();
+    if let Err(e) = chip8.load_rom_bytes(rom.as_deref().unwrap_or(&SPLASH_ROM)) {
+        eprintln!("couldn't load ROM: {}", e);
+        std::process::exit(1);
+    }
+    let saves = open_saves(&settings, rom.as_deref(), &mut chip8);
+    if args.resume {
+        resume(saves.as_ref(), &settings, &mut chip8);
+    }
+    let patches = match args.patch.as_deref().map(Patches::load) {
+        Some(Ok(patches)) => patches,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => Patches::default(),
+    };
+    patches.apply(&mut chip8);
+
+    let profiler = if args.profile || args.profile_json.is_some() || args.profile_heatmap.is_some()
+    {
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        chip8.add_hook(Box::new(profiler.clone()));
+        Some(profiler)
+    } else {
+        None
+    };
+    let tracer = match &args.trace {
+        Some(path) => {
+            let format = args
+                .trace_format
+                .unwrap_or_else(|| TraceFormat::for_path(path));
+            match TraceWriter::create(path, format) {
+                Ok(writer) => {
+                    let writer = Rc::new(RefCell::new(writer));
+                    chip8.add_hook(Box::new(writer.clone()));
+                    Some(writer)
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => None,
+    };
+    let frame_dump = args.dump_frames.as_ref().map(|dir| {
+        let format = args.dump_format.unwrap_or(DumpFormat::Png);
+        FrameDump::create(dir, args.every.unwrap_or(1), format, &settings).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+    let smc_detector = args.warn_smc.then(|| {
+        let detector = Rc::new(RefCell::new(SmcDetector::new()));
+        chip8.add_hook(Box::new(detector.clone()));
+        detector
+    });
+    let crash_reporter = args
+        .crash_report
+        .as_deref()
+        .map(|dir| CrashReporter::attach(dir, &mut chip8));
+
+    let debug_server = match args.debug_listen.map(DebugServer::bind).transpose() {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let control = match args.control_socket.as_deref().map(ControlSocket::bind) {
+        Some(Ok(control)) => Some(control),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let mut debugger = Debugger::new();
+    // only a debugger client can ask for the calls, what drew the pixels or to go back, don't
+    // pay for tracing them otherwise
+    if debug_server.is_some() {
+        debugger.trace_calls(&mut chip8);
+        debugger.trace_draws(&mut chip8);
+        debugger.record_history(&mut chip8);
+    }
+    if paused {
+        debugger.pause(&chip8);
+    }
+    match args.symbols.as_deref().map(load_symbols) {
+        Some(Ok(symbols)) => debugger.set_symbols(symbols),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => {}
+    }
+
+    // both players' machines have to roll the same random numbers
+    let netplay = match (args.host, args.connect, &rom) {
+        (Some(addr), _, Some(rom)) => Some(Netplay::host(addr, rom)),
+        (_, Some(addr), Some(rom)) => Some(Netplay::connect(addr, rom)),
+        _ => None,
+    };
+    let netplay = match netplay.transpose() {
+        Ok(Some((netplay, seed))) => {
+            chip8.set_rng(Box::new(StdRng::seed_from_u64(seed)));
+            Some(netplay)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    // and a replay the ones it was recorded with
+    let (recorder, player, seed) = match (&args.record, &args.replay, &rom) {
+        (Some(path), _, Some(rom)) => {
+            let seed = rand::random();
+            Recorder::create(path, rom, seed).map(|recorder| (Some(recorder), None, Some(seed)))
+        }
+        (_, Some(path), Some(rom)) => {
+            Player::load(path, rom).map(|(player, seed)| (None, Some(player), Some(seed)))
+        }
+        _ => Ok((None, None, None)),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(seed) = seed {
+        chip8.set_rng(Box::new(StdRng::seed_from_u64(seed)));
+    }
+
+    let watch = match args
+        .rom_path
+        .as_deref()
+        .filter(|_| args.watch)
+        .map(Watcher::new)
+    {
+        Some(Ok(watcher)) => Some(watcher),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let script = match args
+        .script
+        .as_deref()
+        .map(|path| Script::load(path, &mut chip8))
+    {
+        Some(Ok(script)) => Some(script),
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let macros = Macros::new(settings.macros.clone());
+    let keypad = Keypad::with_keymap(settings.keymap.clone());
+    let mut session = Session {
+        chip8,
+        rom,
+        settings,
+        saves,
+        patches,
+        script,
+        debugger,
+        debug_server,
+        control,
+        frame_advance: args.frame_advance.then_some(0),
+        netplay,
+        watch,
+        sprites: None,
+        slots: None,
+        keymaps: None,
+        recorder,
+        player,
+        autosave: args.autosave.map(|seconds| Autosave {
+            interval: Duration::from_secs(seconds),
+            last: Instant::now(),
+        }),
+        keypad,
+        touches: Touches::new(),
+        macros,
+        frame_dump,
+        crash_reporter,
+        profiler: profiler.clone(),
+        calibrator: None,
+        heatmap: false,
+    };
+    session.calibrate();
+    // no window, sound or keyboard, the ROM runs until it's done
+    let mut exit_code = if args.headless {
+        let exit_code = run_headless(&mut session, &config, &args);
+        match args
+            .dump_state_json
+            .as_deref()
+            .map(|path| statedump::write(path, &session.chip8, args.base64_memory))
+        {
+            Some(Err(e)) => {
+                error!("{}", e);
+                1
+            }
+            _ => exit_code,
+        }
+    } else {
+        let sdl_context = sdl2::init().unwrap_or_else(|e| sdl_failure("start SDL", e));
+        let video_subsystem = sdl_context
+            .video()
+            .unwrap_or_else(|e| sdl_failure("start SDL video", e));
+        // a machine without sound still runs the game, just quietly
+        let audio_device = if args.no_audio {
+            None
+        } else {
+            open_audio(&sdl_context, &session.settings)
+                .map_err(|e| warn!("no sound: {}", e))
+                .ok()
+        };
+        // two screens wide when comparing
+        let screens = if args.compare.is_some() { 2 } else { 1 };
+        let mut window_builder = video_subsystem.window(
+            "chip8 emulator",
+            chip_8::DISPLAY_WIDTH as u32 * scale_factor * screens,
+            session.chip8.display_height() as u32 * scale_factor,
+        );
+        // the window size is in points, drawing goes by the pixels underneath
+        window_builder
+            .position_centered()
+            .resizable()
+            .allow_highdpi();
+        if fullscreen {
+            window_builder.fullscreen_desktop();
+        }
+        let mut window = window_builder
+            .build()
+            .unwrap_or_else(|e| sdl_failure("open a window", e));
+        icon::set_window_icon(&mut window);
+        let mut canvas_builder = window.into_canvas();
+        if session.settings.vsync {
+            canvas_builder = canvas_builder.present_vsync();
+        }
+        let mut canvas = canvas_builder
+            .build()
+            .unwrap_or_else(|e| sdl_failure("draw in the window", e));
+        if !fullscreen {
+            fit_to_dpi(&video_subsystem, &mut canvas);
+        }
+        let texture_creator = canvas.texture_creator();
+        let mut display = SdlDisplay::new(canvas, &texture_creator, &session.settings)
+            .unwrap_or_else(|e| sdl_failure("draw in the window", e));
+        let mut audio = SdlAudio::new(audio_device);
+        let event_pump = sdl_context
+            .event_pump()
+            .unwrap_or_else(|e| sdl_failure("read the keyboard", e));
+        let mut input = SdlInput::new(event_pump);
+
+        // the left machine is the session's and saves like it would on its own, the right one
+        // only gets the saved flags to start from
+        if let (Some(quirks), Some(rom)) = (args.compare, session.rom.clone()) {
+            let settings = &session.settings;
+            display
+                .enable_compare(&texture_creator, settings)
+                .unwrap_or_else(|e| sdl_failure("draw in the window", e));
+            let mut right = Chip8::builder()
+                .variant(settings.variant)
+                .layout(settings.layout)
+                .quirks(Quirks {
+                    memory: settings.quirks.memory,
+                    unknown_opcode: settings.quirks.unknown_opcode,
+                    ..quirks
+                })
+                .build();
+            right.load_rom_bytes(&rom).unwrap();
+            right.rpl_flags = session.chip8.rpl_flags;
+            session.patches.apply(&mut right);
+            for machine in [&mut session.chip8, &mut right] {
+                machine.set_speed(settings.speed);
+                machine.set_timing(settings.timing);
+            }
+            let keypad = Keypad::with_keymap(settings.keymap.clone());
+            // lent to the comparison, the session gets it back once the window is closed
+            let left = std::mem::replace(&mut session.chip8, Chip8::builder().build());
+            let mut compare = Compare::new([left, right], rom, session.patches.clone(), keypad);
+            let exit_code = compare.run(&mut display, &mut audio, &mut input);
+            let [left, _] = compare.into_machines();
+            session.chip8 = left;
+            exit_code
+        } else if args.threaded {
+            threaded::run(
+                &mut session.chip8,
+                session.patches.clone(),
+                Keypad::with_keymap(session.settings.keymap.clone()),
+                &mut display,
+                &mut audio,
+                &mut input,
+            )
+        } else {
+            let mut show_menu = menu.is_some();
+            let previews = menu.as_ref().map(|menu| {
+                Previews::start(menu.roms().to_vec(), session.settings.save_dir.clone())
+            });
+            loop {
+                if let (true, Some(menu), Some(previews)) = (show_menu, &mut menu, &previews) {
+                    let path = match pick_rom(menu, previews, &mut display, &mut input) {
+                        Some(path) => path,
+                        None => break 0,
+                    };
+                    match session.open(&path, &config, &args) {
+                        Ok(()) => display.configure(&session.settings),
+                        Err(e) => {
+                            warn!("{}", e);
+                            continue;
+                        }
+                    }
+                    show_menu = false;
+                }
+                match run(&mut session, &mut display, &mut audio, &mut input) {
+                    Exit::Quit => break 0,
+                    Exit::Error => break 1,
+                    // back to the game picker if there is one
+                    Exit::Reset if menu.is_some() => show_menu = true,
+                    Exit::Reset => session.restart(),
+                    Exit::Exited if menu.is_some() => show_menu = true,
+                    Exit::Exited => break 0,
+                    Exit::Open(path) => match session.open(&path, &config, &args) {
+                        Ok(()) => display.configure(&session.settings),
+                        Err(e) => warn!("{}", e),
+                    },
+                    // a broken save leaves the old version running until the next one
+                    Exit::Reload(path) if args.watch_reconfigure => {
+                        match session.open(&path, &config, &args) {
+                            Ok(()) => display.configure(&session.settings),
+                            Err(e) => warn!("{}", e),
+                        }
+                    }
+                    Exit::Reload(path) => {
+                        if let Err(e) = session.reload(&path) {
+                            warn!("{}", e);
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    persist_flags(session.saves.as_mut(), &session.chip8);
+    session.persist_memory();
+    if session.autosave.is_some() {
+        save_state(session.saves.as_ref(), &session.chip8);
+    }
+    if let Some(recorder) = &mut session.recorder {
+        recorder.finish();
+    }
+    if let Some(profiler) = profiler {
+        let report = profiler.borrow().report();
+        if args.profile {
+            print!("{}", report);
+        }
+        if let Some(path) = &args.profile_json {
+            let json = serde_json::to_string_pretty(&report).unwrap();
+            if let Err(e) = fs::write(path, json) {
+                error!("couldn't write {}: {}", path.display(), e);
+                exit_code = 1;
+            }
+        }
+        if let Some(path) = &args.profile_heatmap {
+            let png = profiler.borrow().heat().encode_png(HEATMAP_SCALE);
+            if let Err(e) = fs::write(path, png) {
+                error!("couldn't write {}: {}", path.display(), e);
+                exit_code = 1;
+            }
+        }
+    }
+    if let Some(tracer) = tracer {
+        if let Err(e) = tracer.borrow_mut().finish() {
+            error!("{}", e);
+            exit_code = 1;
+        }
+    }
+    if let Some(detector) = smc_detector {
+        let count = detector.borrow().warnings().len();
+        info!("{} writes into code", count);
+    }
+    exit_code
+}
+
+// make the window as big on a high-DPI display as it would be on a normal one
+fn fit_to_dpi(video_subsystem: &VideoSubsystem, canvas: &mut WindowCanvas) {
+    let window_size = canvas.window().size();
+    let drawable_width = match canvas.output_size() {
+        Ok((width, _)) => width,
+        Err(_) => return,
+    };
+    let dpi = canvas
+        .window()
+        .display_index()
+        .and_then(|index| video_subsystem.display_dpi(index))
+        .map(|(_, horizontal, _)| horizontal)
+        .ok();
+    let (width, height) = display::hidpi_window_size(window_size, drawable_width, dpi);
+    if (width, height) != window_size {
+        let window = canvas.window_mut();
+        if window.set_size(width, height).is_ok() {
+            window.set_position(WindowPos::Centered, WindowPos::Centered);
+        }
+    }
+}
+
+// what the SDL frontend can't do without. the usual reason is running where there's no display
+fn sdl_failure(what: &str, e: impl std::fmt::Display) -> ! {
+    eprintln!("couldn't {}: {}", what, e);
+    eprintln!(
+        "without a display (is DISPLAY or WAYLAND_DISPLAY set?) --headless runs the ROM anyway"
+    );
+    std::process::exit(1);
+}
+
+fn run_headless(session: &mut Session, config: &Config, args: &RunArgs) -> i32 {
+    // there's no monitor to wait for
+    session.settings.vsync = false;
+    let mut headless = Headless::new(args.frames);
+    let mut audio = Headless::new(None);
+    loop {
+        match run(session, &mut Headless::new(None), &mut audio, &mut headless) {
+            Exit::Error => return 1,
+            // only the control socket can ask for another ROM without a window
+            Exit::Open(path) => {
+                if let Err(e) = session.open(&path, config, args) {
+                    warn!("{}", e);
+                }
+            }
+            _ => return 0,
+        }
+    }
+}
+
+fn open_audio(sdl_context: &Sdl, settings: &Settings) -> Result<AudioDevice<Beeper>, String> {
+    let audio_subsystem = sdl_context.audio()?;
+    let devices: Vec<String> = (0..audio_subsystem.num_audio_playback_devices().unwrap_or(0))
+        .filter_map(|index| audio_subsystem.audio_playback_device_name(index).ok())
+        .collect();
+    if let Some(name) = settings
+        .audio_device
+        .as_ref()
+        .filter(|name| !devices.contains(name))
+    {
+        return Err(format!("no audio device {:?}, there's {:?}", name, devices));
+    }
+    let desired_spec = AudioSpecDesired {
+        freq: Some(settings.sample_rate),
+        channels: Some(1),
+        samples: Some(settings.buffer_samples),
+    };
+    let audio_device =
+        audio_subsystem.open_playback(settings.audio_device.as_deref(), &desired_spec, |spec| {
+            let tone = ToneGenerator::new(
+                settings.waveform,
+                settings.beep_freq,
+                spec.freq,
+                settings.volume.clamp(0.0, 1.0),
+            );
+            Beeper::new(tone, spec.freq)
+        })?;
+    // the device doesn't have to go along with what was asked for
+    let obtained = audio_device.spec();
+    if obtained.freq != settings.sample_rate || obtained.samples != settings.buffer_samples {
+        warn!(
+            "audio: got {} Hz with {} sample buffers ({:.1} ms) instead of {} Hz with {}",
+            obtained.freq,
+            obtained.samples,
+            obtained.samples as f32 * 1000.0 / obtained.freq as f32,
+            settings.sample_rate,
+            settings.buffer_samples
+        );
+    }
+    Ok(audio_device)
+}
+
+pub fn load_symbols(path: &Path) -> Result<Symbols, String> {
+    fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| Symbols::parse(&contents))
+        .map_err(|e| format!("couldn't load symbols {}: {}", path.display(), e))
+}
@@ -0,0 +1,55 @@
+//! A CHIP-8 interpreter that can be embedded in other programs. The frontend in `main.rs`
+//! (SDL window, audio, config files) is built on top of this crate and nothing more. Only the
+//! frontend needs SDL and its command line stack, they're behind the default `sdl` and `cli`
+//! features.
+//!
+//! ```
+//! use chip_8::{Chip8, Variant};
+//!
+//! let mut chip8 = Chip8::builder().variant(Variant::Chip8).build();
+//! // V0 = 0x2A, then loop forever
+//! chip8.load_rom_bytes(&[0x60, 0x2A, 0x12, 0x02]).unwrap();
+//! chip8.emulate_cycle().unwrap();
+//! assert_eq!(chip8.registers()[0], 0x2A);
+//! // call this at TIMER_FREQ, and emulate_cycle at whatever speed the ROM wants
+//! chip8.tick_timers();
+//! ```
+//!
+//! Or let the interpreter keep time: `Chip8::run_frame()` runs a 60th of a second at
+//! `Chip8::speed()` instructions per second and returns the [`Event`]s that came of it
+//! (display updates, the buzzer starting and stopping, waiting for a key, exiting, errors).
+//!
+//! The screen is `Chip8::framebuffer()`, a [`FrameBuffer`] `DISPLAY_WIDTH` pixels wide and
+//! `Chip8::display_height()` rows high. Implement [`Hooks`] to observe execution, and
+//! [`Peripheral`] to put more hardware on the bus.
+//!
+//! Keys are pressed with `Chip8::set_key`. A [`Keypad`] holds a keymap and turns presses of
+//! [`HostKey`]s, the frontend's keys, into those.
+//!
+//! The interpreter logs through [`tracing`](https://docs.rs/tracing): a `frame` span around
+//! every `run_frame`, an `instruction` event at trace level for every instruction run, and
+//! debug events for loading ROMs and resets. Nothing is printed unless a subscriber is set up.
+
+pub mod analysis;
+pub mod asm;
+pub mod chip8;
+pub mod debugger;
+pub mod expression;
+pub mod framebuffer;
+pub mod frontend;
+pub mod input;
+pub mod savestate;
+pub mod symbols;
+
+pub use crate::analysis::{analyze, Analysis};
+pub use crate::chip8::chip8::{
+    create_chip8, decode, try_decode, Chip8, Chip8Builder, Chip8Error, DecodeCacheStats, Event,
+    FrameBudget, FrameOutput, Hooks, KeyWait, Layout, MachineCodePolicy, MemoryPolicy, Opcode,
+    Peripheral, Quirks, RunLimit, Snapshot, Status, Timing, UnknownOpcodePolicy, Variant,
+    CYCLE_FREQ, DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, MAX_ROM_SIZE, MEM_SIZE,
+    RPL_FLAG_COUNT, STACK_SIZE, TIMER_FREQ,
+};
+pub use crate::debugger::Debugger;
+pub use crate::framebuffer::FrameBuffer;
+pub use crate::input::{default_keymap, HostKey, KeyEdge, Keypad, Macro, Macros, Touches};
+pub use crate::symbols::Symbols;
@@ -0,0 +1,83 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing::Subscriber;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+// what -v and -vv show: info by default (what was always printed), debug for what the
+// emulator is doing (ROMs loaded, saves, settings), then trace for every frame and instruction
+pub fn level(verbosity: u8) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+// messages go to stderr, and with --log-json to that file too, one JSON object a line with the
+// frame span it happened in
+pub fn init(verbosity: u8, json: Option<&Path>) -> Result<(), String> {
+    let json = json
+        .map(|path| {
+            File::create(path).map_err(|e| format!("couldn't create {}: {}", path.display(), e))
+        })
+        .transpose()?;
+    subscriber(verbosity, json)
+        .try_init()
+        .map_err(|e| format!("couldn't start logging: {}", e))
+}
+
+fn subscriber(verbosity: u8, json: Option<File>) -> impl Subscriber + Send + Sync {
+    // the module a message came from is only interesting when looking into things
+    let stderr = fmt::layer()
+        .with_writer(std::io::stderr)
+        .without_time()
+        .with_target(verbosity > 0);
+    let json = json.map(|file| fmt::layer().json().with_writer(Mutex::new(file)));
+    tracing_subscriber::registry()
+        .with(level(verbosity))
+        .with(stderr)
+        .with(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::Chip8;
+    use std::fs;
+
+    #[test]
+    fn test_json_log() {
+        let path = std::env::temp_dir().join(format!("chip8-log-{}.json", std::process::id()));
+        let subscriber = subscriber(2, Some(File::create(&path).unwrap()));
+        tracing::subscriber::with_default(subscriber, || {
+            let mut chip8 = Chip8::builder().build();
+            // V0 += 1 forever
+            chip8.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+            chip8.run_frame();
+        });
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["level"], "DEBUG");
+        assert_eq!(lines[0]["fields"]["message"], "ROM loaded");
+        assert_eq!(lines[0]["fields"]["size"], 4);
+        let instructions: Vec<_> = lines
+            .iter()
+            .filter(|line| line["fields"]["message"] == "instruction")
+            .collect();
+        // a 60th of the default speed
+        assert_eq!(instructions.len(), 14);
+        assert_eq!(instructions[0]["fields"]["pc"], 0x200);
+        assert_eq!(instructions[0]["span"]["name"], "frame");
+        assert_eq!(instructions[1]["fields"]["pc"], 0x202);
+
+        assert_eq!(level(0), LevelFilter::INFO);
+        assert_eq!(level(5), LevelFilter::TRACE);
+    }
+}
@@ -1,159 +1,697 @@
 extern crate sdl2;
 
 mod audio;
-mod chip8;
+mod builtin;
+mod calibrate;
+mod commands;
+mod compare;
+mod config;
+mod control;
+mod crash;
+mod debug_server;
+mod desktop;
+mod display;
+mod font;
+mod framedump;
+mod headless;
+mod heatmap;
+mod icon;
+mod keymaps;
+mod launch;
+mod logging;
+mod menu;
+mod netplay;
+mod overlay;
+mod patches;
+mod persistence;
+mod profiler;
+mod replay;
+mod rom;
+mod runloop;
+mod scripting;
+mod sdl;
+mod session;
+mod slots;
+mod smc;
+mod sprites;
+mod statedump;
+mod threaded;
+mod thumbnail;
+mod title;
+mod trace;
+mod watch;
 
-use audio::SquareWave;
+use audio::Waveform;
+use commands::analyze::analyze;
+use commands::asm::assemble;
+use commands::audio_devices::audio_devices;
+use commands::desktop_entry::install_desktop_entry;
+use commands::disasm::disasm;
+use commands::man::man_page;
+use commands::state_info::state_info;
+use commands::trace_diff::trace_diff;
+use config::{AudioConfig, Config, QuirksConfig};
+use display::{Blend, Filter, Scaling};
+use framedump::DumpFormat;
+use keymaps::KeymapPreset;
+use trace::TraceFormat;
 
-use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
 
-use clap::Parser;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 
-use sdl2::audio::AudioSpecDesired;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Point;
-use sdl2::render::WindowCanvas;
-use crate::chip8::chip8::Chip8;
+use chip_8::{
+    Chip8, Layout, MachineCodePolicy, MemoryPolicy, Quirks, Timing, UnknownOpcodePolicy, Variant,
+};
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-struct Args {
-    // Path to the ROM file
-    #[clap(value_parser)]
-    rom_path: PathBuf,
-    // Pixel scale factor
-    #[clap(long, value_parser, default_value_t = 6)]
-    scale_factor: u32,
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    // without a subcommand it's `run`, so `chip-8 rom.ch8` still starts the ROM
+    #[clap(flatten)]
+    run: RunArgs,
+    /// Say more about what's going on: -v for debug messages, -vv for every frame and instruction
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Also write the log to this file as JSON, one object a line
+    #[clap(long, global = true, value_parser)]
+    log_json: Option<PathBuf>,
 }
 
-fn main() {
-    let args = Args::parse();
-    let filename = args.rom_path;
-    let scale_factor = args.scale_factor;
-    let filepath = Path::new(&filename);
-    assert!(filepath.is_file());
-
-    let mut chip8 = chip8::chip8::create_chip8();
-    chip8.load_rom(filepath);
-
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    // audio init
-    let audio_subsystem = sdl_context.audio().unwrap();
-    let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1),
-        samples: None,
-    };
-    let audio_device = audio_subsystem
-        .open_playback(None, &desired_spec, |spec| SquareWave {
-            phase_inc: 440.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.25,
-        })
-        .unwrap();
-    let window = video_subsystem
-        .window(
-            "chip8 emulator",
-            chip8::chip8::DISPLAY_WIDTH as u32 * scale_factor,
-            chip8::chip8::DISPLAY_HEIGHT as u32 * scale_factor,
-        )
-        .position_centered()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
+// what running a ROM takes, shared by run, debug, record and play
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Path to the ROM file, or - to read it from stdin. Without one a menu of the ROMs in the
+    /// current directory is shown, or a splash screen if there are none
+    #[clap(value_parser)]
+    rom_path: Option<PathBuf>,
+    /// Download the ROM from a URL (needs the "http" feature)
+    #[clap(long, value_parser, conflicts_with = "rom-path")]
+    url: Option<String>,
+    /// Pick a ROM from this directory instead of the current one
+    #[clap(long, value_parser, conflicts_with_all = &["rom-path", "url"])]
+    dir: Option<PathBuf>,
+    /// Run one of the ROMs that come with the emulator: pong, keypad or beep (needs the
+    /// "bundled-roms" feature)
+    #[clap(long, value_parser, conflicts_with_all = &["rom-path", "url", "dir"])]
+    builtin: Option<String>,
+    /// Pixel scale factor for the initial window size
+    #[clap(long, value_parser)]
+    scale_factor: Option<u32>,
+    /// Start in fullscreen, Alt+Enter toggles it
+    #[clap(long, value_parser)]
+    fullscreen: bool,
+    /// Let the monitor's refresh pace the emulator instead of sleeping between frames
+    #[clap(long, value_parser)]
+    vsync: bool,
+    /// Faint lines between the chip-8 pixels at high scale factors, F10 toggles them
+    #[clap(long, value_parser)]
+    grid: bool,
+    /// How the screen fills the window: integer (whole multiples, with a border), fit (keeping
+    /// the aspect ratio) or stretch. F4 switches at runtime
+    #[clap(long, value_enum)]
+    scaling: Option<Scaling>,
+    /// Same as --scaling integer
+    #[clap(long, value_parser, conflicts_with = "scaling")]
+    integer_scaling: bool,
+    /// Display filter
+    #[clap(long, value_enum)]
+    filter: Option<Filter>,
+    /// Combine the last N frames to hide flicker
+    #[clap(long, value_parser)]
+    blend: Option<usize>,
+    /// How --blend combines frames
+    #[clap(long, value_enum)]
+    blend_mode: Option<Blend>,
+    /// Milliseconds switched off pixels take to fade out, like the phosphor of old displays
+    #[clap(long, value_parser)]
+    persistence: Option<u32>,
+    /// The keys to start from, the config's [keymap] goes on top. F11 switches while running
+    #[clap(long, value_enum)]
+    keymap_preset: Option<KeymapPreset>,
+    /// Instructions per second
+    #[clap(long, value_parser)]
+    speed: Option<u64>,
+    /// Raise the speed for games that poll the delay timer but don't get to it within a frame, and
+    /// bring it back down (never below --speed) once they spend most of each frame waiting
+    #[clap(long, value_parser)]
+    auto_speed: bool,
+    /// How long instructions take: flat (--speed of them a second) or vip (as long as on the
+    /// COSMAC VIP, so drawing is slow like it was)
+    #[clap(long, value_parser = Timing::from_str)]
+    timing: Option<Timing>,
+    /// Interpreter variant: chip8, hires (64x64) or chip8x (color)
+    #[clap(long, value_parser = Variant::from_str)]
+    variant: Option<Variant>,
+    /// Where the ROM is loaded and starts: vip (0x200), chip8x (0x300) or eti660 (0x600).
+    /// Defaults to the variant's
+    #[clap(long, value_parser = Layout::preset)]
+    layout: Option<Layout>,
+    /// Load the ROM at this address instead (0x200 or above), it also starts there unless
+    /// --start-address is given
+    #[clap(long, value_parser = debug_server::parse_number)]
+    load_address: Option<usize>,
+    /// Start running from this address instead
+    #[clap(long, value_parser = debug_server::parse_number)]
+    start_address: Option<usize>,
+    /// Interpreter behaviours to copy: cosmac (the original VIP), schip or modern
+    #[clap(long, value_parser = Quirks::preset)]
+    quirks: Option<Quirks>,
+    /// Reads and writes past the end of memory: wrap around, or stop with an error
+    #[clap(long, value_parser = MemoryPolicy::from_str)]
+    memory_policy: Option<MemoryPolicy>,
+    /// 0NNN calls to 1802 machine code, which can't be run: ignore them, or stop with an error
+    #[clap(long, value_parser = MachineCodePolicy::from_str)]
+    machine_code: Option<MachineCodePolicy>,
+    /// Words that aren't instructions, usually data run as code: stop with an error (fault), or
+    /// pass over them quietly (skip) or with a warning (warn)
+    #[clap(long, value_parser = UnknownOpcodePolicy::from_str)]
+    unknown_opcode: Option<UnknownOpcodePolicy>,
+    /// Beep frequency in Hz
+    #[clap(long, value_parser)]
+    beep_freq: Option<f32>,
+    /// Beep volume, 0.0 to 1.0
+    #[clap(long, value_parser)]
+    volume: Option<f32>,
+    /// Beep waveform
+    #[clap(long, value_enum)]
+    waveform: Option<Waveform>,
+    /// Audio samples per second
+    #[clap(long, value_parser)]
+    sample_rate: Option<i32>,
+    /// Audio buffer size in samples, a power of two: smaller beeps sooner, too small crackles
+    #[clap(long, value_parser)]
+    audio_buffer: Option<u16>,
+    /// Audio output device, by the name SDL gives it. `chip-8 audio-devices` lists them
+    #[clap(long, value_parser)]
+    audio_device: Option<String>,
+    /// Don't open the audio device at all, for machines without one. M mutes while running
+    #[clap(long, value_parser)]
+    no_audio: bool,
+    /// Run without a window, sound or keyboard until the ROM exits (for CI and scripts)
+    #[clap(long, value_parser, conflicts_with_all = &["dir", "compare"])]
+    headless: bool,
+    /// With --headless, stop after this many frames (60 a second)
+    #[clap(long, value_parser, requires = "headless")]
+    frames: Option<u64>,
+    /// With --headless, write the registers, timers, stack, screen and memory to this JSON file
+    /// (- for stdout) once the run is over
+    #[clap(long, value_parser, requires = "headless")]
+    dump_state_json: Option<PathBuf>,
+    /// Put the memory in the --dump-state-json file as base64 instead of an array of numbers
+    #[clap(long, value_parser, requires = "dump-state-json")]
+    base64_memory: bool,
+    /// Directory for persisted RPL flags and autosaves instead of ~/.local/share/chip8/saves
+    #[clap(long, value_parser)]
+    save_dir: Option<PathBuf>,
+    /// Save the game to the save directory every this many seconds and on exit
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    autosave: Option<u64>,
+    /// Carry on from the ROM's last autosave
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &["compare", "host", "connect", "record", "replay"]
+    )]
+    resume: bool,
+    /// Config file to use instead of ~/.config/chip8/config.toml
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
+    /// Accept debugger connections on this address, e.g. 127.0.0.1:4242
+    #[clap(long, value_parser)]
+    debug_listen: Option<SocketAddr>,
+    /// Take JSON-RPC requests (load, pause, resume, step, key, screenshot, state) on a Unix socket
+    /// at this path, for launchers and test runners
+    #[clap(long, value_parser)]
+    control_socket: Option<PathBuf>,
+    /// Symbol file with names for the ROM's addresses, used by the debugger
+    #[clap(long, value_parser)]
+    symbols: Option<PathBuf>,
+    /// TOML file with memory patches and freezes for the ROM
+    #[clap(long, value_parser)]
+    patch: Option<PathBuf>,
+    /// Rhai script with on_frame, on_instruction and on_memory_write callbacks
+    #[clap(long, value_parser)]
+    script: Option<PathBuf>,
+    /// Start paused in frame advance mode: F7 runs one frame, F6 lets it run freely
+    #[clap(long, value_parser)]
+    frame_advance: bool,
+    /// Print a profiling report on exit
+    #[clap(long, value_parser)]
+    profile: bool,
+    /// Write the profiling report to a JSON file on exit
+    #[clap(long, value_parser)]
+    profile_json: Option<PathBuf>,
+    /// Write a picture of how often every memory address was read, written and run to a PNG file
+    /// on exit. F9 shows it live
+    #[clap(long, value_parser)]
+    profile_heatmap: Option<PathBuf>,
+    /// Write every instruction run with the registers to a file, to load into other tools or
+    /// diff against another emulator
+    #[clap(long, value_parser, conflicts_with = "compare")]
+    trace: Option<PathBuf>,
+    /// jsonl (one JSON object a line) or vcd (a timeline for waveform viewers). By default vcd
+    /// for a .vcd file, jsonl otherwise
+    #[clap(long, value_parser = TraceFormat::from_str, requires = "trace")]
+    trace_format: Option<TraceFormat>,
+    /// Write the frames run to this directory as pictures named by frame number, to compare
+    /// against ones known to be right
+    #[clap(long, value_parser)]
+    dump_frames: Option<PathBuf>,
+    /// With --dump-frames, only every Nth frame (1 by default)
+    #[clap(long, value_parser, requires = "dump-frames")]
+    every: Option<u64>,
+    /// png (as shown, with the palette and filter) or pbm (the bare chip-8 screen)
+    #[clap(long, value_parser = DumpFormat::from_str, requires = "dump-frames")]
+    dump_format: Option<DumpFormat>,
+    /// Warn on stderr when the ROM writes into its own code
+    #[clap(long, value_parser)]
+    warn_smc: bool,
+    /// When the ROM stops with an error, write a report of it with the last instructions run, a
+    /// save state and the screen to a new directory in this one, for bug reports
+    #[clap(long, value_parser, conflicts_with = "compare")]
+    crash_report: Option<PathBuf>,
+    /// Run the ROM a second time with these quirks (cosmac, schip or modern), side by side
+    #[clap(long, value_parser = Quirks::preset, conflicts_with = "dir")]
+    compare: Option<Quirks>,
+    /// Network play (experimental): wait for another player on this address, e.g. 0.0.0.0:4243
+    #[clap(long, value_parser, conflicts_with_all = &["dir", "compare"])]
+    host: Option<SocketAddr>,
+    /// Network play (experimental): join the game hosted on this address
+    #[clap(long, value_parser, conflicts_with_all = &["dir", "compare", "host"])]
+    connect: Option<SocketAddr>,
+    /// Record the keys pressed to a replay file
+    #[clap(long, value_parser, conflicts_with_all = &["dir", "compare", "host", "connect"])]
+    record: Option<PathBuf>,
+    /// Play a recorded replay back, saying where the game goes differently than it did
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &["dir", "compare", "host", "connect", "record"]
+    )]
+    replay: Option<PathBuf>,
+    /// Reload the ROM whenever its file is saved, keeping the settings it runs with
+    #[clap(
+        long,
+        value_parser,
+        requires = "rom-path",
+        conflicts_with_all = &["compare", "host", "connect"]
+    )]
+    watch: bool,
+    /// With --watch, work the settings out again for the new ROM like opening it does
+    #[clap(long, value_parser, requires = "watch")]
+    watch_reconfigure: bool,
+    /// Run the machine on a thread of its own so slow drawing can't hold it back. Leaves out
+    /// the debugger, scripts, viewers, save slots, network play and recording
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = &[
+            "dir", "headless", "compare", "host", "connect", "record", "replay", "watch",
+            "debug-listen", "script", "frame-advance", "profile", "profile-json", "warn-smc",
+            "crash-report", "profile-heatmap", "trace", "dump-frames", "auto-speed",
+            "control-socket"
+        ]
+    )]
+    threaded: bool,
+}
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a ROM, what `chip-8 rom.ch8` does too
+    Run(RunArgs),
+    /// Run a ROM paused, with the debugger listening on --debug-listen (127.0.0.1:4242 by
+    /// default) for a client to set breakpoints and continue
+    Debug(RunArgs),
+    /// List a ROM's instructions with their addresses, and the bytes that aren't reached as code
+    /// as data
+    Disasm {
+        /// The ROM to list
+        #[clap(value_parser)]
+        rom_path: PathBuf,
+        /// Interpreter variant, guessed from the ROM if not given
+        #[clap(long, value_parser = Variant::from_str)]
+        variant: Option<Variant>,
+        /// Symbol file with names for the ROM's addresses, shown as labels
+        #[clap(long, value_parser)]
+        symbols: Option<PathBuf>,
+    },
+    /// Compile Octo source to a ROM
+    Asm {
+        /// The Octo source file (.8o)
+        #[clap(value_parser)]
+        source: PathBuf,
+        /// Where to write the ROM, the source with a .ch8 extension by default
+        #[clap(short, long, value_parser)]
+        output: Option<PathBuf>,
+    },
+    /// Print what can be told about a ROM without running it: reachable code, jumps and calls,
+    /// the opcodes used and the quirks they depend on
+    Analyze {
+        /// The ROM to look at
+        #[clap(value_parser)]
+        rom_path: PathBuf,
+        /// Interpreter variant, guessed from the ROM if not given
+        #[clap(long, value_parser = Variant::from_str)]
+        variant: Option<Variant>,
+    },
+    /// Print what's in a save state file: format version, variant, quirks, the ROM it belongs to
+    /// and where the machine was
+    StateInfo {
+        /// The save state file
+        #[clap(value_parser)]
+        path: PathBuf,
+    },
+    /// Run a ROM and compare every instruction with a reference trace in the --trace JSON Lines
+    /// format, e.g. another emulator's log converted to it. Stops at the first difference
+    TraceDiff {
+        /// The ROM to run
+        #[clap(value_parser)]
+        rom_path: PathBuf,
+        /// The trace to compare with
+        #[clap(value_parser)]
+        reference: PathBuf,
+        /// Interpreter variant, guessed from the ROM if not given
+        #[clap(long, value_parser = Variant::from_str)]
+        variant: Option<Variant>,
+        /// Interpreter behaviours to copy: cosmac (the original VIP), schip or modern
+        #[clap(long, value_parser = Quirks::preset)]
+        quirks: Option<Quirks>,
+        /// Instructions per second, the timers only match at the speed the reference ran at
+        #[clap(long, value_parser)]
+        speed: Option<u64>,
+        /// How long instructions take: flat or vip
+        #[clap(long, value_parser = Timing::from_str)]
+        timing: Option<Timing>,
+        /// Instructions to show before the one that differs
+        #[clap(long, value_parser, default_value_t = 8)]
+        context: usize,
+    },
+    /// Play a ROM and record the keys pressed to a replay file
+    Record {
+        /// Replay file to write
+        #[clap(
+            value_parser,
+            conflicts_with_all = &["dir", "compare", "host", "connect", "record", "replay", "resume"]
+        )]
+        file: PathBuf,
+        #[clap(flatten)]
+        run: RunArgs,
+    },
+    /// Play a recorded replay back on its ROM, saying where the game goes differently than it did
+    Play {
+        /// Replay file to play back
+        #[clap(
+            value_parser,
+            conflicts_with_all = &["dir", "compare", "host", "connect", "record", "replay", "resume"]
+        )]
+        file: PathBuf,
+        #[clap(flatten)]
+        run: RunArgs,
+    },
+    /// Print a completion script for bash, zsh, fish, elvish or powershell, e.g.
+    /// `chip-8 completions bash > ~/.local/share/bash-completion/completions/chip-8`
+    Completions {
+        /// The shell to complete for
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+    /// Print the man page, e.g. `chip-8 man > ~/.local/share/man/man1/chip-8.1`
+    Man,
+    /// Add the emulator to the desktop's application menu and open .ch8, .c8 and .sc8 files
+    /// with it (Linux)
+    InstallDesktopEntry,
+    /// List the audio output devices --audio-device can pick
+    AudioDevices,
+}
 
-    let cycle_interval = freq_to_period_duration(chip8::chip8::CYCLE_FREQ);
-    let mut sound_playing = false;
-    let mut last_tick = Instant::now();
+impl RunArgs {
+    // the subset of the config that was given on the command line
+    fn config_overrides(&self) -> Config {
+        Config {
+            scale_factor: self.scale_factor,
+            fullscreen: self.fullscreen.then_some(true),
+            vsync: self.vsync.then_some(true),
+            grid: self.grid.then_some(true),
+            scaling: self.scaling,
+            integer_scaling: self.integer_scaling.then_some(true),
+            filter: self.filter,
+            blend: self.blend,
+            blend_mode: self.blend_mode,
+            persistence: self.persistence,
+            keymap_preset: self.keymap_preset,
+            speed: self.speed,
+            auto_speed: self.auto_speed.then_some(true),
+            timing: self.timing.map(|timing| timing.name().to_string()),
+            variant: self.variant.map(|variant| variant.name().to_string()),
+            load_address: self.load_address.or(self.layout.map(|layout| layout.load)),
+            start_address: self
+                .start_address
+                .or(self.layout.map(|layout| layout.start)),
+            // a preset sets every quirk but the memory, machine code and unknown opcode policies
+            quirks: QuirksConfig {
+                clipping: self.quirks.map(|quirks| quirks.clipping),
+                memory: self.memory_policy.map(|policy| policy.name().to_string()),
+                shift_vy: self.quirks.map(|quirks| quirks.shift_vy),
+                increment_i: self.quirks.map(|quirks| quirks.increment_i),
+                jump_vx: self.quirks.map(|quirks| quirks.jump_vx),
+                vf_reset: self.quirks.map(|quirks| quirks.vf_reset),
+                display_wait: self.quirks.map(|quirks| quirks.display_wait),
+                machine_code: self.machine_code.map(|policy| policy.name().to_string()),
+                unknown_opcode: self.unknown_opcode.map(|policy| policy.name().to_string()),
+            },
+            save_dir: self.save_dir.clone(),
+            audio: AudioConfig {
+                beep_freq: self.beep_freq,
+                volume: self.volume,
+                waveform: self.waveform,
+                sample_rate: self.sample_rate,
+                buffer_samples: self.audio_buffer,
+                device: self.audio_device.clone(),
+            },
+            ..Config::default()
+        }
+    }
+}
 
-    'running: loop {
-        let cycle_start = Instant::now();
+// what the completions and man page call the binary
+const BIN_NAME: &str = "chip-8";
+// where `debug` listens without --debug-listen
+const DEBUG_PORT: u16 = 4242;
 
-        if Instant::now() - last_tick >= chip8::chip8::TICK_INTERVAL {
-            chip8.timer_tick();
-            last_tick = Instant::now();
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = logging::init(cli.verbose, cli.log_json.as_deref()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    // debug starts with the machine stopped
+    let mut paused = false;
+    let args = match cli.command {
+        None => cli.run,
+        Some(Command::Run(args)) => args,
+        Some(Command::Debug(mut args)) => {
+            args.debug_listen = args
+                .debug_listen
+                .or_else(|| Some(SocketAddr::from(([127, 0, 0, 1], DEBUG_PORT))));
+            paused = true;
+            args
         }
-
-        chip8.emulate_cycle();
-        if chip8.sound_timer > 0 && !sound_playing {
-            audio_device.resume();
-            sound_playing = true;
-        } else if chip8.sound_timer == 0 && sound_playing {
-            audio_device.pause();
-            sound_playing = false;
+        Some(Command::Record { file, mut run }) => {
+            run.record = Some(file);
+            run
         }
-
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(keycode) = keycode {
-                        chip8.key_down(keycode);
-                    }
-                }
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(keycode) = keycode {
-                        chip8.key_up(keycode);
-                    }
-                }
-                _ => {}
+        Some(Command::Play { file, mut run }) => {
+            run.replay = Some(file);
+            run
+        }
+        Some(Command::Disasm {
+            rom_path,
+            variant,
+            symbols,
+        }) => std::process::exit(disasm(&rom_path, variant, symbols.as_deref())),
+        Some(Command::Asm { source, output }) => {
+            std::process::exit(assemble(&source, output.as_deref()))
+        }
+        Some(Command::Analyze { rom_path, variant }) => {
+            std::process::exit(analyze(&rom_path, variant))
+        }
+        Some(Command::StateInfo { path }) => std::process::exit(state_info(&path)),
+        Some(Command::TraceDiff {
+            rom_path,
+            reference,
+            variant,
+            quirks,
+            speed,
+            timing,
+            context,
+        }) => {
+            let mut builder = Chip8::builder();
+            if let Some(quirks) = quirks {
+                builder = builder.quirks(quirks);
+            }
+            if let Some(speed) = speed {
+                builder = builder.speed(speed);
+            }
+            if let Some(timing) = timing {
+                builder = builder.timing(timing);
             }
+            std::process::exit(trace_diff(&rom_path, &reference, variant, builder, context))
         }
-        if chip8.draw {
-            draw_canvas(&mut canvas, &mut chip8, scale_factor);
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Cli::command(), BIN_NAME, &mut io::stdout());
+            std::process::exit(0)
         }
-
-        std::thread::sleep((cycle_start + cycle_interval) - Instant::now())
-    }
+        Some(Command::Man) => std::process::exit(man_page(&mut io::stdout())),
+        Some(Command::InstallDesktopEntry) => std::process::exit(install_desktop_entry()),
+        Some(Command::AudioDevices) => std::process::exit(audio_devices()),
+    };
+    std::process::exit(launch::launch(args, paused))
 }
 
-fn draw_canvas(canvas: &mut WindowCanvas, chip8: &mut Chip8, scale_factor: u32) {
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
-    for i in 0..(chip8::chip8::DISPLAY_WIDTH * chip8::chip8::DISPLAY_HEIGHT) {
-        if chip8.gfx[i] {
-            let x = i % chip8::chip8::DISPLAY_WIDTH;
-            let y = i / chip8::chip8::DISPLAY_WIDTH;
-            for subpixel_x in 0..scale_factor {
-                for subpixel_y in 0..scale_factor {
-                    canvas
-                        .draw_point(Point::new(
-                            (x as u32 * scale_factor + subpixel_x) as i32,
-                            (y as u32 * scale_factor + subpixel_y) as i32,
-                        ))
-                        .unwrap();
-                }
-            }
+#[test]
+fn test_args() {
+    Cli::command().debug_assert();
+    assert!(
+        Cli::try_parse_from(["chip-8", "rom.ch8", "--url", "http://example.com/rom.ch8"]).is_err()
+    );
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--dir", "roms"]).is_err());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--every", "2"]).is_err());
+    let args = Cli::try_parse_from([
+        "chip-8",
+        "rom.ch8",
+        "--dump-frames",
+        "frames",
+        "--every",
+        "10",
+        "--dump-format",
+        "pbm",
+    ])
+    .unwrap();
+    assert_eq!(args.run.every, Some(10));
+    assert_eq!(args.run.dump_format, Some(DumpFormat::Pbm));
+    assert!(
+        Cli::try_parse_from(["chip-8", "rom.ch8", "--threaded", "--host", "0.0.0.0:4243"]).is_err()
+    );
+    let args = Cli::try_parse_from(["chip-8", "analyze", "rom.ch8"]).unwrap();
+    assert!(matches!(args.command, Some(Command::Analyze { .. })));
+    assert!(Cli::try_parse_from(["chip-8", "--speed", "10", "analyze", "rom.ch8"]).is_err());
+    let args = Cli::try_parse_from(["chip-8", "state-info", "game.sav"]).unwrap();
+    assert!(matches!(args.command, Some(Command::StateInfo { .. })));
+    let args = Cli::try_parse_from(["chip-8", "install-desktop-entry"]).unwrap();
+    assert!(matches!(args.command, Some(Command::InstallDesktopEntry)));
+    let args = Cli::try_parse_from(["chip-8", "audio-devices"]).unwrap();
+    assert!(matches!(args.command, Some(Command::AudioDevices)));
+    let args = Cli::try_parse_from([
+        "chip-8",
+        "trace-diff",
+        "rom.ch8",
+        "ref.jsonl",
+        "--speed",
+        "700",
+    ])
+    .unwrap();
+    assert!(matches!(
+        args.command,
+        Some(Command::TraceDiff {
+            speed: Some(700),
+            context: 8,
+            ..
+        })
+    ));
+    assert!(Cli::try_parse_from(["chip-8", "--watch"]).is_err());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--frames", "10"]).is_err());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--headless", "--frames", "10"]).is_ok());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--dump-state-json", "-"]).is_err());
+    assert!(Cli::try_parse_from([
+        "chip-8",
+        "rom.ch8",
+        "--headless",
+        "--dump-state-json",
+        "-",
+        "--base64-memory"
+    ])
+    .is_ok());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--watch", "--watch-reconfigure"]).is_ok());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--watch-reconfigure"]).is_err());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--autosave", "0"]).is_err());
+    assert!(Cli::try_parse_from(["chip-8", "--scaling", "fit", "--integer-scaling"]).is_err());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--autosave", "30", "--resume"]).is_ok());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--resume", "--replay", "r"]).is_err());
+    assert!(Cli::try_parse_from(["chip-8", "rom.ch8", "--trace-format", "vcd"]).is_err());
+    assert!(
+        Cli::try_parse_from(["chip-8", "rom.ch8", "--trace", "t", "--trace-format", "vcd"]).is_ok()
+    );
+    assert_eq!(
+        Cli::try_parse_from(["chip-8", "game.ch8"])
+            .unwrap()
+            .run
+            .rom_path,
+        Some(PathBuf::from("game.ch8"))
+    );
+    // the subcommands that run a ROM take the same flags
+    let args = Cli::try_parse_from(["chip-8", "run", "game.ch8", "--speed", "900"]).unwrap();
+    assert!(matches!(
+        args.command,
+        Some(Command::Run(RunArgs {
+            speed: Some(900),
+            ..
+        }))
+    ));
+    let args = Cli::try_parse_from(["chip-8", "debug", "game.ch8", "-v"]).unwrap();
+    assert!(matches!(args.command, Some(Command::Debug(_))));
+    assert_eq!(args.verbose, 1);
+    let args =
+        Cli::try_parse_from(["chip-8", "play", "game.replay", "game.ch8", "--headless"]).unwrap();
+    match args.command {
+        Some(Command::Play { file, run }) => {
+            assert_eq!(file, PathBuf::from("game.replay"));
+            assert_eq!(run.rom_path, Some(PathBuf::from("game.ch8")));
+            assert!(run.headless);
         }
+        command => panic!("{:?}", command),
     }
-    canvas.present();
-    chip8.draw = false;
-}
-
-fn freq_to_period_duration(freq_hertz: u64) -> Duration {
-    Duration::from_nanos(1_000_000_000 / freq_hertz)
+    assert!(
+        Cli::try_parse_from(["chip-8", "record", "a.replay", "game.ch8", "--replay", "b"]).is_err()
+    );
+    assert!(Cli::try_parse_from(["chip-8", "record"]).is_err());
+    let args = Cli::try_parse_from(["chip-8", "asm", "game.8o", "-o", "game.ch8"]).unwrap();
+    assert!(matches!(
+        args.command,
+        Some(Command::Asm {
+            output: Some(_),
+            ..
+        })
+    ));
+    let args = Cli::try_parse_from(["chip-8", "disasm", "game.ch8"]).unwrap();
+    assert!(matches!(
+        args.command,
+        Some(Command::Disasm { symbols: None, .. })
+    ));
 }
 
 #[test]
-fn test_freq_to_period_duration() {
-    let freq = 1;
-    // 1 Hz
-    assert_eq!(freq_to_period_duration(freq), Duration::from_secs(1));
-    // 1 MHz
-    assert_eq!(freq_to_period_duration(1_000_000), Duration::from_micros(1));
+fn test_completions() {
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut Cli::command(), BIN_NAME, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        for word in ["auto-speed", "disasm", "trace-diff", "crt"] {
+            assert!(script.contains(word), "{:?} has no {}", shell, word);
+        }
+    }
+    let mut page = Vec::new();
+    assert_eq!(man_page(&mut page), 0);
+    let page = String::from_utf8(page).unwrap();
+    assert!(page.starts_with(".ie"), "{}", &page[..40]);
+    assert!(page.contains(".TH chip-8 1"));
+    assert!(page.contains("completions"));
+    // the arguments' doc comments are their descriptions
+    assert!(page.contains("Pixel scale factor for the initial window size"));
 }
-
@@ -0,0 +1,210 @@
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use chip_8::{FrameBuffer, HostKey};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const TITLE: &str = "SELECT A ROM";
+
+// the game picker shown when started without a ROM: the ROMs in a directory, one per line,
+// with a picture of the selected one once there is one
+pub struct Menu {
+    roms: Vec<PathBuf>,
+    selected: usize,
+    // first line on screen
+    scroll: usize,
+    thumbnails: Vec<Option<FrameBuffer>>,
+}
+
+impl Menu {
+    pub fn new(roms: Vec<PathBuf>) -> Menu {
+        Menu {
+            thumbnails: vec![None; roms.len()],
+            roms,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    pub fn roms(&self) -> &[PathBuf] {
+        &self.roms
+    }
+
+    // returns true when it's the selected ROM's, which has to be redrawn
+    pub fn set_thumbnail(&mut self, index: usize, thumbnail: FrameBuffer) -> bool {
+        if let Some(slot) = self.thumbnails.get_mut(index) {
+            *slot = Some(thumbnail);
+        }
+        index == self.selected
+    }
+
+    pub fn selected(&self) -> Option<&Path> {
+        self.roms.get(self.selected).map(PathBuf::as_path)
+    }
+
+    // up and down wrap around at the ends. returns the ROM to run when one is picked
+    pub fn key_down(&mut self, host_key: HostKey) -> Option<PathBuf> {
+        let last = self.roms.len().checked_sub(1)?;
+        match host_key {
+            HostKey::Up => self.selected = self.selected.checked_sub(1).unwrap_or(last),
+            HostKey::Down => {
+                self.selected = if self.selected == last {
+                    0
+                } else {
+                    self.selected + 1
+                }
+            }
+            HostKey::Home => self.selected = 0,
+            HostKey::End => self.selected = last,
+            HostKey::Return | HostKey::KpEnter => return self.selected().map(Path::to_path_buf),
+            _ => {}
+        }
+        None
+    }
+
+    // the entries that fit in `lines` lines, scrolled so the selected one is among them
+    pub fn visible(&mut self, lines: usize) -> Range<usize> {
+        let lines = lines.max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + lines {
+            self.scroll = self.selected + 1 - lines;
+        }
+        self.scroll..(self.scroll + lines).min(self.roms.len())
+    }
+
+    pub fn draw(
+        &mut self,
+        canvas: &mut WindowCanvas,
+        foreground: Color,
+        background: Color,
+    ) -> Result<(), String> {
+        let (width, height) = canvas.output_size()?;
+        // about 32 characters across
+        let pixel_size = (width / 128).max(1);
+        let line_height = (GLYPH_HEIGHT + 2) * pixel_size;
+        let margin = (2 * pixel_size) as i32;
+        canvas.set_draw_color(background);
+        canvas.clear();
+        font::draw_text(canvas, TITLE, margin, margin, pixel_size, foreground)?;
+        // the thumbnail in the top right corner with pixels half the size of the text's, about
+        // a quarter of the width. names are cut short to leave room for it
+        let thumbnail = self.thumbnails.get(self.selected).and_then(Option::as_ref);
+        let mut text_width = width.saturating_sub(margin as u32 * 2);
+        if let Some(thumbnail) = thumbnail {
+            let thumbnail_pixel_size = (pixel_size / 2).max(1);
+            let thumbnail_width = (thumbnail.width() as u32 + 2) * thumbnail_pixel_size;
+            let x = (width - margin as u32).saturating_sub(thumbnail_width) as i32;
+            draw_thumbnail(
+                canvas,
+                thumbnail,
+                x,
+                margin,
+                thumbnail_pixel_size,
+                foreground,
+            )?;
+            text_width = (x - 2 * margin).max(0) as u32;
+        }
+        let columns = (text_width / ((GLYPH_WIDTH + 1) * pixel_size)) as usize;
+        // a blank line under the title
+        let lines = (height.saturating_sub(margin as u32 * 2) / line_height).saturating_sub(2);
+        for (line, index) in self.visible(lines as usize).enumerate() {
+            let name = self.roms[index]
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            let marker = if index == self.selected { ">" } else { " " };
+            let y = margin + ((line as u32 + 2) * line_height) as i32;
+            let text: String = format!("{} {}", marker, name)
+                .chars()
+                .take(columns)
+                .collect();
+            font::draw_text(canvas, &text, margin, y, pixel_size, foreground)?;
+        }
+        Ok(())
+    }
+}
+
+// framed, one screen pixel per pixel_size
+fn draw_thumbnail(
+    canvas: &mut WindowCanvas,
+    thumbnail: &FrameBuffer,
+    x: i32,
+    y: i32,
+    pixel_size: u32,
+    color: Color,
+) -> Result<(), String> {
+    let (width, height) = thumbnail.size();
+    canvas.set_draw_color(color);
+    canvas.draw_rect(Rect::new(
+        x,
+        y,
+        (width as u32 + 2) * pixel_size,
+        (height as u32 + 2) * pixel_size,
+    ))?;
+    for row in 0..height {
+        for column in (0..width).filter(|column| thumbnail.get(*column, row)) {
+            canvas.fill_rect(Rect::new(
+                x + ((column as u32 + 1) * pixel_size) as i32,
+                y + ((row as u32 + 1) * pixel_size) as i32,
+                pixel_size,
+                pixel_size,
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn menu_of(count: usize) -> Menu {
+        Menu::new(
+            (0..count)
+                .map(|i| PathBuf::from(format!("{}.ch8", i)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut menu = menu_of(3);
+        assert_eq!(menu.selected(), Some(Path::new("0.ch8")));
+        assert_eq!(menu.key_down(HostKey::Up), None);
+        assert_eq!(menu.selected(), Some(Path::new("2.ch8")));
+        menu.key_down(HostKey::Down);
+        assert_eq!(menu.selected(), Some(Path::new("0.ch8")));
+        menu.key_down(HostKey::Down);
+        assert_eq!(menu.key_down(HostKey::Return), Some(PathBuf::from("1.ch8")));
+        menu.key_down(HostKey::End);
+        assert_eq!(menu.selected(), Some(Path::new("2.ch8")));
+
+        let mut empty = menu_of(0);
+        assert_eq!(empty.key_down(HostKey::Down), None);
+        assert_eq!(empty.key_down(HostKey::Return), None);
+        assert_eq!(empty.visible(4), 0..0);
+    }
+
+    #[test]
+    fn test_scrolling() {
+        let mut menu = menu_of(10);
+        assert_eq!(menu.visible(4), 0..4);
+        for _ in 0..5 {
+            menu.key_down(HostKey::Down);
+        }
+        assert_eq!(menu.visible(4), 2..6);
+        // going back up only scrolls once the selection leaves the screen
+        for _ in 0..3 {
+            menu.key_down(HostKey::Up);
+        }
+        assert_eq!(menu.visible(4), 2..6);
+        menu.key_down(HostKey::Up);
+        assert_eq!(menu.visible(4), 1..5);
+        menu.key_down(HostKey::End);
+        assert_eq!(menu.visible(4), 6..10);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::persistence;
+use chip_8::Chip8;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// frames from a key being pressed to it reaching both machines, time for it to cross the network
+const INPUT_DELAY: u64 = 4;
+// frames between state checksums
+const CHECKSUM_INTERVAL: u64 = 60;
+// how long the handshake may take
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// two players on one game, over TCP. both machines run the same ROM from the same RNG seed and
+// see the same key presses at the same frame, so they stay in step without sending the screen.
+// a key pressed on either side is scheduled INPUT_DELAY frames ahead and sent to the other one,
+// and a frame only runs once the other side said it's done with its keys for it. the host is
+// side 0, its keys go first when both press something in the same frame.
+//
+// the protocol is lines of text:
+//   hello SHA1 SEED  the host's ROM and RNG seed, answered with `hello SHA1`
+//   key FRAME K 1|0  keypad key K goes down (1) or up (0) at FRAME
+//   end FRAME        no more keys for FRAME
+//   sum FRAME HASH   Chip8::state_hash after FRAME, to notice when the machines went apart
+pub struct Netplay {
+    stream: TcpStream,
+    // 0 for the host, 1 for the one that connected
+    side: usize,
+    // received bytes that aren't a whole line yet
+    buffer: Vec<u8>,
+    // the next frame to run
+    frame: u64,
+    // frames before this one have all of the other side's keys
+    peer_ready: u64,
+    // key changes by frame, each side's separately
+    scheduled: BTreeMap<u64, [Vec<(u8, bool)>; 2]>,
+    // keys pressed here since the last end_frame
+    pending: Vec<(u8, bool)>,
+    // checksums waiting for the other side's for the same frame
+    checksums: HashMap<u64, u64>,
+    desynced: bool,
+}
+
+impl Netplay {
+    // waits for the other player to connect. returns the RNG seed to use
+    pub fn host(addr: SocketAddr, rom: &[u8]) -> Result<(Netplay, u64), String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("netplay {}: {}", addr, e))?;
+        info!("waiting for the other player on {}", addr);
+        let (stream, peer) = listener.accept().map_err(|e| e.to_string())?;
+        let mut netplay = Netplay::new(stream, 0)?;
+        let seed = rand::random::<u64>();
+        let sha1 = persistence::rom_hash(rom);
+        netplay.send(&format!("hello {} {}", sha1, seed))?;
+        match netplay
+            .handshake_line()?
+            .split_whitespace()
+            .collect::<Vec<_>>()[..]
+        {
+            ["hello", peer_sha1] if peer_sha1 == sha1 => {}
+            ["hello", _] => return Err("the other player has a different ROM".to_string()),
+            _ => return Err("unexpected handshake".to_string()),
+        }
+        info!("{} joined", peer);
+        netplay.start()?;
+        Ok((netplay, seed))
+    }
+
+    // joins a game hosted on `addr`. returns the RNG seed to use
+    pub fn connect(addr: SocketAddr, rom: &[u8]) -> Result<(Netplay, u64), String> {
+        let stream = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT)
+            .map_err(|e| format!("netplay {}: {}", addr, e))?;
+        let mut netplay = Netplay::new(stream, 1)?;
+        let sha1 = persistence::rom_hash(rom);
+        let seed = match netplay
+            .handshake_line()?
+            .split_whitespace()
+            .collect::<Vec<_>>()[..]
+        {
+            ["hello", host_sha1, seed] => {
+                netplay.send(&format!("hello {}", sha1))?;
+                if host_sha1 != sha1 {
+                    return Err("the host has a different ROM".to_string());
+                }
+                seed.parse().map_err(|_| "bad seed in the handshake")?
+            }
+            _ => return Err("unexpected handshake".to_string()),
+        };
+        netplay.start()?;
+        Ok((netplay, seed))
+    }
+
+    fn new(stream: TcpStream, side: usize) -> Result<Netplay, String> {
+        stream
+            .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        // keys are tiny and late ones stall the game
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        Ok(Netplay {
+            stream,
+            side,
+            buffer: Vec::new(),
+            frame: 0,
+            // nobody can press anything in time for the first few frames
+            peer_ready: INPUT_DELAY,
+            scheduled: BTreeMap::new(),
+            pending: Vec::new(),
+            checksums: HashMap::new(),
+            desynced: false,
+        })
+    }
+
+    fn handshake_line(&mut self) -> Result<String, String> {
+        self.read_line()?
+            .ok_or_else(|| "the other player didn't answer".to_string())
+    }
+
+    // the handshake is done, from now on nothing waits for the network
+    fn start(&mut self) -> Result<(), String> {
+        self.stream.set_nonblocking(true).map_err(|e| e.to_string())
+    }
+
+    fn send(&mut self, line: &str) -> Result<(), String> {
+        writeln!(self.stream, "{}", line).map_err(|e| format!("netplay: {}", e))
+    }
+
+    // a whole line, or None when there's none yet
+    fn read_line(&mut self) -> Result<Option<String>, String> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                return Ok(Some(String::from_utf8_lossy(&line).trim().to_string()));
+            }
+            let mut chunk = [0; 512];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err("the other player disconnected".to_string()),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(format!("netplay: {}", e)),
+            }
+        }
+    }
+
+    // takes in everything the other side sent so far
+    pub fn poll(&mut self) -> Result<(), String> {
+        while let Some(line) = self.read_line()? {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |word: &str| {
+                word.parse::<u64>()
+                    .map_err(|_| format!("netplay: bad message {:?}", line))
+            };
+            match words[..] {
+                ["key", frame, key, pressed] => {
+                    let key = number(key)? as u8 & 0xF;
+                    self.scheduled.entry(number(frame)?).or_default()[1 - self.side]
+                        .push((key, pressed == "1"));
+                }
+                ["end", frame] => self.peer_ready = number(frame)?.saturating_add(1),
+                ["sum", frame, hash] => self.check_sum(number(frame)?, number(hash)?),
+                _ => return Err(format!("netplay: bad message {:?}", line)),
+            }
+        }
+        Ok(())
+    }
+
+    fn check_sum(&mut self, frame: u64, hash: u64) {
+        match self.checksums.remove(&frame) {
+            Some(other) if other != hash && !self.desynced => {
+                warn!("netplay: the machines went out of step at frame {}", frame);
+                self.desynced = true;
+            }
+            Some(_) => {}
+            None => {
+                self.checksums.insert(frame, hash);
+            }
+        }
+    }
+
+    // the other side's keys for the next frame are in
+    pub fn ready(&self) -> bool {
+        self.frame < self.peer_ready
+    }
+
+    // a keypad key pressed here, it reaches the machine INPUT_DELAY frames later
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.pending.push((key, pressed));
+    }
+
+    // before running a frame: the key presses scheduled for it, the host's first
+    pub fn start_frame(&mut self, chip8: &mut Chip8) {
+        if let Some(sides) = self.scheduled.remove(&self.frame) {
+            for (key, pressed) in sides.iter().flatten() {
+                chip8.set_key(*key, *pressed);
+            }
+        }
+    }
+
+    // after running a frame: send the keys pressed meanwhile and, now and then, a checksum
+    pub fn end_frame(&mut self, chip8: &Chip8) -> Result<(), String> {
+        let target = self.frame + INPUT_DELAY;
+        for (key, pressed) in std::mem::take(&mut self.pending) {
+            self.send(&format!("key {} {} {}", target, key, pressed as u8))?;
+            self.scheduled.entry(target).or_default()[self.side].push((key, pressed));
+        }
+        self.send(&format!("end {}", target))?;
+        if self.frame % CHECKSUM_INTERVAL == 0 {
+            let hash = chip8.state_hash();
+            self.send(&format!("sum {} {}", self.frame, hash))?;
+            self.check_sum(self.frame, hash);
+        }
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::thread;
+
+    // one frame on both sides, as the main loop does it
+    fn frame(netplay: &mut Netplay, chip8: &mut Chip8) {
+        netplay.poll().unwrap();
+        assert!(netplay.ready(), "stalled at frame {}", netplay.frame);
+        netplay.start_frame(chip8);
+        for _ in 0..14 {
+            chip8.emulate_cycle().unwrap();
+        }
+        chip8.tick_timers();
+        netplay.end_frame(chip8).unwrap();
+    }
+
+    #[test]
+    fn test_netplay() {
+        // a random number in V0 every frame, and the last key pressed in V1
+        let rom = [0xC0, 0xFF, 0xF1, 0x0A, 0x12, 0x00];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let host = thread::spawn(move || Netplay::host(addr, &rom).unwrap());
+        let (mut client, client_seed) = loop {
+            match Netplay::connect(addr, &rom) {
+                Ok(client) => break client,
+                // the host isn't listening yet
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        let (mut host, host_seed) = host.join().unwrap();
+        assert_eq!(host_seed, client_seed);
+
+        let mut machines = [create_chip8(), create_chip8()];
+        for chip8 in machines.iter_mut() {
+            chip8.set_rng(Box::new(StdRng::seed_from_u64(host_seed)));
+            chip8.load_rom_bytes(&rom).unwrap();
+        }
+        let [host_chip8, client_chip8] = &mut machines;
+        // the client presses 7 at the start and lets go a frame later
+        client.set_key(7, true);
+        for number in 0..20 {
+            frame(&mut host, host_chip8);
+            if number == 1 {
+                client.set_key(7, false);
+            }
+            frame(&mut client, client_chip8);
+            // the other side's end for the next frame has to arrive before it runs
+            while !host.ready() || !client.ready() {
+                host.poll().unwrap();
+                client.poll().unwrap();
+            }
+        }
+        assert_eq!(host_chip8.registers()[1], 7);
+        assert_eq!(host_chip8.state_hash(), client_chip8.state_hash());
+        assert!(!host.desynced && !client.desynced);
+
+        // somebody cheated
+        host_chip8.set_register(5, 1);
+        for _ in 20..61 {
+            frame(&mut host, host_chip8);
+            frame(&mut client, client_chip8);
+            while !host.ready() || !client.ready() {
+                host.poll().unwrap();
+                client.poll().unwrap();
+            }
+        }
+        // the client's checksum for frame 60 may still be on its way
+        for _ in 0..100 {
+            host.poll().unwrap();
+            if host.desynced {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(host.desynced);
+
+        // a frame number that can't go any higher
+        client.send("end 18446744073709551615").unwrap();
+        for _ in 0..100 {
+            host.poll().unwrap();
+            if host.peer_ready == u64::MAX {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(host.peer_ready, u64::MAX);
+    }
+}
@@ -0,0 +1,244 @@
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use chip_8::analysis::opcode_name;
+use chip_8::input::TOUCH_LAYOUT;
+use chip_8::{try_decode, Chip8};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, WindowCanvas};
+use std::time::{Duration, Instant};
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// instructions the HUD lists from pc on
+const HUD_INSTRUCTIONS: usize = 3;
+
+// frames and instructions per second, measured over the last full second
+pub struct Stats {
+    frames: u32,
+    instructions: u32,
+    window_start: Instant,
+    pub fps: u32,
+    pub ips: u32,
+}
+
+impl Stats {
+    pub fn new(now: Instant) -> Stats {
+        Stats {
+            frames: 0,
+            instructions: 0,
+            window_start: now,
+            fps: 0,
+            ips: 0,
+        }
+    }
+
+    pub fn count_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn count_instructions(&mut self, count: u32) {
+        self.instructions += count;
+    }
+
+    // returns true when the per second figures changed
+    pub fn update(&mut self, now: Instant) -> bool {
+        let elapsed = now - self.window_start;
+        if elapsed < UPDATE_INTERVAL {
+            return false;
+        }
+        let seconds = elapsed.as_secs_f64();
+        self.fps = (self.frames as f64 / seconds).round() as u32;
+        self.ips = (self.instructions as f64 / seconds).round() as u32;
+        self.frames = 0;
+        self.instructions = 0;
+        self.window_start = now;
+        true
+    }
+}
+
+pub fn draw_stats(
+    canvas: &mut WindowCanvas,
+    stats: &Stats,
+    chip8: &Chip8,
+    pixel_size: u32,
+    foreground: Color,
+    background: Color,
+) -> Result<(), String> {
+    let lines = [
+        format!("FPS {}", stats.fps),
+        format!("IPS {}", stats.ips),
+        format!("DT {} ST {}", chip8.delay_timer(), chip8.sound_timer),
+        format!("SOUND {}", if chip8.sound_timer > 0 { "ON" } else { "OFF" }),
+    ];
+    let line_height = (GLYPH_HEIGHT + 1) * pixel_size;
+    let width = lines
+        .iter()
+        .map(|line| font::text_width(line, pixel_size))
+        .max()
+        .unwrap_or(0);
+    let margin = pixel_size as i32;
+    canvas.set_draw_color(background);
+    canvas.fill_rect(Rect::new(
+        0,
+        0,
+        width + 2 * pixel_size,
+        line_height * lines.len() as u32 + pixel_size,
+    ))?;
+    for (i, line) in lines.iter().enumerate() {
+        let y = margin + (i as u32 * line_height) as i32;
+        font::draw_text(canvas, line, margin, y, pixel_size, foreground)?;
+    }
+    Ok(())
+}
+
+// the registers, timers and the next few instructions, for debugging without a debugger
+pub fn hud_lines(chip8: &Chip8) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "PC {:03X} I {:03X} SP {:X}",
+            chip8.pc(),
+            chip8.index(),
+            chip8.stack().len()
+        ),
+        format!(
+            "DT {:02X} ST {:02X}",
+            chip8.delay_timer(),
+            chip8.sound_timer
+        ),
+    ];
+    for (row, values) in chip8.registers().chunks(4).enumerate() {
+        let line: Vec<String> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+            .collect();
+        lines.push(line.join(" "));
+    }
+    let memory = chip8.memory();
+    for i in 0..HUD_INSTRUCTIONS {
+        let address = (chip8.pc() + 2 * i) % memory.len();
+        let word = u16::from_be_bytes([memory[address], memory[(address + 1) % memory.len()]]);
+        let name = match try_decode(word, chip8.variant()) {
+            Some(opcode) => opcode_name(&opcode),
+            None => "DATA".to_string(),
+        };
+        let marker = if i == 0 { '>' } else { ' ' };
+        lines.push(format!("{}{:03X} {:04X} {}", marker, address, word, name));
+    }
+    lines
+}
+
+// hud_lines in the top right corner
+pub fn draw_hud(
+    canvas: &mut WindowCanvas,
+    chip8: &Chip8,
+    pixel_size: u32,
+    foreground: Color,
+    background: Color,
+) -> Result<(), String> {
+    let lines = hud_lines(chip8);
+    let line_height = (GLYPH_HEIGHT + 1) * pixel_size;
+    let width = lines
+        .iter()
+        .map(|line| font::text_width(line, pixel_size))
+        .max()
+        .unwrap_or(0)
+        + 2 * pixel_size;
+    let (output_width, _) = canvas.output_size()?;
+    let left = output_width.saturating_sub(width) as i32;
+    canvas.set_draw_color(background);
+    canvas.fill_rect(Rect::new(
+        left,
+        0,
+        width,
+        line_height * lines.len() as u32 + pixel_size,
+    ))?;
+    for (i, line) in lines.iter().enumerate() {
+        let y = pixel_size as i32 + (i as u32 * line_height) as i32;
+        font::draw_text(
+            canvas,
+            line,
+            left + pixel_size as i32,
+            y,
+            pixel_size,
+            foreground,
+        )?;
+    }
+    Ok(())
+}
+
+// the touchscreen keypad: the window split into the 4x4 touch_key goes by, faintly outlined
+// with the key in the middle of each
+pub fn draw_touch_keypad(canvas: &mut WindowCanvas, foreground: Color) -> Result<(), String> {
+    let (width, height) = canvas.output_size()?;
+    let (cell_width, cell_height) = (width / 4, height / 4);
+    let pixel_size = (cell_height / (4 * GLYPH_HEIGHT)).max(1);
+    let faint = Color::RGBA(foreground.r, foreground.g, foreground.b, 64);
+    canvas.set_blend_mode(BlendMode::Blend);
+    for (row, keys) in TOUCH_LAYOUT.iter().enumerate() {
+        for (column, key) in keys.iter().enumerate() {
+            let (x, y) = (column as u32 * cell_width, row as u32 * cell_height);
+            canvas.set_draw_color(faint);
+            canvas.draw_rect(Rect::new(x as i32, y as i32, cell_width, cell_height))?;
+            let label = format!("{:X}", key);
+            font::draw_text(
+                canvas,
+                &label,
+                (x + (cell_width - GLYPH_WIDTH * pixel_size) / 2) as i32,
+                (y + (cell_height - GLYPH_HEIGHT * pixel_size) / 2) as i32,
+                pixel_size,
+                faint,
+            )?;
+        }
+    }
+    canvas.set_blend_mode(BlendMode::None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_update() {
+        let start = Instant::now();
+        let mut stats = Stats::new(start);
+        for _ in 0..120 {
+            stats.count_instructions(1);
+        }
+        stats.count_frame();
+        stats.count_frame();
+        assert!(!stats.update(start + Duration::from_millis(500)));
+        assert_eq!(stats.ips, 0);
+        assert!(stats.update(start + Duration::from_secs(2)));
+        assert_eq!(stats.ips, 60);
+        assert_eq!(stats.fps, 1);
+        // counters start over for the next second
+        assert!(stats.update(start + Duration::from_secs(3)));
+        assert_eq!(stats.ips, 0);
+    }
+
+    #[test]
+    fn test_hud_lines() {
+        let mut chip8 = chip_8::create_chip8();
+        // V0 = 0x2A, clear the screen, then a word that isn't an instruction
+        chip8
+            .load_rom_bytes(&[0x60, 0x2A, 0x00, 0xE0, 0xFF, 0xFF])
+            .unwrap();
+        chip8.emulate_cycle().unwrap();
+        assert_eq!(
+            hud_lines(&chip8),
+            [
+                "PC 202 I 000 SP 0",
+                "DT 00 ST 00",
+                "V0 2A V1 00 V2 00 V3 00",
+                "V4 00 V5 00 V6 00 V7 00",
+                "V8 00 V9 00 VA 00 VB 00",
+                "VC 00 VD 00 VE 00 VF 00",
+                ">202 00E0 00E0",
+                " 204 FFFF DATA",
+                " 206 0000 0000",
+            ]
+        );
+    }
+}
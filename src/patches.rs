@@ -0,0 +1,160 @@
+use crate::debug_server::parse_number;
+use chip_8::{Chip8, MEM_SIZE};
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// game genie style cheats. patches are written once after the ROM is loaded, freezes are
+// written again every frame so the game can't change them:
+//
+//   [patch]
+//   0x2A4 = 0x00
+//
+//   [freeze]
+//   0x3F0 = 3
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Patches {
+    patches: BTreeMap<usize, u8>,
+    freezes: BTreeMap<usize, u8>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatchFile {
+    #[serde(default)]
+    patch: BTreeMap<String, u8>,
+    #[serde(default)]
+    freeze: BTreeMap<String, u8>,
+}
+
+fn parse_addresses(table: BTreeMap<String, u8>) -> Result<BTreeMap<usize, u8>, String> {
+    table
+        .into_iter()
+        .map(|(address, value)| {
+            let address = parse_number(&address)?;
+            check_address(address)?;
+            Ok((address, value))
+        })
+        .collect()
+}
+
+fn check_address(address: usize) -> Result<(), String> {
+    if address >= MEM_SIZE {
+        return Err(format!("{:#05X} is out of memory", address));
+    }
+    Ok(())
+}
+
+impl Patches {
+    pub fn load(path: &Path) -> Result<Patches, String> {
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| Patches::parse(&contents))
+            .map_err(|e| format!("couldn't load patches {}: {}", path.display(), e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Patches, String> {
+        let file: PatchFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+        Ok(Patches {
+            patches: parse_addresses(file.patch)?,
+            freezes: parse_addresses(file.freeze)?,
+        })
+    }
+
+    // poke a value now and again whenever the ROM is loaded
+    pub fn add_patch(
+        &mut self,
+        chip8: &mut Chip8,
+        address: usize,
+        value: u8,
+    ) -> Result<(), String> {
+        check_address(address)?;
+        self.patches.insert(address, value);
+        chip8.set_memory(address, value);
+        Ok(())
+    }
+
+    pub fn add_freeze(
+        &mut self,
+        chip8: &mut Chip8,
+        address: usize,
+        value: u8,
+    ) -> Result<(), String> {
+        check_address(address)?;
+        self.freezes.insert(address, value);
+        chip8.set_memory(address, value);
+        Ok(())
+    }
+
+    // false if the address wasn't frozen
+    pub fn remove_freeze(&mut self, address: usize) -> bool {
+        self.freezes.remove(&address).is_some()
+    }
+
+    pub fn patches(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.patches
+            .iter()
+            .map(|(address, value)| (*address, *value))
+    }
+
+    pub fn freezes(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.freezes
+            .iter()
+            .map(|(address, value)| (*address, *value))
+    }
+
+    // after the ROM is loaded
+    pub fn apply(&self, chip8: &mut Chip8) {
+        for (address, value) in self.patches().chain(self.freezes()) {
+            chip8.set_memory(address, value);
+        }
+    }
+
+    // every frame
+    pub fn apply_freezes(&self, chip8: &mut Chip8) {
+        for (address, value) in self.freezes() {
+            chip8.set_memory(address, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+
+    #[test]
+    fn test_parse() {
+        let patches = Patches::parse("[patch]\n0x2A4 = 0x12\n\n[freeze]\n1008 = 3\n").unwrap();
+        assert_eq!(patches.patches().collect::<Vec<_>>(), vec![(0x2A4, 0x12)]);
+        assert_eq!(patches.freezes().collect::<Vec<_>>(), vec![(0x3F0, 3)]);
+        assert_eq!(Patches::parse(""), Ok(Patches::default()));
+        assert!(Patches::parse("[patch]\n0x1000 = 1\n").is_err());
+        assert!(Patches::parse("[patch]\nfoo = 1\n").is_err());
+        assert!(Patches::parse("[patch]\n0x300 = 256\n").is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&[0x12, 0x00]).unwrap();
+        let mut patches = Patches::parse("[patch]\n0x200 = 0x13\n[freeze]\n0x300 = 7\n").unwrap();
+        patches.apply(&mut chip8);
+        assert_eq!(&chip8.memory()[0x200..0x202], &[0x13, 0x00]);
+        assert_eq!(chip8.memory()[0x300], 7);
+
+        chip8.set_memory(0x300, 0);
+        chip8.set_memory(0x200, 0x12);
+        patches.apply_freezes(&mut chip8);
+        assert_eq!(chip8.memory()[0x300], 7);
+        assert_eq!(chip8.memory()[0x200], 0x12);
+
+        assert!(patches.remove_freeze(0x300));
+        assert!(!patches.remove_freeze(0x300));
+        patches.add_freeze(&mut chip8, 0x301, 9).unwrap();
+        assert_eq!(chip8.memory()[0x301], 9);
+        assert!(patches.add_patch(&mut chip8, MEM_SIZE, 1).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use chip_8::RPL_FLAG_COUNT;
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// hex SHA-1 of the ROM, so saves follow the game rather than its file name
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+// $XDG_DATA_HOME/chip8/saves, falling back to ~/.local/share/chip8/saves
+pub fn default_save_dir() -> Option<PathBuf> {
+    Some(data_dir()?.join("chip8").join("saves"))
+}
+
+// $XDG_DATA_HOME, falling back to ~/.local/share
+pub fn data_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => Some(
+            PathBuf::from(env::var_os("HOME")?)
+                .join(".local")
+                .join("share"),
+        ),
+    }
+}
+
+// the PNG pictures kept with the saves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Thumbnail {
+    // of the autosave
+    State,
+    Slot(usize),
+    // the game picker's, of the ROM after running for a bit
+    Preview,
+}
+
+// data that outlives a run, like battery backed saves on a cartridge. one file per ROM and kind
+pub struct Saves {
+    dir: PathBuf,
+    rom_hash: String,
+}
+
+impl Saves {
+    pub fn new(dir: &Path, rom: &[u8]) -> Saves {
+        Saves {
+            dir: dir.to_path_buf(),
+            rom_hash: rom_hash(rom),
+        }
+    }
+
+    fn flags_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.rpl", self.rom_hash))
+    }
+
+    fn memory_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.mem", self.rom_hash))
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.sav", self.rom_hash))
+    }
+
+    fn slot_path(&self, slot: usize) -> PathBuf {
+        self.dir.join(format!("{}.slot{}.sav", self.rom_hash, slot))
+    }
+
+    fn thumbnail_path(&self, thumbnail: Thumbnail) -> PathBuf {
+        match thumbnail {
+            Thumbnail::State => self.state_path().with_extension("png"),
+            Thumbnail::Slot(slot) => self.slot_path(slot).with_extension("png"),
+            Thumbnail::Preview => self.dir.join(format!("{}.preview.png", self.rom_hash)),
+        }
+    }
+
+    pub fn rom_hash(&self) -> &str {
+        &self.rom_hash
+    }
+
+    // None if the ROM never saved any flags
+    pub fn load_flags(&self) -> Result<Option<[u8; RPL_FLAG_COUNT]>, String> {
+        let path = self.flags_path();
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("couldn't read {}: {}", path.display(), e)),
+        };
+        // older or truncated files are padded with zeros
+        let mut flags = [0; RPL_FLAG_COUNT];
+        let length = contents.len().min(RPL_FLAG_COUNT);
+        flags[..length].copy_from_slice(&contents[..length]);
+        Ok(Some(flags))
+    }
+
+    pub fn save_flags(&self, flags: &[u8; RPL_FLAG_COUNT]) -> Result<(), String> {
+        let path = self.flags_path();
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&path, flags))
+            .map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+    }
+
+    // the RAM range from the last run, None if there wasn't one
+    pub fn load_memory(&self) -> Result<Option<Vec<u8>>, String> {
+        let path = self.memory_path();
+        match fs::read(&path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("couldn't read {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save_memory(&self, memory: &[u8]) -> Result<(), String> {
+        let path = self.memory_path();
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&path, memory))
+            .map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+    }
+
+    // the last autosave, None if there isn't one
+    pub fn load_state(&self) -> Result<Option<Vec<u8>>, String> {
+        read_state(&self.state_path())
+    }
+
+    pub fn save_state(&self, state: &[u8]) -> Result<(), String> {
+        self.write_state(&self.state_path(), state)
+    }
+
+    // a save slot (0 to 9) and when it was saved, None if it's empty
+    pub fn load_slot(&self, slot: usize) -> Result<Option<(Vec<u8>, SystemTime)>, String> {
+        let path = self.slot_path(slot);
+        let saved = match fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+            Ok(saved) => saved,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("couldn't read {}: {}", path.display(), e)),
+        };
+        Ok(read_state(&path)?.map(|state| (state, saved)))
+    }
+
+    pub fn save_slot(&self, slot: usize, state: &[u8]) -> Result<(), String> {
+        self.write_state(&self.slot_path(slot), state)
+    }
+
+    pub fn load_thumbnail(&self, thumbnail: Thumbnail) -> Result<Option<Vec<u8>>, String> {
+        read_state(&self.thumbnail_path(thumbnail))
+    }
+
+    pub fn save_thumbnail(&self, thumbnail: Thumbnail, png: &[u8]) -> Result<(), String> {
+        self.write_state(&self.thumbnail_path(thumbnail), png)
+    }
+
+    // written next to the old one and moved over it, so a crash halfway through doesn't lose both
+    fn write_state(&self, path: &Path, state: &[u8]) -> Result<(), String> {
+        let temp_path = path.with_extension("sav.tmp");
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&temp_path, state))
+            .and_then(|_| fs::rename(&temp_path, path))
+            .map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+    }
+}
+
+fn read_state(path: &Path) -> Result<Option<Vec<u8>>, String> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("couldn't read {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rom_hash() {
+        assert_eq!(rom_hash(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    }
+
+    #[test]
+    fn test_flags_round_trip() {
+        let dir = env::temp_dir().join("chip8_saves_test");
+        let _ = fs::remove_dir_all(&dir);
+        let saves = Saves::new(&dir, &[0x12, 0x00]);
+        assert_eq!(saves.load_flags(), Ok(None));
+        let mut flags = [0; RPL_FLAG_COUNT];
+        flags[0] = 42;
+        saves.save_flags(&flags).unwrap();
+        assert_eq!(saves.load_flags(), Ok(Some(flags)));
+        // a different ROM has its own flags
+        assert_eq!(Saves::new(&dir, &[0x13, 0x00]).load_flags(), Ok(None));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_memory_round_trip() {
+        let dir = env::temp_dir().join("chip8_memory_saves_test");
+        let _ = fs::remove_dir_all(&dir);
+        let saves = Saves::new(&dir, &[0x12, 0x00]);
+        assert_eq!(saves.load_memory(), Ok(None));
+        saves.save_memory(&[1, 2, 3]).unwrap();
+        assert_eq!(saves.load_memory(), Ok(Some(vec![1, 2, 3])));
+        // kept apart from the flags
+        assert_eq!(saves.load_flags(), Ok(None));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = env::temp_dir().join("chip8_state_saves_test");
+        let _ = fs::remove_dir_all(&dir);
+        let saves = Saves::new(&dir, &[0x12, 0x00]);
+        assert_eq!(saves.load_state(), Ok(None));
+        saves.save_state(&[1, 2, 3]).unwrap();
+        saves.save_state(&[4, 5]).unwrap();
+        assert_eq!(saves.load_state(), Ok(Some(vec![4, 5])));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_slots() {
+        let dir = env::temp_dir().join("chip8_slot_saves_test");
+        let _ = fs::remove_dir_all(&dir);
+        let saves = Saves::new(&dir, &[0x12, 0x00]);
+        assert_eq!(saves.load_slot(3), Ok(None));
+        saves.save_slot(3, &[1, 2, 3]).unwrap();
+        saves.save_slot(4, &[4]).unwrap();
+        let (state, saved) = saves.load_slot(3).unwrap().unwrap();
+        assert_eq!(state, vec![1, 2, 3]);
+        assert!(saved <= SystemTime::now());
+        assert_eq!(saves.load_slot(4).unwrap().unwrap().0, vec![4]);
+        // apart from the autosave
+        assert_eq!(saves.load_state(), Ok(None));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_thumbnails() {
+        let dir = env::temp_dir().join("chip8_thumbnail_saves_test");
+        let _ = fs::remove_dir_all(&dir);
+        let saves = Saves::new(&dir, &[0x12, 0x00]);
+        assert_eq!(saves.load_thumbnail(Thumbnail::Preview), Ok(None));
+        for (thumbnail, png) in [
+            (Thumbnail::State, [1]),
+            (Thumbnail::Slot(2), [2]),
+            (Thumbnail::Preview, [3]),
+        ] {
+            saves.save_thumbnail(thumbnail, &png).unwrap();
+        }
+        assert_eq!(saves.load_thumbnail(Thumbnail::Slot(2)), Ok(Some(vec![2])));
+        assert_eq!(saves.load_thumbnail(Thumbnail::Slot(3)), Ok(None));
+        let hash = saves.rom_hash();
+        assert!(dir.join(format!("{}.png", hash)).exists());
+        assert!(dir.join(format!("{}.slot2.png", hash)).exists());
+        assert!(dir.join(format!("{}.preview.png", hash)).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
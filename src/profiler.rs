@@ -0,0 +1,292 @@
+use crate::heatmap::Heat;
+use chip_8::analysis::opcode_name;
+use chip_8::{Chip8, DecodeCacheStats, Hooks, Opcode, MEM_SIZE};
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const REPORT_LENGTH: usize = 10;
+
+// counts what a ROM spends its time on. attach with `Chip8::add_hook`
+pub struct Profiler {
+    address_counts: HashMap<usize, u64>,
+    opcode_counts: HashMap<String, u64>,
+    // backward jumps, keyed by (loop start, loop end)
+    loop_counts: HashMap<(usize, usize), u64>,
+    cycles: u64,
+    draw_calls: u64,
+    last_pc: usize,
+    // latest numbers from the interpreter, when it predecodes
+    decode_cache: Option<DecodeCacheStats>,
+    heat: Heat,
+    started: Instant,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Report {
+    pub total_cycles: u64,
+    pub draw_calls: u64,
+    pub elapsed_secs: f64,
+    pub average_ips: f64,
+    pub hot_addresses: Vec<AddressCount>,
+    pub hot_loops: Vec<LoopCount>,
+    pub opcodes: Vec<OpcodeCount>,
+    pub decode_cache: Option<DecodeCacheReport>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DecodeCacheReport {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct AddressCount {
+    pub address: usize,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LoopCount {
+    pub start: usize,
+    pub end: usize,
+    pub iterations: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct OpcodeCount {
+    pub opcode: String,
+    pub count: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            address_counts: HashMap::new(),
+            opcode_counts: HashMap::new(),
+            loop_counts: HashMap::new(),
+            cycles: 0,
+            draw_calls: 0,
+            last_pc: 0,
+            decode_cache: None,
+            heat: Heat::new(),
+            started: Instant::now(),
+        }
+    }
+
+    // reads, writes and runs of every address, for the heatmap
+    pub fn heat(&self) -> &Heat {
+        &self.heat
+    }
+
+    pub fn report(&self) -> Report {
+        self.report_after(self.started.elapsed())
+    }
+
+    fn report_after(&self, elapsed: Duration) -> Report {
+        let mut hot_addresses: Vec<AddressCount> = self
+            .address_counts
+            .iter()
+            .map(|(&address, &count)| AddressCount { address, count })
+            .collect();
+        hot_addresses.sort_by(|a, b| b.count.cmp(&a.count).then(a.address.cmp(&b.address)));
+        hot_addresses.truncate(REPORT_LENGTH);
+
+        let mut hot_loops: Vec<LoopCount> = self
+            .loop_counts
+            .iter()
+            .map(|(&(start, end), &iterations)| LoopCount {
+                start,
+                end,
+                iterations,
+            })
+            .collect();
+        hot_loops.sort_by(|a, b| b.iterations.cmp(&a.iterations).then(a.start.cmp(&b.start)));
+        hot_loops.truncate(REPORT_LENGTH);
+
+        let mut opcodes: Vec<OpcodeCount> = self
+            .opcode_counts
+            .iter()
+            .map(|(opcode, &count)| OpcodeCount {
+                opcode: opcode.clone(),
+                count,
+            })
+            .collect();
+        opcodes.sort_by(|a, b| b.count.cmp(&a.count).then(a.opcode.cmp(&b.opcode)));
+
+        let elapsed_secs = elapsed.as_secs_f64();
+        Report {
+            total_cycles: self.cycles,
+            draw_calls: self.draw_calls,
+            elapsed_secs,
+            average_ips: if elapsed_secs > 0.0 {
+                self.cycles as f64 / elapsed_secs
+            } else {
+                0.0
+            },
+            hot_addresses,
+            hot_loops,
+            opcodes,
+            decode_cache: self.decode_cache.map(|stats| DecodeCacheReport {
+                hits: stats.hits,
+                misses: stats.misses,
+                hit_rate: stats.hit_rate(),
+            }),
+        }
+    }
+}
+
+impl Hooks for Profiler {
+    fn before_instruction(&mut self, chip8: &Chip8, opcode: &Opcode) {
+        self.cycles += 1;
+        self.last_pc = chip8.pc();
+        *self.address_counts.entry(chip8.pc()).or_insert(0) += 1;
+        *self.opcode_counts.entry(opcode_name(opcode)).or_insert(0) += 1;
+        for byte in 0..2 {
+            self.heat.executes[(chip8.pc() + byte) % MEM_SIZE] += 1;
+        }
+        // the instructions that read memory at I, writes come in through on_memory_write
+        let reads = match *opcode {
+            Opcode::OP_DXYN(_, _, n) => n as usize,
+            Opcode::OP_FX65(x) => x + 1,
+            _ => 0,
+        };
+        for byte in 0..reads {
+            self.heat.reads[(chip8.index() + byte) % MEM_SIZE] += 1;
+        }
+    }
+
+    fn after_instruction(&mut self, chip8: &Chip8, _opcode: &Opcode) {
+        self.decode_cache = chip8.decode_cache_stats();
+        // anything that moves pc backwards closes a loop
+        if chip8.pc() <= self.last_pc {
+            *self
+                .loop_counts
+                .entry((chip8.pc(), self.last_pc))
+                .or_insert(0) += 1;
+        }
+    }
+
+    fn on_display(&mut self, _chip8: &Chip8) {
+        self.draw_calls += 1;
+    }
+
+    fn on_memory_write(&mut self, _chip8: &Chip8, address: usize, _value: u8) {
+        self.heat.writes[address % MEM_SIZE] += 1;
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "total cycles: {}", self.total_cycles)?;
+        writeln!(f, "draw calls:   {}", self.draw_calls)?;
+        writeln!(f, "average IPS:  {:.0}", self.average_ips)?;
+        writeln!(f, "hot addresses:")?;
+        for entry in &self.hot_addresses {
+            writeln!(f, "  {:#05X}  {}", entry.address, entry.count)?;
+        }
+        writeln!(f, "hot loops:")?;
+        for entry in &self.hot_loops {
+            writeln!(
+                f,
+                "  {:#05X}..={:#05X}  {}",
+                entry.start, entry.end, entry.iterations
+            )?;
+        }
+        writeln!(f, "opcodes:")?;
+        for entry in &self.opcodes {
+            writeln!(f, "  {:<5} {}", entry.opcode, entry.count)?;
+        }
+        if let Some(cache) = &self.decode_cache {
+            writeln!(
+                f,
+                "decode cache: {:.1}% hits ({} hits, {} misses)",
+                cache.hit_rate * 100.0,
+                cache.hits,
+                cache.misses
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_profile_loop() {
+        let mut chip8 = create_chip8();
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        chip8.add_hook(Box::new(profiler.clone()));
+        // 0x200: V0 += 1
+        // 0x202: draw
+        // 0x204: jump 0x200
+        chip8
+            .load_rom_bytes(&[0x70, 0x01, 0xD0, 0x01, 0x12, 0x00])
+            .unwrap();
+        for _ in 0..9 {
+            chip8.emulate_cycle().unwrap();
+        }
+
+        let report = profiler.borrow().report_after(Duration::from_secs(3));
+        assert_eq!(report.total_cycles, 9);
+        assert_eq!(report.draw_calls, 3);
+        assert_eq!(report.average_ips, 3.0);
+        assert_eq!(
+            report.hot_addresses[0],
+            AddressCount {
+                address: 0x200,
+                count: 3
+            }
+        );
+        assert_eq!(
+            report.hot_loops,
+            vec![LoopCount {
+                start: 0x200,
+                end: 0x204,
+                iterations: 3
+            }]
+        );
+        assert_eq!(report.opcodes.len(), 3);
+        assert_eq!(report.opcodes[0].opcode, "1MMM");
+        assert_eq!(report.decode_cache, None);
+        let profiler = profiler.borrow();
+        let heat = profiler.heat();
+        assert_eq!(heat.executes[0x200..0x206], [3; 6]);
+        // the draw read one row of the font at I = 0
+        assert_eq!(heat.reads[0], 3);
+        assert_eq!(heat.reads[1], 0);
+    }
+
+    #[test]
+    fn test_profile_decode_cache() {
+        let mut chip8 = Chip8::builder().predecode(true).build();
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        chip8.add_hook(Box::new(profiler.clone()));
+        // 0x200: V0 = 0xF0, 0x202: I = 0x204, 0x204: store V0 at 0x204, 0x206: jump 0x204.
+        // the store writes the byte that's already there but still invalidates 0x204
+        chip8
+            .load_rom_bytes(&[0x60, 0xF0, 0xA2, 0x04, 0xF0, 0x55, 0x12, 0x04])
+            .unwrap();
+        for _ in 0..5 {
+            chip8.emulate_cycle().unwrap();
+        }
+        assert_eq!(profiler.borrow().heat().writes[0x204], 2);
+        let report = profiler.borrow().report();
+        assert_eq!(
+            report.decode_cache,
+            Some(DecodeCacheReport {
+                hits: 4,
+                misses: 1,
+                hit_rate: 0.8
+            })
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use crate::persistence;
+use chip_8::Chip8;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::{error, warn};
+
+const HEADER: &str = "chip8-replay 1";
+// frames between state checksums
+const CHECKSUM_INTERVAL: u64 = 60;
+
+// a recorded game: the ROM, the RNG seed and the keypad keys by frame, so that playing it back
+// gives the same game again. every CHECKSUM_INTERVAL frames Chip8::state_hash is written down
+// too, playback compares them and says at which frame the game went differently (a changed
+// ROM, config or emulator version). the file is lines of text:
+//
+//   chip8-replay 1
+//   rom SHA1
+//   seed SEED
+//   key FRAME K 1|0   keypad key K goes down (1) or up (0) before FRAME runs
+//   sum FRAME HASH    the state hash after FRAME
+pub struct Recorder {
+    file: BufWriter<File>,
+    // the next frame to run
+    frame: u64,
+    // a write failed, it was said once and the rest isn't written
+    failed: bool,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, rom: &[u8], seed: u64) -> Result<Recorder, String> {
+        let file =
+            File::create(path).map_err(|e| format!("couldn't create {}: {}", path.display(), e))?;
+        let mut file = BufWriter::new(file);
+        writeln!(
+            file,
+            "{}\nrom {}\nseed {}",
+            HEADER,
+            persistence::rom_hash(rom),
+            seed
+        )
+        .map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+        Ok(Recorder {
+            file,
+            frame: 0,
+            failed: false,
+        })
+    }
+
+    fn write(&mut self, line: &str) {
+        if let (false, Err(e)) = (self.failed, writeln!(self.file, "{}", line)) {
+            error!("couldn't write the replay: {}", e);
+            self.failed = true;
+        }
+    }
+
+    // a keypad key pressed here, it takes effect in the next frame
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.write(&format!("key {} {} {}", self.frame, key, pressed as u8));
+    }
+
+    pub fn end_frame(&mut self, chip8: &Chip8) {
+        if self.frame % CHECKSUM_INTERVAL == 0 {
+            self.write(&format!("sum {} {}", self.frame, chip8.state_hash()));
+        }
+        self.frame += 1;
+    }
+
+    // the process exits without dropping anything
+    pub fn finish(&mut self) {
+        if let (false, Err(e)) = (self.failed, self.file.flush()) {
+            error!("couldn't write the replay: {}", e);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Player {
+    keys: BTreeMap<u64, Vec<(u8, bool)>>,
+    sums: BTreeMap<u64, u64>,
+    frame: u64,
+    // the first frame whose checksum didn't match
+    diverged: Option<u64>,
+}
+
+impl Player {
+    // returns the RNG seed to use
+    pub fn load(path: &Path, rom: &[u8]) -> Result<(Player, u64), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        Player::parse(&contents, rom).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(contents: &str, rom: &[u8]) -> Result<(Player, u64), String> {
+        let mut lines = contents.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim()) != Some(HEADER) {
+            return Err("not a replay".to_string());
+        }
+        let mut player = Player {
+            keys: BTreeMap::new(),
+            sums: BTreeMap::new(),
+            frame: 0,
+            diverged: None,
+        };
+        let mut seed = None;
+        for (number, line) in lines {
+            let bad_line = || format!("line {}: {:?}", number + 1, line);
+            let number = |word: &str| word.parse::<u64>().map_err(|_| bad_line());
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["rom", sha1] if sha1 != persistence::rom_hash(rom) => {
+                    return Err("it was recorded with a different ROM".to_string())
+                }
+                ["rom", _] => {}
+                ["seed", value] => seed = Some(number(value)?),
+                ["key", frame, key, pressed] => player
+                    .keys
+                    .entry(number(frame)?)
+                    .or_default()
+                    .push((number(key)? as u8 & 0xF, pressed == "1")),
+                ["sum", frame, hash] => {
+                    player.sums.insert(number(frame)?, number(hash)?);
+                }
+                [] => {}
+                _ => return Err(bad_line()),
+            }
+        }
+        let seed = seed.ok_or("there's no seed")?;
+        Ok((player, seed))
+    }
+
+    // before running a frame: the keys recorded for it
+    pub fn start_frame(&mut self, chip8: &mut Chip8) {
+        for (key, pressed) in self.keys.remove(&self.frame).unwrap_or_default() {
+            chip8.set_key(key, pressed);
+        }
+    }
+
+    pub fn end_frame(&mut self, chip8: &Chip8) {
+        if let Some(expected) = self.sums.remove(&self.frame) {
+            if expected != chip8.state_hash() && self.diverged.is_none() {
+                warn!(
+                    "replay: the game went differently than recorded, first noticed at frame {}",
+                    self.frame
+                );
+                self.diverged = Some(self.frame);
+            }
+        }
+        self.frame += 1;
+    }
+
+    // nothing left to play back
+    pub fn finished(&self) -> bool {
+        self.keys.is_empty() && self.sums.is_empty()
+    }
+
+    pub fn diverged(&self) -> Option<u64> {
+        self.diverged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // a random number in V0 every frame, and the last key pressed in V1
+    const ROM: [u8; 6] = [0xC0, 0xFF, 0xF1, 0x0A, 0x12, 0x00];
+
+    fn machine(seed: u64) -> Chip8 {
+        let mut chip8 = create_chip8();
+        chip8.set_rng(Box::new(StdRng::seed_from_u64(seed)));
+        chip8.load_rom_bytes(&ROM).unwrap();
+        chip8
+    }
+
+    fn frame(chip8: &mut Chip8) {
+        for _ in 0..14 {
+            chip8.emulate_cycle().unwrap();
+        }
+        chip8.tick_timers();
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = std::env::temp_dir().join("chip8_replay_test.txt");
+        let mut recorder = Recorder::create(&path, &ROM, 42).unwrap();
+        let mut chip8 = machine(42);
+        for number in 0..130 {
+            match number {
+                10 => chip8.set_key(7, true),
+                11 => chip8.set_key(7, false),
+                _ => {}
+            }
+            if number == 10 || number == 11 {
+                recorder.set_key(7, number == 10);
+            }
+            frame(&mut chip8);
+            recorder.end_frame(&chip8);
+        }
+        recorder.finish();
+        let recorded = chip8.state_hash();
+
+        let (mut player, seed) = Player::load(&path, &ROM).unwrap();
+        assert_eq!(seed, 42);
+        let mut chip8 = machine(seed);
+        for _ in 0..130 {
+            player.start_frame(&mut chip8);
+            frame(&mut chip8);
+            player.end_frame(&chip8);
+        }
+        assert!(player.finished());
+        assert_eq!(player.diverged(), None);
+        assert_eq!(chip8.registers()[1], 7);
+        assert_eq!(chip8.state_hash(), recorded);
+
+        // the same game with a key pressed that wasn't
+        let (mut player, seed) = Player::load(&path, &ROM).unwrap();
+        let mut chip8 = machine(seed);
+        for number in 0..130 {
+            if number == 70 || number == 71 {
+                chip8.set_key(3, number == 70);
+            }
+            player.start_frame(&mut chip8);
+            frame(&mut chip8);
+            player.end_frame(&chip8);
+        }
+        assert_eq!(player.diverged(), Some(120));
+
+        assert!(Player::load(&path, &[0x12, 0x00])
+            .unwrap_err()
+            .contains("different ROM"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Player::parse("hello", &ROM).is_err());
+        assert!(Player::parse(HEADER, &ROM).unwrap_err().contains("no seed"));
+        assert_eq!(
+            Player::parse(&format!("{}\nseed 1\nkey x", HEADER), &ROM).err(),
+            Some("line 3: \"key x\"".to_string())
+        );
+    }
+}
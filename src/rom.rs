@@ -0,0 +1,348 @@
+use crate::persistence;
+use chip_8::{Layout, Variant, MAX_ROM_SIZE};
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+pub const ROM_EXTENSIONS: [&str; 3] = ["ch8", "c8", "sc8"];
+// signatures of files that are definitely not ROMs. only ones of 4 bytes or more, shorter ones
+// are too likely to be real instructions
+const FOREIGN_MAGIC: [(&[u8], &str); 6] = [
+    (
+        b"PK\x03\x04",
+        "a zip archive (name it .zip to load the ROM inside)",
+    ),
+    (b"\x7FELF", "an ELF executable"),
+    (b"\x89PNG", "a PNG image"),
+    (b"GIF8", "a GIF image"),
+    (b"%PDF", "a PDF document"),
+    (b"\xFF\xD8\xFF", "a JPEG image"),
+];
+
+// what we could tell about a ROM before running it
+#[derive(Debug, PartialEq)]
+pub struct RomInfo {
+    pub size: usize,
+    pub sha1: String,
+    // the interpreter the ROM seems to be written for, going by the opcodes in it
+    pub guessed_variant: &'static str,
+    pub warnings: Vec<String>,
+}
+
+// "-" reads the ROM from stdin. zip archives are searched for the first ROM, or
+// "archive.zip#inner.ch8" picks a specific entry
+pub fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    if path == Path::new("-") {
+        let mut rom = Vec::new();
+        io::stdin()
+            .read_to_end(&mut rom)
+            .map_err(|e| format!("couldn't read ROM from stdin: {}", e))?;
+        return Ok(rom);
+    }
+    if let Some((archive_path, entry)) = split_archive_path(path) {
+        let file = File::open(&archive_path)
+            .map_err(|e| format!("couldn't read {}: {}", archive_path.display(), e))?;
+        return read_from_archive(file, entry.as_deref())
+            .map_err(|e| format!("{}: {}", archive_path.display(), e));
+    }
+    if path.extension().is_some_and(|extension| extension == "8o") {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        return chip_8::asm::compile(&source).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    fs::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))
+}
+
+// refuse files that can't be a ROM for `variant` loaded by `layout`, and collect what's
+// suspicious about the rest
+pub fn validate_rom(rom: &[u8], variant: Variant, layout: Layout) -> Result<RomInfo, String> {
+    if rom.is_empty() {
+        return Err("ROM is empty".to_string());
+    }
+    if let Some((_, kind)) = FOREIGN_MAGIC
+        .iter()
+        .find(|(magic, _)| rom.starts_with(magic))
+    {
+        return Err(format!("this is {}, not a chip-8 ROM", kind));
+    }
+    if rom.len() > layout.max_rom_size() {
+        return Err(format!(
+            "ROM is {} bytes, the most that fits from {:#05X} is {}",
+            rom.len(),
+            layout.load,
+            layout.max_rom_size()
+        ));
+    }
+    let mut warnings = Vec::new();
+    if rom.len() % 2 == 1 {
+        warnings.push(format!(
+            "ROM is an odd number of bytes ({}), it may be truncated",
+            rom.len()
+        ));
+    }
+    let guessed_variant = guess_variant(rom);
+    if let Ok(guessed) = guessed_variant.parse::<Variant>() {
+        if guessed != variant {
+            warnings.push(format!(
+                "this looks like a {0} ROM, try --variant {0}",
+                guessed_variant
+            ));
+        }
+    }
+    Ok(RomInfo {
+        size: rom.len(),
+        sha1: persistence::rom_hash(rom),
+        guessed_variant,
+        warnings,
+    })
+}
+
+// a rough guess from the opcodes in the ROM. data mixed in with the code can throw it off, so
+// it's only used for hints
+pub fn guess_variant(rom: &[u8]) -> &'static str {
+    if rom.starts_with(&[0x12, 0x60]) {
+        return "hires";
+    }
+    let opcodes: Vec<u16> = rom
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    let has = |matches: fn(u16) -> bool| opcodes.iter().any(|opcode| matches(*opcode));
+    // F000 NNNN, the audio opcodes and the register range saves are XO-CHIP only
+    if has(|opcode| opcode == 0xF000 || opcode == 0xF002 || opcode & 0xF00E == 0x5002) {
+        "xo-chip"
+    } else if has(|opcode| {
+        // scrolling, exit, lores/hires and the big font
+        (0x00FB..=0x00FF).contains(&opcode)
+            || opcode & 0xFFF0 == 0x00C0
+            || opcode & 0xF0FF == 0xF030
+    }) {
+        "schip"
+    } else if has(|opcode| opcode == 0x02A0) {
+        "chip8x"
+    } else {
+        "chip8"
+    }
+}
+
+// "roms.zip" -> ("roms.zip", None), "roms.zip#pong.ch8" -> ("roms.zip", Some("pong.ch8"))
+pub fn split_archive_path(path: &Path) -> Option<(PathBuf, Option<String>)> {
+    let path = path.to_str()?;
+    let lowercase = path.to_ascii_lowercase();
+    if let Some(index) = lowercase.rfind(".zip#") {
+        let (archive, entry) = path.split_at(index + ".zip".len());
+        return Some((PathBuf::from(archive), Some(entry[1..].to_string())));
+    }
+    if lowercase.ends_with(".zip") {
+        return Some((PathBuf::from(path), None));
+    }
+    None
+}
+
+// the ROMs directly in `dir`, sorted by name
+pub fn scan_roms(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("couldn't read {}: {}", dir.display(), e))?;
+    let mut roms: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_type()
+                .map(|kind| kind.is_file())
+                .unwrap_or(false)
+        })
+        .map(|entry| entry.path())
+        .filter(|path| path.to_str().map(is_rom_name).unwrap_or(false))
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| ROM_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn read_from_archive<R: Read + Seek>(reader: R, entry: Option<&str>) -> Result<Vec<u8>, String> {
+    let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
+    let name = match entry {
+        Some(name) => name.to_string(),
+        None => {
+            let mut first_rom = None;
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i).map_err(|e| e.to_string())?;
+                if is_rom_name(file.name()) {
+                    first_rom = Some(file.name().to_string());
+                    break;
+                }
+            }
+            first_rom.ok_or(format!("no {} file in archive", ROM_EXTENSIONS.join("/")))?
+        }
+    };
+    let entry = archive
+        .by_name(&name)
+        .map_err(|e| format!("{}: {}", name, e))?;
+    // one byte past the limit so oversized ROMs are still reported as such
+    let mut rom = Vec::new();
+    entry
+        .take(MAX_ROM_SIZE as u64 + 1)
+        .read_to_end(&mut rom)
+        .map_err(|e| format!("{}: {}", name, e))?;
+    Ok(rom)
+}
+
+#[cfg(feature = "http")]
+pub fn download_rom(url: &str) -> Result<Vec<u8>, String> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| format!("couldn't download {}: {}", url, e))?;
+    // read one byte past the limit so oversized ROMs are still reported as such
+    let mut rom = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_ROM_SIZE as u64 + 1)
+        .read_to_end(&mut rom)
+        .map_err(|e| format!("couldn't download {}: {}", url, e))?;
+    Ok(rom)
+}
+
+#[cfg(not(feature = "http"))]
+pub fn download_rom(url: &str) -> Result<Vec<u8>, String> {
+    Err(format!(
+        "can't download {}: built without the \"http\" feature",
+        url
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_rom() {
+        let path = std::env::temp_dir().join("chip8_read_rom_test.ch8");
+        fs::write(&path, [0x12, 0x00]).unwrap();
+        assert_eq!(read_rom(&path).unwrap(), vec![0x12, 0x00]);
+        fs::remove_file(&path).unwrap();
+        assert!(read_rom(&path).is_err());
+
+        let path = std::env::temp_dir().join("chip8_read_rom_test.8o");
+        fs::write(&path, ": main\n  v0 := 1\n  jump main\n").unwrap();
+        assert_eq!(read_rom(&path).unwrap(), vec![0x60, 0x01, 0x12, 0x00]);
+        fs::write(&path, ": main\n  v0 := 1000\n").unwrap();
+        assert_eq!(
+            read_rom(&path).unwrap_err(),
+            format!("{}: line 2: 1000 doesn't fit in 8 bits", path.display())
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_scan_roms() {
+        let dir = std::env::temp_dir().join("chip8_scan_roms_test");
+        fs::create_dir_all(dir.join("nested.ch8")).unwrap();
+        for name in ["tetris.ch8", "Blitz.C8", "notes.txt", "ant.sc8"].iter() {
+            fs::write(dir.join(name), [0x12, 0x00]).unwrap();
+        }
+        let names: Vec<_> = scan_roms(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["Blitz.C8", "ant.sc8", "tetris.ch8"]);
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(scan_roms(&dir).is_err());
+    }
+
+    fn test_archive() -> Vec<u8> {
+        use std::io::Write;
+        use zip::write::FileOptions;
+
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = FileOptions::default();
+        writer.start_file("readme.txt", options).unwrap();
+        writer.write_all(b"not a rom").unwrap();
+        writer.start_file("games/pong.ch8", options).unwrap();
+        writer.write_all(&[0x12, 0x00]).unwrap();
+        writer.start_file("TETRIS.C8", options).unwrap();
+        writer.write_all(&[0x00, 0xE0]).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_read_from_archive() {
+        let archive = test_archive();
+        let first = read_from_archive(io::Cursor::new(&archive), None).unwrap();
+        assert_eq!(first, vec![0x12, 0x00]);
+        let named = read_from_archive(io::Cursor::new(&archive), Some("TETRIS.C8")).unwrap();
+        assert_eq!(named, vec![0x00, 0xE0]);
+        assert!(read_from_archive(io::Cursor::new(&archive), Some("missing.ch8")).is_err());
+        assert!(read_from_archive(io::Cursor::new(b"not a zip"), None).is_err());
+    }
+
+    #[test]
+    fn test_validate_rom() {
+        let info = validate_rom(
+            &[0x00, 0xE0, 0x12, 0x02],
+            Variant::Chip8,
+            Variant::Chip8.layout(),
+        )
+        .unwrap();
+        assert_eq!(info.size, 4);
+        assert_eq!(info.sha1.len(), 40);
+        assert_eq!(info.guessed_variant, "chip8");
+        assert!(info.warnings.is_empty());
+
+        assert!(validate_rom(&[], Variant::Chip8, Variant::Chip8.layout()).is_err());
+        assert!(validate_rom(b"\x7FELF\x02\x01", Variant::Chip8, Variant::Chip8.layout()).is_err());
+        assert!(validate_rom(b"PK\x03\x04rest", Variant::Chip8, Variant::Chip8.layout()).is_err());
+        // fits at 0x200 but not at 0x300 or 0x600
+        let big = vec![0; 0xD02];
+        assert!(validate_rom(&big, Variant::Chip8, Variant::Chip8.layout()).is_ok());
+        assert!(validate_rom(&big, Variant::Chip8x, Variant::Chip8x.layout()).is_err());
+        assert_eq!(
+            validate_rom(&big, Variant::Chip8, Layout::ETI660),
+            Err("ROM is 3330 bytes, the most that fits from 0x600 is 2560".to_string())
+        );
+
+        let odd =
+            validate_rom(&[0x12, 0x00, 0x00], Variant::Chip8, Variant::Chip8.layout()).unwrap();
+        assert_eq!(odd.warnings.len(), 1);
+        // a hires ROM run as plain chip-8 gets a hint
+        let hires = validate_rom(&[0x12, 0x60], Variant::Chip8, Variant::Chip8.layout()).unwrap();
+        assert_eq!(hires.guessed_variant, "hires");
+        assert_eq!(
+            hires.warnings,
+            vec!["this looks like a hires ROM, try --variant hires".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_guess_variant() {
+        assert_eq!(guess_variant(&[0x00, 0xFF, 0x12, 0x00]), "schip");
+        assert_eq!(guess_variant(&[0xF0, 0x00, 0x12, 0x34]), "xo-chip");
+        assert_eq!(guess_variant(&[0x02, 0xA0]), "chip8x");
+        assert_eq!(guess_variant(&[0x60, 0x0F]), "chip8");
+    }
+
+    #[test]
+    fn test_split_archive_path() {
+        assert_eq!(split_archive_path(Path::new("pong.ch8")), None);
+        assert_eq!(
+            split_archive_path(Path::new("roms/ALL.ZIP")),
+            Some((PathBuf::from("roms/ALL.ZIP"), None))
+        );
+        assert_eq!(
+            split_archive_path(Path::new("roms.zip#games/pong.ch8")),
+            Some((
+                PathBuf::from("roms.zip"),
+                Some("games/pong.ch8".to_string())
+            ))
+        );
+    }
+}
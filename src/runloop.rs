@@ -0,0 +1,1169 @@
+use crate::control::Control;
+use crate::keymaps::KeymapPicker;
+use crate::menu::Menu;
+use crate::netplay::Netplay;
+use crate::replay::Recorder;
+use crate::scripting::Script;
+use crate::sdl::SdlDisplay;
+use crate::session::{persist_flags, save_state, use_slot, Session};
+use crate::slots::{SlotAction, SlotPicker};
+use crate::sprites::SpriteViewer;
+use crate::thumbnail::Previews;
+use crate::title::FrameRate;
+use crate::{debug_server, keymaps, title};
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info, trace_span, warn};
+
+use chip_8::frontend::{AudioSink, Display, InputEvent, InputSource};
+use chip_8::{Chip8, FrameBudget, HostKey, KeyEdge, Status};
+
+// how late the main loop may get before it gives up on catching up
+const MAX_FRAMES_BEHIND: u32 = 5;
+
+// keypad keys reach the machine through netplay, a few frames later. a replay presses its own
+fn press_key(
+    edge: KeyEdge,
+    chip8: &mut Chip8,
+    netplay: &mut Option<Netplay>,
+    replaying: bool,
+    recorder: &mut Option<Recorder>,
+) {
+    match netplay {
+        Some(netplay) => netplay.set_key(edge.key(), edge.pressed()),
+        None if replaying => {}
+        None => {
+            chip8.key_edge(edge);
+            if let Some(recorder) = recorder {
+                recorder.set_key(edge.key(), edge.pressed());
+            }
+        }
+    }
+}
+
+pub enum Exit {
+    Quit,
+    // the machine stopped with an error
+    Error,
+    // the user wants to run another ROM
+    Open(PathBuf),
+    Reset,
+    // the ROM ended itself with 00FD
+    Exited,
+    // the watched ROM file changed
+    Reload(PathBuf),
+}
+
+// the game picker, until a ROM is picked from it or dropped on the window. None when the user
+// quits instead
+pub fn pick_rom<I: InputSource>(
+    menu: &mut Menu,
+    previews: &Previews,
+    display: &mut SdlDisplay,
+    input: &mut I,
+) -> Option<PathBuf> {
+    let frame_interval = freq_to_period_duration(chip_8::TIMER_FREQ);
+    let mut redraw = true;
+    loop {
+        for event in input.poll() {
+            match event {
+                InputEvent::Quit => return None,
+                InputEvent::Open(path) => return Some(path),
+                InputEvent::KeyDown(host_key) | InputEvent::KeyRepeat(host_key) => {
+                    if let Some(path) = menu.key_down(host_key) {
+                        return Some(path);
+                    }
+                    redraw = true;
+                }
+                InputEvent::ToggleFullscreen => {
+                    display.toggle_fullscreen();
+                    redraw = true;
+                }
+                InputEvent::Redraw => redraw = true,
+                _ => {}
+            }
+        }
+        for (index, thumbnail) in previews.ready() {
+            redraw |= menu.set_thumbnail(index, thumbnail);
+        }
+        if redraw {
+            if let Err(e) = display.draw_menu(menu) {
+                warn!("couldn't draw: {}", e);
+            }
+            redraw = false;
+        }
+        std::thread::sleep(frame_interval);
+    }
+}
+
+// the main loop, one iteration per chip-8 frame: a batch of instructions, a timer tick, input,
+// drawing and one sleep
+pub fn run<D: Display, A: AudioSink, I: InputSource>(
+    session: &mut Session,
+    display: &mut D,
+    audio: &mut A,
+    input: &mut I,
+) -> Exit {
+    let chip8 = &mut session.chip8;
+    let mut sound_playing = false;
+    let mut audio_pattern = None;
+    let mut pitch = chip8.pitch;
+    let frame_interval = freq_to_period_duration(chip_8::TIMER_FREQ);
+    let mut pacer = Pacer::new(session.settings.vsync);
+    let mut budget = FrameBudget::default();
+    let mut frame_rate = FrameRate::new(Instant::now());
+    chip8.draw = true;
+
+    loop {
+        if let Some(server) = &mut session.debug_server {
+            server.poll(chip8, &mut session.debugger, &mut session.patches);
+        }
+        let controls = match &mut session.control {
+            Some(control) => control.poll(chip8, &mut session.debugger),
+            None => Vec::new(),
+        };
+        for control in controls {
+            match control {
+                Control::Open(_)
+                    if session.netplay.is_some()
+                        || session.recorder.is_some()
+                        || session.player.is_some() =>
+                {
+                    warn!("the game can't change during network play or a replay");
+                }
+                Control::Open(path) => {
+                    audio.set_playing(false);
+                    return Exit::Open(path);
+                }
+                Control::Key(edge) => press_key(
+                    edge,
+                    chip8,
+                    &mut session.netplay,
+                    session.player.is_some(),
+                    &mut session.recorder,
+                ),
+            }
+        }
+
+        if let Some(Err(e)) = session.netplay.as_mut().map(Netplay::poll) {
+            warn!("{}, playing on alone", e);
+            session.netplay = None;
+        }
+        for _ in 0..pacer.frames_due(frame_interval) {
+            let run_frame = match &mut session.frame_advance {
+                None => true,
+                Some(0) => false,
+                Some(frames) => {
+                    *frames -= 1;
+                    true
+                }
+            };
+            let _frame = trace_span!("frame").entered();
+            // with network play a frame waits until the other player's keys for it are in
+            let run_frame = run_frame
+                && session.netplay.as_ref().map_or(true, Netplay::ready)
+                && session.sprites.is_none()
+                && session.slots.is_none()
+                && session.keymaps.is_none();
+            if run_frame && !session.debugger.is_paused() {
+                for edge in session.macros.frame() {
+                    press_key(
+                        edge,
+                        chip8,
+                        &mut session.netplay,
+                        session.player.is_some(),
+                        &mut session.recorder,
+                    );
+                }
+            }
+            if let (true, false, Some(netplay)) = (
+                run_frame,
+                session.debugger.is_paused(),
+                &mut session.netplay,
+            ) {
+                netplay.start_frame(chip8);
+            }
+            if let (true, false, Some(player)) =
+                (run_frame, session.debugger.is_paused(), &mut session.player)
+            {
+                player.start_frame(chip8);
+            }
+            let mut instructions = 0;
+            if run_frame {
+                budget.start(chip8);
+            }
+            while run_frame && budget.has_time() {
+                if !session.debugger.before_cycle(chip8) {
+                    break;
+                }
+                let result = chip8.emulate_cycle();
+                // a DXYN held back for the next frame didn't run, the rest of the frame would
+                // only be spent waiting
+                if chip8.waiting_for_vblank() {
+                    break;
+                }
+                budget.spend(chip8);
+                match result {
+                    Ok(Status::Running) => {}
+                    Ok(Status::Exited) => {
+                        audio.set_playing(false);
+                        return Exit::Exited;
+                    }
+                    Err(e) => {
+                        audio.set_playing(false);
+                        error!("emulation error: {}", e);
+                        display.show_error(&e.to_string());
+                        match &session.crash_reporter {
+                            Some(reporter) => {
+                                match reporter.write(chip8, session.rom.as_deref(), &e) {
+                                    Ok(dir) => info!("crash report in {}", dir.display()),
+                                    Err(e) => error!("no crash report: {}", e),
+                                }
+                            }
+                            None => {
+                                info!("--crash-report DIR writes a report of this for a bug report")
+                            }
+                        }
+                        return Exit::Error;
+                    }
+                }
+                instructions += 1;
+                run_script(&mut session.script, chip8, Script::after_cycle);
+            }
+
+            // time stands still while the debugger has the machine stopped
+            if run_frame && !session.debugger.is_paused() {
+                chip8.tick_timers();
+                frame_rate.count_frame();
+                if let Some(Err(e)) = session.frame_dump.as_mut().map(|dump| dump.frame(chip8)) {
+                    warn!("{}, not dumping frames any more", e);
+                    session.frame_dump = None;
+                }
+                if let Some(speed) = session
+                    .calibrator
+                    .as_ref()
+                    .and_then(|calibrator| calibrator.borrow_mut().end_frame(chip8))
+                {
+                    debug!(speed, "auto speed");
+                }
+                if let Some(Err(e)) = session
+                    .netplay
+                    .as_mut()
+                    .map(|netplay| netplay.end_frame(chip8))
+                {
+                    warn!("{}, playing on alone", e);
+                    session.netplay = None;
+                }
+                if let Some(recorder) = &mut session.recorder {
+                    recorder.end_frame(chip8);
+                }
+                if let Some(player) = &mut session.player {
+                    player.end_frame(chip8);
+                    if player.finished() {
+                        if player.diverged().is_none() {
+                            info!("replay: the game went as recorded");
+                        }
+                        session.player = None;
+                    }
+                }
+                persist_flags(session.saves.as_mut(), chip8);
+                if let Some(autosave) = &mut session.autosave {
+                    if autosave.due(Instant::now()) {
+                        save_state(session.saves.as_ref(), chip8);
+                    }
+                }
+                session.patches.apply_freezes(chip8);
+                run_script(&mut session.script, chip8, Script::on_frame);
+                // show every frame when going one at a time, and the heatmap as it warms up
+                if display.end_frame(instructions)
+                    || session.frame_advance.is_some()
+                    || session.heatmap
+                {
+                    chip8.draw = true;
+                }
+            }
+        }
+        if frame_rate.due(Instant::now()) {
+            let state = if session.debugger.is_paused() || session.frame_advance.is_some() {
+                "paused"
+            } else if chip8.key_wait().is_some() {
+                "waiting for a key"
+            } else {
+                "running"
+            };
+            display.set_title(&title::window_title(
+                &session.settings,
+                frame_rate.fps(),
+                state,
+            ));
+        }
+        if chip8.audio_pattern != audio_pattern || chip8.pitch != pitch {
+            audio_pattern = chip8.audio_pattern;
+            pitch = chip8.pitch;
+            if let Some(pattern) = audio_pattern {
+                audio.set_pattern(pattern, chip8.audio_playback_rate());
+            }
+        }
+        // a frame's worth of beep now and then is just a click
+        let paused = session.frame_advance.is_some()
+            || session.sprites.is_some()
+            || session.slots.is_some()
+            || session.keymaps.is_some();
+        if (chip8.sound_timer > 0 && !paused) != sound_playing {
+            sound_playing = !sound_playing;
+            audio.set_playing(sound_playing);
+        }
+        if sound_playing {
+            audio.set_sound_timer(chip8.sound_timer);
+        }
+
+        for event in input.poll() {
+            match event {
+                InputEvent::Quit => return Exit::Quit,
+                // both players' machines have to stay the same, and so do a recording and the game
+                InputEvent::Open(_) | InputEvent::Reset
+                    if session.netplay.is_some()
+                        || session.recorder.is_some()
+                        || session.player.is_some() =>
+                {
+                    warn!("the game can't change during network play or a replay");
+                }
+                InputEvent::Open(path) => {
+                    audio.set_playing(false);
+                    return Exit::Open(path);
+                }
+                InputEvent::Reset => {
+                    audio.set_playing(false);
+                    return Exit::Reset;
+                }
+                InputEvent::ToggleFrameAdvance => {
+                    session.frame_advance = match session.frame_advance {
+                        None => Some(0),
+                        Some(_) => None,
+                    }
+                }
+                InputEvent::AdvanceFrame => {
+                    *session.frame_advance.get_or_insert(0) += 1;
+                }
+                InputEvent::ToggleOverlay => {
+                    display.toggle_overlay();
+                    chip8.draw = true;
+                }
+                InputEvent::ToggleHud => {
+                    display.toggle_hud();
+                    chip8.draw = true;
+                }
+                InputEvent::ToggleGrid => {
+                    display.toggle_grid();
+                    chip8.draw = true;
+                }
+                InputEvent::CycleScaling => {
+                    display.cycle_scaling();
+                    chip8.draw = true;
+                }
+                // with the debugger, what drew the pixel clicked on
+                InputEvent::Click(x, y) => {
+                    if let (Some(_), Some((x, y))) = (&session.debug_server, display.pixel_at(x, y))
+                    {
+                        let writers = debug_server::pixel_writers(&session.debugger, x, y);
+                        if writers.is_empty() {
+                            info!("pixel {},{}: not drawn", x, y);
+                        } else {
+                            info!("pixel {},{}: {}", x, y, writers);
+                        }
+                    }
+                }
+                InputEvent::ToggleFullscreen => {
+                    display.toggle_fullscreen();
+                    chip8.draw = true;
+                }
+                InputEvent::Redraw => chip8.draw = true,
+                // unless the ROM has M as one of its keys
+                InputEvent::KeyDown(HostKey::M) if !session.keypad.is_mapped(HostKey::M) => {
+                    audio.toggle_mute()
+                }
+                // the other player would have to wait
+                InputEvent::ToggleSprites if session.netplay.is_some() => {
+                    warn!("the sprite viewer pauses the game, not during network play");
+                }
+                InputEvent::ToggleSprites => {
+                    session.sprites = match session.sprites {
+                        None => Some(SpriteViewer::new(chip8.index())),
+                        Some(_) => None,
+                    };
+                    session.slots = None;
+                    session.keymaps = None;
+                    session.heatmap = false;
+                    chip8.draw = true;
+                }
+                // loading one would pull the games apart, or the recording away from the game
+                InputEvent::ToggleSlots
+                    if session.netplay.is_some()
+                        || session.recorder.is_some()
+                        || session.player.is_some() =>
+                {
+                    warn!("save slots can't be used during network play or a replay");
+                }
+                InputEvent::ToggleSlots => {
+                    session.slots = match (&session.slots, &session.saves) {
+                        (None, Some((saves, _))) => Some(SlotPicker::new(saves)),
+                        (None, None) => {
+                            warn!("save slots need a ROM and somewhere to save to");
+                            None
+                        }
+                        (Some(_), _) => None,
+                    };
+                    session.sprites = None;
+                    session.keymaps = None;
+                    session.heatmap = false;
+                    chip8.draw = true;
+                }
+                InputEvent::ToggleKeymaps if session.netplay.is_some() => {
+                    warn!("the keymap picker pauses the game, not during network play");
+                }
+                InputEvent::ToggleKeymaps => {
+                    session.keymaps = match session.keymaps {
+                        None => Some(KeymapPicker::new(session.settings.keymap_preset)),
+                        Some(_) => None,
+                    };
+                    session.sprites = None;
+                    session.slots = None;
+                    session.heatmap = false;
+                    chip8.draw = true;
+                }
+                InputEvent::KeyDown(host_key) if session.keymaps.is_some() => {
+                    let picked = session
+                        .keymaps
+                        .as_mut()
+                        .and_then(|picker| picker.key_down(host_key));
+                    if let Some(preset) = picked {
+                        session.settings.keymap_preset = preset;
+                        session.settings.keymap =
+                            keymaps::keymap(preset, &session.settings.keymap_overrides);
+                        session.keypad.set_keymap(session.settings.keymap.clone());
+                        session.keymaps = None;
+                        info!("keymap: {}", preset.name());
+                    }
+                    chip8.draw = true;
+                }
+                InputEvent::ToggleHeatmap if session.profiler.is_none() => {
+                    warn!(
+                        "the memory heatmap needs --profile, --profile-json or --profile-heatmap"
+                    );
+                }
+                InputEvent::ToggleHeatmap => {
+                    session.heatmap = !session.heatmap;
+                    session.sprites = None;
+                    session.slots = None;
+                    session.keymaps = None;
+                    chip8.draw = true;
+                }
+                InputEvent::KeyDown(host_key) if session.slots.is_some() => {
+                    let action = session
+                        .slots
+                        .as_mut()
+                        .and_then(|picker| picker.key_down(host_key));
+                    if let (Some(action), Some((saves, _))) = (action, &session.saves) {
+                        if let SlotAction::Load(_) = action {
+                            session.debugger.clear_history();
+                        }
+                        if use_slot(action, saves, &session.settings, chip8) {
+                            session.slots = None;
+                        }
+                    }
+                    chip8.draw = true;
+                }
+                InputEvent::KeyUp(host_key @ (HostKey::LShift | HostKey::RShift))
+                    if session.slots.is_some() =>
+                {
+                    if let Some(picker) = &mut session.slots {
+                        picker.key_up(host_key);
+                    }
+                    chip8.draw = true;
+                }
+                InputEvent::KeyDown(host_key) | InputEvent::KeyRepeat(host_key)
+                    if session.sprites.is_some() =>
+                {
+                    if let Some(viewer) = &mut session.sprites {
+                        chip8.draw |= viewer.key_down(host_key, chip8);
+                    }
+                }
+                InputEvent::KeyRepeat(_) => {}
+                InputEvent::Finger(finger, ..) | InputEvent::FingerUp(finger) => {
+                    let at = match event {
+                        InputEvent::Finger(_, x, y) => Some((x, y)),
+                        _ => None,
+                    };
+                    for edge in session.touches.touch(finger, at) {
+                        press_key(
+                            edge,
+                            chip8,
+                            &mut session.netplay,
+                            session.player.is_some(),
+                            &mut session.recorder,
+                        );
+                    }
+                    display.show_touch_keypad();
+                    chip8.draw = true;
+                }
+                // the macro presses its keys from the next frame on
+                InputEvent::KeyDown(host_key) if session.macros.is_macro(host_key) => {
+                    session.macros.key_down(host_key)
+                }
+                InputEvent::KeyUp(host_key) if session.macros.is_macro(host_key) => {
+                    session.macros.key_up(host_key)
+                }
+                InputEvent::KeyDown(host_key) | InputEvent::KeyUp(host_key) => {
+                    let edge = match event {
+                        InputEvent::KeyDown(_) => session.keypad.key_down(host_key),
+                        _ => session.keypad.key_up(host_key),
+                    };
+                    if let Some(edge) = edge {
+                        press_key(
+                            edge,
+                            chip8,
+                            &mut session.netplay,
+                            session.player.is_some(),
+                            &mut session.recorder,
+                        );
+                    }
+                }
+            }
+        }
+        if chip8.draw {
+            let presented = match (&session.sprites, &session.slots, &session.keymaps) {
+                (Some(viewer), _, _) => {
+                    display.present_sprites(chip8, viewer.address, viewer.height)
+                }
+                (None, Some(picker), _) => {
+                    display.present_slots(chip8, &picker.slots, picker.saving())
+                }
+                (None, None, Some(picker)) => display.present_keymaps(chip8, picker.selected),
+                (None, None, None) => match (&session.profiler, session.heatmap) {
+                    (Some(profiler), true) => {
+                        display.present_heatmap(chip8, &profiler.borrow().heat().colors())
+                    }
+                    _ => display.present(chip8),
+                },
+            };
+            if let Err(e) = presented {
+                warn!("couldn't draw: {}", e);
+            }
+            chip8.draw = false;
+            chip8.clear_dirty_rows();
+        }
+
+        if let Some(watcher) = &mut session.watch {
+            if watcher.changed() {
+                audio.set_playing(false);
+                return Exit::Reload(watcher.rom_path().to_path_buf());
+            }
+        }
+
+        // presenting waits for the monitor
+        if pacer.is_vsync() {
+            chip8.draw = true;
+        }
+        pacer.wait(frame_interval);
+    }
+}
+
+// how the main loop keeps time: by sleeping until the next chip-8 frame is due, or with vsync
+// by presenting a picture every time around, which waits for the monitor, and running as many
+// chip-8 frames as the time since the last time adds up to
+enum Pacer {
+    Sleep {
+        next_frame: Instant,
+    },
+    Vsync {
+        last: Instant,
+        // time not spent on frames yet
+        accumulated: Duration,
+    },
+}
+
+impl Pacer {
+    fn new(vsync: bool) -> Pacer {
+        if vsync {
+            Pacer::Vsync {
+                last: Instant::now(),
+                accumulated: Duration::ZERO,
+            }
+        } else {
+            Pacer::Sleep {
+                next_frame: Instant::now(),
+            }
+        }
+    }
+
+    fn is_vsync(&self) -> bool {
+        matches!(self, Pacer::Vsync { .. })
+    }
+
+    // the chip-8 frames to run this time around the loop
+    fn frames_due(&mut self, frame_interval: Duration) -> u32 {
+        match self {
+            Pacer::Sleep { .. } => 1,
+            Pacer::Vsync { last, accumulated } => {
+                let now = Instant::now();
+                *accumulated += now - *last;
+                *last = now;
+                take_frames(accumulated, frame_interval)
+            }
+        }
+    }
+
+    fn wait(&mut self, frame_interval: Duration) {
+        match self {
+            Pacer::Sleep { next_frame } => wait_for_next_frame(next_frame, frame_interval),
+            // when the driver doesn't wait for the monitor after all, at least don't spin
+            Pacer::Vsync { last, .. } => {
+                if last.elapsed() < Duration::from_millis(1) {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+    }
+}
+
+// whole frames out of the accumulated time, the rest stays for next time. like
+// wait_for_next_frame it starts over when far behind
+fn take_frames(accumulated: &mut Duration, frame_interval: Duration) -> u32 {
+    let frames = (accumulated.as_nanos() / frame_interval.as_nanos()) as u32;
+    if frames > MAX_FRAMES_BEHIND {
+        *accumulated = Duration::ZERO;
+        return 1;
+    }
+    *accumulated -= frame_interval * frames;
+    frames
+}
+
+// step by whole frames so the timers don't drift with loop jitter. when we fall far behind (a
+// slow draw, the window being dragged) start over instead of rushing to catch up
+pub fn wait_for_next_frame(next_frame: &mut Instant, frame_interval: Duration) {
+    *next_frame += frame_interval;
+    let now = Instant::now();
+    if now.saturating_duration_since(*next_frame) > frame_interval * MAX_FRAMES_BEHIND {
+        *next_frame = now;
+    }
+    std::thread::sleep(next_frame.saturating_duration_since(now));
+}
+
+// a failing script is reported and switched off, the game keeps running
+fn run_script(
+    script: &mut Option<Script>,
+    chip8: &mut Chip8,
+    call: fn(&mut Script, &mut Chip8) -> Result<(), String>,
+) {
+    if let Some(e) = script.as_mut().and_then(|script| call(script, chip8).err()) {
+        error!("{}", e);
+        *script = None;
+    }
+}
+
+pub fn freq_to_period_duration(freq_hertz: u64) -> Duration {
+    Duration::from_nanos(1_000_000_000 / freq_hertz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::keymaps::KeymapPreset;
+    use crate::patches::Patches;
+    use crate::profiler::Profiler;
+    use crate::session::{open_saves, resume, Autosave};
+    use chip_8::frontend::Rgb;
+    use chip_8::{Debugger, Keypad, Macros, Touches, Variant};
+    use std::cell::RefCell;
+    use std::collections::{HashMap, VecDeque};
+    use std::fs;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct MockDisplay {
+        presented: usize,
+        frames: usize,
+        // the address of every sprite viewer page shown
+        sprites: Vec<usize>,
+        // the color of the program start in every heatmap shown
+        heatmaps: Vec<Rgb>,
+        titles: Vec<String>,
+    }
+
+    impl Display for MockDisplay {
+        fn present(&mut self, _chip8: &Chip8) -> Result<(), String> {
+            self.presented += 1;
+            Ok(())
+        }
+
+        fn present_sprites(
+            &mut self,
+            _chip8: &Chip8,
+            address: usize,
+            _height: usize,
+        ) -> Result<(), String> {
+            self.sprites.push(address);
+            Ok(())
+        }
+
+        fn present_heatmap(&mut self, _chip8: &Chip8, colors: &[Rgb]) -> Result<(), String> {
+            self.heatmaps.push(colors[0x200]);
+            Ok(())
+        }
+
+        fn end_frame(&mut self, _instructions: u64) -> bool {
+            self.frames += 1;
+            false
+        }
+
+        fn set_title(&mut self, title: &str) {
+            self.titles.push(title.to_string());
+        }
+    }
+
+    #[derive(Default)]
+    struct MockAudio {
+        playing: Vec<bool>,
+        mutes: usize,
+    }
+
+    impl AudioSink for MockAudio {
+        fn set_playing(&mut self, playing: bool) {
+            self.playing.push(playing);
+        }
+
+        fn toggle_mute(&mut self) {
+            self.mutes += 1;
+        }
+    }
+
+    // hands out one batch of events per frame
+    struct MockInput {
+        frames: VecDeque<Vec<InputEvent>>,
+    }
+
+    impl InputSource for MockInput {
+        fn poll(&mut self) -> Vec<InputEvent> {
+            self.frames.pop_front().unwrap_or_default()
+        }
+    }
+
+    fn session(rom: &[u8]) -> Session {
+        let mut chip8 = Chip8::builder().build();
+        chip8.load_rom_bytes(rom).unwrap();
+        Session {
+            chip8,
+            rom: Some(rom.to_vec()),
+            settings: Config::default().resolve().unwrap(),
+            saves: None,
+            patches: Patches::default(),
+            script: None,
+            debugger: Debugger::new(),
+            debug_server: None,
+            control: None,
+            frame_advance: None,
+            netplay: None,
+            watch: None,
+            sprites: None,
+            slots: None,
+            keymaps: None,
+            recorder: None,
+            player: None,
+            autosave: None,
+            keypad: Keypad::new(),
+            touches: Touches::new(),
+            macros: Macros::default(),
+            frame_dump: None,
+            crash_reporter: None,
+            profiler: None,
+            calibrator: None,
+            heatmap: false,
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        // V0 = 2, beep for 2 frames, clear the screen, wait for a key in V1, loop forever
+        let mut session = session(&[0x60, 0x02, 0xF0, 0x18, 0x00, 0xE0, 0xF1, 0x0A, 0x12, 0x08]);
+        let mut display = MockDisplay::default();
+        let mut audio = MockAudio::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![],
+                vec![InputEvent::KeyDown(HostKey::Num1)],
+                vec![InputEvent::KeyUp(HostKey::Num1)],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        let exit = run(&mut session, &mut display, &mut audio, &mut input);
+        assert!(matches!(exit, Exit::Quit));
+        assert_eq!(display.frames, 4);
+        // the first frame and the clear in it
+        assert_eq!(display.presented, 1);
+        assert_eq!(audio.playing, vec![true, false]);
+        assert_eq!(session.chip8.registers()[1], 1);
+    }
+
+    #[test]
+    fn test_macros() {
+        // wait for a key in V1, then loop forever
+        let mut session = session(&[0xF1, 0x0A, 0x12, 0x02]);
+        session.macros = Macros::new(HashMap::from([(
+            HostKey::F,
+            chip_8::Macro::parse("7*2", false).unwrap(),
+        )]));
+        let mut input = MockInput {
+            frames: vec![
+                vec![InputEvent::KeyDown(HostKey::F)],
+                vec![InputEvent::KeyUp(HostKey::F)],
+                vec![],
+                vec![],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        run(
+            &mut session,
+            &mut MockDisplay::default(),
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        // the macro's key rather than the keymap's E
+        assert_eq!(session.chip8.registers()[1], 7);
+    }
+
+    #[test]
+    fn test_restart() {
+        // V0 += 1 forever
+        let mut session = session(&[0x70, 0x01, 0x12, 0x00]);
+        let mut input = MockInput {
+            frames: vec![vec![InputEvent::Reset]].into(),
+        };
+        let exit = run(
+            &mut session,
+            &mut MockDisplay::default(),
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        assert!(matches!(exit, Exit::Reset));
+        assert_ne!(session.chip8.registers()[0], 0);
+        session.restart();
+        assert_eq!(session.chip8.registers()[0], 0);
+        assert_eq!(session.chip8.pc(), 0x200);
+        session.chip8.emulate_cycle().unwrap();
+        assert_eq!(session.chip8.registers()[0], 1);
+    }
+
+    #[test]
+    fn test_exit() {
+        // V0 = 1, exit, V0 = 2
+        let mut session = session(&[0x60, 0x01, 0x00, 0xFD, 0x60, 0x02]);
+        let mut input = MockInput {
+            frames: vec![vec![InputEvent::Quit]].into(),
+        };
+        let exit = run(
+            &mut session,
+            &mut MockDisplay::default(),
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        assert!(matches!(exit, Exit::Exited));
+        assert_eq!(session.chip8.registers()[0], 1);
+    }
+
+    #[test]
+    fn test_persist_memory() {
+        let dir = std::env::temp_dir().join("chip8_persist_memory_test");
+        let _ = fs::remove_dir_all(&dir);
+        let mut session = session(&[0x12, 0x00]);
+        session.settings.save_dir = Some(dir.clone());
+        session.settings.persist_memory = Some(0x300..0x302);
+        session.restart();
+        session.chip8.set_memory(0x300, 7);
+        session.chip8.set_memory(0x302, 9);
+        // saved when the ROM starts over, and back after it did
+        session.restart();
+        assert_eq!(session.chip8.memory()[0x300..0x303], [7, 0, 0]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_autosave() {
+        let dir = std::env::temp_dir().join("chip8_autosave_test");
+        let _ = fs::remove_dir_all(&dir);
+        // V0 += 1 forever
+        let rom = [0x70, 0x01, 0x12, 0x00];
+        let mut session = session(&rom);
+        session.settings.save_dir = Some(dir.clone());
+        session.autosave = Some(Autosave {
+            interval: Duration::from_secs(0),
+            last: Instant::now(),
+        });
+        session.restart();
+        let mut input = MockInput {
+            frames: vec![vec![], vec![InputEvent::Quit]].into(),
+        };
+        run(
+            &mut session,
+            &mut MockDisplay::default(),
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        assert_eq!(session.chip8.registers()[0], 14);
+
+        // the last frame's state is picked up again, the quirks are the ones configured now
+        let mut chip8 = Chip8::builder().build();
+        chip8.load_rom_bytes(&rom).unwrap();
+        let saves = open_saves(&session.settings, Some(&rom), &mut chip8);
+        session.settings.quirks.jump_vx = true;
+        resume(saves.as_ref(), &session.settings, &mut chip8);
+        assert_eq!(chip8.registers()[0], 14);
+        assert!(chip8.quirks.jump_vx);
+        // not for another ROM, or as another variant
+        let mut other = Chip8::builder().build();
+        let saves = open_saves(&session.settings, Some(&[0x12, 0x00]), &mut other);
+        resume(saves.as_ref(), &session.settings, &mut other);
+        assert_eq!(other.registers()[0], 0);
+        session.settings.variant = Variant::Hires;
+        let saves = open_saves(&session.settings, Some(&rom), &mut other);
+        resume(saves.as_ref(), &session.settings, &mut other);
+        assert_eq!(other.registers()[0], 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_slots() {
+        let dir = std::env::temp_dir().join("chip8_slots_test");
+        let _ = fs::remove_dir_all(&dir);
+        // V0 += 1 forever, 7 times in a frame at the default speed
+        let mut session = session(&[0x70, 0x01, 0x12, 0x00]);
+        session.settings.save_dir = Some(dir.clone());
+        session.restart();
+        let mut input = MockInput {
+            frames: vec![
+                vec![InputEvent::ToggleSlots],
+                vec![InputEvent::KeyDown(HostKey::LShift)],
+                vec![InputEvent::KeyDown(HostKey::Num2)],
+                vec![],
+                vec![InputEvent::ToggleSlots],
+                // an empty one leaves the picker open
+                vec![InputEvent::KeyDown(HostKey::Num3)],
+                vec![InputEvent::KeyDown(HostKey::Num2)],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        run(
+            &mut session,
+            &mut MockDisplay::default(),
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        // saved after the first frame, then two more ran, then back to the save and one more
+        assert_eq!(session.chip8.registers()[0], 14);
+        assert!(session.slots.is_none());
+        let (saves, _) = session.saves.as_ref().unwrap();
+        assert!(saves.load_slot(2).unwrap().is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_frame_advance() {
+        // V0 += 1 forever, 7 times in a frame at the default speed
+        let mut session = session(&[0x70, 0x01, 0x12, 0x00]);
+        session.frame_advance = Some(0);
+        session.settings.title = "{state} at {fps}fps".to_string();
+        let mut display = MockDisplay::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![],
+                vec![InputEvent::AdvanceFrame],
+                vec![],
+                vec![InputEvent::ToggleFrameAdvance],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        run(
+            &mut session,
+            &mut display,
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        // nothing until asked for, then one frame, then free running again for the last one
+        assert_eq!(display.frames, 2);
+        assert_eq!(session.chip8.registers()[0], 14);
+        assert_eq!(session.frame_advance, None);
+        // the title is filled in right away, then not again within a second
+        assert_eq!(display.titles, ["paused at -fps"]);
+    }
+
+    #[test]
+    fn test_sprite_viewer() {
+        // V0 += 1 forever, 7 times in a frame at the default speed
+        let mut session = session(&[0x70, 0x01, 0x12, 0x00]);
+        session.chip8.set_index(0x300);
+        let mut display = MockDisplay::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![InputEvent::ToggleSprites],
+                vec![InputEvent::KeyDown(HostKey::Down)],
+                vec![InputEvent::KeyDown(HostKey::Num1)],
+                vec![InputEvent::ToggleSprites],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        run(
+            &mut session,
+            &mut display,
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        // the first frame and the last one ran
+        assert_eq!(display.frames, 2);
+        assert_eq!(session.chip8.registers()[0], 14);
+        assert!(session.sprites.is_none());
+        // the viewer opened where I points and was redrawn when it moved
+        assert_eq!(display.sprites, vec![0x300, 0x305]);
+    }
+
+    #[test]
+    fn test_keymap_picker() {
+        // V0 += 1 forever, 7 times in a frame at the default speed
+        let mut session = session(&[0x70, 0x01, 0x12, 0x00]);
+        let mut display = MockDisplay::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![InputEvent::ToggleKeymaps],
+                vec![InputEvent::KeyDown(HostKey::Down)],
+                vec![InputEvent::KeyDown(HostKey::Return)],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        run(
+            &mut session,
+            &mut display,
+            &mut MockAudio::default(),
+            &mut input,
+        );
+        // the game waited while the picker was open
+        assert_eq!(display.frames, 2);
+        assert_eq!(session.chip8.registers()[0], 14);
+        assert!(session.keymaps.is_none());
+        assert_eq!(session.settings.keymap_preset, KeymapPreset::LeftHand);
+        assert_eq!(session.keypad.keymap().get(&HostKey::W), Some(&0x2));
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let inputs = || MockInput {
+            frames: vec![
+                vec![InputEvent::ToggleHeatmap],
+                vec![],
+                vec![InputEvent::ToggleHeatmap],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        // nothing to show without the profiler
+        let mut unprofiled = session(&[0x70, 0x01, 0x12, 0x00]);
+        let mut display = MockDisplay::default();
+        run(
+            &mut unprofiled,
+            &mut display,
+            &mut MockAudio::default(),
+            &mut inputs(),
+        );
+        assert!(display.heatmaps.is_empty());
+
+        let mut session = session(&[0x70, 0x01, 0x12, 0x00]);
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        session.chip8.add_hook(Box::new(profiler.clone()));
+        session.profiler = Some(profiler);
+        let mut display = MockDisplay::default();
+        run(
+            &mut session,
+            &mut display,
+            &mut MockAudio::default(),
+            &mut inputs(),
+        );
+        // shown after every frame the game kept running for, until closed
+        assert_eq!(display.heatmaps.len(), 2);
+        assert_eq!(display.heatmaps[0], Rgb::new(0, 255, 0));
+        assert!(!session.heatmap);
+        assert_eq!(session.chip8.registers()[0], 28);
+    }
+
+    #[test]
+    fn test_mute() {
+        let mut session = session(&[0x12, 0x00]);
+        let mut audio = MockAudio::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![InputEvent::KeyDown(HostKey::M)],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        run(
+            &mut session,
+            &mut MockDisplay::default(),
+            &mut audio,
+            &mut input,
+        );
+        assert_eq!(audio.mutes, 1);
+
+        // a ROM that uses M gets it as a key
+        let mut keymap = session.keypad.keymap().clone();
+        keymap.insert(HostKey::M, 0xA);
+        session.keypad.set_keymap(keymap);
+        let mut audio = MockAudio::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![InputEvent::KeyDown(HostKey::M)],
+                vec![InputEvent::Quit],
+            ]
+            .into(),
+        };
+        run(
+            &mut session,
+            &mut MockDisplay::default(),
+            &mut audio,
+            &mut input,
+        );
+        assert_eq!(audio.mutes, 0);
+    }
+
+    #[test]
+    fn test_take_frames() {
+        let frame = Duration::from_millis(10);
+        let mut accumulated = Duration::from_millis(25);
+        assert_eq!(take_frames(&mut accumulated, frame), 2);
+        assert_eq!(accumulated, Duration::from_millis(5));
+        // a 120Hz monitor runs a frame every other time
+        accumulated += Duration::from_millis(4);
+        assert_eq!(take_frames(&mut accumulated, frame), 0);
+        accumulated += Duration::from_millis(4);
+        assert_eq!(take_frames(&mut accumulated, frame), 1);
+        assert_eq!(accumulated, Duration::from_millis(3));
+        // the window was being dragged
+        accumulated += Duration::from_secs(1);
+        assert_eq!(take_frames(&mut accumulated, frame), 1);
+        assert_eq!(accumulated, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_freq_to_period_duration() {
+        let freq = 1;
+        // 1 Hz
+        assert_eq!(freq_to_period_duration(freq), Duration::from_secs(1));
+        // 1 MHz
+        assert_eq!(freq_to_period_duration(1_000_000), Duration::from_micros(1));
+    }
+}
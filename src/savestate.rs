@@ -0,0 +1,450 @@
+//! save state files. a fixed header that every version can read, then the machine:
+//!
+//! ```text
+//!   offset  size  what
+//!   0       4     "CH8S"
+//!   4       2     format version, little endian like every number in the file
+//!   6       1     flags, bit 0 set if the body is deflated
+//!   7       1     variant: 0 chip8, 1 hires, 2 chip8x
+//!   8       1     quirks, one bit each from bit 0: clipping, shift_vy, increment_i, jump_vx,
+//!                 vf_reset, display_wait, the memory policy and the machine code policy (set
+//!                 for fault). the unknown opcode policy didn't fit, it's in the body
+//!   9       40    SHA-1 of the ROM in hex, or all zeros when it isn't known
+//!   49      4     length of the body
+//!   53            the body, see write_body
+//! ```
+//!
+//! fields are only ever added to the end of the body. a newer version reads the older ones by
+//! filling in what they don't have (see read_body), so states keep loading after an upgrade.
+//! files from a newer version than this one are refused instead of guessed at
+
+use crate::chip8::chip8::{
+    KeyWait, MachineCodePolicy, MemoryPolicy, Quirks, Snapshot, UnknownOpcodePolicy, Variant,
+    DISPLAY_WIDTH, MEM_SIZE, RPL_FLAG_COUNT, STACK_SIZE,
+};
+use crate::framebuffer::FrameBuffer;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{Read, Write};
+
+/// the first 4 bytes of every save state
+pub const MAGIC: &[u8; 4] = b"CH8S";
+/// the format written, and the newest one read
+pub const VERSION: u16 = 2;
+const HEADER_SIZE: usize = 53;
+const FLAG_COMPRESSED: u8 = 1;
+const VARIANTS: [Variant; 3] = [Variant::Chip8, Variant::Hires, Variant::Chip8x];
+const UNKNOWN_OPCODE_POLICIES: [UnknownOpcodePolicy; 3] = [
+    UnknownOpcodePolicy::Fault,
+    UnknownOpcodePolicy::Skip,
+    UnknownOpcodePolicy::Warn,
+];
+
+/// what a save state says about itself, without loading the machine
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub version: u16,
+    pub compressed: bool,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub rom_hash: Option<String>,
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "format version {}", self.version)?;
+        writeln!(
+            f,
+            "compressed: {}",
+            if self.compressed { "yes" } else { "no" }
+        )?;
+        writeln!(f, "variant: {}", self.variant.name())?;
+        let quirks = &self.quirks;
+        let names: Vec<&str> = [
+            (quirks.clipping, "clipping"),
+            (quirks.shift_vy, "shift_vy"),
+            (quirks.increment_i, "increment_i"),
+            (quirks.jump_vx, "jump_vx"),
+            (quirks.vf_reset, "vf_reset"),
+            (quirks.display_wait, "display_wait"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect();
+        let names = if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        };
+        writeln!(
+            f,
+            "quirks: {}, memory {}, machine code {}, unknown opcodes {}",
+            names,
+            quirks.memory.name(),
+            quirks.machine_code.name(),
+            quirks.unknown_opcode.name()
+        )?;
+        match &self.rom_hash {
+            Some(hash) => writeln!(f, "rom: sha1 {}", hash),
+            None => writeln!(f, "rom: unknown"),
+        }
+    }
+}
+
+/// the file for `snapshot`, with the ROM it was taken running if it's known
+pub fn write(snapshot: &Snapshot, rom_hash: Option<&str>, compress: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_body(&mut body, snapshot);
+    if compress {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        // writing to a Vec doesn't fail
+        encoder.write_all(&body).unwrap();
+        body = encoder.finish().unwrap();
+    }
+    let quirks = &snapshot.quirks;
+    let quirk_bits = [
+        quirks.clipping,
+        quirks.shift_vy,
+        quirks.increment_i,
+        quirks.jump_vx,
+        quirks.vf_reset,
+        quirks.display_wait,
+        quirks.memory == MemoryPolicy::Fault,
+        quirks.machine_code == MachineCodePolicy::Fault,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0, |bits, (bit, on)| bits | (*on as u8) << bit);
+    let rom_hash = match rom_hash {
+        Some(hash) if hash.len() == 40 && hash.is_ascii() => hash.to_string(),
+        _ => "0".repeat(40),
+    };
+
+    let mut file = Vec::with_capacity(HEADER_SIZE + body.len());
+    file.extend_from_slice(MAGIC);
+    file.extend_from_slice(&VERSION.to_le_bytes());
+    file.push(if compress { FLAG_COMPRESSED } else { 0 });
+    file.push(
+        VARIANTS
+            .iter()
+            .position(|v| *v == snapshot.variant)
+            .unwrap() as u8,
+    );
+    file.push(quirk_bits);
+    file.extend_from_slice(rom_hash.as_bytes());
+    file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    file.extend_from_slice(&body);
+    file
+}
+
+/// only the header, to tell what a file is before loading it
+pub fn read_header(file: &[u8]) -> Result<Header, String> {
+    if file.len() < HEADER_SIZE || &file[..4] != MAGIC {
+        return Err("not a save state".to_string());
+    }
+    let version = u16::from_le_bytes([file[4], file[5]]);
+    if version == 0 || version > VERSION {
+        return Err(format!(
+            "save state format version {} is newer than this emulator (up to {})",
+            version, VERSION
+        ));
+    }
+    let variant = *VARIANTS
+        .get(file[7] as usize)
+        .ok_or(format!("unknown variant {}", file[7]))?;
+    let bit = |n: u8| file[8] & 1 << n != 0;
+    let quirks = Quirks {
+        clipping: bit(0),
+        shift_vy: bit(1),
+        increment_i: bit(2),
+        jump_vx: bit(3),
+        vf_reset: bit(4),
+        display_wait: bit(5),
+        memory: if bit(6) {
+            MemoryPolicy::Fault
+        } else {
+            MemoryPolicy::Wrap
+        },
+        machine_code: if bit(7) {
+            MachineCodePolicy::Fault
+        } else {
+            MachineCodePolicy::Ignore
+        },
+        // in the body
+        unknown_opcode: Quirks::default().unknown_opcode,
+    };
+    let rom_hash = String::from_utf8_lossy(&file[9..49]).into_owned();
+    Ok(Header {
+        version,
+        compressed: file[6] & FLAG_COMPRESSED != 0,
+        variant,
+        quirks,
+        rom_hash: (rom_hash != "0".repeat(40)).then_some(rom_hash),
+    })
+}
+
+/// the header and the machine, for Chip8::restore
+pub fn read(file: &[u8]) -> Result<(Header, Snapshot), String> {
+    let header = read_header(file)?;
+    let length = u32::from_le_bytes([file[49], file[50], file[51], file[52]]) as usize;
+    let mut body = file
+        .get(HEADER_SIZE..HEADER_SIZE + length)
+        .ok_or("save state is cut short")?
+        .to_vec();
+    if header.compressed {
+        let mut inflated = Vec::new();
+        DeflateDecoder::new(&body[..])
+            .read_to_end(&mut inflated)
+            .map_err(|e| format!("save state is damaged: {}", e))?;
+        body = inflated;
+    }
+    let snapshot = read_body(&body, &header).ok_or("save state is cut short")?;
+    // the body has the rest of the quirks
+    let header = Header {
+        quirks: snapshot.quirks,
+        ..header
+    };
+    Ok((header, snapshot))
+}
+
+// version 1:
+//
+//   speed (8), memory (4096), V0 to VF (16), I (2), pc (2), stack depth (1) and the return
+//   addresses (2 each), delay timer, sound timer, XO-CHIP audio pattern (1, and 16 if it's 1),
+//   pitch, RPL flags (16), screen height (1) and its rows (8 each, leftmost pixel in the
+//   lowest bit), CHIP-8X color zones (2, and 1 each), CHIP-8X background, FX0A state (3: 0 for
+//   none, 1 and X for waiting for a press, 2, X and the key for waiting for it to be let go)
+//   and whether the ROM exited (1)
+//
+// version 2 adds the unknown opcode policy (1: 0 fault, 1 skip, 2 warn)
+fn write_body(body: &mut Vec<u8>, snapshot: &Snapshot) {
+    body.extend_from_slice(&snapshot.speed.to_le_bytes());
+    body.extend_from_slice(&snapshot.memory);
+    body.extend_from_slice(&snapshot.registers);
+    body.extend_from_slice(&(snapshot.index as u16).to_le_bytes());
+    body.extend_from_slice(&(snapshot.pc as u16).to_le_bytes());
+    body.push(snapshot.stack.len() as u8);
+    for address in &snapshot.stack {
+        body.extend_from_slice(&(*address as u16).to_le_bytes());
+    }
+    body.push(snapshot.delay_timer);
+    body.push(snapshot.sound_timer);
+    match &snapshot.audio_pattern {
+        Some(pattern) => {
+            body.push(1);
+            body.extend_from_slice(pattern);
+        }
+        None => body.push(0),
+    }
+    body.push(snapshot.pitch);
+    body.extend_from_slice(&snapshot.rpl_flags);
+    let screen = &snapshot.framebuffer;
+    body.push(screen.height() as u8);
+    for y in 0..screen.height() {
+        let row = (0..DISPLAY_WIDTH).fold(0u64, |row, x| row | (screen.get(x, y) as u64) << x);
+        body.extend_from_slice(&row.to_le_bytes());
+    }
+    body.extend_from_slice(&(snapshot.zone_colors.len() as u16).to_le_bytes());
+    body.extend_from_slice(&snapshot.zone_colors);
+    body.push(snapshot.background_color);
+    body.extend_from_slice(&match snapshot.key_wait {
+        None => [0, 0, 0],
+        Some(KeyWait::Press(x)) => [1, x as u8, 0],
+        Some(KeyWait::Release(x, key)) => [2, x as u8, key],
+    });
+    body.push(snapshot.exited as u8);
+    body.push(
+        UNKNOWN_OPCODE_POLICIES
+            .iter()
+            .position(|policy| *policy == snapshot.quirks.unknown_opcode)
+            .unwrap() as u8,
+    );
+}
+
+// None if the body is shorter than its version says
+fn read_body(body: &[u8], header: &Header) -> Option<Snapshot> {
+    let mut body = body;
+    let mut take = |n: usize| {
+        if body.len() < n {
+            return None;
+        }
+        let (taken, rest) = body.split_at(n);
+        body = rest;
+        Some(taken)
+    };
+    let speed = u64::from_le_bytes(take(8)?.try_into().ok()?);
+    let memory = take(MEM_SIZE)?.to_vec();
+    let registers = take(16)?.try_into().ok()?;
+    let index = u16::from_le_bytes(take(2)?.try_into().ok()?) as usize;
+    let pc = u16::from_le_bytes(take(2)?.try_into().ok()?) as usize;
+    let depth = (take(1)?[0] as usize).min(STACK_SIZE);
+    let mut stack = Vec::with_capacity(depth);
+    for _ in 0..depth {
+        stack.push(u16::from_le_bytes(take(2)?.try_into().ok()?) as usize);
+    }
+    let delay_timer = take(1)?[0];
+    let sound_timer = take(1)?[0];
+    let audio_pattern = match take(1)?[0] {
+        0 => None,
+        _ => Some(take(16)?.try_into().ok()?),
+    };
+    let pitch = take(1)?[0];
+    let rpl_flags: [u8; RPL_FLAG_COUNT] = take(RPL_FLAG_COUNT)?.try_into().ok()?;
+    let height = take(1)?[0] as usize;
+    let mut framebuffer = FrameBuffer::new(DISPLAY_WIDTH, height);
+    for y in 0..height {
+        let row = u64::from_le_bytes(take(8)?.try_into().ok()?);
+        for x in 0..DISPLAY_WIDTH {
+            framebuffer.set(x, y, row & 1 << x != 0);
+        }
+    }
+    let zones = u16::from_le_bytes(take(2)?.try_into().ok()?) as usize;
+    let zone_colors = take(zones)?.to_vec();
+    let background_color = take(1)?[0];
+    let key_wait = match take(3)? {
+        [1, x, _] => Some(KeyWait::Press(*x as usize & 0xF)),
+        [2, x, key] => Some(KeyWait::Release(*x as usize & 0xF, *key & 0xF)),
+        _ => None,
+    };
+    let exited = take(1)?[0] != 0;
+    // fields of later versions go here, e.g.
+    //   let foo = if header.version >= 3 { take(1)?[0] } else { DEFAULT_FOO };
+    let unknown_opcode = if header.version >= 2 {
+        let policy = take(1)?[0] as usize;
+        *UNKNOWN_OPCODE_POLICIES
+            .get(policy)
+            .unwrap_or(&Quirks::default().unknown_opcode)
+    } else {
+        Quirks::default().unknown_opcode
+    };
+    Some(Snapshot {
+        variant: header.variant,
+        quirks: Quirks {
+            unknown_opcode,
+            ..header.quirks
+        },
+        speed,
+        memory,
+        registers,
+        index,
+        pc,
+        stack,
+        delay_timer,
+        sound_timer,
+        audio_pattern,
+        pitch,
+        rpl_flags,
+        framebuffer,
+        zone_colors,
+        background_color,
+        key_wait,
+        exited,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::chip8::Chip8;
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::builder()
+            .variant(Variant::Hires)
+            .quirks(Quirks {
+                unknown_opcode: UnknownOpcodePolicy::Warn,
+                ..Quirks::preset("cosmac").unwrap()
+            })
+            .build();
+        // call 0x206, which draws the 0 and waits for a key
+        chip8
+            .load_rom_bytes(&[0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0xD0, 0x15, 0xF3, 0x0A])
+            .unwrap();
+        // the display wait quirk holds the draw until the next frame
+        chip8.run_frame();
+        chip8.run_frame();
+        chip8.audio_pattern = Some([0xAA; 16]);
+        chip8
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chip8 = machine();
+        let snapshot = chip8.snapshot();
+        assert_eq!(snapshot.key_wait, Some(KeyWait::Press(3)));
+        for compress in [false, true] {
+            let file = write(&snapshot, Some(&"ab".repeat(20)), compress);
+            let (header, read_back) = read(&file).unwrap();
+            assert_eq!(read_back, snapshot);
+            assert_eq!(header.version, VERSION);
+            assert_eq!(header.compressed, compress);
+            assert_eq!(header.variant, Variant::Hires);
+            assert_eq!(header.quirks, snapshot.quirks);
+            assert_eq!(header.rom_hash, Some("ab".repeat(20)));
+        }
+        let plain = write(&snapshot, None, false);
+        assert!(write(&snapshot, None, true).len() < plain.len() / 4);
+        assert_eq!(read_header(&plain).unwrap().rom_hash, None);
+
+        let mut restored = Chip8::builder().build();
+        restored.restore(&read(&plain).unwrap().1).unwrap();
+        assert_eq!(restored.state_hash(), chip8.state_hash());
+    }
+
+    #[test]
+    fn test_version_1() {
+        // the same machine as version 1 wrote it, without the unknown opcode policy
+        let snapshot = machine().snapshot();
+        let mut file = write(&snapshot, None, false);
+        file.pop();
+        file[4] = 1;
+        let length = (file.len() - HEADER_SIZE) as u32;
+        file[49..53].copy_from_slice(&length.to_le_bytes());
+        let (header, read_back) = read(&file).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(read_back.quirks.unknown_opcode, UnknownOpcodePolicy::Fault);
+        assert_eq!(
+            read_back,
+            Snapshot {
+                quirks: read_back.quirks,
+                ..snapshot
+            }
+        );
+    }
+
+    #[test]
+    fn test_bad_files() {
+        let file = write(&machine().snapshot(), None, true);
+        assert!(read(b"CH8S").is_err());
+        assert!(read(&file[..file.len() - 10]).is_err());
+        let mut newer = file.clone();
+        newer[4] = 3;
+        assert!(read(&newer).unwrap_err().contains("newer"));
+        let mut damaged = file.clone();
+        damaged[60..70].copy_from_slice(&[0xFF; 10]);
+        assert!(read(&damaged).is_err());
+        let mut other = file;
+        other[0] = b'X';
+        assert_eq!(read(&other).unwrap_err(), "not a save state");
+
+        // colors the display has no entry for are refused when restoring
+        let mut chip8 = Chip8::builder().build();
+        let mut snapshot = machine().snapshot();
+        snapshot.zone_colors[3] = 8;
+        let (_, read_back) = read(&write(&snapshot, None, true)).unwrap();
+        assert_eq!(chip8.restore(&read_back).unwrap_err(), "zone color 8");
+        let mut snapshot = machine().snapshot();
+        snapshot.background_color = 4;
+        let (_, read_back) = read(&write(&snapshot, None, true)).unwrap();
+        assert_eq!(chip8.restore(&read_back).unwrap_err(), "background color 4");
+        // a key FX0A waits for is one of the 16 like the register
+        let mut snapshot = machine().snapshot();
+        snapshot.key_wait = Some(KeyWait::Release(0x12, 0x17));
+        let (_, read_back) = read(&write(&snapshot, None, true)).unwrap();
+        assert_eq!(read_back.key_wait, Some(KeyWait::Release(2, 7)));
+    }
+}
@@ -0,0 +1,303 @@
+use chip_8::Chip8;
+
+#[cfg(feature = "scripting")]
+pub use rhai_script::Script;
+
+// scripts are written in Rhai (https://rhai.rs). the top level runs once when the script is
+// loaded, after that these functions are called if the script defines them:
+//
+//   on_frame()                       60 times a second
+//   on_instruction(pc, opcode)       after every instruction
+//   on_memory_write(address, value)  when an instruction stores to memory
+//
+// and the script can call
+//
+//   peek(address), poke(address, value)
+//   reg(x), set_reg(x, value), pc(), index()
+//   press(key), release(key), key_down(key)
+#[cfg(feature = "scripting")]
+mod rhai_script {
+    use super::Chip8;
+    use chip_8::{Hooks, Opcode};
+
+    use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Scope, AST};
+    use std::cell::RefCell;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    // the machine as the script sees it during a call. copied in before and applied after, so
+    // the registered functions don't need to borrow the emulator
+    #[derive(Default)]
+    struct Machine {
+        memory: Vec<u8>,
+        registers: [u8; 16],
+        pc: usize,
+        index: usize,
+        // presses and releases to apply, and the keys held as of the call
+        keys: Vec<(u8, bool)>,
+        held: [bool; 16],
+    }
+
+    impl Machine {
+        fn load(&mut self, chip8: &Chip8) {
+            self.memory.clear();
+            self.memory.extend_from_slice(chip8.memory());
+            self.registers = *chip8.registers();
+            self.pc = chip8.pc();
+            self.index = chip8.index();
+            self.held = chip8.keys();
+        }
+
+        fn apply(&mut self, chip8: &mut Chip8) {
+            for (address, value) in self.memory.iter().enumerate() {
+                if chip8.memory()[address] != *value {
+                    chip8.set_memory(address, *value);
+                }
+            }
+            for (x, value) in self.registers.iter().enumerate() {
+                chip8.set_register(x, *value);
+            }
+            for (key, pressed) in self.keys.drain(..) {
+                chip8.set_key(key, pressed);
+            }
+        }
+    }
+
+    enum Event {
+        Instruction(usize, u16),
+        MemoryWrite(usize, u8),
+    }
+
+    // collects what happened during a cycle, the script hears about it afterwards
+    #[derive(Default)]
+    struct Recorder {
+        instructions: bool,
+        memory_writes: bool,
+        events: Vec<Event>,
+    }
+
+    impl Hooks for Recorder {
+        fn before_instruction(&mut self, chip8: &Chip8, _opcode: &Opcode) {
+            if self.instructions {
+                self.events
+                    .push(Event::Instruction(chip8.pc(), chip8.next_instruction()));
+            }
+        }
+
+        fn on_memory_write(&mut self, _chip8: &Chip8, address: usize, value: u8) {
+            if self.memory_writes {
+                self.events.push(Event::MemoryWrite(address, value));
+            }
+        }
+    }
+
+    pub struct Script {
+        engine: Engine,
+        ast: AST,
+        scope: Scope<'static>,
+        machine: Rc<RefCell<Machine>>,
+        recorder: Rc<RefCell<Recorder>>,
+        on_frame: bool,
+    }
+
+    impl Script {
+        pub fn load(path: &Path, chip8: &mut Chip8) -> Result<Script, String> {
+            let machine = Rc::new(RefCell::new(Machine::default()));
+            let engine = create_engine(&machine);
+            let ast = engine
+                .compile_file(path.to_path_buf())
+                .map_err(|e| format!("couldn't load script {}: {}", path.display(), e))?;
+            let defines = |name: &str| ast.iter_functions().any(|f| f.name == name);
+            let recorder = Rc::new(RefCell::new(Recorder {
+                instructions: defines("on_instruction"),
+                memory_writes: defines("on_memory_write"),
+                events: Vec::new(),
+            }));
+            let on_frame = defines("on_frame");
+            if recorder.borrow().instructions || recorder.borrow().memory_writes {
+                chip8.add_hook(Box::new(recorder.clone()));
+            }
+
+            let mut script = Script {
+                engine,
+                ast,
+                scope: Scope::new(),
+                machine,
+                recorder,
+                on_frame,
+            };
+            script.machine.borrow_mut().load(chip8);
+            let result = script
+                .engine
+                .run_ast_with_scope(&mut script.scope, &script.ast);
+            script.machine.borrow_mut().apply(chip8);
+            result.map_err(|e| format!("script error: {}", e))?;
+            Ok(script)
+        }
+
+        pub fn on_frame(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+            if self.on_frame {
+                self.call(chip8, "on_frame", ())?;
+            }
+            Ok(())
+        }
+
+        // report the instructions and memory writes of the cycles that ran since the last call
+        pub fn after_cycle(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+            let events: Vec<Event> = self.recorder.borrow_mut().events.drain(..).collect();
+            for event in events {
+                match event {
+                    Event::Instruction(pc, opcode) => {
+                        self.call(chip8, "on_instruction", (pc as i64, opcode as i64))?
+                    }
+                    Event::MemoryWrite(address, value) => {
+                        self.call(chip8, "on_memory_write", (address as i64, value as i64))?
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        fn call(
+            &mut self,
+            chip8: &mut Chip8,
+            name: &str,
+            args: impl FuncArgs,
+        ) -> Result<(), String> {
+            self.machine.borrow_mut().load(chip8);
+            let options = CallFnOptions::new().eval_ast(false).rewind_scope(false);
+            let result = self.engine.call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                name,
+                args,
+            );
+            self.machine.borrow_mut().apply(chip8);
+            result
+                .map(|_| ())
+                .map_err(|e| format!("script error in {}: {}", name, e))
+        }
+    }
+
+    fn create_engine(machine: &Rc<RefCell<Machine>>) -> Engine {
+        let mut engine = Engine::new();
+        let m = machine.clone();
+        engine.register_fn("peek", move |address: i64| -> i64 {
+            let machine = m.borrow();
+            machine.memory[address as usize % machine.memory.len()] as i64
+        });
+        let m = machine.clone();
+        engine.register_fn("poke", move |address: i64, value: i64| {
+            let mut machine = m.borrow_mut();
+            let length = machine.memory.len();
+            machine.memory[address as usize % length] = value as u8;
+        });
+        let m = machine.clone();
+        engine.register_fn("reg", move |x: i64| -> i64 {
+            m.borrow().registers[x as usize & 0xF] as i64
+        });
+        let m = machine.clone();
+        engine.register_fn("set_reg", move |x: i64, value: i64| {
+            m.borrow_mut().registers[x as usize & 0xF] = value as u8;
+        });
+        let m = machine.clone();
+        engine.register_fn("pc", move || -> i64 { m.borrow().pc as i64 });
+        let m = machine.clone();
+        engine.register_fn("index", move || -> i64 { m.borrow().index as i64 });
+        let m = machine.clone();
+        engine.register_fn("press", move |key: i64| {
+            m.borrow_mut().keys.push((key as u8, true));
+        });
+        let m = machine.clone();
+        engine.register_fn("release", move |key: i64| {
+            m.borrow_mut().keys.push((key as u8, false));
+        });
+        let m = machine.clone();
+        engine.register_fn("key_down", move |key: i64| -> bool {
+            m.borrow().held[key as usize & 0xF]
+        });
+        engine
+    }
+}
+
+// same interface when built without the feature, so the frontend doesn't need cfgs of its own
+#[cfg(not(feature = "scripting"))]
+pub enum Script {}
+
+#[cfg(not(feature = "scripting"))]
+impl Script {
+    pub fn load(path: &std::path::Path, _chip8: &mut Chip8) -> Result<Script, String> {
+        Err(format!(
+            "can't run {}: built without the \"scripting\" feature",
+            path.display()
+        ))
+    }
+
+    pub fn on_frame(&mut self, _chip8: &mut Chip8) -> Result<(), String> {
+        match *self {}
+    }
+
+    pub fn after_cycle(&mut self, _chip8: &mut Chip8) -> Result<(), String> {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+    use std::fs;
+
+    fn load(name: &str, source: &str, chip8: &mut Chip8) -> Result<Script, String> {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, source).unwrap();
+        let script = Script::load(&path, chip8);
+        fs::remove_file(&path).unwrap();
+        script
+    }
+
+    #[test]
+    fn test_script_api() {
+        let mut chip8 = create_chip8();
+        // I = 0x300, V0 = 7, store V0, loop
+        chip8
+            .load_rom_bytes(&[0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0x12, 0x06])
+            .unwrap();
+        let source = "
+            poke(0x400, 0x42);
+            fn on_frame() { set_reg(5, peek(0x400)); if key_down(0xA) { set_reg(7, 1); } press(0xA); }
+            fn on_instruction(pc, opcode) { poke(0x500 + pc - 0x200, opcode >> 8); }
+            fn on_memory_write(address, value) { set_reg(6, address - 0x300 + value); }
+        ";
+        let mut script = load("chip8_script_test.rhai", source, &mut chip8).unwrap();
+        assert_eq!(chip8.memory()[0x400], 0x42);
+
+        script.on_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.registers()[5], 0x42);
+        assert!(chip8.keys()[0xA]);
+        // pressed by the last call
+        script.on_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.registers()[7], 1);
+
+        for _ in 0..3 {
+            chip8.emulate_cycle().unwrap();
+            script.after_cycle(&mut chip8).unwrap();
+        }
+        assert_eq!(&chip8.memory()[0x500..0x505], &[0xA3, 0, 0x60, 0, 0xF0]);
+        assert_eq!(chip8.registers()[6], 7);
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let mut chip8 = create_chip8();
+        assert!(load("chip8_script_syntax.rhai", "fn (", &mut chip8).is_err());
+        let mut script = load(
+            "chip8_script_fail.rhai",
+            "fn on_frame() { throw \"boom\"; }",
+            &mut chip8,
+        )
+        .unwrap();
+        assert!(script.on_frame(&mut chip8).unwrap_err().contains("boom"));
+    }
+}
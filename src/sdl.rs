@@ -0,0 +1,604 @@
+use crate::audio::Beeper;
+use crate::config::Settings;
+use crate::display::{self, Scaling, Screen};
+use crate::heatmap;
+use crate::keymaps::KeymapPicker;
+use crate::menu::Menu;
+use crate::overlay::{self, Stats};
+use crate::slots::SlotPicker;
+use crate::sprites::SpriteViewer;
+use chip_8::frontend::{AudioSink, Display, InputEvent, InputSource, Rgb, SaveSlot};
+use chip_8::input::HostKey;
+use chip_8::Chip8;
+
+use sdl2::audio::AudioDevice;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::{FullscreenType, WindowContext};
+use sdl2::EventPump;
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing::{info, warn};
+
+// the window, with the filters from display.rs, the F2 overlay, the F12 HUD and the F10 grid
+pub struct SdlDisplay<'a> {
+    canvas: WindowCanvas,
+    texture: Texture<'a>,
+    screen: Screen,
+    foreground: Color,
+    background: Color,
+    scaling: Scaling,
+    stats: Stats,
+    show_stats: bool,
+    show_hud: bool,
+    grid: bool,
+    // drawn once the window has been touched
+    touch_keypad: bool,
+    // the right hand machine's screen in compare mode
+    compare: Option<(Screen, Texture<'a>)>,
+}
+
+impl<'a> SdlDisplay<'a> {
+    pub fn new(
+        mut canvas: WindowCanvas,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        settings: &Settings,
+    ) -> Result<SdlDisplay<'a>, String> {
+        let texture = create_texture(texture_creator)?;
+        canvas.set_draw_color(settings.background);
+        canvas.clear();
+        canvas.present();
+        Ok(SdlDisplay {
+            canvas,
+            texture,
+            screen: Screen::new(settings.filter, settings.blend, settings.blend_mode)
+                .persistence(settings.persistence),
+            foreground: settings.foreground,
+            background: settings.background,
+            scaling: settings.scaling,
+            stats: Stats::new(Instant::now()),
+            show_stats: false,
+            show_hud: false,
+            grid: settings.grid,
+            touch_keypad: false,
+            compare: None,
+        })
+    }
+
+    // room for a second screen, see present_pair
+    pub fn enable_compare(
+        &mut self,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        settings: &Settings,
+    ) -> Result<(), String> {
+        let screen = Screen::new(settings.filter, settings.blend, settings.blend_mode)
+            .persistence(settings.persistence);
+        self.compare = Some((screen, create_texture(texture_creator)?));
+        Ok(())
+    }
+
+    pub fn draw_menu(&mut self, menu: &mut Menu) -> Result<(), String> {
+        menu.draw(&mut self.canvas, self.foreground, self.background)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    // after another ROM was loaded with its own settings
+    pub fn configure(&mut self, settings: &Settings) {
+        self.screen = Screen::new(settings.filter, settings.blend, settings.blend_mode)
+            .persistence(settings.persistence);
+        self.foreground = settings.foreground;
+        self.background = settings.background;
+        self.scaling = settings.scaling;
+        self.grid = settings.grid;
+    }
+}
+
+impl Display for SdlDisplay<'_> {
+    fn present(&mut self, chip8: &Chip8) -> Result<(), String> {
+        self.stats.count_frame();
+        self.screen.update(chip8);
+        let (output_width, output_height) = self.canvas.output_size()?;
+        let viewport = display::viewport(
+            output_width,
+            output_height,
+            self.screen.size(),
+            self.scaling,
+        );
+        // the letterbox bars are left black rather than in the background color
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.screen.draw(
+            &mut self.canvas,
+            &mut self.texture,
+            viewport,
+            self.foreground,
+            self.background,
+        )?;
+        if self.grid {
+            display::draw_grid(&mut self.canvas, viewport, self.screen.size())?;
+        }
+        if self.touch_keypad {
+            overlay::draw_touch_keypad(&mut self.canvas, self.foreground)?;
+        }
+        let scale_factor = viewport.width() / chip_8::DISPLAY_WIDTH as u32;
+        let pixel_size = (scale_factor / 3).max(1);
+        if self.show_stats {
+            overlay::draw_stats(
+                &mut self.canvas,
+                &self.stats,
+                chip8,
+                pixel_size,
+                self.foreground,
+                self.background,
+            )?;
+        }
+        if self.show_hud {
+            overlay::draw_hud(
+                &mut self.canvas,
+                chip8,
+                pixel_size,
+                self.foreground,
+                self.background,
+            )?;
+        }
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn present_pair(&mut self, left: &Chip8, right: &Chip8) -> Result<(), String> {
+        let (right_screen, right_texture) = match &mut self.compare {
+            Some(compare) => compare,
+            None => return self.present(left),
+        };
+        self.stats.count_frame();
+        self.screen.update(left);
+        right_screen.update(right);
+        let (output_width, output_height) = self.canvas.output_size()?;
+        let viewports = display::column_viewports(
+            output_width,
+            output_height,
+            self.screen.size(),
+            2,
+            self.scaling,
+        );
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.screen.draw(
+            &mut self.canvas,
+            &mut self.texture,
+            viewports[0],
+            self.foreground,
+            self.background,
+        )?;
+        right_screen.draw(
+            &mut self.canvas,
+            right_texture,
+            viewports[1],
+            self.foreground,
+            self.background,
+        )?;
+        if self.grid {
+            for viewport in viewports {
+                display::draw_grid(&mut self.canvas, viewport, self.screen.size())?;
+            }
+        }
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn present_sprites(
+        &mut self,
+        chip8: &Chip8,
+        address: usize,
+        height: usize,
+    ) -> Result<(), String> {
+        SpriteViewer { address, height }.draw(
+            &mut self.canvas,
+            chip8.memory(),
+            self.foreground,
+            self.background,
+        )?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn present_keymaps(&mut self, _chip8: &Chip8, selected: usize) -> Result<(), String> {
+        KeymapPicker::draw(&mut self.canvas, selected, self.foreground, self.background)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn present_slots(
+        &mut self,
+        _chip8: &Chip8,
+        slots: &[Option<SaveSlot>],
+        saving: bool,
+    ) -> Result<(), String> {
+        SlotPicker::draw(
+            &mut self.canvas,
+            slots,
+            saving,
+            self.foreground,
+            self.background,
+        )?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn present_heatmap(&mut self, _chip8: &Chip8, colors: &[Rgb]) -> Result<(), String> {
+        let colors: Vec<Color> = colors
+            .iter()
+            .map(|color| Color::RGB(color.r, color.g, color.b))
+            .collect();
+        heatmap::draw(&mut self.canvas, &colors, self.foreground, self.background)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn end_frame(&mut self, instructions: u64) -> bool {
+        self.stats.count_instructions(instructions as u32);
+        self.stats.update(Instant::now());
+        // the overlay and the HUD show the timers and registers and the filters change the
+        // picture between frames, keep them all current
+        let compare_changed = match &mut self.compare {
+            Some((screen, _)) => screen.tick(),
+            None => false,
+        };
+        self.screen.tick() | compare_changed || self.show_stats || self.show_hud
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Err(e) = self.canvas.window_mut().set_title(title) {
+            warn!("couldn't set the window title: {}", e);
+        }
+    }
+
+    fn show_error(&mut self, message: &str) {
+        let _ = self
+            .canvas
+            .window_mut()
+            .set_title(&format!("chip8 emulator - {}", message));
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen_type = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(e) = window.set_fullscreen(fullscreen_type) {
+            warn!("couldn't toggle fullscreen: {}", e);
+        }
+    }
+
+    fn toggle_overlay(&mut self) {
+        self.show_stats = !self.show_stats;
+    }
+
+    fn toggle_hud(&mut self) {
+        self.show_hud = !self.show_hud;
+    }
+
+    fn toggle_grid(&mut self) {
+        self.grid = !self.grid;
+    }
+
+    fn show_touch_keypad(&mut self) {
+        self.touch_keypad = true;
+    }
+
+    fn pixel_at(&self, x: i32, y: i32) -> Option<(usize, usize)> {
+        // clicks come in window coordinates, which on high-DPI displays are fewer than pixels
+        let (output_width, output_height) = self.canvas.output_size().ok()?;
+        let (window_width, window_height) = self.canvas.window().size();
+        let x = x * output_width as i32 / window_width.max(1) as i32;
+        let y = y * output_height as i32 / window_height.max(1) as i32;
+        let viewport = display::viewport(
+            output_width,
+            output_height,
+            self.screen.size(),
+            self.scaling,
+        );
+        display::pixel_at(viewport, self.screen.size(), x, y)
+    }
+
+    fn cycle_scaling(&mut self) {
+        self.scaling = self.scaling.next();
+        info!("scaling: {}", self.scaling.name());
+    }
+}
+
+// big enough for every variant, only the top left part is used for smaller ones
+fn create_texture(texture_creator: &TextureCreator<WindowContext>) -> Result<Texture<'_>, String> {
+    texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            chip_8::DISPLAY_WIDTH as u32,
+            chip_8::HIRES_DISPLAY_HEIGHT as u32,
+        )
+        .map_err(|e| e.to_string())
+}
+
+// the beeper, or nothing at all with --no-audio
+pub struct SdlAudio {
+    device: Option<AudioDevice<Beeper>>,
+    playing: bool,
+    muted: bool,
+}
+
+impl SdlAudio {
+    // the device runs from here on, the beeper fades the sound in and out
+    pub fn new(device: Option<AudioDevice<Beeper>>) -> SdlAudio {
+        if let Some(device) = &device {
+            device.resume();
+        }
+        SdlAudio {
+            device,
+            playing: false,
+            muted: false,
+        }
+    }
+
+    fn update_gate(&mut self) {
+        if let Some(device) = &mut self.device {
+            let mut beeper = device.lock();
+            if self.playing && !self.muted {
+                beeper.open_gate();
+            } else {
+                beeper.close_gate();
+            }
+        }
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+        self.update_gate();
+    }
+
+    fn set_sound_timer(&mut self, ticks: u8) {
+        if let Some(device) = &mut self.device {
+            device.lock().set_sound_timer(ticks);
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: [u8; 16], playback_rate: f32) {
+        if let Some(device) = &mut self.device {
+            device.lock().set_pattern(pattern, playback_rate);
+        }
+    }
+
+    fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        self.update_gate();
+    }
+}
+
+// SDL's key to the frontend-neutral one keymaps, macros and the menus go by
+pub fn host_key(keycode: Keycode) -> HostKey {
+    match keycode {
+        Keycode::A => HostKey::A,
+        Keycode::B => HostKey::B,
+        Keycode::C => HostKey::C,
+        Keycode::D => HostKey::D,
+        Keycode::E => HostKey::E,
+        Keycode::F => HostKey::F,
+        Keycode::G => HostKey::G,
+        Keycode::H => HostKey::H,
+        Keycode::I => HostKey::I,
+        Keycode::J => HostKey::J,
+        Keycode::K => HostKey::K,
+        Keycode::L => HostKey::L,
+        Keycode::M => HostKey::M,
+        Keycode::N => HostKey::N,
+        Keycode::O => HostKey::O,
+        Keycode::P => HostKey::P,
+        Keycode::Q => HostKey::Q,
+        Keycode::R => HostKey::R,
+        Keycode::S => HostKey::S,
+        Keycode::T => HostKey::T,
+        Keycode::U => HostKey::U,
+        Keycode::V => HostKey::V,
+        Keycode::W => HostKey::W,
+        Keycode::X => HostKey::X,
+        Keycode::Y => HostKey::Y,
+        Keycode::Z => HostKey::Z,
+        Keycode::Num0 => HostKey::Num0,
+        Keycode::Num1 => HostKey::Num1,
+        Keycode::Num2 => HostKey::Num2,
+        Keycode::Num3 => HostKey::Num3,
+        Keycode::Num4 => HostKey::Num4,
+        Keycode::Num5 => HostKey::Num5,
+        Keycode::Num6 => HostKey::Num6,
+        Keycode::Num7 => HostKey::Num7,
+        Keycode::Num8 => HostKey::Num8,
+        Keycode::Num9 => HostKey::Num9,
+        Keycode::F1 => HostKey::F1,
+        Keycode::F2 => HostKey::F2,
+        Keycode::F3 => HostKey::F3,
+        Keycode::F4 => HostKey::F4,
+        Keycode::F5 => HostKey::F5,
+        Keycode::F6 => HostKey::F6,
+        Keycode::F7 => HostKey::F7,
+        Keycode::F8 => HostKey::F8,
+        Keycode::F9 => HostKey::F9,
+        Keycode::F10 => HostKey::F10,
+        Keycode::F11 => HostKey::F11,
+        Keycode::F12 => HostKey::F12,
+        Keycode::Kp0 => HostKey::Kp0,
+        Keycode::Kp1 => HostKey::Kp1,
+        Keycode::Kp2 => HostKey::Kp2,
+        Keycode::Kp3 => HostKey::Kp3,
+        Keycode::Kp4 => HostKey::Kp4,
+        Keycode::Kp5 => HostKey::Kp5,
+        Keycode::Kp6 => HostKey::Kp6,
+        Keycode::Kp7 => HostKey::Kp7,
+        Keycode::Kp8 => HostKey::Kp8,
+        Keycode::Kp9 => HostKey::Kp9,
+        Keycode::KpPlus => HostKey::KpPlus,
+        Keycode::KpMinus => HostKey::KpMinus,
+        Keycode::KpMultiply => HostKey::KpMultiply,
+        Keycode::KpDivide => HostKey::KpDivide,
+        Keycode::KpPeriod => HostKey::KpPeriod,
+        Keycode::KpEnter => HostKey::KpEnter,
+        Keycode::Up => HostKey::Up,
+        Keycode::Down => HostKey::Down,
+        Keycode::Left => HostKey::Left,
+        Keycode::Right => HostKey::Right,
+        Keycode::Home => HostKey::Home,
+        Keycode::End => HostKey::End,
+        Keycode::PageUp => HostKey::PageUp,
+        Keycode::PageDown => HostKey::PageDown,
+        Keycode::Insert => HostKey::Insert,
+        Keycode::Delete => HostKey::Delete,
+        Keycode::Return => HostKey::Return,
+        Keycode::Escape => HostKey::Escape,
+        Keycode::Backspace => HostKey::Backspace,
+        Keycode::Tab => HostKey::Tab,
+        Keycode::Space => HostKey::Space,
+        Keycode::LShift => HostKey::LShift,
+        Keycode::RShift => HostKey::RShift,
+        Keycode::LCtrl => HostKey::LCtrl,
+        Keycode::RCtrl => HostKey::RCtrl,
+        Keycode::LAlt => HostKey::LAlt,
+        Keycode::RAlt => HostKey::RAlt,
+        Keycode::Minus => HostKey::Minus,
+        Keycode::Equals => HostKey::Equals,
+        Keycode::Plus => HostKey::Plus,
+        Keycode::LeftBracket => HostKey::LeftBracket,
+        Keycode::RightBracket => HostKey::RightBracket,
+        Keycode::Backslash => HostKey::Backslash,
+        Keycode::Semicolon => HostKey::Semicolon,
+        Keycode::Quote => HostKey::Quote,
+        Keycode::Comma => HostKey::Comma,
+        Keycode::Period => HostKey::Period,
+        Keycode::Slash => HostKey::Slash,
+        Keycode::Backquote => HostKey::Backquote,
+        other => HostKey::Other(other as i32),
+    }
+}
+
+pub struct SdlInput {
+    event_pump: EventPump,
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump) -> SdlInput {
+        SdlInput { event_pump }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        self.event_pump
+            .poll_iter()
+            .filter_map(|event| match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => Some(InputEvent::Quit),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleOverlay),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleSprites),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::CycleScaling),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::Reset),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleFrameAdvance),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleSlots),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleHeatmap),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleGrid),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleKeymaps),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => Some(InputEvent::ToggleHud),
+                // held down it keeps stepping, at the key repeat rate
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    ..
+                } => Some(InputEvent::AdvanceFrame),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    Some(InputEvent::ToggleFullscreen)
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => Some(InputEvent::Redraw),
+                Event::DropFile { filename, .. } => Some(InputEvent::Open(PathBuf::from(filename))),
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => Some(InputEvent::Click(x, y)),
+                Event::FingerDown {
+                    finger_id, x, y, ..
+                }
+                | Event::FingerMotion {
+                    finger_id, x, y, ..
+                } => Some(InputEvent::Finger(finger_id, x, y)),
+                Event::FingerUp { finger_id, .. } => Some(InputEvent::FingerUp(finger_id)),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: true,
+                    ..
+                } => Some(InputEvent::KeyRepeat(host_key(keycode))),
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => Some(InputEvent::KeyDown(host_key(keycode))),
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => Some(InputEvent::KeyUp(host_key(keycode))),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,419 @@
+use crate::calibrate::SpeedCalibrator;
+use crate::config::{Config, Settings};
+use crate::control::ControlSocket;
+use crate::crash::CrashReporter;
+use crate::debug_server::DebugServer;
+use crate::framedump::FrameDump;
+use crate::keymaps::KeymapPicker;
+use crate::netplay::Netplay;
+use crate::patches::Patches;
+use crate::persistence::{Saves, Thumbnail};
+use crate::profiler::Profiler;
+use crate::replay::{Player, Recorder};
+use crate::scripting::Script;
+use crate::slots::{SlotAction, SlotPicker};
+use crate::sprites::SpriteViewer;
+use crate::watch::Watcher;
+use crate::RunArgs;
+use crate::{rom, thumbnail};
+
+use std::cell::RefCell;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info, warn};
+
+use chip_8::savestate;
+use chip_8::{Chip8, Debugger, Keypad, Layout, Macros, Symbols, Touches, Variant, RPL_FLAG_COUNT};
+
+// shown when started without a ROM: "CHIP-8" in the middle of the screen
+pub const SPLASH_ROM: [u8; 56] = [
+    0x00, 0xE0, // clear the screen
+    0xA2, 0x1A, // I = glyphs
+    0x60, 0x0C, // V0 = x
+    0x61, 0x0D, // V1 = y
+    0x62, 0x05, // V2 = glyph size
+    0x63, 0x06, // V3 = glyphs left
+    0xD0, 0x15, // draw a glyph
+    0x70, 0x07, // move right
+    0xF2, 0x1E, // next glyph
+    0x73, 0xFF, // V3 -= 1
+    0x33, 0x00, // done when V3 == 0
+    0x12, 0x0C, // otherwise draw the next one
+    0x12, 0x18, // done, loop forever
+    0x70, 0x80, 0x80, 0x80, 0x70, // C
+    0x88, 0x88, 0xF8, 0x88, 0x88, // H
+    0xF8, 0x20, 0x20, 0x20, 0xF8, // I
+    0xF0, 0x88, 0xF0, 0x80, 0x80, // P
+    0x00, 0x00, 0x70, 0x00, 0x00, // -
+    0x70, 0x88, 0x70, 0x88, 0x70, // 8
+];
+
+// the machine and everything attached to it, whatever frontend shows it
+pub struct Session {
+    pub chip8: Chip8,
+    // what's running, None for the splash screen
+    pub rom: Option<Vec<u8>>,
+    pub settings: Settings,
+    pub saves: Option<(Saves, [u8; RPL_FLAG_COUNT])>,
+    pub patches: Patches,
+    pub script: Option<Script>,
+    pub debugger: Debugger,
+    pub debug_server: Option<DebugServer>,
+    pub control: Option<ControlSocket>,
+    // in frame advance mode, the frames still to run
+    pub frame_advance: Option<u32>,
+    pub netplay: Option<Netplay>,
+    // with --watch, the ROM file to reload when it changes
+    pub watch: Option<Watcher>,
+    // the sprite viewer while it's open
+    pub sprites: Option<SpriteViewer>,
+    // the save slot picker while it's open
+    pub slots: Option<SlotPicker>,
+    // the keymap picker while it's open
+    pub keymaps: Option<KeymapPicker>,
+    // with --record and --replay
+    pub recorder: Option<Recorder>,
+    pub player: Option<Player>,
+    pub autosave: Option<Autosave>,
+    // the host keys held down, to press and release the keypad's once
+    pub keypad: Keypad,
+    // fingers on the touchscreen keypad
+    pub touches: Touches,
+    // the config's [macros], pressing keypad keys frame by frame
+    pub macros: Macros,
+    pub frame_dump: Option<FrameDump>,
+    pub crash_reporter: Option<CrashReporter>,
+    pub profiler: Option<Rc<RefCell<Profiler>>>,
+    // hooked on once a ROM runs with auto_speed, and kept for the ones after
+    pub calibrator: Option<Rc<RefCell<SpeedCalibrator>>>,
+    // the memory heatmap is showing instead of the screen
+    pub heatmap: bool,
+}
+
+// with --autosave, how often and when it was last done
+pub struct Autosave {
+    pub interval: Duration,
+    pub last: Instant,
+}
+
+impl Autosave {
+    pub fn due(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last) < self.interval {
+            return false;
+        }
+        self.last = now;
+        true
+    }
+}
+
+impl Session {
+    // reset the machine and run another ROM with its own settings
+    pub fn open(&mut self, path: &Path, config: &Config, args: &RunArgs) -> Result<(), String> {
+        let rom = rom::read_rom(path)?;
+        let rom_settings = resolve_settings(config, Some(path), Some(&rom), args)?;
+        check_rom(
+            &path.display().to_string(),
+            &rom,
+            rom_settings.variant,
+            rom_settings.layout,
+        )
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        // while the range is still the old ROM's
+        self.persist_memory();
+        // the window is created once, so these can't change
+        self.settings = Settings {
+            scale_factor: self.settings.scale_factor,
+            fullscreen: self.settings.fullscreen,
+            vsync: self.settings.vsync,
+            ..rom_settings
+        };
+        // patches and symbols are made for one particular ROM
+        self.patches = Patches::default();
+        self.debugger.set_symbols(Symbols::default());
+        self.load(Some(rom));
+        debug!(
+            rom = %path.display(),
+            variant = self.settings.variant.name(),
+            load = self.settings.layout.load,
+            start = self.settings.layout.start,
+            speed = self.settings.speed,
+            quirks = ?self.settings.quirks,
+            "opened"
+        );
+        if self.watch.is_some() {
+            self.watch = Watcher::new(path).ok();
+        }
+        Ok(())
+    }
+
+    // the ROM was saved again: run the new version from the start with the same settings
+    pub fn reload(&mut self, path: &Path) -> Result<(), String> {
+        let rom = rom::read_rom(path)?;
+        check_rom(
+            &path.display().to_string(),
+            &rom,
+            self.settings.variant,
+            self.settings.layout,
+        )
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.persist_memory();
+        self.load(Some(rom));
+        info!("reloaded {}", path.display());
+        Ok(())
+    }
+
+    // run the same ROM again from the start, with its patches
+    pub fn restart(&mut self) {
+        self.persist_memory();
+        let rom = self.rom.take();
+        self.load(rom);
+    }
+
+    // reset the machine and run `rom`, or the splash screen if there's none or it doesn't load
+    pub fn load(&mut self, rom: Option<Vec<u8>>) {
+        // before reset clears them
+        persist_flags(self.saves.as_mut(), &self.chip8);
+        if self.autosave.is_some() {
+            save_state(self.saves.as_ref(), &self.chip8);
+        }
+        let chip8 = &mut self.chip8;
+        chip8.reset();
+        self.debugger.clear_history();
+        chip8.quirks = self.settings.quirks;
+        self.keypad.set_keymap(self.settings.keymap.clone());
+        self.macros = Macros::new(self.settings.macros.clone());
+        chip8.set_variant(self.settings.variant);
+        chip8.set_layout(self.settings.layout);
+        chip8.set_speed(self.settings.speed);
+        chip8.set_timing(self.settings.timing);
+        self.saves = None;
+        self.rom = rom.filter(|rom| match chip8.load_rom_bytes(rom) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("couldn't load ROM: {}", e);
+                false
+            }
+        });
+        match &self.rom {
+            Some(rom) => self.saves = open_saves(&self.settings, Some(rom), chip8),
+            // the splash screen is made to run where the variant puts ROMs
+            None => {
+                chip8.set_layout(self.settings.variant.layout());
+                chip8.load_rom_bytes(&SPLASH_ROM).unwrap()
+            }
+        }
+        self.patches.apply(chip8);
+        self.calibrate();
+    }
+
+    // start calibrating the speed over again from the configured one, if it's to be
+    pub fn calibrate(&mut self) {
+        if self.settings.auto_speed && self.calibrator.is_none() {
+            let calibrator = Rc::new(RefCell::new(SpeedCalibrator::new()));
+            self.chip8.add_hook(Box::new(calibrator.clone()));
+            self.calibrator = Some(calibrator);
+        }
+        if let Some(calibrator) = &self.calibrator {
+            let base = self.settings.auto_speed.then_some(self.settings.speed);
+            calibrator.borrow_mut().reset(base);
+        }
+    }
+
+    // write out the RAM range the ROM keeps between runs, if it has one
+    pub fn persist_memory(&self) {
+        if let (Some((saves, _)), Some(range)) = (&self.saves, &self.settings.persist_memory) {
+            if let Err(e) = saves.save_memory(&self.chip8.memory()[range.clone()]) {
+                warn!("{}", e);
+            }
+        }
+    }
+}
+
+// refuse what can't be a ROM and say what we know about the rest
+pub fn check_rom(name: &str, rom: &[u8], variant: Variant, layout: Layout) -> Result<(), String> {
+    let info = rom::validate_rom(rom, variant, layout)?;
+    info!(
+        "{}: {} bytes, sha1 {}, looks like {}",
+        name, info.size, info.sha1, info.guessed_variant
+    );
+    if layout != variant.layout() {
+        info!(
+            "{}: loaded at {:#05X}, starting at {:#05X}",
+            name, layout.load, layout.start
+        );
+    }
+    for warning in &info.warnings {
+        warn!("{}", warning);
+    }
+    Ok(())
+}
+
+// the ROM's saves with its flags and saved RAM loaded into the machine, and the flags as last
+// saved. nothing is persisted for the splash screen
+pub fn open_saves(
+    settings: &Settings,
+    rom: Option<&[u8]>,
+    chip8: &mut Chip8,
+) -> Option<(Saves, [u8; RPL_FLAG_COUNT])> {
+    let saves = Saves::new(settings.save_dir.as_ref()?, rom?);
+    match saves.load_flags() {
+        Ok(Some(flags)) => chip8.rpl_flags = flags,
+        Ok(None) => {}
+        Err(e) => warn!("{}", e),
+    }
+    if let Some(range) = &settings.persist_memory {
+        restore_memory(&saves, range.clone(), chip8);
+    }
+    Some((saves, chip8.rpl_flags))
+}
+
+// the ROM's autosave back in the machine
+pub fn resume(
+    saves: Option<&(Saves, [u8; RPL_FLAG_COUNT])>,
+    settings: &Settings,
+    chip8: &mut Chip8,
+) {
+    let saves = match saves {
+        Some((saves, _)) => saves,
+        None => return,
+    };
+    match saves.load_state() {
+        Ok(Some(state)) => {
+            if let Err(e) = restore_state(saves, &state, settings, chip8) {
+                warn!("couldn't resume: {}", e);
+            }
+        }
+        Ok(None) => info!("no autosave to resume from, starting over"),
+        Err(e) => warn!("{}", e),
+    }
+}
+
+// a saved state back in the machine. it has to be for the variant the ROM runs as now, the
+// quirks and speed are the ones configured now
+fn restore_state(
+    saves: &Saves,
+    state: &[u8],
+    settings: &Settings,
+    chip8: &mut Chip8,
+) -> Result<(), String> {
+    let (header, snapshot) = savestate::read(state)?;
+    if header.rom_hash.as_deref() != Some(saves.rom_hash()) {
+        return Err("the save is for another ROM".to_string());
+    }
+    if snapshot.variant != settings.variant {
+        return Err(format!(
+            "the save ran as {}, not {}",
+            snapshot.variant.name(),
+            settings.variant.name()
+        ));
+    }
+    // the flags may have been saved since by another run
+    let rpl_flags = chip8.rpl_flags;
+    chip8
+        .restore(&snapshot)
+        .map_err(|e| format!("the save has {}", e))?;
+    chip8.rpl_flags = rpl_flags;
+    chip8.quirks = settings.quirks;
+    chip8.set_speed(settings.speed);
+    Ok(())
+}
+
+// what was picked in the slot picker, returns true when it's done and can close
+pub fn use_slot(action: SlotAction, saves: &Saves, settings: &Settings, chip8: &mut Chip8) -> bool {
+    match action {
+        SlotAction::Save(slot) => {
+            let state = savestate::write(&chip8.snapshot(), Some(saves.rom_hash()), true);
+            let thumbnail = thumbnail::encode_png(chip8.framebuffer());
+            match saves
+                .save_slot(slot, &state)
+                .and_then(|_| saves.save_thumbnail(Thumbnail::Slot(slot), &thumbnail))
+            {
+                Ok(()) => info!("saved to slot {}", slot),
+                Err(e) => warn!("{}", e),
+            }
+            true
+        }
+        SlotAction::Load(slot) => match saves.load_slot(slot) {
+            Ok(Some((state, _))) => match restore_state(saves, &state, settings, chip8) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("couldn't load slot {}: {}", slot, e);
+                    false
+                }
+            },
+            Ok(None) => {
+                warn!("slot {} is empty", slot);
+                false
+            }
+            Err(e) => {
+                warn!("{}", e);
+                false
+            }
+        },
+    }
+}
+
+// snapshot the machine for --autosave, nothing is saved for the splash screen
+pub fn save_state(saves: Option<&(Saves, [u8; RPL_FLAG_COUNT])>, chip8: &Chip8) {
+    if let Some((saves, _)) = saves {
+        let state = savestate::write(&chip8.snapshot(), Some(saves.rom_hash()), true);
+        let thumbnail = thumbnail::encode_png(chip8.framebuffer());
+        if let Err(e) = saves
+            .save_state(&state)
+            .and_then(|_| saves.save_thumbnail(Thumbnail::State, &thumbnail))
+        {
+            warn!("{}", e);
+        } else {
+            debug!(rom = saves.rom_hash(), "state saved");
+        }
+    }
+}
+
+// a save from before the range was changed only fills what still overlaps
+fn restore_memory(saves: &Saves, range: Range<usize>, chip8: &mut Chip8) {
+    match saves.load_memory() {
+        Ok(Some(memory)) => {
+            for (address, value) in range.zip(memory) {
+                chip8.set_memory(address, value);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("{}", e),
+    }
+}
+
+// write the flags out if the ROM changed them
+pub fn persist_flags(saves: Option<&mut (Saves, [u8; RPL_FLAG_COUNT])>, chip8: &Chip8) {
+    if let Some((saves, saved_flags)) = saves {
+        if chip8.rpl_flags != *saved_flags {
+            if let Err(e) = saves.save_flags(&chip8.rpl_flags) {
+                warn!("{}", e);
+            }
+            *saved_flags = chip8.rpl_flags;
+        }
+    }
+}
+
+// global config, then the ROM's overrides, then the command line
+pub fn resolve_settings(
+    config: &Config,
+    rom_path: Option<&Path>,
+    rom: Option<&[u8]>,
+    args: &RunArgs,
+) -> Result<Settings, String> {
+    let mut config = config.for_rom(rom_path, rom);
+    config.merge(&args.config_overrides());
+    let mut settings = config.resolve()?;
+    // the title calls the ROM by its file name if the config has no name for it
+    let file_name = rom_path
+        .and_then(Path::file_stem)
+        .map(|name| name.to_string_lossy().into_owned());
+    settings.rom_name = settings
+        .rom_name
+        .or(file_name)
+        .or_else(|| args.builtin.clone());
+    Ok(settings)
+}
@@ -0,0 +1,219 @@
+use crate::font::{self, GLYPH_HEIGHT};
+use crate::persistence::Saves;
+use chip_8::frontend::SaveSlot;
+use chip_8::savestate;
+use chip_8::HostKey;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+pub const SLOT_COUNT: usize = 10;
+// slots on a row of the picker
+const COLUMNS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlotAction {
+    Load(usize),
+    Save(usize),
+}
+
+// the F8 save slot picker: a number key loads that slot, with shift held it saves to it
+// instead. the machine is paused while it's open
+pub struct SlotPicker {
+    pub slots: Vec<Option<SaveSlot>>,
+    shift: bool,
+}
+
+impl SlotPicker {
+    pub fn new(saves: &Saves) -> SlotPicker {
+        SlotPicker {
+            slots: read_slots(saves),
+            shift: false,
+        }
+    }
+
+    pub fn saving(&self) -> bool {
+        self.shift
+    }
+
+    // None for keys that don't pick a slot
+    pub fn key_down(&mut self, host_key: HostKey) -> Option<SlotAction> {
+        if matches!(host_key, HostKey::LShift | HostKey::RShift) {
+            self.shift = true;
+            return None;
+        }
+        let slot = slot_key(host_key)?;
+        Some(if self.shift {
+            SlotAction::Save(slot)
+        } else {
+            SlotAction::Load(slot)
+        })
+    }
+
+    pub fn key_up(&mut self, host_key: HostKey) {
+        if matches!(host_key, HostKey::LShift | HostKey::RShift) {
+            self.shift = false;
+        }
+    }
+
+    pub fn draw(
+        canvas: &mut WindowCanvas,
+        slots: &[Option<SaveSlot>],
+        saving: bool,
+        foreground: Color,
+        background: Color,
+    ) -> Result<(), String> {
+        // in units of one thumbnail pixel: a title line, then the slots in keyboard order,
+        // 1 to 9 and 0, each a label over the screen it was saved with
+        let thumbnail_height = slots
+            .iter()
+            .flatten()
+            .map(|slot| slot.thumbnail.height())
+            .max()
+            .unwrap_or(chip_8::DISPLAY_HEIGHT);
+        let line_height = GLYPH_HEIGHT as usize + 2;
+        let cell_width = chip_8::DISPLAY_WIDTH + 4;
+        let cell_height = line_height + thumbnail_height + 4;
+        let rows = SLOT_COUNT.div_ceil(COLUMNS);
+        let units_wide = 2 + COLUMNS * cell_width;
+        let units_high = 2 + line_height + rows * cell_height;
+        let (width, height) = canvas.output_size()?;
+        let pixel_size = (width / units_wide as u32)
+            .min(height / units_high as u32)
+            .max(1);
+        let unit = |units: usize| (units as u32 * pixel_size) as i32;
+
+        canvas.set_draw_color(background);
+        canvas.clear();
+        let title = match saving {
+            true => "SAVE TO SLOT 0-9",
+            false => "LOAD SLOT 0-9, SHIFT SAVES",
+        };
+        font::draw_text(canvas, title, unit(2), unit(2), pixel_size, foreground)?;
+        let now = SystemTime::now();
+        for (position, slot) in (1..SLOT_COUNT).chain(0..1).enumerate() {
+            let x = 2 + position % COLUMNS * cell_width;
+            let y = 2 + line_height + position / COLUMNS * cell_height;
+            let label = match &slots[slot] {
+                Some(save) => format!("{} {}", slot, age(save.saved, now)),
+                None => format!("{} EMPTY", slot),
+            };
+            font::draw_text(canvas, &label, unit(x), unit(y), pixel_size, foreground)?;
+            let y = y + line_height;
+            canvas.set_draw_color(foreground);
+            canvas.draw_rect(Rect::new(
+                unit(x),
+                unit(y),
+                (chip_8::DISPLAY_WIDTH + 2) as u32 * pixel_size,
+                (thumbnail_height + 2) as u32 * pixel_size,
+            ))?;
+            if let Some(save) = &slots[slot] {
+                let (thumbnail_width, thumbnail_height) = save.thumbnail.size();
+                for row in 0..thumbnail_height {
+                    for column in (0..thumbnail_width).filter(|x| save.thumbnail.get(*x, row)) {
+                        canvas.fill_rect(Rect::new(
+                            unit(x + 1 + column),
+                            unit(y + 1 + row),
+                            pixel_size,
+                            pixel_size,
+                        ))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the top row of number keys or the keypad's
+fn slot_key(host_key: HostKey) -> Option<usize> {
+    let slot = match host_key {
+        HostKey::Num0 | HostKey::Kp0 => 0,
+        HostKey::Num1 | HostKey::Kp1 => 1,
+        HostKey::Num2 | HostKey::Kp2 => 2,
+        HostKey::Num3 | HostKey::Kp3 => 3,
+        HostKey::Num4 | HostKey::Kp4 => 4,
+        HostKey::Num5 | HostKey::Kp5 => 5,
+        HostKey::Num6 | HostKey::Kp6 => 6,
+        HostKey::Num7 | HostKey::Kp7 => 7,
+        HostKey::Num8 | HostKey::Kp8 => 8,
+        HostKey::Num9 | HostKey::Kp9 => 9,
+        _ => return None,
+    };
+    Some(slot)
+}
+
+// every slot, with the screen from the state saved in it. a slot that can't be read is shown
+// as empty and saving to it replaces it
+pub fn read_slots(saves: &Saves) -> Vec<Option<SaveSlot>> {
+    (0..SLOT_COUNT)
+        .map(|slot| {
+            let (state, saved) = match saves.load_slot(slot) {
+                Ok(slot) => slot?,
+                Err(e) => {
+                    warn!("{}", e);
+                    return None;
+                }
+            };
+            match savestate::read(&state) {
+                Ok((_, snapshot)) => Some(SaveSlot {
+                    saved,
+                    thumbnail: snapshot.framebuffer,
+                }),
+                Err(e) => {
+                    warn!("save slot {}: {}", slot, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// how long ago, roughly, in the few characters there's room for
+fn age(saved: SystemTime, now: SystemTime) -> String {
+    let seconds = now
+        .duration_since(saved)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    match seconds {
+        0..=59 => "NOW".to_string(),
+        60..=3599 => format!("{}M AGO", seconds / 60),
+        3600..=86399 => format!("{}H AGO", seconds / 3600),
+        _ => format!("{}D AGO", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let mut picker = SlotPicker {
+            slots: vec![None; SLOT_COUNT],
+            shift: false,
+        };
+        assert_eq!(picker.key_down(HostKey::Num3), Some(SlotAction::Load(3)));
+        assert_eq!(picker.key_down(HostKey::LShift), None);
+        assert!(picker.saving());
+        assert_eq!(picker.key_down(HostKey::Kp0), Some(SlotAction::Save(0)));
+        picker.key_up(HostKey::LShift);
+        assert_eq!(picker.key_down(HostKey::Num9), Some(SlotAction::Load(9)));
+        assert_eq!(picker.key_down(HostKey::A), None);
+    }
+
+    #[test]
+    fn test_age() {
+        let now = SystemTime::now();
+        let ago = |seconds| age(now - Duration::from_secs(seconds), now);
+        assert_eq!(ago(5), "NOW");
+        assert_eq!(ago(150), "2M AGO");
+        assert_eq!(ago(7200), "2H AGO");
+        assert_eq!(ago(3 * 86400), "3D AGO");
+        // a clock that went backwards
+        assert_eq!(age(now + Duration::from_secs(10), now), "NOW");
+    }
+}
@@ -0,0 +1,156 @@
+use chip_8::{Chip8, Hooks, Opcode, MEM_SIZE};
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use tracing::warn;
+
+// how far past the current instruction a write still lands in the code being run
+const LOOKAHEAD: usize = 8;
+// instructions shown leading up to the write
+const TRACE_LENGTH: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    // an address that ran as an instruction before
+    Executed,
+    // the current instruction or the few right after it
+    NearPc,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    // the instruction doing the write
+    pub pc: usize,
+    pub opcode: Opcode,
+    pub address: usize,
+    pub value: u8,
+    pub target: Target,
+    // addresses of the last instructions, oldest first, ending with pc
+    pub trace: Vec<usize>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let target = match self.target {
+            Target::Executed => "code that already ran",
+            Target::NearPc => "the code about to run",
+        };
+        write!(
+            f,
+            "{:#05X} {:?} wrote {:#04X} to {:#05X}, {}. trace:",
+            self.pc, self.opcode, self.value, self.address, target
+        )?;
+        for address in &self.trace {
+            write!(f, " {:#05X}", address)?;
+        }
+        Ok(())
+    }
+}
+
+// warns about self-modifying code: instructions (FX55, FX33) storing into addresses that ran
+// as code or are about to. every instruction and target pair is reported once, on stderr.
+// attach with `Chip8::add_hook`
+pub struct SmcDetector {
+    executed: Vec<bool>,
+    trace: VecDeque<usize>,
+    current: Option<(usize, Opcode)>,
+    reported: HashSet<(usize, usize)>,
+    warnings: Vec<Warning>,
+}
+
+impl SmcDetector {
+    pub fn new() -> SmcDetector {
+        SmcDetector {
+            executed: vec![false; MEM_SIZE],
+            trace: VecDeque::with_capacity(TRACE_LENGTH),
+            current: None,
+            reported: HashSet::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+}
+
+impl Hooks for SmcDetector {
+    fn before_instruction(&mut self, chip8: &Chip8, opcode: &Opcode) {
+        let pc = chip8.pc();
+        self.executed[pc] = true;
+        self.executed[(pc + 1) % MEM_SIZE] = true;
+        if self.trace.len() == TRACE_LENGTH {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(pc);
+        self.current = Some((pc, *opcode));
+    }
+
+    fn on_memory_write(&mut self, _chip8: &Chip8, address: usize, value: u8) {
+        let (pc, opcode) = match self.current {
+            Some(current) => current,
+            None => return,
+        };
+        let target = if self.executed[address] {
+            Target::Executed
+        } else if (pc..pc + 2 + LOOKAHEAD).contains(&address) {
+            Target::NearPc
+        } else {
+            return;
+        };
+        if !self.reported.insert((pc, address)) {
+            return;
+        }
+        let warning = Warning {
+            pc,
+            opcode,
+            address,
+            value,
+            target,
+            trace: self.trace.iter().copied().collect(),
+        };
+        warn!("self-modifying code: {}", warning);
+        self.warnings.push(warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_detection() {
+        let detector = Rc::new(RefCell::new(SmcDetector::new()));
+        let mut chip8 = create_chip8();
+        chip8.add_hook(Box::new(detector.clone()));
+        // store V0 over the first instruction, then somewhere no code ran, then over what's
+        // coming up, in a loop
+        chip8
+            .load_rom_bytes(&[
+                0xA2, 0x00, 0xF0, 0x55, 0xA3, 0x00, 0xF0, 0x55, 0xA2, 0x0E, 0xF0, 0x55, 0x12, 0x0A,
+            ])
+            .unwrap();
+        for _ in 0..9 {
+            chip8.emulate_cycle().unwrap();
+        }
+
+        let detector = detector.borrow();
+        let warnings = detector.warnings();
+        // the loop only reports its write once
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].pc, 0x202);
+        assert_eq!(warnings[0].address, 0x200);
+        assert_eq!(warnings[0].target, Target::Executed);
+        assert_eq!(warnings[0].trace, [0x200, 0x202]);
+        assert_eq!(
+            warnings[0].to_string(),
+            "0x202 OP_FX55(0) wrote 0x00 to 0x200, code that already ran. trace: 0x200 0x202"
+        );
+        assert_eq!(warnings[1].pc, 0x20A);
+        assert_eq!(warnings[1].address, 0x20E);
+        assert_eq!(warnings[1].target, Target::NearPc);
+    }
+}
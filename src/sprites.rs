@@ -0,0 +1,183 @@
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use chip_8::{Chip8, HostKey};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::WindowCanvas;
+
+// sprites on a page, as a grid
+pub const COLUMNS: usize = 8;
+pub const ROWS: usize = 4;
+// tallest sprite DXYN can draw
+const MAX_HEIGHT: usize = 15;
+
+// the F3 sprite viewer: memory read as 8 pixel wide sprites, a page of them at a time, for
+// finding a ROM's graphics. the machine is paused while it's open
+//
+//   left/right   one byte back or forward, sprites don't have to start anywhere in particular
+//   up/down      one sprite
+//   page up/down one page
+//   -/=          shorter or taller sprites
+//   home         the start of the program
+//   i            where I points
+pub struct SpriteViewer {
+    pub address: usize,
+    pub height: usize,
+}
+
+impl SpriteViewer {
+    pub fn new(address: usize) -> SpriteViewer {
+        SpriteViewer { address, height: 5 }
+    }
+
+    fn page_size(&self) -> usize {
+        COLUMNS * ROWS * self.height
+    }
+
+    // returns false for keys it doesn't use
+    pub fn key_down(&mut self, host_key: HostKey, chip8: &Chip8) -> bool {
+        let last = chip8.memory().len() - 1;
+        self.address = match host_key {
+            HostKey::Left => self.address.saturating_sub(1),
+            HostKey::Right => self.address + 1,
+            HostKey::Up => self.address.saturating_sub(self.height),
+            HostKey::Down => self.address + self.height,
+            HostKey::PageUp => self.address.saturating_sub(self.page_size()),
+            HostKey::PageDown => self.address + self.page_size(),
+            HostKey::Home => chip8.layout().load,
+            HostKey::I => chip8.index(),
+            HostKey::Minus | HostKey::KpMinus => {
+                self.height = (self.height - 1).max(1);
+                self.address
+            }
+            HostKey::Equals | HostKey::Plus | HostKey::KpPlus => {
+                self.height = (self.height + 1).min(MAX_HEIGHT);
+                self.address
+            }
+            _ => return false,
+        }
+        .min(last);
+        true
+    }
+
+    // the sprites on the page with their addresses, rows past the end of memory left out
+    pub fn page<'a>(&self, memory: &'a [u8]) -> Vec<(usize, &'a [u8])> {
+        (0..COLUMNS * ROWS)
+            .map(|sprite| self.address + sprite * self.height)
+            .take_while(|address| *address < memory.len())
+            .map(|address| {
+                let end = (address + self.height).min(memory.len());
+                (address, &memory[address..end])
+            })
+            .collect()
+    }
+
+    pub fn draw(
+        &self,
+        canvas: &mut WindowCanvas,
+        memory: &[u8],
+        foreground: Color,
+        background: Color,
+    ) -> Result<(), String> {
+        // in units of one sprite pixel: a title line, then rows of sprites each with the
+        // address of the first one on the left and a gap around every sprite
+        let label_width = 4 * (GLYPH_WIDTH + 1) as usize;
+        let cell_width = 8 + 2;
+        let cell_height = self.height + 2;
+        let line_height = GLYPH_HEIGHT as usize + 2;
+        let units_wide = 1 + label_width + COLUMNS * cell_width;
+        let units_high = 1 + line_height + ROWS * cell_height.max(line_height);
+        let (width, height) = canvas.output_size()?;
+        let pixel_size = (width / units_wide as u32)
+            .min(height / units_high as u32)
+            .max(1);
+        let unit = |units: usize| (units as u32 * pixel_size) as i32;
+
+        canvas.set_draw_color(background);
+        canvas.clear();
+        let title = format!(
+            "SPRITES {:03X}-{:03X} 8X{}",
+            self.address,
+            (self.address + self.page_size() - 1).min(memory.len() - 1),
+            self.height
+        );
+        font::draw_text(canvas, &title, unit(1), unit(1), pixel_size, foreground)?;
+        for (number, (address, rows)) in self.page(memory).into_iter().enumerate() {
+            let (row, column) = (number / COLUMNS, number % COLUMNS);
+            let y = unit(1 + line_height + row * cell_height.max(line_height));
+            if column == 0 {
+                let label = format!("{:03X}", address);
+                font::draw_text(canvas, &label, unit(1), y, pixel_size, foreground)?;
+            }
+            let x = unit(1 + label_width + column * cell_width);
+            canvas.set_draw_color(foreground);
+            for (line, bits) in rows.iter().enumerate() {
+                for bit in (0..8).filter(|bit| bits & (0x80 >> bit) != 0) {
+                    canvas.fill_rect(Rect::new(
+                        x + unit(bit),
+                        y + unit(line),
+                        pixel_size,
+                        pixel_size,
+                    ))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        let mut chip8 = chip_8::create_chip8();
+        chip8.set_index(0x300);
+        let mut viewer = SpriteViewer::new(0x200);
+        assert!(viewer.key_down(HostKey::Down, &chip8));
+        assert_eq!(viewer.address, 0x205);
+        viewer.key_down(HostKey::Left, &chip8);
+        assert_eq!(viewer.address, 0x204);
+        viewer.key_down(HostKey::PageDown, &chip8);
+        assert_eq!(viewer.address, 0x204 + 32 * 5);
+        viewer.key_down(HostKey::I, &chip8);
+        assert_eq!(viewer.address, 0x300);
+        viewer.key_down(HostKey::Equals, &chip8);
+        assert_eq!(viewer.height, 6);
+        for _ in 0..10 {
+            viewer.key_down(HostKey::Minus, &chip8);
+        }
+        assert_eq!(viewer.height, 1);
+        // stays in memory
+        for _ in 0..30 {
+            viewer.key_down(HostKey::PageUp, &chip8);
+        }
+        assert_eq!(viewer.address, 0);
+        viewer.key_down(HostKey::Home, &chip8);
+        assert_eq!(viewer.address, 0x200);
+        viewer.address = 0xFFF;
+        viewer.key_down(HostKey::Right, &chip8);
+        assert_eq!(viewer.address, 0xFFF);
+        assert!(!viewer.key_down(HostKey::Num1, &chip8));
+    }
+
+    #[test]
+    fn test_page() {
+        let memory: Vec<u8> = (0..=255).collect();
+        let mut viewer = SpriteViewer::new(2);
+        viewer.height = 3;
+        let page = viewer.page(&memory);
+        assert_eq!(page.len(), COLUMNS * ROWS);
+        assert_eq!(page[0], (2, &[2, 3, 4][..]));
+        assert_eq!(page[1], (5, &[5, 6, 7][..]));
+        // the last one is cut short by the end of memory
+        viewer.address = 250;
+        assert_eq!(
+            viewer.page(&memory),
+            vec![(250, &[250, 251, 252][..]), (253, &[253, 254, 255][..])]
+        );
+        viewer.address = 254;
+        assert_eq!(viewer.page(&memory), vec![(254, &[254, 255][..])]);
+    }
+}
@@ -0,0 +1,146 @@
+use chip_8::{Chip8, DISPLAY_WIDTH};
+
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// --dump-state-json: the machine as a headless run left it, for test harnesses to check
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StateDump {
+    pub variant: &'static str,
+    pub pc: usize,
+    pub index: usize,
+    pub registers: Vec<u8>,
+    // the calls being returned from, oldest first. a return goes on after the call
+    pub stack: Vec<usize>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub exited: bool,
+    pub display: Bitmap,
+    pub memory: Memory,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Bitmap {
+    pub width: usize,
+    pub height: usize,
+    // a string a row, "1" for lit pixels and "0" for dark ones
+    pub rows: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Memory {
+    Bytes(Vec<u8>),
+    Base64(String),
+}
+
+impl StateDump {
+    pub fn new(chip8: &Chip8, base64_memory: bool) -> StateDump {
+        let framebuffer = chip8.framebuffer();
+        let height = chip8.display_height();
+        StateDump {
+            variant: chip8.variant().name(),
+            pc: chip8.pc(),
+            index: chip8.index(),
+            registers: chip8.registers().to_vec(),
+            stack: chip8.stack().to_vec(),
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer,
+            exited: chip8.has_exited(),
+            display: Bitmap {
+                width: DISPLAY_WIDTH,
+                height,
+                rows: (0..height)
+                    .map(|y| {
+                        (0..DISPLAY_WIDTH)
+                            .map(|x| if framebuffer.get(x, y) { '1' } else { '0' })
+                            .collect()
+                    })
+                    .collect(),
+            },
+            memory: if base64_memory {
+                Memory::Base64(base64(chip8.memory()))
+            } else {
+                Memory::Bytes(chip8.memory().to_vec())
+            },
+        }
+    }
+}
+
+// to `path`, or stdout for -
+pub fn write(path: &Path, chip8: &Chip8, base64_memory: bool) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&StateDump::new(chip8, base64_memory)).unwrap() + "\n";
+    if path == Path::new("-") {
+        return io::stdout()
+            .write_all(json.as_bytes())
+            .map_err(|e| format!("couldn't write the state: {}", e));
+    }
+    fs::write(path, json).map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+}
+
+// standard alphabet, padded
+pub fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(&[0xFF, 0xEF, 0x00, 0x01]), "/+8AAQ==");
+    }
+
+    #[test]
+    fn test_state_dump() {
+        // V3 = 0x42, I = the font's 0, then call 0x206, which draws the glyph's top row and loops
+        let rom = [0x63, 0x42, 0xA0, 0x00, 0x22, 0x06, 0xD0, 0x01, 0x12, 0x08];
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(&rom).unwrap();
+        for _ in 0..5 {
+            chip8.emulate_cycle().unwrap();
+        }
+        let dump = StateDump::new(&chip8, false);
+        assert_eq!(dump.pc, 0x208);
+        assert_eq!(dump.registers[3], 0x42);
+        assert_eq!(dump.stack, [0x204]);
+        assert_eq!(dump.display.rows.len(), 32);
+        assert!(dump.display.rows[0].starts_with("11110000"));
+        assert_eq!(dump.display.rows[1], "0".repeat(64));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&dump).unwrap()).unwrap();
+        assert_eq!(json["variant"], "chip8");
+        assert_eq!(json["index"], 0);
+        assert_eq!(json["memory"][0x201], 0x42);
+        assert_eq!(json["display"]["width"], 64);
+
+        let json = serde_json::to_value(StateDump::new(&chip8, true)).unwrap();
+        let memory = json["memory"].as_str().unwrap();
+        assert_eq!(memory.len(), 4096 / 3 * 4 + 4);
+        assert_eq!(&memory[..8], &base64(&chip8.memory()[..6]));
+    }
+}
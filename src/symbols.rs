@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// names for addresses, from a symbol file that goes with the ROM. one symbol per line, the
+/// number and the name in either order, Octo's `:` and `:const` are allowed in front:
+///
+/// ```text
+///   # comment
+///   main_loop 0x204
+///   0x2A4 draw_score
+///   lives = 0x3F0
+///   : title 0x300
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbols {
+    by_address: BTreeMap<usize, String>,
+    by_name: HashMap<String, usize>,
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+impl Symbols {
+    /// the contents of a symbol file. errors name the line
+    pub fn parse(contents: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == '=')
+                .filter(|word| !word.is_empty() && *word != ":" && *word != ":const")
+                .collect();
+            let symbol = match words.as_slice() {
+                [] => continue,
+                [first, second] => match (parse_number(first), parse_number(second)) {
+                    (Some(address), None) if is_name(second) => Some((*second, address)),
+                    (None, Some(address)) if is_name(first) => Some((*first, address)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let (name, address) = symbol
+                .ok_or_else(|| format!("line {}: expected a name and an address", number + 1))?;
+            symbols.insert(name, address);
+        }
+        Ok(symbols)
+    }
+
+    /// a later name for the same address, or address for the same name, replaces the earlier one
+    pub fn insert(&mut self, name: &str, address: usize) {
+        if let Some(old_address) = self.by_name.insert(name.to_string(), address) {
+            self.by_address.remove(&old_address);
+        }
+        if let Some(old_name) = self.by_address.insert(address, name.to_string()) {
+            if old_name != name {
+                self.by_name.remove(&old_name);
+            }
+        }
+    }
+
+    pub fn name(&self, address: usize) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    pub fn address(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_address.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_address.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let symbols = Symbols::parse(
+            "# from the listing\n\
+             main_loop 0x204\n\
+             0x2A4 draw_score\n\
+             lives = 1008\n\
+             : title 0x300\n\
+             :const SPEED 0x10\n\
+             \n",
+        )
+        .unwrap();
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(symbols.address("main_loop"), Some(0x204));
+        assert_eq!(symbols.name(0x2A4), Some("draw_score"));
+        assert_eq!(symbols.name(0x3F0), Some("lives"));
+        assert_eq!(symbols.address("title"), Some(0x300));
+        assert_eq!(symbols.name(0x10), Some("SPEED"));
+        assert_eq!(symbols.name(0x206), None);
+
+        assert_eq!(
+            Symbols::parse("main 0x200\n0x202 0x204"),
+            Err("line 2: expected a name and an address".to_string())
+        );
+        assert!(Symbols::parse("main loop 0x200").is_err());
+        assert!(Symbols::parse("main").is_err());
+    }
+
+    #[test]
+    fn test_renaming() {
+        let mut symbols = Symbols::default();
+        symbols.insert("start", 0x200);
+        symbols.insert("main", 0x200);
+        assert_eq!(symbols.name(0x200), Some("main"));
+        assert_eq!(symbols.address("start"), None);
+        assert_eq!(symbols.len(), 1);
+        symbols.insert("main", 0x204);
+        assert_eq!(symbols.name(0x200), None);
+        assert_eq!(symbols.address("main"), Some(0x204));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::patches::Patches;
+use chip_8::frontend::{AudioSink, Display, InputEvent, InputSource};
+use chip_8::{Chip8, Event, HostKey, KeyEdge, Keypad, Snapshot, Timing, Touches};
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Instant;
+use tracing::{error, warn};
+
+// --threaded: the machine runs on a thread of its own and keeps its own time, the window only
+// shows the screens it sends over. slow drawing or a window being dragged around can't hold
+// the game back or make it catch up in bursts. what needs the machine at hand between
+// instructions (the debugger, scripts, netplay, recording, the viewers) isn't available
+
+// from the window to the machine
+enum Command {
+    Key(KeyEdge),
+    Reset,
+    Quit,
+}
+
+// from the machine, one per frame it ran
+struct Frame {
+    events: Vec<Event>,
+    instructions: u64,
+    // the whole machine after it, for the window's copy. only 4K of memory, cheap to send
+    snapshot: Box<Snapshot>,
+}
+
+// runs until the window is closed or the ROM stops, then leaves the machine's last state in
+// `chip8` for the flags and the autosave. returns the exit code
+pub fn run<D: Display, A: AudioSink, I: InputSource>(
+    chip8: &mut Chip8,
+    patches: Patches,
+    mut keypad: Keypad,
+    display: &mut D,
+    audio: &mut A,
+    input: &mut I,
+) -> i32 {
+    let start = chip8.snapshot();
+    let timing = chip8.timing();
+    let (commands, command_receiver) = mpsc::channel();
+    let (frame_sender, frames) = mpsc::channel();
+    let emulator =
+        thread::spawn(move || emulate(start, timing, patches, command_receiver, frame_sender));
+    let frame_interval = crate::runloop::freq_to_period_duration(chip_8::TIMER_FREQ);
+    let mut touches = Touches::new();
+    let mut exit_code = 0;
+    let mut sound_playing = false;
+    let mut audio_pattern = None;
+    let mut pitch = chip8.pitch;
+    chip8.draw = true;
+
+    'running: loop {
+        // the window goes at the machine's pace, and takes whatever piled up if it fell behind
+        let first = match frames.recv_timeout(frame_interval) {
+            Ok(frame) => Some(frame),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        for frame in first.into_iter().chain(frames.try_iter()) {
+            // from a copy of this machine, so it fits
+            chip8.restore(&frame.snapshot).unwrap();
+            for event in frame.events {
+                match event {
+                    Event::DisplayUpdated => chip8.draw = true,
+                    Event::Exited => break 'running,
+                    Event::Error(e) => {
+                        error!("emulation error: {}", e);
+                        display.show_error(&e.to_string());
+                        exit_code = 1;
+                        break 'running;
+                    }
+                    _ => {}
+                }
+            }
+            if display.end_frame(frame.instructions) {
+                chip8.draw = true;
+            }
+        }
+        if chip8.audio_pattern != audio_pattern || chip8.pitch != pitch {
+            audio_pattern = chip8.audio_pattern;
+            pitch = chip8.pitch;
+            if let Some(pattern) = audio_pattern {
+                audio.set_pattern(pattern, chip8.audio_playback_rate());
+            }
+        }
+        if (chip8.sound_timer > 0) != sound_playing {
+            sound_playing = !sound_playing;
+            audio.set_playing(sound_playing);
+        }
+        if sound_playing {
+            audio.set_sound_timer(chip8.sound_timer);
+        }
+
+        for event in input.poll() {
+            let command = match event {
+                InputEvent::Quit => break 'running,
+                InputEvent::Reset => Some(Command::Reset),
+                InputEvent::ToggleFullscreen => {
+                    display.toggle_fullscreen();
+                    None
+                }
+                InputEvent::ToggleOverlay => {
+                    display.toggle_overlay();
+                    None
+                }
+                InputEvent::ToggleHud => {
+                    display.toggle_hud();
+                    None
+                }
+                InputEvent::ToggleGrid => {
+                    display.toggle_grid();
+                    None
+                }
+                InputEvent::CycleScaling => {
+                    display.cycle_scaling();
+                    None
+                }
+                InputEvent::KeyDown(HostKey::M) if !keypad.is_mapped(HostKey::M) => {
+                    audio.toggle_mute();
+                    None
+                }
+                InputEvent::KeyDown(host_key) => keypad.key_down(host_key).map(Command::Key),
+                InputEvent::KeyUp(host_key) => keypad.key_up(host_key).map(Command::Key),
+                InputEvent::Finger(finger, ..) | InputEvent::FingerUp(finger) => {
+                    let at = match event {
+                        InputEvent::Finger(_, x, y) => Some((x, y)),
+                        _ => None,
+                    };
+                    for edge in touches.touch(finger, at) {
+                        let _ = commands.send(Command::Key(edge));
+                    }
+                    display.show_touch_keypad();
+                    None
+                }
+                // one ROM, no frame advance, viewers or debugger
+                _ => None,
+            };
+            chip8.draw |= matches!(
+                event,
+                InputEvent::ToggleFullscreen
+                    | InputEvent::ToggleOverlay
+                    | InputEvent::ToggleHud
+                    | InputEvent::ToggleGrid
+                    | InputEvent::CycleScaling
+                    | InputEvent::Redraw
+                    | InputEvent::Finger(..)
+            );
+            if let Some(command) = command {
+                // it only stops by itself after an Exited or Error frame, handled above
+                let _ = commands.send(command);
+            }
+        }
+
+        if chip8.draw {
+            if let Err(e) = display.present(chip8) {
+                warn!("couldn't draw: {}", e);
+            }
+            chip8.draw = false;
+            chip8.clear_dirty_rows();
+        }
+    }
+
+    audio.set_playing(false);
+    let _ = commands.send(Command::Quit);
+    match emulator.join() {
+        Ok(last) => chip8.restore(&last).unwrap(),
+        Err(_) => exit_code = 1,
+    }
+    exit_code
+}
+
+// the machine's side: a frame every 60th of a second, with the commands that came in meanwhile
+fn emulate(
+    start: Snapshot,
+    timing: Timing,
+    patches: Patches,
+    commands: Receiver<Command>,
+    frames: Sender<Frame>,
+) -> Snapshot {
+    let mut chip8 = Chip8::builder()
+        .variant(start.variant)
+        .quirks(start.quirks)
+        .timing(timing)
+        .build();
+    chip8.restore(&start).unwrap();
+    let frame_interval = crate::runloop::freq_to_period_duration(chip_8::TIMER_FREQ);
+    let mut next_frame = Instant::now();
+    loop {
+        for command in commands.try_iter() {
+            match command {
+                Command::Key(edge) => chip8.key_edge(edge),
+                Command::Reset => chip8.restore(&start).unwrap(),
+                Command::Quit => return chip8.snapshot(),
+            }
+        }
+        let output = chip8.run_frame();
+        patches.apply_freezes(&mut chip8);
+        let stopped = output
+            .events
+            .iter()
+            .any(|event| matches!(event, Event::Exited | Event::Error(_)));
+        let frame = Frame {
+            events: output.events,
+            instructions: output.instructions,
+            snapshot: Box::new(chip8.snapshot()),
+        };
+        // nobody's watching any more
+        if frames.send(frame).is_err() || stopped {
+            return chip8.snapshot();
+        }
+        crate::runloop::wait_for_next_frame(&mut next_frame, frame_interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[derive(Default)]
+    struct MockDisplay {
+        presented: usize,
+    }
+
+    impl Display for MockDisplay {
+        fn present(&mut self, _chip8: &Chip8) -> Result<(), String> {
+            self.presented += 1;
+            Ok(())
+        }
+    }
+
+    struct MockAudio;
+
+    impl AudioSink for MockAudio {
+        fn set_playing(&mut self, _playing: bool) {}
+    }
+
+    struct MockInput {
+        frames: VecDeque<Vec<InputEvent>>,
+    }
+
+    impl InputSource for MockInput {
+        fn poll(&mut self) -> Vec<InputEvent> {
+            self.frames.pop_front().unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_threaded() {
+        // draw a 0, wait for a key in V0, then exit
+        let mut chip8 = Chip8::builder().build();
+        chip8
+            .load_rom_bytes(&[0xD0, 0x05, 0xF0, 0x0A, 0x00, 0xFD])
+            .unwrap();
+        let mut display = MockDisplay::default();
+        let mut input = MockInput {
+            frames: vec![
+                vec![],
+                vec![InputEvent::KeyDown(HostKey::W)],
+                vec![InputEvent::KeyUp(HostKey::W)],
+            ]
+            .into(),
+        };
+        let exit_code = run(
+            &mut chip8,
+            Patches::default(),
+            Keypad::new(),
+            &mut display,
+            &mut MockAudio,
+            &mut input,
+        );
+        assert_eq!(exit_code, 0);
+        // the machine that ran handed back its state
+        assert_eq!(chip8.registers()[0], 5);
+        assert!(chip8.has_exited());
+        assert!(chip8.framebuffer().get(0, 0));
+        assert!(display.presented >= 1);
+    }
+
+    #[test]
+    fn test_threaded_quit() {
+        // V0 += 1 forever
+        let mut chip8 = Chip8::builder().build();
+        chip8.load_rom_bytes(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut input = MockInput {
+            frames: vec![vec![], vec![], vec![InputEvent::Quit]].into(),
+        };
+        let exit_code = run(
+            &mut chip8,
+            Patches::default(),
+            Keypad::new(),
+            &mut MockDisplay::default(),
+            &mut MockAudio,
+            &mut input,
+        );
+        assert_eq!(exit_code, 0);
+        assert!(chip8.registers()[0] > 0);
+        assert!(!chip8.has_exited());
+    }
+}
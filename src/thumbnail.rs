@@ -0,0 +1,222 @@
+use crate::persistence::{Saves, Thumbnail};
+use crate::rom;
+use chip_8::{Chip8, Event, FrameBuffer, Variant};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use tracing::warn;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+// how long a ROM runs before its screen is taken for the game picker, 2 seconds
+const PREVIEW_FRAMES: u32 = 120;
+
+// the screen as a PNG the size of the screen, black and white at one bit per pixel. small
+// enough to write whenever a state is saved, and file managers show it
+pub fn encode_png(framebuffer: &FrameBuffer) -> Vec<u8> {
+    let (width, height) = framebuffer.size();
+    let rows = (0..height).map(|y| {
+        let mut row = vec![0; width.div_ceil(8)];
+        for x in (0..width).filter(|x| framebuffer.get(*x, y)) {
+            row[x / 8] |= 0x80 >> (x % 8);
+        }
+        row
+    });
+    // 1 bit grayscale
+    png(width, height, 1, 0, rows)
+}
+
+// `pixels` as RGB bytes, row by row
+pub fn encode_rgb_png(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    let rows = pixels.chunks(width * 3).map(|row| row.to_vec());
+    // 8 bits a channel of truecolor
+    png(width, height, 8, 2, rows)
+}
+
+fn png(
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    rows: impl Iterator<Item = Vec<u8>>,
+) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // the standard compression and filters, not interlaced
+    header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+
+    // every row starts with its filter type, 0 for none
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    for row in rows {
+        encoder.write_all(&[0]).unwrap();
+        encoder.write_all(&row).unwrap();
+    }
+    let data = encoder.finish().unwrap();
+
+    let mut png = SIGNATURE.to_vec();
+    for (kind, contents) in [(b"IHDR", &header), (b"IDAT", &data), (b"IEND", &Vec::new())] {
+        png.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+        png.extend_from_slice(kind);
+        png.extend_from_slice(contents);
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(contents);
+        png.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    png
+}
+
+// reads back what encode_png wrote, and nothing fancier
+pub fn decode_png(png: &[u8]) -> Result<FrameBuffer, String> {
+    let mut rest = png
+        .strip_prefix(SIGNATURE)
+        .ok_or("not a PNG file".to_string())?;
+    let mut size = None;
+    let mut data = Vec::new();
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let contents = rest.get(8..8 + length).ok_or("the PNG is cut short")?;
+        match kind {
+            b"IHDR" if length == 13 => {
+                if contents[8..] != [1, 0, 0, 0, 0] {
+                    return Err("only 1 bit grayscale PNGs can be read".to_string());
+                }
+                let width = u32::from_be_bytes(contents[..4].try_into().unwrap()) as usize;
+                let height = u32::from_be_bytes(contents[4..8].try_into().unwrap()) as usize;
+                size = Some((width, height));
+            }
+            b"IDAT" => data.extend_from_slice(contents),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = rest.get(8 + length + 4..).ok_or("the PNG is cut short")?;
+    }
+    let (width, height) = size.ok_or("the PNG has no header")?;
+    let row_length = 1 + width.div_ceil(8);
+    let mut rows = Vec::new();
+    ZlibDecoder::new(&data[..])
+        .take((row_length * height) as u64)
+        .read_to_end(&mut rows)
+        .map_err(|e| format!("the PNG is damaged: {}", e))?;
+    if rows.len() != row_length * height {
+        return Err("the PNG is cut short".to_string());
+    }
+    let mut framebuffer = FrameBuffer::new(width, height);
+    for (y, row) in rows.chunks_exact(row_length).enumerate() {
+        if row[0] != 0 {
+            return Err("only unfiltered PNGs can be read".to_string());
+        }
+        for x in 0..width {
+            framebuffer.set(x, y, row[1 + x / 8] & (0x80 >> (x % 8)) != 0);
+        }
+    }
+    Ok(framebuffer)
+}
+
+// the screen after the ROM ran on its own for a couple of seconds, as the variant it looks
+// like. ROMs waiting for a key by then show whatever they're waiting with
+pub fn preview(rom: &[u8]) -> Option<FrameBuffer> {
+    let variant = rom::guess_variant(rom).parse().unwrap_or(Variant::Chip8);
+    let mut chip8 = Chip8::builder().variant(variant).build();
+    chip8.load_rom_bytes(rom).ok()?;
+    for _ in 0..PREVIEW_FRAMES {
+        let output = chip8.run_frame();
+        let stopped = |event: &Event| matches!(event, Event::Exited | Event::Error(_));
+        if output.events.iter().any(stopped) {
+            break;
+        }
+    }
+    Some(chip8.framebuffer().clone())
+}
+
+// previews of the game picker's ROMs, by index, made on another thread as they're needed
+// the first time and kept in the save directory (when there is one) for the next
+pub struct Previews {
+    receiver: Receiver<(usize, FrameBuffer)>,
+}
+
+impl Previews {
+    pub fn start(roms: Vec<PathBuf>, save_dir: Option<PathBuf>) -> Previews {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for (index, path) in roms.iter().enumerate() {
+                let preview = match cached_preview(path, save_dir.as_deref()) {
+                    Some(preview) => preview,
+                    None => continue,
+                };
+                // the picker is gone
+                if sender.send((index, preview)).is_err() {
+                    return;
+                }
+            }
+        });
+        Previews { receiver }
+    }
+
+    // the ones made since the last call
+    pub fn ready(&self) -> Vec<(usize, FrameBuffer)> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+fn cached_preview(path: &Path, save_dir: Option<&Path>) -> Option<FrameBuffer> {
+    let rom = rom::read_rom(path).ok()?;
+    let saves = save_dir.map(|dir| Saves::new(dir, &rom));
+    if let Some(Ok(Some(png))) = saves
+        .as_ref()
+        .map(|saves| saves.load_thumbnail(Thumbnail::Preview))
+    {
+        if let Ok(preview) = decode_png(&png) {
+            return Some(preview);
+        }
+    }
+    let preview = preview(&rom)?;
+    if let Some(saves) = &saves {
+        if let Err(e) = saves.save_thumbnail(Thumbnail::Preview, &encode_png(&preview)) {
+            warn!("{}", e);
+        }
+    }
+    Some(preview)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut framebuffer = FrameBuffer::new(64, 32);
+        for (x, y) in [(0, 0), (7, 0), (8, 1), (63, 31), (30, 17)].iter() {
+            framebuffer.set(*x, *y, true);
+        }
+        let png = encode_png(&framebuffer);
+        assert!(png.starts_with(SIGNATURE));
+        assert!(png.ends_with(&[0xAE, 0x42, 0x60, 0x82]));
+        assert_eq!(decode_png(&png), Ok(framebuffer));
+        // odd sizes pad the last byte of every row
+        let mut framebuffer = FrameBuffer::new(13, 3);
+        framebuffer.set(12, 2, true);
+        assert_eq!(decode_png(&encode_png(&framebuffer)), Ok(framebuffer));
+
+        assert!(decode_png(b"GIF89a").is_err());
+        assert!(decode_png(&png[..png.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_preview() {
+        // draw the 0 from the font at 0, 0, then wait for a key
+        let screen = preview(&[0xD0, 0x05, 0xF0, 0x0A]).unwrap();
+        assert_eq!(screen.size(), (64, 32));
+        assert!(screen.get(0, 0) && screen.get(3, 4));
+        assert!(!screen.get(1, 1));
+        // hires ROMs get their taller screen
+        assert_eq!(preview(&[0x12, 0x60]).unwrap().size(), (64, 64));
+    }
+}
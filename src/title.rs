@@ -0,0 +1,143 @@
+use crate::config::Settings;
+
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_TITLE: &str = "{rom} - chip8 emulator";
+// how often the title is filled in again
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// the window title: the title template from the config with the placeholders filled in.
+// `fps` is None until a second has gone by to count them in
+pub fn window_title(settings: &Settings, fps: Option<u32>, state: &str) -> String {
+    let value = |placeholder: &str| match placeholder {
+        "rom" => Some(settings.rom_name.as_deref().unwrap_or("no ROM").to_string()),
+        "variant" => Some(settings.variant.name().to_string()),
+        "speed" => Some(settings.speed.to_string()),
+        "fps" => Some(fps.map_or("-".to_string(), |fps| fps.to_string())),
+        "state" => Some(state.to_string()),
+        _ => None,
+    };
+    fill(&settings.title, value).unwrap_or_else(|_| settings.title.clone())
+}
+
+// for the config, before anything is run with it
+pub fn check_template(template: &str) -> Result<(), String> {
+    fill(template, |placeholder| {
+        ["rom", "variant", "speed", "fps", "state"]
+            .contains(&placeholder)
+            .then(String::new)
+    })
+    .map(|_| ())
+}
+
+// "{name}" replaced by its value, "{{" and "}}" for braces
+fn fill(template: &str, value: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        filled += &rest[..start];
+        let brace = &rest[start..start + 1];
+        rest = &rest[start + 1..];
+        if rest.starts_with(brace) {
+            filled += brace;
+            rest = &rest[1..];
+            continue;
+        }
+        let end = match (brace, rest.find('}')) {
+            ("{", Some(end)) => end,
+            _ => return Err(format!("unmatched {} in the title", brace)),
+        };
+        let placeholder = &rest[..end];
+        filled += &value(placeholder).ok_or(format!(
+            "unknown placeholder {{{}}} in the title, expected {{rom}}, {{variant}}, {{speed}}, {{fps}} or {{state}}",
+            placeholder
+        ))?;
+        rest = &rest[end + 1..];
+    }
+    Ok(filled + rest)
+}
+
+// frames run a second, for {fps}
+pub struct FrameRate {
+    since: Instant,
+    frames: u32,
+    fps: Option<u32>,
+    updated: bool,
+}
+
+impl FrameRate {
+    pub fn new(now: Instant) -> FrameRate {
+        FrameRate {
+            since: now,
+            frames: 0,
+            fps: None,
+            updated: false,
+        }
+    }
+
+    pub fn count_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    // whether it's time to update the title: right away, then once a second
+    pub fn due(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.since);
+        if !self.updated {
+            self.updated = true;
+            return true;
+        }
+        if elapsed < UPDATE_INTERVAL {
+            return false;
+        }
+        self.fps = Some((self.frames as f64 / elapsed.as_secs_f64()).round() as u32);
+        self.frames = 0;
+        self.since = now;
+        true
+    }
+
+    pub fn fps(&self) -> Option<u32> {
+        self.fps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_window_title() {
+        let mut settings = Config::default().resolve().unwrap();
+        assert_eq!(
+            window_title(&settings, None, "running"),
+            "no ROM - chip8 emulator"
+        );
+        settings.title = "{rom} - {variant} - {fps}fps - {state} {{{speed}}}".to_string();
+        settings.rom_name = Some("Pong".to_string());
+        assert_eq!(
+            window_title(&settings, Some(60), "paused"),
+            "Pong - chip8 - 60fps - paused {840}"
+        );
+        assert!(check_template(&settings.title).is_ok());
+        assert_eq!(
+            check_template("{rom} {ips}"),
+            Err("unknown placeholder {ips} in the title, expected {rom}, {variant}, {speed}, {fps} or {state}".to_string())
+        );
+        assert!(check_template("{rom").is_err());
+        assert!(check_template("rom}").is_err());
+    }
+
+    #[test]
+    fn test_frame_rate() {
+        let start = Instant::now();
+        let mut rate = FrameRate::new(start);
+        assert!(rate.due(start));
+        assert!(!rate.due(start));
+        for _ in 0..90 {
+            rate.count_frame();
+        }
+        assert!(!rate.due(start + Duration::from_millis(500)));
+        assert!(rate.due(start + Duration::from_millis(1500)));
+        assert_eq!(rate.fps(), Some(60));
+    }
+}
@@ -0,0 +1,510 @@
+use chip_8::analysis::opcode_name;
+use chip_8::{try_decode, Chip8, FrameBudget, Hooks, Opcode, Status};
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+// frames in a row without an instruction run before a comparison gives up, it's waiting for a
+// key that nobody is going to press
+const STUCK_FRAMES: usize = 60;
+
+// VCD signals as (name, width in bits), in the order of `Step::values`
+const SIGNALS: [(&str, usize); 22] = [
+    ("pc", 12),
+    ("opcode", 16),
+    ("i", 12),
+    ("sp", 5),
+    ("dt", 8),
+    ("st", 8),
+    ("v0", 8),
+    ("v1", 8),
+    ("v2", 8),
+    ("v3", 8),
+    ("v4", 8),
+    ("v5", 8),
+    ("v6", 8),
+    ("v7", 8),
+    ("v8", 8),
+    ("v9", 8),
+    ("va", 8),
+    ("vb", 8),
+    ("vc", 8),
+    ("vd", 8),
+    ("ve", 8),
+    ("vf", 8),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    // one JSON object a line, for jq, scripts and diffing against other emulators' logs
+    Jsonl,
+    // a value change dump, for waveform viewers like GTKWave. time counts instructions
+    Vcd,
+}
+
+impl TraceFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            TraceFormat::Jsonl => "jsonl",
+            TraceFormat::Vcd => "vcd",
+        }
+    }
+
+    // vcd for a .vcd file, JSON Lines otherwise
+    pub fn for_path(path: &Path) -> TraceFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("vcd") => TraceFormat::Vcd,
+            _ => TraceFormat::Jsonl,
+        }
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<TraceFormat, String> {
+        [TraceFormat::Jsonl, TraceFormat::Vcd]
+            .iter()
+            .copied()
+            .find(|format| format.name() == name.to_ascii_lowercase())
+            .ok_or(format!(
+                "unknown trace format {}, expected jsonl or vcd",
+                name
+            ))
+    }
+}
+
+// the machine as an instruction is about to run
+#[derive(Serialize, Clone, Debug, PartialEq)]
+struct Step {
+    step: u64,
+    pc: usize,
+    opcode: u16,
+    i: usize,
+    sp: usize,
+    dt: u8,
+    st: u8,
+    v: [u8; 16],
+}
+
+impl Step {
+    fn of(chip8: &Chip8, step: u64) -> Step {
+        Step {
+            step,
+            pc: chip8.pc(),
+            opcode: chip8.next_instruction(),
+            i: chip8.index(),
+            sp: chip8.stack().len(),
+            dt: chip8.delay_timer(),
+            st: chip8.sound_timer,
+            v: *chip8.registers(),
+        }
+    }
+
+    // one line of context around a divergence
+    fn describe(&self, chip8: &Chip8) -> String {
+        let name = try_decode(self.opcode, chip8.variant())
+            .map(|opcode| opcode_name(&opcode))
+            .unwrap_or_else(|| "????".to_string());
+        let v: Vec<String> = self.v.iter().map(|v| format!("{:02X}", v)).collect();
+        format!(
+            "{:>8}  {:#05X}  {:04X} {:<5} I {:#05X} SP {} DT {:>3} ST {:>3}  V {}",
+            self.step,
+            self.pc,
+            self.opcode,
+            name,
+            self.i,
+            self.sp,
+            self.dt,
+            self.st,
+            v.join(" ")
+        )
+    }
+
+    fn values(&self) -> Vec<u64> {
+        let registers = [self.pc, self.opcode as usize, self.i, self.sp];
+        let timers = [self.dt, self.st];
+        registers
+            .iter()
+            .map(|value| *value as u64)
+            .chain(timers.iter().chain(&self.v).map(|value| *value as u64))
+            .collect()
+    }
+}
+
+// --trace FILE: writes down every instruction run with the registers it found. attach with
+// `Chip8::add_hook`, and `finish` it before exiting or the end of it is lost
+pub struct TraceWriter {
+    out: BufWriter<File>,
+    format: TraceFormat,
+    step: u64,
+    // what the VCD signals were at the last step, only changes are written
+    last: Option<Vec<u64>>,
+    // the first write that failed, nothing more is written after it
+    error: Option<String>,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path, format: TraceFormat) -> Result<TraceWriter, String> {
+        let file =
+            File::create(path).map_err(|e| format!("couldn't create {}: {}", path.display(), e))?;
+        let mut writer = TraceWriter {
+            out: BufWriter::new(file),
+            format,
+            step: 0,
+            last: None,
+            error: None,
+        };
+        if format == TraceFormat::Vcd {
+            let header = writer.vcd_header();
+            writer.write(&header);
+        }
+        Ok(writer)
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        if let Err(e) = self.out.flush() {
+            self.error.get_or_insert(e.to_string());
+        }
+        match self.error.take() {
+            Some(e) => Err(format!("couldn't write the trace: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.error.is_none() {
+            if let Err(e) = self.out.write_all(text.as_bytes()) {
+                self.error = Some(e.to_string());
+            }
+        }
+    }
+
+    fn vcd_header(&self) -> String {
+        let mut header = format!(
+            "$version chip-8 {} $end\n$timescale 1ns $end\n$scope module chip8 $end\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        for (signal, (name, width)) in SIGNALS.iter().enumerate() {
+            header += &format!("$var wire {} {} {} $end\n", width, vcd_id(signal), name);
+        }
+        header + "$upscope $end\n$enddefinitions $end\n"
+    }
+
+    fn vcd_changes(&mut self, step: &Step) -> String {
+        let values = step.values();
+        let mut changes = format!("#{}\n", step.step);
+        for (signal, value) in values.iter().enumerate() {
+            let changed = self
+                .last
+                .as_ref()
+                .map_or(true, |last| last[signal] != *value);
+            if changed {
+                changes += &format!("b{:b} {}\n", value, vcd_id(signal));
+            }
+        }
+        self.last = Some(values);
+        changes
+    }
+}
+
+// VCD names signals with printable characters from '!' on
+fn vcd_id(signal: usize) -> char {
+    (b'!' + signal as u8) as char
+}
+
+impl Hooks for TraceWriter {
+    fn before_instruction(&mut self, chip8: &Chip8, _opcode: &Opcode) {
+        let step = Step::of(chip8, self.step);
+        self.step += 1;
+        let text = match self.format {
+            TraceFormat::Jsonl => serde_json::to_string(&step).unwrap() + "\n",
+            TraceFormat::Vcd => self.vcd_changes(&step),
+        };
+        self.write(&text);
+    }
+}
+
+// a line of a reference trace for `compare`, in the JSON Lines format --trace writes. traces
+// from other emulators only need the fields they have, what's missing isn't compared
+#[derive(Deserialize, Default, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ReferenceStep {
+    pc: Option<usize>,
+    opcode: Option<u16>,
+    i: Option<usize>,
+    sp: Option<usize>,
+    dt: Option<u8>,
+    st: Option<u8>,
+    v: Option<[u8; 16]>,
+}
+
+impl ReferenceStep {
+    // "V3: 05 here, 0A in the reference" for every field that doesn't match
+    fn differences(&self, step: &Step) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: String, ours: String, theirs: Option<String>| {
+            if let Some(theirs) = theirs.filter(|theirs| *theirs != ours) {
+                differences.push(format!(
+                    "{}: {} here, {} in the reference",
+                    name, ours, theirs
+                ));
+            }
+        };
+        let address = |address: usize| format!("{:#05X}", address);
+        compare("pc".into(), address(step.pc), self.pc.map(address));
+        let opcode = |opcode: u16| format!("{:04X}", opcode);
+        compare(
+            "opcode".into(),
+            opcode(step.opcode),
+            self.opcode.map(opcode),
+        );
+        compare("I".into(), address(step.i), self.i.map(address));
+        compare(
+            "SP".into(),
+            step.sp.to_string(),
+            self.sp.map(|sp| sp.to_string()),
+        );
+        compare(
+            "DT".into(),
+            step.dt.to_string(),
+            self.dt.map(|dt| dt.to_string()),
+        );
+        compare(
+            "ST".into(),
+            step.st.to_string(),
+            self.st.map(|st| st.to_string()),
+        );
+        for (x, ours) in step.v.iter().enumerate() {
+            let register = |v: &[u8; 16]| format!("{:02X}", v[x]);
+            compare(
+                format!("V{:X}", x),
+                format!("{:02X}", ours),
+                self.v.as_ref().map(register),
+            );
+        }
+        differences
+    }
+}
+
+pub fn read_reference(path: &Path) -> Result<Vec<ReferenceStep>, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comparison {
+    // every step of the reference was matched
+    Matched(u64),
+    // the first step that didn't match, what was different and the steps up to it
+    Diverged {
+        step: u64,
+        differences: Vec<String>,
+        context: Vec<String>,
+    },
+    // the ROM stopped before the reference did
+    Stopped {
+        step: u64,
+        reason: String,
+    },
+}
+
+// trace-diff: runs the ROM frame by frame like `Chip8::run_frame` and checks every instruction
+// against the reference before running it. random numbers are taken from the reference, CXKK
+// can't be expected to roll the same ones here
+pub fn compare(chip8: &mut Chip8, reference: &[ReferenceStep], context: usize) -> Comparison {
+    let mut recent = VecDeque::new();
+    let mut budget = FrameBudget::default();
+    let mut step = 0;
+    let mut idle_frames = 0;
+    while step < reference.len() {
+        let frame_start = step;
+        budget.start(chip8);
+        while budget.has_time() && step < reference.len() {
+            if chip8.key_wait().is_some() || chip8.waiting_for_vblank() {
+                break;
+            }
+            let ours = Step::of(chip8, step as u64);
+            if recent.len() == context + 1 {
+                recent.pop_front();
+            }
+            recent.push_back(ours.describe(chip8));
+            let differences = reference[step].differences(&ours);
+            if !differences.is_empty() {
+                return Comparison::Diverged {
+                    step: ours.step,
+                    differences,
+                    context: recent.into(),
+                };
+            }
+            let result = chip8.emulate_cycle();
+            // a DXYN held back for the next frame didn't run, it's checked again then
+            if chip8.waiting_for_vblank() {
+                recent.pop_back();
+                break;
+            }
+            budget.spend(chip8);
+            step += 1;
+            let random = reference.get(step).and_then(|next| next.v);
+            if let (Some(Opcode::OP_CXKK(x, _)), Some(v)) =
+                (try_decode(ours.opcode, chip8.variant()), random)
+            {
+                chip8.set_register(x, v[x]);
+            }
+            let reason = match result {
+                Ok(Status::Running) => continue,
+                Ok(Status::Exited) => "the ROM exited".to_string(),
+                Err(e) => e.to_string(),
+            };
+            return Comparison::Stopped {
+                step: step as u64,
+                reason,
+            };
+        }
+        chip8.tick_timers();
+        idle_frames = if step == frame_start {
+            idle_frames + 1
+        } else {
+            0
+        };
+        if idle_frames == STUCK_FRAMES {
+            return Comparison::Stopped {
+                step: step as u64,
+                reason: "waiting for a key".to_string(),
+            };
+        }
+    }
+    Comparison::Matched(step as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::create_chip8;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    fn trace(format: TraceFormat) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "chip8-trace-{}.{}",
+            std::process::id(),
+            format.name()
+        ));
+        assert_eq!(TraceFormat::for_path(&path), format);
+        let writer = Rc::new(RefCell::new(TraceWriter::create(&path, format).unwrap()));
+        let mut chip8 = create_chip8();
+        chip8.add_hook(Box::new(writer.clone()));
+        // V0 = 5, V1 = V0 + 1 (V1 += 1 after copying), then around again
+        chip8
+            .load_rom_bytes(&[0x60, 0x05, 0x81, 0x00, 0x71, 0x01, 0x12, 0x00])
+            .unwrap();
+        for _ in 0..5 {
+            chip8.emulate_cycle().unwrap();
+        }
+        writer.borrow_mut().finish().unwrap();
+        let trace = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        trace
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let trace = trace(TraceFormat::Jsonl);
+        let steps: Vec<serde_json::Value> = trace
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(steps.len(), 5);
+        assert_eq!(steps[0]["pc"], 0x200);
+        assert_eq!(steps[0]["opcode"], 0x6005);
+        assert_eq!(steps[2]["v"][0], 5);
+        assert_eq!(steps[4]["step"], 4);
+        assert_eq!(steps[4]["pc"], 0x200);
+        assert_eq!(steps[4]["v"][1], 6);
+    }
+
+    #[test]
+    fn test_vcd() {
+        let trace = trace(TraceFormat::Vcd);
+        assert!(trace.contains("$var wire 12 ! pc $end\n"));
+        assert!(trace.contains("$var wire 8 6 vf $end\n$upscope $end\n$enddefinitions $end\n"));
+        let changes = trace.split("$enddefinitions $end\n").nth(1).unwrap();
+        // '#' is also the id of I
+        let steps: Vec<&str> = changes[1..].split("\n#").collect();
+        assert_eq!(steps.len(), 5);
+        // everything at the start, 22 signals
+        assert_eq!(steps[0].lines().count(), 1 + 22);
+        assert!(steps[0].contains("b1000000000 !\n"));
+        // then only what changed: pc, the opcode and V0
+        assert_eq!(steps[1], "1\nb1000000010 !\nb1000000100000000 \"\nb101 '");
+        assert_eq!("VCD".parse(), Ok(TraceFormat::Vcd));
+    }
+
+    fn reference(lines: &[&str]) -> Vec<ReferenceStep> {
+        lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn run(rom: &[u8], reference: &[ReferenceStep]) -> Comparison {
+        let mut chip8 = create_chip8();
+        chip8.load_rom_bytes(rom).unwrap();
+        compare(&mut chip8, reference, 2)
+    }
+
+    #[test]
+    fn test_compare() {
+        let rom = [0x60, 0x05, 0x81, 0x00, 0x71, 0x01, 0x12, 0x00];
+        let trace = trace(TraceFormat::Jsonl);
+        let mut steps = reference(&trace.lines().collect::<Vec<_>>());
+        assert_eq!(run(&rom, &steps), Comparison::Matched(5));
+
+        steps[3].v.as_mut().unwrap()[1] = 7;
+        match run(&rom, &steps) {
+            Comparison::Diverged {
+                step,
+                differences,
+                context,
+            } => {
+                assert_eq!(step, 3);
+                assert_eq!(differences, ["V1: 06 here, 07 in the reference"]);
+                assert_eq!(context.len(), 3);
+                assert!(context[2].starts_with("       3  0x206  1200 1MMM  I 0x000"));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // only what the reference has is compared, and CXKK rolls what it says
+        let rom = [0xC0, 0xFF, 0x12, 0x00];
+        let steps = reference(&[
+            r#"{"pc": 512, "opcode": 49407}"#,
+            r#"{"pc": 514, "v": [66, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]}"#,
+            r#"{"pc": 512}"#,
+        ]);
+        assert_eq!(run(&rom, &steps), Comparison::Matched(3));
+
+        // FX0A with nobody to press a key
+        let steps = reference(&[r#"{}"#, r#"{}"#, r#"{}"#]);
+        assert_eq!(
+            run(&[0xF0, 0x0A], &steps),
+            Comparison::Stopped {
+                step: 1,
+                reason: "waiting for a key".to_string()
+            }
+        );
+    }
+}
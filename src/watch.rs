@@ -0,0 +1,107 @@
+use crate::rom;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// how often the file is looked at, checking every frame would be a lot of system calls
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+// notices when the ROM (or the .8o source, or the zip it's in) is saved again. it goes by the
+// modification time, so there's no need for a file watching library
+pub struct Watcher {
+    rom_path: PathBuf,
+    // what's looked at, the archive for a ROM in a zip
+    file: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Option<Instant>,
+}
+
+impl Watcher {
+    pub fn new(rom_path: &Path) -> Result<Watcher, String> {
+        if rom_path == Path::new("-") {
+            return Err("--watch needs a ROM file, not stdin".to_string());
+        }
+        let file = match rom::split_archive_path(rom_path) {
+            Some((archive, _)) => archive,
+            None => rom_path.to_path_buf(),
+        };
+        let modified = modified(&file);
+        Ok(Watcher {
+            rom_path: rom_path.to_path_buf(),
+            file,
+            modified,
+            last_check: Some(Instant::now()),
+        })
+    }
+
+    pub fn rom_path(&self) -> &Path {
+        &self.rom_path
+    }
+
+    // whether the file changed since the last time this said so. a file that's gone (some
+    // editors delete it before writing the new one) isn't a change yet
+    pub fn changed(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_check
+            .is_some_and(|last_check| now < last_check + CHECK_INTERVAL)
+        {
+            return false;
+        }
+        self.last_check = Some(now);
+        match modified(&self.file) {
+            Some(modified) if Some(modified) != self.modified => {
+                self.modified = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    #[test]
+    fn test_watcher() {
+        let path = std::env::temp_dir().join("chip8_watch_test.ch8");
+        fs::write(&path, [0x12, 0x00]).unwrap();
+        let mut watcher = Watcher::new(&path).unwrap();
+        watcher.last_check = None;
+        assert!(!watcher.changed());
+
+        // the clock may not have moved since the first write
+        let later = watcher.modified.unwrap() + Duration::from_secs(1);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        // not looked at again so soon
+        assert!(!watcher.changed());
+        watcher.last_check = None;
+        assert!(watcher.changed());
+        watcher.last_check = None;
+        assert!(!watcher.changed());
+
+        fs::remove_file(&path).unwrap();
+        watcher.last_check = None;
+        assert!(!watcher.changed());
+
+        assert_eq!(
+            Watcher::new(Path::new("roms.zip#pong.ch8")).unwrap().file,
+            PathBuf::from("roms.zip")
+        );
+        assert!(Watcher::new(Path::new("-")).is_err());
+    }
+}
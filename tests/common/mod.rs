@@ -0,0 +1,94 @@
+// shared helpers for the headless ROM tests
+#![allow(dead_code)]
+
+use chip_8::chip8::chip8::{create_chip8, Chip8};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+pub const ROM_START: u16 = 0x200;
+// 840 instructions per second at 60 frames per second
+pub const CYCLES_PER_FRAME: usize = 14;
+
+// assembles a ROM one instruction at a time, starting at 0x200
+#[derive(Default)]
+pub struct RomBuilder {
+    bytes: Vec<u8>,
+}
+
+impl RomBuilder {
+    pub fn new() -> RomBuilder {
+        RomBuilder::default()
+    }
+
+    // address of the next instruction
+    pub fn here(&self) -> u16 {
+        ROM_START + self.bytes.len() as u16
+    }
+
+    pub fn op(&mut self, opcode: u16) -> &mut RomBuilder {
+        self.bytes.extend_from_slice(&opcode.to_be_bytes());
+        self
+    }
+
+    pub fn ops(&mut self, opcodes: &[u16]) -> &mut RomBuilder {
+        for opcode in opcodes {
+            self.op(*opcode);
+        }
+        self
+    }
+
+    // raw bytes at a fixed address, padding with zeros up to it
+    pub fn data_at(&mut self, address: u16, data: &[u8]) -> &mut RomBuilder {
+        assert!(
+            address >= self.here(),
+            "data at {:#X} overlaps the ROM",
+            address
+        );
+        self.bytes.resize((address - ROM_START) as usize, 0);
+        self.bytes.extend_from_slice(data);
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        self.bytes.clone()
+    }
+}
+
+// a machine with a fixed RNG seed so runs are reproducible
+pub fn machine(rom: &[u8]) -> Chip8 {
+    let mut chip8 = create_chip8();
+    chip8.set_rng(Box::new(StdRng::seed_from_u64(0x8)));
+    chip8.load_rom_bytes(rom).unwrap();
+    chip8
+}
+
+// the clock is virtual: a fixed number of instructions, then one timer tick per frame
+pub fn run_frames(chip8: &mut Chip8, frames: usize) {
+    for _ in 0..frames {
+        for _ in 0..CYCLES_PER_FRAME {
+            chip8.emulate_cycle().unwrap();
+        }
+        chip8.tick_timers();
+    }
+}
+
+// the screen as text, for readable assertion failures
+pub fn screen(chip8: &Chip8) -> String {
+    let mut text = String::new();
+    for row in chip8.framebuffer().iter_rows() {
+        text.extend(row.map(|lit| if lit { '#' } else { '.' }));
+        text.push('\n');
+    }
+    text
+}
+
+pub fn assert_screen_hash(chip8: &Chip8, expected: u64) {
+    let hash = chip8.framebuffer_hash();
+    assert_eq!(
+        hash,
+        expected,
+        "framebuffer hash {:#018X} doesn't match, screen was:\n{}",
+        hash,
+        screen(chip8)
+    );
+}
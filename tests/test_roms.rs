@@ -0,0 +1,272 @@
+// runs small hand-assembled test ROMs headlessly and checks what ends up on screen
+mod common;
+
+use common::{assert_screen_hash, machine, run_frames, screen, RomBuilder};
+
+use chip_8::Quirks;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+const REPORT: u16 = 0x380;
+const SCRATCH: u16 = 0x3C0;
+const PASS_SPRITE: u16 = 0x3F0;
+const FAIL_SPRITE: u16 = 0x3F8;
+
+// draws the 16 font digits in two rows of eight
+fn hex_digits_rom() -> Vec<u8> {
+    RomBuilder::new()
+        .ops(&[
+            0x00E0, // 200: clear
+            0x6000, // 202: V0 = digit
+            0x6101, // 204: V1 = x
+            0x6201, // 206: V2 = y
+            0xF029, // 208: I = sprite for V0
+            0xD125, // 20A: draw
+            0x7001, // 20C: next digit
+            0x7108, // 20E: move right
+            0x4141, // 210: past the 8th column?
+            0x2220, // 212:   new line
+            0x4010, // 214: all 16 drawn?
+            0x1216, // 216:   halt
+            0x1208, // 218: loop
+        ])
+        .data_at(0x220, &[])
+        .ops(&[
+            0x6101, // 220: x = 1
+            0x7208, // 222: y += 8
+            0x00EE, // 224: return
+        ])
+        .build()
+}
+
+// each check leaves VA = 1 on success and calls REPORT, which draws a tick or a cross
+// at (VC, VD) and moves along
+struct FlagsRom {
+    rom: RomBuilder,
+    checks: usize,
+}
+
+impl FlagsRom {
+    fn new() -> FlagsRom {
+        let mut rom = RomBuilder::new();
+        rom.ops(&[0x00E0, 0x6C01, 0x6D01]);
+        FlagsRom { rom, checks: 0 }
+    }
+
+    // run `setup`, then compare V0 and, if given, VF
+    fn check(&mut self, setup: &[u16], v0: u8, vf: Option<u8>) -> &mut FlagsRom {
+        self.checks += 1;
+        self.rom.op(0x6A00).ops(setup);
+        let checks = if vf.is_some() { 2 } else { 1 };
+        // compare and jump straight to the report on a mismatch
+        let report_call = self.rom.here() + checks * 4 + 2;
+        self.rom.op(0x3000 | v0 as u16).op(0x1000 | report_call);
+        if let Some(vf) = vf {
+            self.rom.op(0x3F00 | vf as u16).op(0x1000 | report_call);
+        }
+        self.rom.op(0x6A01).op(0x2000 | REPORT);
+        assert_eq!(self.rom.here(), report_call + 2);
+        self
+    }
+
+    fn build(&mut self) -> Vec<u8> {
+        let halt = self.rom.here();
+        self.rom
+            .op(0x1000 | halt)
+            .data_at(REPORT, &[])
+            .ops(&[
+                0xA000 | PASS_SPRITE, // 380: I = tick
+                0x3A01,               // 382: passed?
+                0xA000 | FAIL_SPRITE, // 384:   I = cross
+                0xDCD5,               // 386: draw
+                0x7C06,               // 388: x += 6
+                0x4C3D,               // 38A: end of the line?
+                0x1000 | 0x390,       // 38C:   new line
+                0x00EE,               // 38E: return
+                0x6C01,               // 390: x = 1
+                0x7D07,               // 392: y += 7
+                0x00EE,               // 394: return
+            ])
+            .data_at(PASS_SPRITE, &[0x08, 0x08, 0x90, 0x50, 0x20])
+            .data_at(FAIL_SPRITE, &[0x88, 0x50, 0x20, 0x50, 0x88]);
+        self.rom.build()
+    }
+}
+
+fn flags_rom() -> FlagsRom {
+    let mut rom = FlagsRom::new();
+    rom
+        // 7XKK wraps and leaves VF alone
+        .check(&[0x6F55, 0x60FF, 0x7002], 0x01, Some(0x55))
+        // 8XY4 without and with carry
+        .check(&[0x6010, 0x6120, 0x8014], 0x30, Some(0))
+        .check(&[0x60FF, 0x6102, 0x8014], 0x01, Some(1))
+        // 8XY5 without and with borrow
+        .check(&[0x6030, 0x6110, 0x8015], 0x20, Some(1))
+        .check(&[0x6010, 0x6130, 0x8015], 0xE0, Some(0))
+        // shifts, with VX == VY so either shift quirk gives the same result
+        .check(&[0x6005, 0x6105, 0x8016], 0x02, Some(1))
+        .check(&[0x6004, 0x6104, 0x8016], 0x02, Some(0))
+        .check(&[0x6081, 0x6181, 0x801E], 0x02, Some(1))
+        .check(&[0x6041, 0x6141, 0x801E], 0x82, Some(0))
+        // logic
+        .check(&[0x60F0, 0x610F, 0x8011], 0xFF, None)
+        .check(&[0x60F0, 0x613C, 0x8012], 0x30, None)
+        .check(&[0x60F0, 0x613C, 0x8013], 0xCC, None)
+        // BCD of 156 read back and summed: 1 + 5 + 6
+        .check(
+            &[0x609C, 0xA000 | SCRATCH, 0xF033, 0xF265, 0x8014, 0x8024],
+            12,
+            Some(0),
+        );
+    rom
+}
+
+#[test]
+fn test_hex_digits() {
+    let mut chip8 = machine(&hex_digits_rom());
+    run_frames(&mut chip8, 20);
+    assert!(screen(&chip8).starts_with(
+        "\
+................................................................
+.####......#.....####....####....#..#....####....####....####...
+.#..#.....##........#.......#....#..#....#.......#..........#...
+"
+    ));
+    assert_screen_hash(&chip8, 0xB2B5_B4EF_5005_EA7C);
+}
+
+#[test]
+fn test_flags() {
+    let mut rom = flags_rom();
+    let mut chip8 = machine(&rom.build());
+    run_frames(&mut chip8, 60);
+    assert_eq!(rom.checks, 13);
+    // a row of ten ticks and a row of three
+    assert_screen_hash(&chip8, 0xF84D_3E1A_807A_CAA8);
+}
+
+// a sprite drawn across the bottom right corner
+fn clipping_rom() -> Vec<u8> {
+    RomBuilder::new()
+        .ops(&[
+            0x00E0, // clear
+            0x603C, // x = 60
+            0x611E, // y = 30
+            0xA20E, // I = sprite
+            0xD014, // draw
+            0x120A, // halt
+            0x0000,
+        ])
+        .data_at(0x20E, &[0xFF, 0x81, 0x81, 0xFF])
+        .build()
+}
+
+#[test]
+fn test_clipping_quirk() {
+    let mut chip8 = machine(&clipping_rom());
+    run_frames(&mut chip8, 2);
+    assert_screen_hash(&chip8, 0x2181_470B_BBB4_0B38);
+
+    let mut chip8 = machine(&clipping_rom());
+    chip8.quirks.clipping = false;
+    run_frames(&mut chip8, 2);
+    // wrapped into all four corners
+    assert_screen_hash(&chip8, 0xA66C_AAB2_8E95_4251);
+}
+
+// checks for the instructions the quirks change, each expecting what `quirks` should do
+fn quirks_rom(quirks: Quirks) -> Vec<u8> {
+    let mut rom = FlagsRom::new();
+    // 8XY6 and 8XYE with VX != VY
+    let (v0, vf) = if quirks.shift_vy {
+        (0x40, 1)
+    } else {
+        (0x02, 0)
+    };
+    rom.check(&[0x6004, 0x6181, 0x8016], v0, Some(vf));
+    let (v0, vf) = if quirks.shift_vy {
+        (0x02, 1)
+    } else {
+        (0x08, 0)
+    };
+    rom.check(&[0x6004, 0x6181, 0x801E], v0, Some(vf));
+    // store 0x77, then load V0 back from wherever I is left
+    let v0 = if quirks.increment_i { 0 } else { 0x77 };
+    rom.check(
+        &[0xA000 | SCRATCH, 0x6077, 0xF055, 0x6000, 0xF065],
+        v0,
+        None,
+    );
+    // 8XY1 leaving VF alone or not
+    let vf = if quirks.vf_reset { 0 } else { 5 };
+    rom.check(&[0x6F05, 0x6003, 0x6104, 0x8011], 0x07, Some(vf));
+    // V0 = 6 and VX = 2 for the X in the jump's address, so BNNN and BXNN land on different
+    // instructions. the setup starts after the check's own first instruction
+    let start = rom.rom.here() + 2;
+    let base = start + 6;
+    let x = base >> 8 & 0xF;
+    let v0 = if quirks.jump_vx { 0x22 } else { 0x44 };
+    rom.check(
+        &[
+            0x6006,
+            0x6002 | x << 8,
+            0xB000 | base,
+            0x0000,
+            0x6022,
+            0x1000 | (base + 8),
+            0x6044,
+        ],
+        v0,
+        None,
+    );
+    rom.build()
+}
+
+#[test]
+fn test_quirks() {
+    let modern = Quirks::default();
+    let vip = Quirks::preset("cosmac").unwrap();
+    let schip = Quirks::preset("schip").unwrap();
+    for quirks in [modern, vip, schip].iter() {
+        let mut chip8 = machine(&quirks_rom(*quirks));
+        chip8.quirks = *quirks;
+        run_frames(&mut chip8, 30);
+        // five ticks
+        assert_screen_hash(&chip8, 0x4E0C_947F_AB4D_76D8);
+    }
+
+    // and crosses where a ROM expects other quirks
+    let mut chip8 = machine(&quirks_rom(vip));
+    run_frames(&mut chip8, 30);
+    assert!(screen(&chip8).contains("#...#"));
+}
+
+// random digits at random positions
+fn random_rom() -> Vec<u8> {
+    RomBuilder::new()
+        .ops(&[
+            0x00E0, // 200: clear
+            0xC03F, // 202: x
+            0xC11F, // 204: y
+            0xC20F, // 206: digit
+            0xF229, // 208: I = digit sprite
+            0xD015, // 20A: draw
+            0x1202, // 20C: again
+        ])
+        .build()
+}
+
+#[test]
+fn test_seeded_rng_is_reproducible() {
+    let mut first = machine(&random_rom());
+    run_frames(&mut first, 10);
+    let mut second = machine(&random_rom());
+    run_frames(&mut second, 10);
+    assert_eq!(first.framebuffer_hash(), second.framebuffer_hash());
+
+    let mut reseeded = machine(&random_rom());
+    reseeded.set_rng(Box::new(StdRng::seed_from_u64(0x1234)));
+    run_frames(&mut reseeded, 10);
+    assert_ne!(first.framebuffer_hash(), reseeded.framebuffer_hash());
+}